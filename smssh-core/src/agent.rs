@@ -0,0 +1,472 @@
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{ErrorKind, Read, Write},
+    net::Shutdown,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use secrecy::{ExposeSecret, SecretString};
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use tracing::{debug, info, warn};
+#[cfg(windows)]
+use uds_windows::{UnixListener, UnixStream};
+
+use crate::{authorized_keys, config::Config, keygen, metrics::Metrics, permissions, systemd};
+
+static SOCKET_FILE_NAME: &str = "agent.sock";
+static PUBLIC_KEYS_FILE_NAME: &str = "agent_public_keys.json";
+const SOCKET_MODE: u32 = 0o600;
+/// How often the socket is checked for clients and the agent for termination
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long the inner ssh-agent gets to create its socket
+const INNER_AGENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longer messages are not requests a client would send
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+// Message numbers of the ssh agent protocol, see draft-miller-ssh-agent
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+
+/// Fetches the key of a key alias
+pub type Fetch<'a> = dyn Fn(&str) -> Result<SecretString> + Sync + 'a;
+
+/// Path of the agent socket in the config directory
+pub fn socket_path() -> PathBuf {
+    Config::config_dir().join(SOCKET_FILE_NAME)
+}
+
+fn public_keys_path() -> PathBuf {
+    Config::config_dir().join(PUBLIC_KEYS_FILE_NAME)
+}
+
+/// The public keys of the key aliases seen by earlier agents, so that they can be listed without
+/// fetching the private keys
+fn load_public_keys() -> BTreeMap<String, String> {
+    std::fs::read_to_string(public_keys_path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn store_public_keys(public_keys: &BTreeMap<String, String>) -> Result<()> {
+    Config::write_atomic(
+        &public_keys_path(),
+        &serde_json::to_string_pretty(public_keys)?,
+    )
+}
+
+/// Remember the public key of a key alias, so that the agent offers it without fetching it
+pub fn remember_public_key(alias: &str, public_key: &str) -> Result<()> {
+    let mut public_keys = load_public_keys();
+    if public_keys.get(alias).map(String::as_str) == Some(public_key) {
+        return Ok(());
+    }
+    public_keys.insert(alias.to_string(), public_key.to_string());
+    store_public_keys(&public_keys)
+}
+
+/// The key blob of a public key in the authorized_keys format, as the agent protocol sends it
+fn key_blob(public_key: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(authorized_keys::blob(public_key)?)
+        .wrap_err("Invalid public key")
+}
+
+/// Read a length prefixed string of the agent protocol from the start of `data`
+fn read_string(data: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    data.get(4..4 + len)
+}
+
+fn write_string(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Read a message, `None` once the other side closed the connection
+fn read_message(stream: &mut UnixStream) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE_LEN {
+        return Err(eyre!("Invalid agent message length {len}"));
+    }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(stream: &mut UnixStream, message: &[u8]) -> Result<()> {
+    stream.write_all(&(message.len() as u32).to_be_bytes())?;
+    stream.write_all(message)?;
+    Ok(())
+}
+
+/// Start a private ssh-agent holding the fetched keys, which does the signing
+fn start_inner_agent(socket: &Path) -> Result<Child> {
+    let mut child = Command::new("ssh-agent")
+        .arg("-D")
+        .arg("-a")
+        .arg(socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .wrap_err("Failed to run ssh-agent")?;
+    let started = Instant::now();
+    while !socket.exists() {
+        if let Some(status) = child.try_wait()? {
+            return Err(eyre!("ssh-agent exited with {status}"));
+        }
+        if started.elapsed() > INNER_AGENT_TIMEOUT {
+            let _ = child.kill();
+            return Err(eyre!(
+                "ssh-agent did not start within {INNER_AGENT_TIMEOUT:?}"
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(child)
+}
+
+/// What the agent learned from its fetches
+struct State {
+    /// Public keys in the authorized_keys format by key alias
+    public_keys: BTreeMap<String, String>,
+    /// When the key of a key alias was last added to the inner agent
+    loaded: HashMap<String, Instant>,
+}
+
+struct Agent<'a> {
+    /// The key aliases offered to clients, in order
+    aliases: Vec<String>,
+    /// How long a fetched key is kept by the inner agent
+    key_ttl: Duration,
+    inner_socket: PathBuf,
+    /// Only locked to read or update, never while fetching a key, so that a slow fetch does not
+    /// hold up the other clients
+    state: Mutex<State>,
+    fetch: &'a Fetch<'a>,
+    metrics: Arc<Metrics>,
+}
+
+impl Agent<'_> {
+    fn state(&self) -> Result<MutexGuard<'_, State>> {
+        self.state
+            .lock()
+            .map_err(|_| eyre!("The agent state is poisoned"))
+    }
+
+    /// Fetch the key of a key alias and add it to the inner agent for the key TTL, remembering
+    /// its public key
+    fn load(&self, alias: &str) -> Result<()> {
+        info!("Fetching the key of '{alias}'");
+        let started = Instant::now();
+        let key = (self.fetch)(alias);
+        self.metrics.fetched(started.elapsed(), key.is_ok());
+        let key = key?;
+        let public_key = keygen::public_key(&key)?;
+
+        let mut ssh_add = Command::new("ssh-add")
+            .args(["-q", "-t", &self.key_ttl.as_secs().max(1).to_string(), "-"])
+            .env("SSH_AUTH_SOCK", &self.inner_socket)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err("Failed to run ssh-add")?;
+        // The key is passed through a pipe, so that it never touches the disk
+        if let Some(mut stdin) = ssh_add.stdin.take() {
+            stdin.write_all(key.expose_secret().as_bytes())?;
+        }
+        let output = ssh_add.wait_with_output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "ssh-add failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let mut state = self.state()?;
+        state.loaded.insert(alias.to_string(), Instant::now());
+        if state.public_keys.get(alias) != Some(&public_key) {
+            state.public_keys.insert(alias.to_string(), public_key);
+            if let Err(e) = store_public_keys(&state.public_keys) {
+                warn!("Failed to remember the public key of '{alias}': {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// The key blobs and comments of the key aliases whose public key is known. Listing never
+    /// fetches a key, so that a client probing the agent can't make it fetch every key.
+    fn identities(&self) -> Result<Vec<u8>> {
+        let mut state = self.state()?;
+        // Pick up the public keys remembered by `smssh pubkey` since the agent started
+        if self
+            .aliases
+            .iter()
+            .any(|alias| !state.public_keys.contains_key(alias))
+        {
+            for (alias, public_key) in load_public_keys() {
+                state.public_keys.entry(alias).or_insert(public_key);
+            }
+        }
+        let mut identities = Vec::new();
+        for alias in &self.aliases {
+            let Some(public_key) = state.public_keys.get(alias) else {
+                continue;
+            };
+            match key_blob(public_key) {
+                Ok(blob) => identities.push((blob, alias)),
+                Err(e) => warn!("Not offering '{alias}': {e}"),
+            }
+        }
+        let mut answer = vec![SSH_AGENT_IDENTITIES_ANSWER];
+        answer.extend_from_slice(&(identities.len() as u32).to_be_bytes());
+        for (blob, comment) in &identities {
+            write_string(&mut answer, blob);
+            write_string(&mut answer, comment.as_bytes());
+        }
+        Ok(answer)
+    }
+
+    /// Pass a request on to the inner agent and return its answer
+    fn forward(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let mut inner = UnixStream::connect(&self.inner_socket)
+            .wrap_err("Failed to connect to the inner ssh-agent")?;
+        write_message(&mut inner, request)?;
+        read_message(&mut inner)?.ok_or(eyre!("The inner ssh-agent closed the connection"))
+    }
+
+    /// Sign with the key of the key alias the requested key belongs to, fetching it first if the
+    /// inner agent no longer holds it
+    fn sign(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let blob = read_string(&request[1..]).ok_or(eyre!("Invalid sign request"))?;
+        let (alias, expired) = {
+            let state = self.state()?;
+            let alias = state
+                .public_keys
+                .iter()
+                .find(|(alias, public_key)| {
+                    self.aliases.contains(alias)
+                        && key_blob(public_key).is_ok_and(|known| known == blob)
+                })
+                .map(|(alias, _)| alias.clone())
+                .ok_or(eyre!("Signature requested for an unknown key"))?;
+            let expired = state
+                .loaded
+                .get(&alias)
+                .is_none_or(|loaded| loaded.elapsed() >= self.key_ttl);
+            (alias, expired)
+        };
+
+        if expired {
+            self.load(&alias)?;
+        } else {
+            self.metrics.cache_hit();
+        }
+        let answer = self.forward(request)?;
+        // The inner agent may have dropped the key just before, or the secret changed
+        if answer.first() == Some(&SSH_AGENT_FAILURE) && !expired {
+            self.load(&alias)?;
+            return self.forward(request);
+        }
+        info!("Signed with the key of '{alias}'");
+        Ok(answer)
+    }
+
+    fn answer(&self, request: &[u8]) -> Vec<u8> {
+        let answer = match request.first() {
+            Some(&SSH_AGENTC_REQUEST_IDENTITIES) => self.identities(),
+            Some(&SSH_AGENTC_SIGN_REQUEST) => self.sign(request),
+            // Clients can't add or remove keys, the key aliases decide them
+            other => {
+                debug!("Unsupported agent request {other:?}");
+                return vec![SSH_AGENT_FAILURE];
+            }
+        };
+        answer.unwrap_or_else(|e| {
+            warn!("Failed to answer: {e:#}");
+            vec![SSH_AGENT_FAILURE]
+        })
+    }
+}
+
+/// Listen on the agent socket, replacing the one left behind by an agent that was killed
+fn bind(path: &Path) -> Result<UnixListener> {
+    Config::create_config_dir()?;
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(eyre!("An agent is already listening on {path:?}"));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener =
+        UnixListener::bind(path).wrap_err_with(|| format!("Failed to listen on {path:?}"))?;
+    permissions::set_mode(path, SOCKET_MODE)?;
+    Ok(listener)
+}
+
+#[cfg(unix)]
+fn ensure_supported() -> Result<()> {
+    Ok(())
+}
+
+/// OpenSSH for Windows only reaches agents through named pipes, and its ssh-agent is a system
+/// service that can't be started privately to hold the fetched keys
+#[cfg(windows)]
+fn ensure_supported() -> Result<()> {
+    Err(eyre!(
+        "The agent is not supported on Windows, connect with `smssh connect` instead"
+    ))
+}
+
+/// Answer the requests of a client until it disconnects
+fn handle(mut stream: UnixStream, agent: &Agent) -> Result<()> {
+    while let Some(request) = read_message(&mut stream)? {
+        let answer = agent.answer(&request);
+        write_message(&mut stream, &answer)?;
+    }
+    Ok(())
+}
+
+/// Serve the keys of the key aliases as an ssh agent on the agent socket until the process is
+/// terminated. Keys are fetched with `fetch` when a client first signs with them, and held by a
+/// private ssh-agent for `key_ttl`, so that they never touch the disk. Only the key aliases whose
+/// public key is remembered, from earlier fetches or `smssh pubkey`, are listed. Prometheus metrics are
+/// served on `metrics_address` if set. Listens on the socket passed by systemd when started by
+/// socket activation, and exits after `idle_timeout` without clients if set.
+pub fn serve(
+    aliases: Vec<String>,
+    key_ttl: Duration,
+    metrics_address: Option<&str>,
+    idle_timeout: Option<Duration>,
+    fetch: &Fetch,
+) -> Result<()> {
+    ensure_supported()?;
+    let path = socket_path();
+    let activated = systemd::activated_listener()?;
+    let is_activated = activated.is_some();
+    let listener = match activated {
+        Some(listener) => listener,
+        None => bind(&path)?,
+    };
+    listener.set_nonblocking(true)?;
+
+    let inner_dir = tempfile::Builder::new().prefix("smssh-agent-").tempdir()?;
+    let inner_socket = inner_dir.path().join(SOCKET_FILE_NAME);
+    let mut inner = start_inner_agent(&inner_socket)?;
+
+    let terminated = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(SIGHUP, terminated.clone())?;
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, terminated.clone())?;
+    }
+
+    let metrics = Arc::new(Metrics::new(
+        "smssh_agent_clients",
+        "Clients like ssh connected to the agent",
+    ));
+    if let Some(address) = metrics_address {
+        crate::metrics::serve(address, metrics.clone())?;
+    }
+    let public_keys = load_public_keys();
+    for alias in aliases
+        .iter()
+        .filter(|alias| !public_keys.contains_key(*alias))
+    {
+        warn!(
+            "Not offering '{alias}' until its public key is known, run `smssh pubkey {alias}` once"
+        );
+    }
+    let agent = Agent {
+        aliases,
+        key_ttl,
+        inner_socket,
+        state: Mutex::new(State {
+            public_keys,
+            loaded: HashMap::new(),
+        }),
+        fetch,
+        metrics: metrics.clone(),
+    };
+    let address = listener.local_addr()?;
+    let listening = address.as_pathname().unwrap_or(&path);
+    info!("Listening on {listening:?}, point IdentityAgent or SSH_AUTH_SOCK at it");
+    let result = std::thread::scope(|scope| {
+        let mut clients = Vec::new();
+        let mut last_client = Instant::now();
+        while !terminated.load(Ordering::Relaxed) {
+            // Clients that disconnected are forgotten, rather than keeping their sockets open
+            clients
+                .retain(|(_, done): &(UnixStream, Arc<AtomicBool>)| !done.load(Ordering::Relaxed));
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if !clients.is_empty() {
+                        last_client = Instant::now();
+                    } else if let Some(idle_timeout) = idle_timeout
+                        && last_client.elapsed() >= idle_timeout
+                    {
+                        info!("Exiting after {idle_timeout:?} without clients");
+                        break;
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            last_client = Instant::now();
+            stream.set_nonblocking(false)?;
+            let done = Arc::new(AtomicBool::new(false));
+            clients.push((stream.try_clone()?, done.clone()));
+            let agent = &agent;
+            let metrics = &metrics;
+            scope.spawn(move || {
+                metrics.adjust_sessions(1);
+                if let Err(e) = handle(stream, agent) {
+                    warn!("Failed to handle a client: {e}");
+                }
+                metrics.adjust_sessions(-1);
+                done.store(true, Ordering::Relaxed);
+            });
+        }
+        // Unblock the clients still connected, so that the scope can end
+        for (client, _) in clients {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    });
+
+    let _ = inner.kill();
+    let _ = inner.wait();
+    // The socket of systemd stays, to start the agent again on the next connection
+    if !is_activated {
+        let _ = std::fs::remove_file(&path);
+    }
+    info!("Stopped, the fetched keys are gone with the inner ssh-agent");
+    result
+}
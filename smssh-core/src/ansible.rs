@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use serde::Serialize;
+
+use crate::{
+    config::{Config, ResolvedHost},
+    connect,
+};
+
+/// The connection variables of a host in an Ansible inventory
+#[derive(Serialize, Debug, Default)]
+pub struct HostVars {
+    /// The address ssh connects to
+    pub ansible_host: String,
+    /// The user of the destination, if it names one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ansible_user: Option<String>,
+    /// The port of the host, if its destination or `-p` sets one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ansible_port: Option<u16>,
+    /// The ssh args of the host other than the port, like jump hosts and -o options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ansible_ssh_common_args: Option<String>,
+}
+
+/// Hosts and their groups, which are made of the tags and the groups of smssh
+#[derive(Debug, Default)]
+pub struct Inventory {
+    /// Connection variables by host name
+    pub hosts: BTreeMap<String, HostVars>,
+    /// Host names by group name
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+/// Ansible group names may only contain letters, digits and underscores
+fn group_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn host_vars(host: &ResolvedHost) -> HostVars {
+    let destination = host
+        .destination
+        .strip_prefix("ssh://")
+        .unwrap_or(&host.destination);
+    let (user, address) = match destination.rsplit_once('@') {
+        Some((user, address)) => (Some(user.to_string()), address),
+        None => (None, destination),
+    };
+    // ssh:// destinations may carry the port
+    let (address, mut port) = match address.rsplit_once(':') {
+        Some((address, port)) if host.destination.starts_with("ssh://") => {
+            (address, port.parse().ok())
+        }
+        _ => (address, None),
+    };
+
+    let mut other_args = Vec::new();
+    let mut args = host.args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-p" {
+            port = args.next().and_then(|port| port.parse().ok()).or(port);
+        } else {
+            other_args.push(connect::shell_quote(arg));
+        }
+    }
+    HostVars {
+        ansible_host: address.to_string(),
+        ansible_user: user,
+        ansible_port: port,
+        ansible_ssh_common_args: (!other_args.is_empty()).then(|| other_args.join(" ")),
+    }
+}
+
+impl Inventory {
+    /// The inventory of all hosts of the config, with their templates applied
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut inventory = Self::default();
+        for name in config.host_names() {
+            let host = config.resolve_host(name)?;
+            for tag in &host.tags {
+                inventory
+                    .groups
+                    .entry(group_name(tag))
+                    .or_default()
+                    .push(name.clone());
+            }
+            inventory.hosts.insert(name.clone(), host_vars(&host));
+        }
+        for (group, group_config) in &config.groups {
+            let members = inventory.groups.entry(group_name(group)).or_default();
+            members.extend(group_config.hosts.iter().cloned());
+        }
+        for members in inventory.groups.values_mut() {
+            members.sort();
+            members.dedup();
+        }
+        Ok(inventory)
+    }
+
+    /// The inventory in the INI format, with the variables on the host lines
+    pub fn to_ini(&self) -> String {
+        let mut ini = String::new();
+        for (name, vars) in &self.hosts {
+            ini.push_str(name);
+            ini.push_str(&format!(" ansible_host={}", vars.ansible_host));
+            if let Some(user) = &vars.ansible_user {
+                ini.push_str(&format!(" ansible_user={user}"));
+            }
+            if let Some(port) = vars.ansible_port {
+                ini.push_str(&format!(" ansible_port={port}"));
+            }
+            if let Some(args) = &vars.ansible_ssh_common_args {
+                ini.push_str(&format!(
+                    " ansible_ssh_common_args=\"{}\"",
+                    args.replace('"', "\\\"")
+                ));
+            }
+            ini.push('\n');
+        }
+        for (group, members) in &self.groups {
+            ini.push_str(&format!("\n[{group}]\n"));
+            for member in members {
+                ini.push_str(member);
+                ini.push('\n');
+            }
+        }
+        ini
+    }
+
+    /// The inventory in the YAML format, with all hosts under `all` and the groups as its
+    /// children
+    pub fn to_yaml(&self) -> Result<String> {
+        /// Group members have no variables of their own
+        #[derive(Serialize)]
+        struct Member {}
+        #[derive(Serialize)]
+        struct Group<'a> {
+            hosts: BTreeMap<&'a str, Member>,
+        }
+        #[derive(Serialize)]
+        struct All<'a> {
+            hosts: &'a BTreeMap<String, HostVars>,
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            children: BTreeMap<&'a str, Group<'a>>,
+        }
+        #[derive(Serialize)]
+        struct Root<'a> {
+            all: All<'a>,
+        }
+
+        let children = self
+            .groups
+            .iter()
+            .map(|(group, members)| {
+                let hosts = members
+                    .iter()
+                    .map(|member| (member.as_str(), Member {}))
+                    .collect();
+                (group.as_str(), Group { hosts })
+            })
+            .collect();
+        let root = Root {
+            all: All {
+                hosts: &self.hosts,
+                children,
+            },
+        };
+        Ok(serde_yml::to_string(&root)?)
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    borrow::Cow,
+    fs::OpenOptions,
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(windows)]
+use color_eyre::eyre::eyre;
+use color_eyre::{Result, eyre::Context};
+#[cfg(unix)]
+use nix::unistd::{User, getuid};
+use serde::Serialize;
+
+use crate::{config::AuditLogConfig, interpolation, permissions};
+
+#[cfg(unix)]
+static SYSLOG_SOCKET: &str = "/dev/log";
+/// Facility authpriv (10) and severity info (6)
+#[cfg(unix)]
+const SYSLOG_PRIORITY: u8 = 10 * 8 + 6;
+const AUDIT_LOG_MODE: u32 = 0o600;
+
+/// A key fetch, a finished connection or a key rotation
+#[derive(Serialize, Debug)]
+pub struct AuditRecord<'a> {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// `fetch`, `connect` or `rotate`
+    pub event: &'static str,
+    /// The local user who ran smssh
+    pub user: String,
+    /// The configured host, unset for plain destinations and key fetches outside of a connection
+    pub host: Option<&'a str>,
+    /// The destination ssh connected to
+    pub destination: Option<&'a str>,
+    /// The key alias whose key was used
+    pub key_alias: &'a str,
+    /// Where the key comes from, see [`crate::config::KeyAliasConfig::source`]
+    pub secret_arn: Cow<'a, str>,
+    /// Exit code of ssh for connections
+    pub exit_code: Option<i32>,
+    /// Why the event failed, unset if it succeeded
+    pub error: Option<String>,
+}
+
+impl<'a> AuditRecord<'a> {
+    /// A record of an event happening now, with no exit code or error yet
+    pub fn new(
+        event: &'static str,
+        host: Option<&'a str>,
+        destination: Option<&'a str>,
+        key_alias: &'a str,
+        secret_arn: Cow<'a, str>,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            event,
+            user: current_user(),
+            host,
+            destination,
+            key_alias,
+            secret_arn,
+            exit_code: None,
+            error: None,
+        }
+    }
+}
+
+/// Name of the local user, or their uid if it has no name
+#[cfg(unix)]
+fn current_user() -> String {
+    let uid = getuid();
+    match User::from_uid(uid) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    }
+}
+
+/// Name of the local user, with their domain if they have one
+#[cfg(windows)]
+fn current_user() -> String {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    match std::env::var("USERDOMAIN") {
+        Ok(domain) if !domain.is_empty() => format!("{domain}\\{user}"),
+        _ => user,
+    }
+}
+
+#[cfg(unix)]
+fn send_to_syslog(line: &str) -> Result<()> {
+    let message = format!("<{SYSLOG_PRIORITY}>smssh[{}]: {line}", std::process::id());
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .send_to(message.as_bytes(), SYSLOG_SOCKET)
+        .wrap_err_with(|| format!("Failed to send the audit record to {SYSLOG_SOCKET}"))?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn send_to_syslog(_line: &str) -> Result<()> {
+    Err(eyre!(
+        "Windows has no syslog, set a file as the audit log instead"
+    ))
+}
+
+/// Append a record to the audit log, failing if it could not be written
+pub fn record(audit_log: &AuditLogConfig, record: &AuditRecord) -> Result<()> {
+    let line = serde_json::to_string(record)?;
+    match audit_log {
+        AuditLogConfig::File { path } => {
+            let path = interpolation::expand(path)?;
+            // Append-only, so that a record is never interleaved with or overwrites another
+            let mut file = permissions::create_with_mode(&mut OpenOptions::new(), AUDIT_LOG_MODE)
+                .append(true)
+                .create(true)
+                .open(&path)
+                .wrap_err_with(|| format!("Failed to open the audit log {path:?}"))?;
+            file.write_all(format!("{line}\n").as_bytes())
+                .wrap_err_with(|| format!("Failed to write to the audit log {path:?}"))?;
+        }
+        AuditLogConfig::Syslog => send_to_syslog(&line)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use color_eyre::{Result, eyre::eyre};
+use secrecy::SecretString;
+
+use crate::{
+    config::ResolvedHost,
+    connect::{self, Route},
+};
+
+/// Appends the public key read from stdin to authorized_keys, unless its blob is already there
+static INSTALL_SCRIPT: &str = r#"set -e
+umask 077
+read -r key
+blob=$(printf '%s\n' "$key" | awk '{ print $2 }')
+mkdir -p ~/.ssh
+file=~/.ssh/authorized_keys
+touch "$file"
+if ! grep -qF "$blob" "$file"; then
+    if [ -s "$file" ] && [ -n "$(tail -c 1 "$file")" ]; then echo >> "$file"; fi
+    printf '%s\n' "$key" >> "$file"
+fi"#;
+
+/// Removes the lines holding the public key blob read from stdin from authorized_keys, keeping
+/// the file itself so that its permissions and owner stay the same
+static REMOVE_SCRIPT: &str = r#"set -e
+umask 077
+read -r blob
+file=~/.ssh/authorized_keys
+awk -v blob="$blob" 'index($0, blob) == 0' "$file" > "$file.smssh"
+cat "$file.smssh" > "$file"
+rm -f "$file.smssh""#;
+
+/// The base64 blob of a public key, identifying it in authorized_keys regardless of the options
+/// and the comment around it
+pub fn blob(public_key: &str) -> Result<&str> {
+    let mut fields = public_key.split_whitespace();
+    match (fields.next(), fields.next()) {
+        (Some(kind), Some(blob))
+            if !public_key.contains('\n')
+                && (kind.starts_with("ssh-")
+                    || kind.starts_with("ecdsa-")
+                    || kind.starts_with("sk-")) =>
+        {
+            Ok(blob)
+        }
+        _ => Err(eyre!("Not a public key in the authorized_keys format")),
+    }
+}
+
+/// Run a script as `user` through sudo, or as the user logged in if `None`
+fn as_user(script: &str, user: Option<&str>) -> String {
+    match user {
+        Some(user) => format!(
+            "exec sudo -n -H -u {} sh -c {}",
+            connect::shell_quote(user),
+            connect::shell_quote(script)
+        ),
+        None => script.to_string(),
+    }
+}
+
+/// Add a public key to the authorized_keys of `user` on a host, connecting with `key`. Does
+/// nothing if the key is already authorized.
+pub fn install(
+    host: &ResolvedHost,
+    route: &Route,
+    key: &SecretString,
+    public_key: &str,
+    user: Option<&str>,
+) -> Result<()> {
+    blob(public_key)?;
+    connect::run_remote(
+        host,
+        route,
+        key,
+        &as_user(INSTALL_SCRIPT, user),
+        &format!("{}\n", public_key.trim()),
+    )?;
+    Ok(())
+}
+
+/// Remove a public key from the authorized_keys of `user` on a host, connecting with `key`
+pub fn remove(
+    host: &ResolvedHost,
+    route: &Route,
+    key: &SecretString,
+    public_key: &str,
+    user: Option<&str>,
+) -> Result<()> {
+    let blob = blob(public_key)?;
+    connect::run_remote(
+        host,
+        route,
+        key,
+        &as_user(REMOVE_SCRIPT, user),
+        &format!("{blob}\n"),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,720 @@
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::provider::{
+    ProvideCredentials, SharedCredentialsProvider, error::CredentialsError,
+};
+use aws_sdk_ec2::{
+    operation::{
+        describe_instances::DescribeInstancesError, start_instances::StartInstancesError,
+        stop_instances::StopInstancesError,
+    },
+    types::Filter,
+};
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_secretsmanager::{
+    Client as SecretsManagerClient,
+    config::http::HttpResponse,
+    error::{ConnectorError, ProvideErrorMetadata, SdkError},
+    operation::{
+        create_secret::CreateSecretError, get_secret_value::GetSecretValueError,
+        list_secrets::ListSecretsError, put_secret_value::PutSecretValueError,
+    },
+    types::Tag,
+};
+use aws_sdk_sts::operation::get_caller_identity::GetCallerIdentityError;
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+use futures_util::{StreamExt, stream};
+use secrecy::{ExposeSecret, SecretString};
+use serde::de::DeserializeOwned;
+use tokio::{runtime::Runtime, time::error::Elapsed};
+use tracing::debug;
+
+use crate::{encryption, history};
+
+/// Runtime shared by the blocking functions, so that one is not built for every request
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+/// The AWS CLI, which looks up the RDS, ElastiCache and Route 53 entries and opens the tunnels
+/// of the SSM and Instance Connect transports
+pub(crate) static CLI_COMMAND: &str = "aws";
+/// Plugin of the AWS CLI that `aws ssm start-session` hands the session to
+static SESSION_MANAGER_PLUGIN: &str = "session-manager-plugin";
+/// Secrets Manager clients with resolved credentials per profile, reused by all fetches
+static SECRETS_MANAGER_CLIENTS: LazyLock<Mutex<HashMap<Option<String>, SecretsManagerClient>>> =
+    LazyLock::new(Default::default);
+
+/// Run a future to completion on the shared runtime.
+pub fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    let runtime = match RUNTIME.get() {
+        Some(runtime) => runtime,
+        None => {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            RUNTIME.get_or_init(|| runtime)
+        }
+    };
+    Ok(runtime.block_on(future))
+}
+
+/// Run a fallible future on the shared runtime, failing with "Could not {action} within ..."
+/// if it does not finish before the timeout.
+pub fn block_on_timeout<T>(
+    future: impl Future<Output = Result<T>>,
+    timeout: Option<Duration>,
+    action: &str,
+) -> Result<T> {
+    let Some(timeout) = timeout else {
+        return block_on(future)?;
+    };
+    // The timer has to be created inside the runtime
+    block_on(async { tokio::time::timeout(timeout, future).await })?.map_err(|elapsed| {
+        Report::new(elapsed).wrap_err(format!(
+            "Could not {action} within {}",
+            history::format_duration(timeout.as_secs())
+        ))
+    })?
+}
+
+/// AWS could not be reached by the daemon fetching a key, relayed to the process asking for it
+#[derive(Debug)]
+pub struct Unreachable(pub String);
+
+impl Display for Unreachable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl StdError for Unreachable {}
+
+/// An error AWS returned to the daemon fetching a key, relayed with its error code
+#[derive(Debug)]
+pub struct Relayed {
+    /// The AWS error code, like "AccessDeniedException"
+    pub code: String,
+    /// The error as the daemon printed it
+    pub message: String,
+}
+
+impl Display for Relayed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl StdError for Relayed {}
+
+/// Whether a request failed because it took too long, covers the timeouts of the requests, of
+/// the credential providers and of [`block_on_timeout`]
+pub fn is_timeout(report: &Report) -> bool {
+    report.chain().any(|error| {
+        error.is::<Elapsed>()
+            || matches!(
+                error.downcast_ref::<CredentialsError>(),
+                Some(CredentialsError::ProviderTimedOut(_))
+            )
+            || matches!(
+                error.downcast_ref::<SdkError<GetSecretValueError, HttpResponse>>(),
+                Some(SdkError::TimeoutError(_))
+            )
+    })
+}
+
+/// Whether a request failed because AWS could not be reached in time, rather than because AWS
+/// refused it. Covers connection failures and the timeouts of [`is_timeout`].
+pub fn is_unreachable(report: &Report) -> bool {
+    is_timeout(report)
+        || report.chain().any(|error| {
+            error.is::<ConnectorError>()
+                || error.is::<Unreachable>()
+                || matches!(
+                    error.downcast_ref::<SdkError<GetSecretValueError, HttpResponse>>(),
+                    Some(SdkError::DispatchFailure(_))
+                )
+        })
+}
+
+/// Whether a request failed because no AWS credentials were found
+pub fn lacks_credentials(report: &Report) -> bool {
+    report.chain().any(|error| {
+        matches!(
+            error.downcast_ref::<CredentialsError>(),
+            Some(CredentialsError::CredentialsNotLoaded(_))
+        )
+    })
+}
+
+/// Load the AWS SDK config from the default chain, using the named profile if given.
+async fn load_sdk_config(profile: Option<&str>) -> SdkConfig {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(profile) = profile {
+        loader = loader.profile_name(profile);
+    }
+    loader.load().await
+}
+
+/// Blocking variant of [`get_key`]
+pub fn get_key_blocking(secret_arn: &str, profile: Option<&str>) -> Result<SecretString> {
+    block_on(get_key(secret_arn, profile))?
+}
+
+/// Fetch the key stored in a secret with the credentials of a profile
+pub async fn get_key(secret_arn: &str, profile: Option<&str>) -> Result<SecretString> {
+    let client = secrets_manager(profile).await?;
+    get_secret(&client, secret_arn).await
+}
+
+/// Get the Secrets Manager client of a profile, resolving its credentials on first use.
+pub async fn secrets_manager(profile: Option<&str>) -> Result<SecretsManagerClient> {
+    let profile_key = profile.map(str::to_string);
+    let cached = SECRETS_MANAGER_CLIENTS
+        .lock()
+        .map_err(|_| eyre!("The AWS client cache is poisoned"))?
+        .get(&profile_key)
+        .cloned();
+    if let Some(client) = cached {
+        return Ok(client);
+    }
+
+    // Not holding the lock while resolving, concurrent fetches for one profile might both
+    // resolve it but only one client is kept
+    let client = SecretsManagerClient::new(&load_credentials(profile).await?);
+    let mut clients = SECRETS_MANAGER_CLIENTS
+        .lock()
+        .map_err(|_| eyre!("The AWS client cache is poisoned"))?;
+    Ok(clients.entry(profile_key).or_insert(client).clone())
+}
+
+/// Load the AWS SDK config and resolve its credentials up front, so that slow credential chains
+/// (SSO, assumed roles) are not hidden inside the first request.
+pub async fn load_credentials(profile: Option<&str>) -> Result<SdkConfig> {
+    let start = Instant::now();
+    let sdk_config = load_sdk_config(profile).await;
+    let provider = sdk_config
+        .credentials_provider()
+        .ok_or_else(|| CredentialsError::not_loaded("No AWS credentials provider is configured"))?;
+    let credentials = provider.provide_credentials().await?;
+    debug!(
+        "Resolved AWS credentials for profile {profile:?} in {:?}",
+        start.elapsed()
+    );
+    Ok(sdk_config
+        .into_builder()
+        .credentials_provider(SharedCredentialsProvider::new(credentials))
+        .build())
+}
+
+/// Fetch the current version of a secret with a client, failing if it holds no string
+pub async fn get_secret(client: &SecretsManagerClient, secret_arn: &str) -> Result<SecretString> {
+    let start = Instant::now();
+    let mut response = client
+        .get_secret_value()
+        .secret_id(secret_arn)
+        .send()
+        .await?;
+    debug!(
+        "GetSecretValue for '{secret_arn}' returned version {:?} after {:?}",
+        response.version_id(),
+        start.elapsed()
+    );
+    // Take the value out of the response so that no plaintext copy outlives this function
+    let secret_value = response
+        .secret_string
+        .take()
+        .ok_or(eyre!("The secret '{secret_arn}' does not contain a key"))?;
+    Ok(encryption::into_secret(secret_value))
+}
+
+/// Blocking variant of [`create_secret`]
+pub fn create_secret_blocking(
+    name: &str,
+    key: &SecretString,
+    profile: Option<&str>,
+    kms_key_id: Option<&str>,
+    tags: &[(String, String)],
+) -> Result<String> {
+    block_on(create_secret(name, key, profile, kms_key_id, tags))?
+}
+
+/// Create a secret holding a key, encrypted with the given KMS key or the account's default one.
+/// Returns the ARN of the new secret.
+pub async fn create_secret(
+    name: &str,
+    key: &SecretString,
+    profile: Option<&str>,
+    kms_key_id: Option<&str>,
+    tags: &[(String, String)],
+) -> Result<String> {
+    let client = secrets_manager(profile).await?;
+    debug!("CreateSecret for '{name}'");
+    let mut request = client
+        .create_secret()
+        .name(name)
+        .secret_string(key.expose_secret())
+        .set_kms_key_id(kms_key_id.map(str::to_string));
+    for (key, value) in tags {
+        request = request.tags(Tag::builder().key(key).value(value).build());
+    }
+    let response = request.send().await?;
+    response
+        .arn
+        .ok_or(eyre!("Secrets Manager did not return the ARN of '{name}'"))
+}
+
+/// Blocking variant of [`put_secret_value`]
+pub fn put_secret_value_blocking(
+    secret_arn: &str,
+    key: &SecretString,
+    profile: Option<&str>,
+) -> Result<String> {
+    block_on(put_secret_value(secret_arn, key, profile))?
+}
+
+/// Store a key as the new current version of a secret, returning the ID of the version. The
+/// previous version stays available as `AWSPREVIOUS`.
+pub async fn put_secret_value(
+    secret_arn: &str,
+    key: &SecretString,
+    profile: Option<&str>,
+) -> Result<String> {
+    let client = secrets_manager(profile).await?;
+    debug!("PutSecretValue for '{secret_arn}'");
+    let response = client
+        .put_secret_value()
+        .secret_id(secret_arn)
+        .secret_string(key.expose_secret())
+        .send()
+        .await?;
+    response.version_id.ok_or(eyre!(
+        "Secrets Manager did not return the new version of '{secret_arn}'"
+    ))
+}
+
+/// A secret to fetch with `fetch_many`
+pub struct SecretRequest<'a> {
+    /// ARN of the secret
+    pub secret_arn: &'a str,
+    /// AWS profile the secret is fetched with
+    pub profile: Option<&'a str>,
+}
+
+/// Blocking variant of [`fetch_many`]
+pub fn fetch_many_blocking(
+    requests: &[SecretRequest],
+    concurrency: usize,
+) -> Result<Vec<Result<SecretString>>> {
+    block_on(fetch_many(requests, concurrency))
+}
+
+/// Fetch several secrets concurrently, running at most `concurrency` requests at a time.
+/// Credentials are resolved once per distinct profile. The results are in the order of the
+/// requests, failing individually.
+pub async fn fetch_many(
+    requests: &[SecretRequest<'_>],
+    concurrency: usize,
+) -> Vec<Result<SecretString>> {
+    let mut profiles: Vec<Option<&str>> = requests.iter().map(|request| request.profile).collect();
+    profiles.sort();
+    profiles.dedup();
+    let clients: HashMap<Option<&str>, Result<SecretsManagerClient, String>> =
+        stream::iter(profiles.into_iter().map(|profile| async move {
+            let client = secrets_manager(profile).await.map_err(|e| e.to_string());
+            (profile, client)
+        }))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    stream::iter(requests.iter().map(|request| {
+        let client = &clients[&request.profile];
+        async move {
+            match client {
+                Ok(client) => get_secret(client, request.secret_arn).await,
+                Err(e) => Err(eyre!("{e}")),
+            }
+        }
+    }))
+    .buffered(concurrency.max(1))
+    .collect()
+    .await
+}
+
+/// List the (name, ARN) pairs of the secrets visible to the given profile.
+pub fn list_secrets_blocking(profile: Option<&str>) -> Result<Vec<(String, String)>> {
+    block_on(list_secrets(profile))?
+}
+
+/// The names and ARNs of the secrets the credentials of a profile can list
+pub async fn list_secrets(profile: Option<&str>) -> Result<Vec<(String, String)>> {
+    let secret_manager = aws_sdk_secretsmanager::Client::new(&load_sdk_config(profile).await);
+    let mut pages = secret_manager.list_secrets().into_paginator().send();
+    let mut secrets = Vec::new();
+    while let Some(page) = pages.next().await {
+        for secret in page?.secret_list() {
+            if let (Some(name), Some(arn)) = (secret.name(), secret.arn()) {
+                secrets.push((name.to_string(), arn.to_string()));
+            }
+        }
+    }
+    secrets.sort();
+    Ok(secrets)
+}
+
+/// Download an S3 object as a string.
+pub fn get_object_blocking(bucket: &str, key: &str) -> Result<String> {
+    block_on(get_object(bucket, key))?
+}
+
+/// Download an object from S3 as text, with the default credentials chain
+pub async fn get_object(bucket: &str, key: &str) -> Result<String> {
+    let s3 = aws_sdk_s3::Client::new(&load_sdk_config(None).await);
+    debug!("GetObject for 's3://{bucket}/{key}'");
+    let response = s3.get_object().bucket(bucket).key(key).send().await?;
+    let bytes = response.body.collect().await?.into_bytes();
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+/// The region of a profile as the SDK resolves it, without resolving its credentials
+pub async fn region(profile: Option<&str>) -> Option<String> {
+    load_sdk_config(profile)
+        .await
+        .region()
+        .map(ToString::to_string)
+}
+
+/// An EC2 client with the credentials of a profile
+pub async fn ec2(profile: Option<&str>) -> aws_sdk_ec2::Client {
+    aws_sdk_ec2::Client::new(&load_sdk_config(profile).await)
+}
+
+/// The state of an EC2 instance, like "running" or "stopped".
+pub async fn instance_state(client: &aws_sdk_ec2::Client, instance_id: &str) -> Result<String> {
+    let response = client
+        .describe_instances()
+        .instance_ids(instance_id)
+        .send()
+        .await?;
+    let state = response
+        .reservations()
+        .iter()
+        .flat_map(|reservation| reservation.instances())
+        .find_map(|instance| instance.state()?.name())
+        .ok_or(eyre!("Instance '{instance_id}' does not exist"))?;
+    Ok(state.as_str().to_string())
+}
+
+/// An EC2 instance listed by `list_instances`
+#[derive(Debug, Clone)]
+pub struct Ec2Instance {
+    /// ID of the instance, like "i-0123456789abcdef0"
+    pub instance_id: String,
+    /// Tags by key
+    pub tags: HashMap<String, String>,
+    /// Public IPv4 address, unset for instances without one
+    pub public_ip: Option<String>,
+    /// Private IPv4 address in its VPC
+    pub private_ip: Option<String>,
+    /// State name, like "running" or "stopped"
+    pub state: String,
+}
+
+/// List the instances that are not terminated and have all of the given tags.
+pub async fn list_instances(
+    client: &aws_sdk_ec2::Client,
+    tags: &[(String, String)],
+) -> Result<Vec<Ec2Instance>> {
+    let mut request = client.describe_instances().filters(
+        Filter::builder()
+            .name("instance-state-name")
+            .set_values(Some(
+                ["pending", "running", "stopping", "stopped"]
+                    .map(str::to_string)
+                    .to_vec(),
+            ))
+            .build(),
+    );
+    for (key, value) in tags {
+        request = request.filters(
+            Filter::builder()
+                .name(format!("tag:{key}"))
+                .values(value)
+                .build(),
+        );
+    }
+    debug!("DescribeInstances with tags {tags:?}");
+    let mut pages = request.into_paginator().send();
+    let mut instances = Vec::new();
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        for instance in page
+            .reservations()
+            .iter()
+            .flat_map(|reservation| reservation.instances())
+        {
+            let Some(instance_id) = instance.instance_id() else {
+                continue;
+            };
+            instances.push(Ec2Instance {
+                instance_id: instance_id.to_string(),
+                tags: instance
+                    .tags()
+                    .iter()
+                    .filter_map(|tag| Some((tag.key()?.to_string(), tag.value()?.to_string())))
+                    .collect(),
+                public_ip: instance.public_ip_address().map(str::to_string),
+                private_ip: instance.private_ip_address().map(str::to_string),
+                state: instance
+                    .state()
+                    .and_then(|state| state.name())
+                    .map(|name| name.as_str().to_string())
+                    .unwrap_or_default(),
+            });
+        }
+    }
+    instances.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+    Ok(instances)
+}
+
+/// The private IP of the running instance with the given private DNS name, like
+/// "ip-10-0-1-23.eu-west-1.compute.internal"
+pub async fn private_ip_by_dns_name(
+    client: &aws_sdk_ec2::Client,
+    dns_name: &str,
+) -> Result<Option<String>> {
+    debug!("DescribeInstances with private DNS name '{dns_name}'");
+    let response = client
+        .describe_instances()
+        .filters(
+            Filter::builder()
+                .name("private-dns-name")
+                .values(dns_name)
+                .build(),
+        )
+        .filters(
+            Filter::builder()
+                .name("instance-state-name")
+                .values("running")
+                .build(),
+        )
+        .send()
+        .await?;
+    Ok(response
+        .reservations()
+        .iter()
+        .flat_map(|reservation| reservation.instances())
+        .find_map(|instance| instance.private_ip_address())
+        .map(str::to_string))
+}
+
+/// Start a stopped instance, without waiting for it to run
+pub async fn start_instance(client: &aws_sdk_ec2::Client, instance_id: &str) -> Result<()> {
+    debug!("StartInstances for '{instance_id}'");
+    client
+        .start_instances()
+        .instance_ids(instance_id)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Stop a running instance, without waiting for it to stop
+pub async fn stop_instance(client: &aws_sdk_ec2::Client, instance_id: &str) -> Result<()> {
+    debug!("StopInstances for '{instance_id}'");
+    client
+        .stop_instances()
+        .instance_ids(instance_id)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Who the credentials of a profile belong to, as reported by STS
+#[derive(Debug, Clone)]
+pub struct CallerIdentity {
+    /// ID of the AWS account
+    pub account: String,
+    /// ARN of the user or assumed role
+    pub arn: String,
+    /// Region of the profile, if one is configured
+    pub region: Option<String>,
+}
+
+/// Blocking variant of [`caller_identity`]
+pub fn caller_identity_blocking(profile: Option<&str>) -> Result<CallerIdentity> {
+    block_on(caller_identity(profile))?
+}
+
+/// Resolve the credentials chain of a profile, including any roles it assumes, and ask STS whose
+/// credentials it resolved to.
+pub async fn caller_identity(profile: Option<&str>) -> Result<CallerIdentity> {
+    let sdk_config = load_credentials(profile).await?;
+    let response = aws_sdk_sts::Client::new(&sdk_config)
+        .get_caller_identity()
+        .send()
+        .await?;
+    Ok(CallerIdentity {
+        account: response.account.unwrap_or_default(),
+        arn: response.arn.unwrap_or_default(),
+        region: sdk_config.region().map(|region| region.to_string()),
+    })
+}
+
+/// Resolve the default AWS credentials chain and return the expiry time of the credentials, if
+/// they expire at all.
+pub fn get_credentials_expiry_blocking() -> Result<Option<SystemTime>> {
+    block_on(get_credentials_expiry())?
+}
+
+/// The expiry time of the credentials of the default chain, if they expire at all
+pub async fn get_credentials_expiry() -> Result<Option<SystemTime>> {
+    credentials_expiry(&load_sdk_config(None).await).await
+}
+
+/// The expiry time of the credentials of an SDK config, if they expire at all.
+pub async fn credentials_expiry(sdk_config: &SdkConfig) -> Result<Option<SystemTime>> {
+    let provider = sdk_config
+        .credentials_provider()
+        .ok_or_else(|| CredentialsError::not_loaded("No AWS credentials provider is configured"))?;
+    let credentials = provider.provide_credentials().await?;
+    Ok(credentials.expiry())
+}
+
+/// List the profile names defined in the shared AWS config and credentials files.
+pub fn list_profiles() -> Vec<String> {
+    let aws_dir = dirs::home_dir().unwrap_or_default().join(".aws");
+    let config_path = std::env::var_os("AWS_CONFIG_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| aws_dir.join("config"));
+    let credentials_path = std::env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| aws_dir.join("credentials"));
+
+    let mut profiles = Vec::new();
+    for (path, is_config) in [(config_path, true), (credentials_path, false)] {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            continue;
+        };
+        for line in contents.lines() {
+            let Some(section) = line
+                .trim()
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            else {
+                continue;
+            };
+            // The config file prefixes all profiles except the default one with "profile "
+            let profile = match section.strip_prefix("profile ") {
+                Some(profile) => profile.trim(),
+                None if is_config && section != "default" => continue,
+                None => section.trim(),
+            };
+            if !profiles.iter().any(|known| known == profile) {
+                profiles.push(profile.to_string());
+            }
+        }
+    }
+    profiles.sort();
+    profiles
+}
+
+/// Whether an executable with the name is in the PATH
+fn in_path(command: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| is_executable(&dir.join(command)))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0)
+}
+
+/// Commands are run from their `.exe`, the only extension `Command` looks for in the PATH
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.with_extension("exe").is_file()
+}
+
+/// Fail unless the AWS CLI is in the PATH, so that the commands needing it fail before they
+/// connect or start anything rather than halfway through.
+pub fn ensure_cli() -> Result<()> {
+    if !in_path(CLI_COMMAND) {
+        return Err(eyre!(
+            "The AWS CLI (`{CLI_COMMAND}`) is not in the PATH, it is needed to look up databases \
+             and Route 53 records and to open SSM and Instance Connect tunnels. See \
+             https://aws.amazon.com/cli/ to install it"
+        ));
+    }
+    Ok(())
+}
+
+/// Fail unless the Session Manager plugin is in the PATH, without which the AWS CLI can't open
+/// SSM sessions.
+pub fn ensure_session_manager_plugin() -> Result<()> {
+    if !in_path(SESSION_MANAGER_PLUGIN) {
+        return Err(eyre!(
+            "The Session Manager plugin (`{SESSION_MANAGER_PLUGIN}`) is not in the PATH, the AWS \
+             CLI needs it to open SSM sessions. See \
+             https://docs.aws.amazon.com/systems-manager/latest/userguide/session-manager-working-with-install-plugin.html \
+             to install it"
+        ));
+    }
+    Ok(())
+}
+
+/// Run an AWS CLI command and parse its JSON output, for the services without an SDK client here
+pub fn cli<T: DeserializeOwned>(args: &[&str], profile: Option<&str>) -> Result<T> {
+    ensure_cli()?;
+    let mut command = Command::new(CLI_COMMAND);
+    command.args(args).args(["--output", "json"]);
+    if let Some(profile) = profile {
+        command.args(["--profile", profile]);
+    }
+    let output = command.output().wrap_err("Failed to run the AWS CLI")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("aws {} failed: {}", args.join(" "), stderr.trim()));
+    }
+    serde_json::from_slice(&output.stdout)
+        .wrap_err_with(|| format!("Invalid output of aws {}", args.join(" ")))
+}
+
+/// The error code of a failed request, if `error` is the service error of one of the calls made
+fn service_error_code<E: ProvideErrorMetadata + StdError + 'static>(
+    error: &(dyn StdError + 'static),
+) -> Option<String> {
+    error.downcast_ref::<E>()?.code().map(str::to_string)
+}
+
+/// The error code AWS returned for a request that failed with `report`, like
+/// "AccessDeniedException" or "InvalidInstanceID.NotFound"
+pub fn error_code(report: &Report) -> Option<String> {
+    report.chain().find_map(|error| {
+        error
+            .downcast_ref::<Relayed>()
+            .map(|relayed| relayed.code.clone())
+            .or_else(|| service_error_code::<GetSecretValueError>(error))
+            .or_else(|| service_error_code::<PutSecretValueError>(error))
+            .or_else(|| service_error_code::<CreateSecretError>(error))
+            .or_else(|| service_error_code::<ListSecretsError>(error))
+            .or_else(|| service_error_code::<GetObjectError>(error))
+            .or_else(|| service_error_code::<DescribeInstancesError>(error))
+            .or_else(|| service_error_code::<StartInstancesError>(error))
+            .or_else(|| service_error_code::<StopInstancesError>(error))
+            .or_else(|| service_error_code::<GetCallerIdentityError>(error))
+    })
+}
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable turning on batch mode like `--batch`, when set to anything but an empty
+/// value or "0"
+static BATCH_ENV_VAR: &str = "SMSSH_BATCH";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turn on batch mode for the rest of the process
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether smssh runs unattended, from a pipeline or cron. Nothing prompts or draws spinners then,
+/// ssh runs with `BatchMode=yes` and progress is reported as lines for scripts to parse.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+        || std::env::var_os(BATCH_ENV_VAR).is_some_and(|value| !value.is_empty() && value != "0")
+}
@@ -0,0 +1,1979 @@
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use secrecy::SecretString;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use zeroize::Zeroizing;
+
+use crate::{encryption, error::Error, interpolation, migrations, permissions, ssh_config};
+
+static CONFIG_FILE_NAME: &str = "config.yaml";
+static CONFIG_DIR_NAME: &str = "smssh";
+static CONFIG_DIR_FALLBACK: &str = "~/.config";
+static BACKUP_DIR_NAME: &str = "backups";
+/// Locked while the config is stored, so that concurrent stores don't lose each other's changes
+static LOCK_FILE_NAME: &str = "config.lock";
+/// Levels of the stored config merged per key on a conflicting store: the settings and the
+/// entries of the hosts, key aliases and the other maps. Deeper changes to the same entry are
+/// not merged, the entry stored last wins.
+const MERGE_DEPTH: usize = 2;
+/// Local copy of the shared config pulled by `config sync`
+static SHARED_CONFIG_FILE_NAME: &str = "shared.yaml";
+/// Read-only config installed by administrators, merged beneath the shared and the user config
+static SYSTEM_CONFIG_PATH: &str = "/etc/smssh/config.yaml";
+/// Locations used before the config moved into its own directory
+static LEGACY_CONFIG_FILE_NAME: &str = "smssh.yaml";
+static LEGACY_BACKUP_DIR_NAME: &str = "smssh-backups";
+/// Number of config backups to keep, older backups are removed on store
+const MAX_BACKUPS: usize = 10;
+/// The config and its backups are only accessible by the owner
+const CONFIG_FILE_MODE: u32 = 0o600;
+const PRIVATE_DIR_MODE: u32 = 0o700;
+
+/// The smssh config: key aliases, hosts and the settings applying to all of them
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Config {
+    /// Version of the config schema, see [crate::migrations]
+    #[serde(default)]
+    pub version: u64,
+    /// Where the keys come from, by key alias name
+    pub key_aliases: HashMap<String, KeyAliasConfig>,
+    /// Hosts by name. Names with `*` or `?` wildcards like `*.staging` are defaults for the hosts
+    /// and destinations they match, see [Config::wildcard_hosts].
+    pub hosts: HashMap<String, HostConfig>,
+    /// Host templates by name, which hosts inherit their unset settings from with `extends`
+    #[serde(default)]
+    pub templates: HashMap<String, HostConfig>,
+    /// Host groups by name, for connecting to or testing several hosts at once
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
+    /// Databases reached through a local forward over a bastion host, see `smssh db`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub databases: HashMap<String, DatabaseConfig>,
+    /// Networks like a VPN that hosts can require to be connected before their key is fetched
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub networks: HashMap<String, NetworkConfig>,
+    /// Key alias used for destinations that are not configured as hosts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_alias: Option<String>,
+    /// Key aliases for destinations that are not configured as hosts, by the pattern of their
+    /// hostname. The first matching pattern is used, before the default key alias.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<PatternConfig>,
+    /// Source of the shared config pulled by `config sync`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_source: Option<String>,
+    /// Where key fetches and connections are audited, disabled if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<AuditLogConfig>,
+    /// Maximum session duration for hosts that don't set their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_session: Option<String>,
+    /// Timeout for fetching keys of aliases that don't set their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_timeout: Option<String>,
+    /// Maximum age of the cached keys used when AWS is unreachable, keys are only cached while
+    /// this is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline_cache: Option<String>,
+    /// Sessions that drop after running at least this long are reported by a desktop
+    /// notification, disabled if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_drops: Option<String>,
+    /// Hosts with any of these tags ask before connecting, unless they set their own policy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub confirm_tags: Vec<String>,
+    /// Whether the ssh agent is forwarded to hosts that don't set their own policy, left to the
+    /// ssh config if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_agent: Option<bool>,
+    /// Passphrase the config is encrypted with at rest, `None` for a plaintext config
+    #[serde(skip)]
+    pub passphrase: Option<SecretString>,
+    /// Read-only config merged beneath this config. Entries inherited unchanged from the base
+    /// are not stored.
+    #[serde(skip)]
+    base: Option<Box<Config>>,
+    /// The stored form of the config when it was loaded, which changes stored by other smssh
+    /// processes since are merged against
+    #[serde(skip)]
+    loaded: Option<serde_yml::Value>,
+}
+
+/// Where the key of a key alias comes from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum KeyAliasConfig {
+    /// Key stored as the string of an AWS Secrets Manager secret
+    SecretsManager {
+        /// ARN of the secret, environment variables are expanded
+        secret_arn: String,
+        /// AWS profile used to fetch the secret, the default credentials chain is used if unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        /// Verified before the secret is fetched
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        second_factor: Option<SecondFactorConfig>,
+        /// Timeout for resolving the credentials and for fetching the secret, like "10s"
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fetch_timeout: Option<String>,
+        /// Secret containing the passphrase of the key, fetched with the same profile. The key is
+        /// decrypted before it is handed to ssh.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        passphrase_secret_arn: Option<String>,
+    },
+    /// Key fetched by an external `smssh-provider-<name>` executable, see [`crate::plugin`]
+    Plugin {
+        /// Name of the plugin, without the `smssh-provider-` prefix
+        name: String,
+        /// Passed to the plugin as they are, after environment variable expansion
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        params: BTreeMap<String, String>,
+    },
+}
+
+/// A local second factor required before a key is fetched
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SecondFactorConfig {
+    /// Time-based one-time password from an authenticator app
+    Totp {
+        /// The base32 secret of the codes
+        secret: String,
+    },
+    /// Command that has to succeed, like one waiting for a security key touch
+    Command {
+        /// Shell command run locally
+        command: String,
+    },
+}
+
+impl KeyAliasConfig {
+    /// Copy of the alias with environment variables expanded in its values.
+    pub fn expanded(&self) -> Result<KeyAliasConfig> {
+        match self {
+            Self::SecretsManager {
+                secret_arn,
+                profile,
+                second_factor,
+                fetch_timeout,
+                passphrase_secret_arn,
+            } => Ok(Self::SecretsManager {
+                secret_arn: interpolation::expand(secret_arn)?,
+                profile: profile.as_deref().map(interpolation::expand).transpose()?,
+                second_factor: second_factor.clone(),
+                fetch_timeout: fetch_timeout.clone(),
+                passphrase_secret_arn: passphrase_secret_arn
+                    .as_deref()
+                    .map(interpolation::expand)
+                    .transpose()?,
+            }),
+            Self::Plugin { name, params } => Ok(Self::Plugin {
+                name: name.clone(),
+                params: params
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), interpolation::expand(value)?)))
+                    .collect::<Result<_>>()?,
+            }),
+        }
+    }
+
+    /// Where the key comes from, the secret ARN or the plugin with its params. Identifies the key
+    /// in audit records, the fingerprints and the offline cache.
+    pub fn source(&self) -> Cow<'_, str> {
+        match self {
+            Self::SecretsManager { secret_arn, .. } => Cow::Borrowed(secret_arn),
+            Self::Plugin { name, params } if params.is_empty() => {
+                Cow::Owned(format!("plugin:{name}"))
+            }
+            Self::Plugin { name, params } => {
+                let params: Vec<_> = params
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect();
+                Cow::Owned(format!("plugin:{name}?{}", params.join("&")))
+            }
+        }
+    }
+
+    /// AWS profile of the alias, also used for the EC2 calls of its hosts
+    pub fn profile(&self) -> Option<&str> {
+        match self {
+            Self::SecretsManager { profile, .. } => profile.as_deref(),
+            Self::Plugin { .. } => None,
+        }
+    }
+
+    /// AWS region of the secret, taken from its ARN
+    pub fn region(&self) -> Option<&str> {
+        match self {
+            Self::SecretsManager { secret_arn, .. } => secret_arn
+                .split(':')
+                .nth(3)
+                .filter(|region| !region.is_empty()),
+            Self::Plugin { .. } => None,
+        }
+    }
+
+    /// ARN of the secret containing the passphrase of the key, if it is protected by one
+    pub fn passphrase_secret_arn(&self) -> Option<&str> {
+        match self {
+            Self::SecretsManager {
+                passphrase_secret_arn,
+                ..
+            } => passphrase_secret_arn.as_deref(),
+            Self::Plugin { .. } => None,
+        }
+    }
+
+    /// The second factor verified before the key is fetched, if any
+    pub fn second_factor(&self) -> Option<&SecondFactorConfig> {
+        match self {
+            Self::SecretsManager { second_factor, .. } => second_factor.as_ref(),
+            Self::Plugin { .. } => None,
+        }
+    }
+}
+
+/// Where key fetches and connections are audited
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AuditLogConfig {
+    /// JSON lines appended to a file
+    File {
+        /// Path of the file, environment variables are expanded
+        path: String,
+    },
+    /// Messages sent to the local syslog or journald socket
+    Syslog,
+}
+
+impl Display for AuditLogConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File { path } => write!(f, "file {path}"),
+            Self::Syslog => write!(f, "syslog"),
+        }
+    }
+}
+
+impl Display for KeyAliasConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let yaml = serde_yml::to_string(self).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", yaml)
+    }
+}
+
+/// How connecting to a host has to be confirmed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmPolicy {
+    /// Connect without asking
+    Never,
+    /// Answer a yes/no question
+    Prompt,
+    /// Type the name of the host
+    Name,
+}
+
+/// What happens to the EC2 instance of a host when its last session closes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum StopPolicy {
+    /// Leave the instance running
+    Never,
+    /// Ask whether to stop the instance
+    Confirm,
+    /// Stop the instance after a short delay that can be cancelled with Ctrl-C
+    Delay,
+}
+
+/// Whether X11 is forwarded to a host, and how much the remote clients are trusted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum X11Forwarding {
+    /// Don't forward X11
+    No,
+    /// Forward X11, subject to the X11 security extension
+    Yes,
+    /// Forward X11 with full access to the display, for clients the extension breaks
+    Trusted,
+}
+
+impl X11Forwarding {
+    /// The ssh args enabling or disabling the forwarding. Options rather than `-x`, `-X` or
+    /// `-Y`, which ssh applies last-wins, so that the flags given on the command line still take
+    /// precedence. Untrusted forwarding is explicit, as some distributions trust X11 clients by
+    /// default.
+    pub fn ssh_args(self) -> &'static [&'static str] {
+        match self {
+            Self::No => &["-o", "ForwardX11=no"],
+            Self::Yes => &["-o", "ForwardX11=yes", "-o", "ForwardX11Trusted=no"],
+            Self::Trusted => &["-o", "ForwardX11=yes", "-o", "ForwardX11Trusted=yes"],
+        }
+    }
+}
+
+/// What is done with the AWS console links of the EC2 instance of a host when a session starts
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ConsoleLinks {
+    /// Leave the links out
+    Never,
+    /// Print the links before ssh starts
+    Print,
+    /// Open the EC2 page of the instance in the default browser and print the other links
+    Open,
+}
+
+/// Transport of a port knock
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KnockProtocol {
+    /// A TCP connection attempt
+    Tcp,
+    /// A UDP datagram
+    Udp,
+}
+
+/// A port knocked on before connecting to a host protected by a port knocking daemon like knockd
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KnockConfig {
+    /// Port knocked on
+    pub port: u16,
+    /// Transport of the knock
+    pub protocol: KnockProtocol,
+    /// Milliseconds to wait after this knock, a short default delay keeps the knocks in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<u64>,
+}
+
+/// Knocks are written like "7000/tcp" or "8000/udp:500", with an optional delay in milliseconds
+impl Display for KnockConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let protocol = match self.protocol {
+            KnockProtocol::Tcp => "tcp",
+            KnockProtocol::Udp => "udp",
+        };
+        write!(f, "{}/{protocol}", self.port)?;
+        if let Some(delay_ms) = self.delay_ms {
+            write!(f, ":{delay_ms}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for KnockConfig {
+    type Err = color_eyre::Report;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (knock, delay_ms) = match value.split_once(':') {
+            Some((knock, delay)) => (
+                knock,
+                Some(
+                    delay
+                        .parse()
+                        .map_err(|_| eyre!("Invalid delay '{delay}' in knock '{value}'"))?,
+                ),
+            ),
+            None => (value, None),
+        };
+        let (port, protocol) = match knock.split_once('/') {
+            Some((port, "tcp")) => (port, KnockProtocol::Tcp),
+            Some((port, "udp")) => (port, KnockProtocol::Udp),
+            Some((_, protocol)) => {
+                return Err(eyre!(
+                    "Invalid protocol '{protocol}' in knock '{value}', use tcp or udp"
+                ));
+            }
+            None => (knock, KnockProtocol::Tcp),
+        };
+        Ok(Self {
+            port: port
+                .parse()
+                .map_err(|_| eyre!("Invalid port '{port}' in knock '{value}'"))?,
+            protocol,
+            delay_ms,
+        })
+    }
+}
+
+/// Where the hostname of a host is looked up before connecting, for internal names that the DNS
+/// of the workstation can't resolve but the AWS API can
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ResolverConfig {
+    /// An A or CNAME record in a Route 53 hosted zone, usually a private one
+    Route53 {
+        /// ID of the hosted zone, like "Z0123456789ABC"
+        zone_id: String,
+    },
+    /// The private IP of the running EC2 instance with the hostname as its private DNS name
+    Ec2,
+}
+
+/// Resolvers are written like "route53:Z0123456789ABC" or "ec2"
+impl Display for ResolverConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Route53 { zone_id } => write!(f, "route53:{zone_id}"),
+            Self::Ec2 => write!(f, "ec2"),
+        }
+    }
+}
+
+impl std::str::FromStr for ResolverConfig {
+    type Err = color_eyre::Report;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.split_once(':') {
+            Some(("route53", zone_id)) if !zone_id.is_empty() => Ok(Self::Route53 {
+                zone_id: zone_id.to_string(),
+            }),
+            None if value == "ec2" => Ok(Self::Ec2),
+            _ => Err(eyre!(
+                "Invalid resolver '{value}', use route53:<ZONE_ID> or ec2"
+            )),
+        }
+    }
+}
+
+/// How ssh reaches the EC2 instance of a host without a network route to its ssh port, through a
+/// tunnel opened by the AWS CLI
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    /// An SSM Session Manager session, which needs the Session Manager plugin of the AWS CLI
+    Ssm,
+    /// An EC2 Instance Connect Endpoint in the VPC of the instance
+    InstanceConnect,
+}
+
+/// Transports are written like in the config, "ssm" or "instance-connect"
+impl Display for Transport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ssm => write!(f, "ssm"),
+            Self::InstanceConnect => write!(f, "instance-connect"),
+        }
+    }
+}
+
+/// A host, or a template of settings inherited by hosts. Unset settings are taken from its
+/// template, the wildcard hosts matching it and the global defaults.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct HostConfig {
+    /// Name of a host template to inherit the unset settings from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Other names the host can be connected by, never inherited from templates
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nicknames: Vec<String>,
+    /// Key alias whose key is used to connect, inherited if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_alias: Option<String>,
+    /// Key aliases tried in order when ssh fails with the previous one, like during a rotation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_aliases: Vec<String>,
+    /// Key aliases whose keys are offered together with the key alias, for hosts where different
+    /// accounts trust different keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_key_aliases: Vec<String>,
+    /// Hosts connected through in order on the way to this one, each with its own key alias
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub jump_hosts: Vec<String>,
+    /// Extra args passed to ssh, like `-p 2222`
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Hostname or `user@hostname` connected to, the host name if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    /// Tags for selecting hosts, like `prod`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Environment variables set on the ssh process
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Remote shell commands run by name with `smssh run`, like `logs: journalctl -u app -f`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub commands: BTreeMap<String, String>,
+    /// Whether sessions are always recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<bool>,
+    /// Sessions are terminated after this long, like "8h"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_session: Option<String>,
+    /// How connecting has to be confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<ConfirmPolicy>,
+    /// Whether the ssh port is probed before the key is fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_reachable: Option<bool>,
+    /// Whether the ssh agent is forwarded to the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_agent: Option<bool>,
+    /// Whether X11 is forwarded to the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_x11: Option<X11Forwarding>,
+    /// How long the first connection to the host stays open in the background once its sessions
+    /// closed, like "10m". The next sessions reuse it through its control socket. Not used when
+    /// the host has a max session, which a reused connection would outlive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_persist: Option<String>,
+    /// ID of the EC2 instance running the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// Whether the instance is started if it is stopped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_start: Option<bool>,
+    /// Whether the instance is stopped when the last session to it closes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_on_exit: Option<StopPolicy>,
+    /// Whether the AWS console, Session Manager and CloudWatch Logs links of the instance are
+    /// printed or opened when a session starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console_links: Option<ConsoleLinks>,
+    /// Local shell command run before connecting, like starting a VPN. The connection is aborted
+    /// if it fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_connect: Option<String>,
+    /// Local shell command run after ssh exits, once the key is removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_disconnect: Option<String>,
+    /// Ports knocked on in order right before ssh is started
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub knock: Vec<KnockConfig>,
+    /// Network that has to be connected before the key is fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    /// Looks up the hostname through AWS before connecting, with the profile of the key alias
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<ResolverConfig>,
+    /// Reaches the instance through an AWS tunnel rather than over the network, with the profile
+    /// of the key alias
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<Transport>,
+}
+
+impl HostConfig {
+    /// Merge this host configuration over `base`, which is usually the template it extends.
+    /// Settings set on this host take precedence over the base. Args of this host are placed
+    /// before the base args, since ssh uses the first value it receives for each option. Tags
+    /// are combined, starting with the base tags, and environment variables and commands of this
+    /// host replace the base ones with the same name.
+    pub fn merge_over(&self, base: &HostConfig) -> HostConfig {
+        HostConfig {
+            extends: base.extends.clone(),
+            nicknames: self.nicknames.clone(),
+            key_alias: self.key_alias.clone().or_else(|| base.key_alias.clone()),
+            fallback_aliases: if self.fallback_aliases.is_empty() {
+                base.fallback_aliases.clone()
+            } else {
+                self.fallback_aliases.clone()
+            },
+            extra_key_aliases: if self.extra_key_aliases.is_empty() {
+                base.extra_key_aliases.clone()
+            } else {
+                self.extra_key_aliases.clone()
+            },
+            jump_hosts: if self.jump_hosts.is_empty() {
+                base.jump_hosts.clone()
+            } else {
+                self.jump_hosts.clone()
+            },
+            args: self.args.iter().chain(&base.args).cloned().collect(),
+            destination: self
+                .destination
+                .clone()
+                .or_else(|| base.destination.clone()),
+            tags: merge_unique(&base.tags, &self.tags),
+            env: base
+                .env
+                .iter()
+                .chain(&self.env)
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            commands: base
+                .commands
+                .iter()
+                .chain(&self.commands)
+                .map(|(name, command)| (name.clone(), command.clone()))
+                .collect(),
+            record: self.record.or(base.record),
+            max_session: self
+                .max_session
+                .clone()
+                .or_else(|| base.max_session.clone()),
+            confirm: self.confirm.or(base.confirm),
+            check_reachable: self.check_reachable.or(base.check_reachable),
+            forward_agent: self.forward_agent.or(base.forward_agent),
+            forward_x11: self.forward_x11.or(base.forward_x11),
+            control_persist: self
+                .control_persist
+                .clone()
+                .or_else(|| base.control_persist.clone()),
+            instance_id: self
+                .instance_id
+                .clone()
+                .or_else(|| base.instance_id.clone()),
+            auto_start: self.auto_start.or(base.auto_start),
+            stop_on_exit: self.stop_on_exit.or(base.stop_on_exit),
+            console_links: self.console_links.or(base.console_links),
+            pre_connect: self
+                .pre_connect
+                .clone()
+                .or_else(|| base.pre_connect.clone()),
+            post_disconnect: self
+                .post_disconnect
+                .clone()
+                .or_else(|| base.post_disconnect.clone()),
+            knock: if self.knock.is_empty() {
+                base.knock.clone()
+            } else {
+                self.knock.clone()
+            },
+            network: self.network.clone().or_else(|| base.network.clone()),
+            resolver: self.resolver.clone().or_else(|| base.resolver.clone()),
+            transport: self.transport.or(base.transport),
+        }
+    }
+
+    /// Whether the host uses the key alias, as its key, one of the fallbacks or an extra key
+    pub fn uses_alias(&self, alias: &str) -> bool {
+        self.key_alias.as_deref() == Some(alias)
+            || self.fallback_aliases.iter().any(|a| a == alias)
+            || self.extra_key_aliases.iter().any(|a| a == alias)
+    }
+}
+
+/// Concatenate two lists, skipping the items already present.
+fn merge_unique(first: &[String], second: &[String]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::with_capacity(first.len() + second.len());
+    for item in first.iter().chain(second) {
+        if !merged.contains(item) {
+            merged.push(item.clone());
+        }
+    }
+    merged
+}
+
+/// The effective settings of a host after applying its templates and defaults
+#[derive(Debug)]
+pub struct ResolvedHost {
+    /// Name of the host in the config
+    pub name: String,
+    /// Other names the host can be connected by
+    pub nicknames: Vec<String>,
+    /// Key alias whose key is used to connect
+    pub key_alias: String,
+    /// Key aliases tried in order when ssh fails with the previous one
+    pub fallback_aliases: Vec<String>,
+    /// Key aliases whose keys are offered together with the key alias
+    pub extra_key_aliases: Vec<String>,
+    /// Hosts connected through in order
+    pub jump_hosts: Vec<String>,
+    /// Extra args passed to ssh
+    pub args: Vec<String>,
+    /// Hostname or `user@hostname` connected to
+    pub destination: String,
+    /// Tags of the host and its templates
+    pub tags: Vec<String>,
+    /// Environment variables set on the ssh process
+    pub env: BTreeMap<String, String>,
+    /// Named commands run with `smssh run`
+    pub commands: BTreeMap<String, String>,
+    /// Whether the session is recorded
+    pub record: bool,
+    /// The session is terminated after this long
+    pub max_session: Option<Duration>,
+    /// Whether to ask before connecting
+    pub confirm: ConfirmPolicy,
+    /// Whether the ssh port is probed before fetching the key
+    pub check_reachable: bool,
+    /// Whether the ssh agent is forwarded, left to the ssh config if unset
+    pub forward_agent: Option<bool>,
+    /// Whether X11 is forwarded, left to the ssh config if unset
+    pub forward_x11: Option<X11Forwarding>,
+    /// How long the connection is kept open for the next sessions, not kept open if unset or if
+    /// the session is limited
+    pub control_persist: Option<Duration>,
+    /// EC2 instance running the host
+    pub instance_id: Option<String>,
+    /// Whether a stopped instance is started before connecting
+    pub auto_start: bool,
+    /// Whether the instance is stopped after the session
+    pub stop_on_exit: StopPolicy,
+    /// Which AWS console links are printed when connecting
+    pub console_links: ConsoleLinks,
+    /// Command run locally before connecting
+    pub pre_connect: Option<String>,
+    /// Command run locally after the session
+    pub post_disconnect: Option<String>,
+    /// Ports knocked on before connecting, in order
+    pub knock: Vec<KnockConfig>,
+    /// Network brought up before connecting
+    pub network: Option<String>,
+    /// Where the hostname is looked up before connecting
+    pub resolver: Option<ResolverConfig>,
+    /// AWS tunnel the instance is reached through
+    pub transport: Option<Transport>,
+}
+
+impl Display for HostConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let yaml = serde_yml::to_string(self).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", yaml)
+    }
+}
+
+/// A named set of hosts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupConfig {
+    /// Names of the hosts in the group
+    pub hosts: Vec<String>,
+}
+
+/// A database whose endpoint is looked up in AWS and forwarded to a local port through a bastion
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatabaseConfig {
+    /// Where the endpoint of the database is looked up
+    pub source: DatabaseSource,
+    /// Name of the host the endpoint is forwarded through
+    pub bastion: String,
+    /// AWS profile used to look up the endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Local port of the forward, a free port is picked if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_port: Option<u16>,
+    /// User in the connection string, the master user of the database if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Database name in the connection string, the initial database if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+}
+
+/// A network detected by an interface being up or a TCP port answering. Without either it is
+/// always considered connected.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct NetworkConfig {
+    /// Network interface present while connected, like "tailscale0" or "utun3"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// HOST:PORT only reachable while connected, like an internal DNS server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
+    /// Local shell command bringing the network up when it is not connected, like `tailscale up`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up: Option<String>,
+}
+
+/// Where the endpoint of a database is looked up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DatabaseSource {
+    /// An RDS instance by its identifier
+    Rds {
+        /// Identifier of the instance
+        instance: String,
+    },
+    /// The writer endpoint of an Aurora cluster by its identifier
+    RdsCluster {
+        /// Identifier of the cluster
+        cluster: String,
+    },
+    /// The primary or configuration endpoint of an ElastiCache replication group
+    ElastiCache {
+        /// Identifier of the replication group
+        replication_group: String,
+    },
+}
+
+impl Display for DatabaseSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rds { instance } => write!(f, "rds:{instance}"),
+            Self::RdsCluster { cluster } => write!(f, "rds-cluster:{cluster}"),
+            Self::ElastiCache { replication_group } => write!(f, "elasticache:{replication_group}"),
+        }
+    }
+}
+
+/// Key alias used for the destinations whose hostname matches a pattern like
+/// `*.prod.example.com`, where `*` matches any characters and `?` a single one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PatternConfig {
+    /// Hostname pattern with `*` and `?` wildcards
+    pub pattern: String,
+    /// Key alias used for the matching destinations
+    pub key_alias: String,
+}
+
+impl PatternConfig {
+    /// Whether the hostname of a destination like `user@host`, `ssh://user@host:port` or `host`
+    /// matches the pattern, ignoring case
+    pub fn matches(&self, destination: &str) -> bool {
+        hostname_matches(&self.pattern, destination)
+    }
+}
+
+/// Whether a host name is a wildcard entry like `*.staging` rather than a host
+pub fn is_wildcard(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Whether the hostname of a destination like `user@host`, `ssh://user@host:port` or `host`
+/// matches a pattern with wildcards, ignoring case
+fn hostname_matches(pattern: &str, destination: &str) -> bool {
+    let destination = destination.strip_prefix("ssh://").unwrap_or(destination);
+    let hostname = destination
+        .rsplit_once('@')
+        .map_or(destination, |(_, hostname)| hostname);
+    let hostname = match hostname.rsplit_once(':') {
+        Some((hostname, port)) if port.chars().all(|c| c.is_ascii_digit()) => hostname,
+        _ => hostname,
+    };
+    let pattern: Vec<char> = pattern.to_ascii_lowercase().chars().collect();
+    let hostname: Vec<char> = hostname.to_ascii_lowercase().chars().collect();
+    wildcard_match(&pattern, &hostname)
+}
+
+/// Match a text against a pattern with `*` and `?` wildcards, backtracking to the last `*`
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match last_star {
+                Some((star, star_text)) => {
+                    p = star + 1;
+                    t = star_text + 1;
+                    last_star = Some((star, star_text + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Display for GroupConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let yaml = serde_yml::to_string(self).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", yaml)
+    }
+}
+
+impl Config {
+    /// An empty config of the current schema version
+    pub fn new() -> Self {
+        Self {
+            version: migrations::CURRENT_VERSION,
+            ..Default::default()
+        }
+    }
+
+    /// Parse the maximum session duration, falling back to the global one when unset.
+    pub fn max_session(&self, value: Option<&str>) -> Result<Option<Duration>> {
+        value
+            .or(self.max_session.as_deref())
+            .map(crate::duration::parse)
+            .transpose()
+    }
+
+    /// Parse the key fetch timeout of an alias, falling back to the global one when unset.
+    pub fn fetch_timeout(&self, alias: &KeyAliasConfig) -> Result<Option<Duration>> {
+        let fetch_timeout = match alias {
+            KeyAliasConfig::SecretsManager { fetch_timeout, .. } => fetch_timeout.as_deref(),
+            KeyAliasConfig::Plugin { .. } => None,
+        };
+        fetch_timeout
+            .or(self.fetch_timeout.as_deref())
+            .map(crate::duration::parse)
+            .transpose()
+    }
+
+    /// Parse the maximum age of the keys in the offline cache, unset if the cache is disabled.
+    pub fn offline_cache(&self) -> Result<Option<Duration>> {
+        self.offline_cache
+            .as_deref()
+            .map(crate::duration::parse)
+            .transpose()
+    }
+
+    /// Parse the minimum duration of the dropped sessions that are notified, unset if disabled.
+    pub fn notify_drops(&self) -> Result<Option<Duration>> {
+        self.notify_drops
+            .as_deref()
+            .map(crate::duration::parse)
+            .transpose()
+    }
+
+    /// The name of the host with the given name or nickname
+    pub fn host_name<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if self.hosts.contains_key(name) {
+            return Some(name);
+        }
+        self.hosts
+            .iter()
+            .find(|(_, host)| host.nicknames.iter().any(|nickname| nickname == name))
+            .map(|(host_name, _)| host_name.as_str())
+    }
+
+    /// The names of the hosts, without the wildcard entries
+    pub fn host_names(&self) -> impl Iterator<Item = &String> {
+        self.hosts.keys().filter(|name| !is_wildcard(name))
+    }
+
+    /// The wildcard entries matching a host name or destination, most specific first. An entry
+    /// is more specific the more characters it has besides the wildcards, like ssh_config users
+    /// would order their `Host` blocks.
+    pub fn wildcard_hosts(&self, name: &str, destination: Option<&str>) -> Vec<&str> {
+        let mut matching: Vec<&str> = self
+            .hosts
+            .keys()
+            .filter(|pattern| is_wildcard(pattern))
+            .filter(|pattern| {
+                hostname_matches(pattern, name)
+                    || destination.is_some_and(|destination| hostname_matches(pattern, destination))
+            })
+            .map(String::as_str)
+            .collect();
+        let literal_len =
+            |pattern: &str| pattern.chars().filter(|c| !matches!(c, '*' | '?')).count();
+        matching.sort_by(|a, b| literal_len(b).cmp(&literal_len(a)).then(a.cmp(b)));
+        matching
+    }
+
+    /// Resolve the effective settings of a host, given by its name or a nickname, by following
+    /// its `extends` chain and applying the matching wildcard entries beneath it. Hosts without a
+    /// key alias fall back to the default key alias. Environment variables are expanded in the
+    /// destination, args and env values.
+    pub fn resolve_host(&self, name: &str) -> Result<ResolvedHost> {
+        let name = self
+            .host_name(name)
+            .ok_or_else(|| Error::Config(format!("Host '{name}' does not exist")))?;
+        if is_wildcard(name) {
+            return Err(Error::Config(format!(
+                "Host '{name}' is a wildcard entry, it only applies to the hosts it matches"
+            ))
+            .into());
+        }
+        let mut merged = self.extended_host(name)?;
+        for pattern in self.wildcard_hosts(name, merged.destination.as_deref()) {
+            merged = merged.merge_over(&self.extended_host(pattern)?);
+        }
+        self.resolve_merged(name, merged)
+    }
+
+    /// Resolve the settings of a destination that is not configured as a host from the wildcard
+    /// entries matching it. The key alias is the given one, else the one of the wildcard entries,
+    /// the first matching pattern or the default key alias.
+    pub fn resolve_destination(
+        &self,
+        destination: &str,
+        key_alias: Option<&str>,
+    ) -> Result<ResolvedHost> {
+        let mut merged = HostConfig {
+            destination: Some(destination.to_string()),
+            ..Default::default()
+        };
+        for pattern in self.wildcard_hosts(destination, None) {
+            merged = merged.merge_over(&self.extended_host(pattern)?);
+        }
+        if let Some(key_alias) = key_alias {
+            merged.key_alias = Some(key_alias.to_string());
+            merged.fallback_aliases.clear();
+        }
+        if merged.key_alias.is_none() {
+            merged.key_alias = self
+                .matching_pattern(destination)
+                .map(|pattern| pattern.key_alias.clone());
+        }
+        self.resolve_merged(destination, merged)
+    }
+
+    /// The settings of a host or wildcard entry merged over the templates it extends
+    fn extended_host(&self, name: &str) -> Result<HostConfig> {
+        let mut merged = self.hosts[name].clone();
+        let mut visited: Vec<String> = Vec::new();
+        while let Some(template_name) = merged.extends.clone() {
+            if visited.contains(&template_name) {
+                return Err(Error::Config(format!(
+                    "Host '{name}' has a cyclic template chain: {visited:?} -> '{template_name}'"
+                ))
+                .into());
+            }
+            let template = self.templates.get(&template_name).ok_or_else(|| {
+                Error::Config(format!(
+                    "Template '{template_name}' extended by host '{name}' does not exist"
+                ))
+            })?;
+            merged = merged.merge_over(template);
+            visited.push(template_name);
+        }
+        Ok(merged)
+    }
+
+    /// The effective settings of a host from its merged config, with the defaults applied
+    fn resolve_merged(&self, name: &str, merged: HostConfig) -> Result<ResolvedHost> {
+        let key_alias = merged
+            .key_alias
+            .or_else(|| self.default_alias.clone())
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "Host '{name}' has no key alias and no default is set"
+                ))
+            })?;
+        let destination = merged
+            .destination
+            .ok_or_else(|| Error::Config(format!("Host '{name}' has no destination")))?;
+        let destination = interpolation::expand(&destination)?;
+        let args = merged
+            .args
+            .iter()
+            .map(|arg| interpolation::expand(arg))
+            .collect::<Result<Vec<_>>>()?;
+        let env = merged
+            .env
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), interpolation::expand(value)?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let max_session = self.max_session(merged.max_session.as_deref())?;
+        // A connection kept open would outlive the session limit, as ssh reuses it without smssh.
+        // Durations are never 0, which ssh would take as keeping the connection open forever.
+        let control_persist = merged
+            .control_persist
+            .as_deref()
+            .map(crate::duration::parse)
+            .transpose()?
+            .filter(|_| max_session.is_none());
+
+        // The tunnel replaces the network route the other settings are about
+        if let Some(transport) = merged.transport {
+            if merged.instance_id.is_none() {
+                return Err(Error::Config(format!(
+                    "Host '{name}' uses the {transport} transport, which needs an instance ID"
+                ))
+                .into());
+            }
+            if !merged.jump_hosts.is_empty()
+                || merged.resolver.is_some()
+                || !merged.knock.is_empty()
+            {
+                return Err(Error::Config(format!(
+                    "Host '{name}' uses the {transport} transport, which can't be combined with \
+                     jump hosts, a resolver or knocks"
+                ))
+                .into());
+            }
+        }
+
+        let confirm = merged.confirm.unwrap_or_else(|| {
+            if merged
+                .tags
+                .iter()
+                .any(|tag| self.confirm_tags.contains(tag))
+            {
+                ConfirmPolicy::Prompt
+            } else {
+                ConfirmPolicy::Never
+            }
+        });
+
+        Ok(ResolvedHost {
+            name: name.to_string(),
+            nicknames: merged.nicknames,
+            key_alias,
+            fallback_aliases: merged.fallback_aliases,
+            extra_key_aliases: merged.extra_key_aliases,
+            jump_hosts: merged.jump_hosts,
+            args,
+            destination,
+            tags: merged.tags,
+            env,
+            commands: merged.commands,
+            record: merged.record.unwrap_or(false),
+            check_reachable: merged.check_reachable.unwrap_or(false),
+            forward_agent: merged.forward_agent.or(self.forward_agent),
+            forward_x11: merged.forward_x11,
+            control_persist,
+            instance_id: merged
+                .instance_id
+                .as_deref()
+                .map(interpolation::expand)
+                .transpose()?,
+            auto_start: merged.auto_start.unwrap_or(false),
+            stop_on_exit: merged.stop_on_exit.unwrap_or(StopPolicy::Never),
+            console_links: merged.console_links.unwrap_or(ConsoleLinks::Never),
+            pre_connect: merged.pre_connect,
+            post_disconnect: merged.post_disconnect,
+            knock: merged.knock,
+            network: merged.network,
+            resolver: merged.resolver,
+            transport: merged.transport,
+            max_session,
+            confirm,
+        })
+    }
+
+    fn base_config_dir() -> PathBuf {
+        dirs::config_dir().unwrap_or_else(|| PathBuf::from(CONFIG_DIR_FALLBACK))
+    }
+
+    /// The config of a key alias with its environment variables expanded
+    pub fn key_alias(&self, name: &str) -> Result<KeyAliasConfig> {
+        self.key_aliases
+            .get(name)
+            .ok_or(Error::Config(format!("Key alias '{name}' does not exist")))?
+            .expanded()
+    }
+
+    /// Where the key of a key alias comes from, as recorded in audit records, the fingerprints
+    /// and the offline cache. Plugin params are kept as configured, so that tokens expanded from
+    /// the environment are never written to disk.
+    pub fn key_source(&self, name: &str) -> Result<String> {
+        let alias = self
+            .key_aliases
+            .get(name)
+            .ok_or(Error::Config(format!("Key alias '{name}' does not exist")))?;
+        Ok(match alias {
+            KeyAliasConfig::SecretsManager { .. } => alias.expanded()?.source().into_owned(),
+            KeyAliasConfig::Plugin { .. } => alias.source().into_owned(),
+        })
+    }
+
+    /// The first pattern matching a destination that is not configured as a host
+    pub fn matching_pattern(&self, destination: &str) -> Option<&PatternConfig> {
+        self.patterns
+            .iter()
+            .find(|pattern| pattern.matches(destination))
+    }
+
+    /// The key alias used to connect to a host, or to an unknown destination with the key alias
+    /// of the matching wildcard entries, the first matching pattern or the default key alias, and
+    /// its expanded config
+    pub fn host_key_alias(&self, host: &str) -> Result<(String, KeyAliasConfig)> {
+        let name = if self.host_name(host).is_some() {
+            self.resolve_host(host)?.key_alias
+        } else if !self.wildcard_hosts(host, None).is_empty() {
+            self.resolve_destination(host, None)?.key_alias
+        } else if let Some(pattern) = self.matching_pattern(host) {
+            pattern.key_alias.clone()
+        } else {
+            self.default_alias
+                .clone()
+                .ok_or(Error::Config(format!("Host '{host}' does not exist")))?
+        };
+        let alias = self.key_alias(&name)?;
+        Ok((name, alias))
+    }
+
+    /// Directory holding the config, its backups and any other state of smssh
+    pub fn config_dir() -> PathBuf {
+        Self::base_config_dir().join(CONFIG_DIR_NAME)
+    }
+
+    /// Create the config directory, readable only by the current user
+    pub fn create_config_dir() -> Result<()> {
+        let dir = Self::config_dir();
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir)
+                .wrap_err_with(|| format!("Failed to create config directory {dir:?}"))?;
+            permissions::set_mode(&dir, PRIVATE_DIR_MODE)?;
+        }
+        Ok(())
+    }
+
+    /// Move the config and its backups from the legacy locations directly in the config
+    /// directory into the smssh config directory.
+    fn migrate_legacy_location() -> Result<()> {
+        let legacy_path = Self::base_config_dir().join(LEGACY_CONFIG_FILE_NAME);
+        let path = Self::config_path();
+        if path.exists() || !legacy_path.exists() {
+            return Ok(());
+        }
+
+        Self::create_config_dir()?;
+        std::fs::rename(&legacy_path, &path)
+            .wrap_err_with(|| format!("Failed to move {legacy_path:?} to {path:?}"))?;
+
+        let legacy_backup_dir = Self::base_config_dir().join(LEGACY_BACKUP_DIR_NAME);
+        let backup_dir = Self::backup_dir();
+        if legacy_backup_dir.exists() && !backup_dir.exists() {
+            std::fs::rename(&legacy_backup_dir, &backup_dir).wrap_err_with(|| {
+                format!("Failed to move {legacy_backup_dir:?} to {backup_dir:?}")
+            })?;
+            let legacy_prefix = format!("{LEGACY_CONFIG_FILE_NAME}.");
+            for entry in std::fs::read_dir(&backup_dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if let Some(timestamp) = file_name.strip_prefix(&legacy_prefix) {
+                    let new_path = backup_dir.join(format!("{CONFIG_FILE_NAME}.{timestamp}"));
+                    std::fs::rename(entry.path(), new_path)?;
+                }
+            }
+        }
+
+        info!("Config moved from {legacy_path:?} to {path:?}");
+        Ok(())
+    }
+
+    /// Path of the local config file
+    pub fn config_path() -> PathBuf {
+        Self::config_dir().join(CONFIG_FILE_NAME)
+    }
+
+    /// Path of the local copy of the synced shared config
+    pub fn shared_config_path() -> PathBuf {
+        Self::config_dir().join(SHARED_CONFIG_FILE_NAME)
+    }
+
+    /// Path of the system config, shared by all users of the machine
+    pub fn system_config_path() -> PathBuf {
+        PathBuf::from(SYSTEM_CONFIG_PATH)
+    }
+
+    /// The read-only config merged beneath this config, if any.
+    pub fn base(&self) -> Option<&Config> {
+        self.base.as_deref()
+    }
+
+    /// Load the system config, if one is installed.
+    fn load_system() -> Result<Option<Self>> {
+        let path = Self::system_config_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        debug!("Merging system config from {path:?}");
+        let contents = std::fs::read_to_string(&path)
+            .wrap_err_with(|| Error::Config(format!("Failed to read system config at {path:?}")))?;
+        let system = Self::parse_shared(&contents).wrap_err_with(|| {
+            Error::Config(format!("Failed to parse system config from {path:?}"))
+        })?;
+        Ok(Some(system))
+    }
+
+    /// Merge the shared config and the system config beneath this config. The shared config
+    /// takes precedence over the system config, both are kept read-only.
+    fn merge_read_only(&mut self, shared: Option<Config>) -> Result<()> {
+        let base = match (shared, Self::load_system()?) {
+            (Some(mut shared), Some(system)) => {
+                shared.merge_base(system);
+                Some(shared)
+            }
+            (shared, system) => shared.or(system),
+        };
+        if let Some(base) = base {
+            self.merge_base(base);
+        }
+        Ok(())
+    }
+
+    /// The config as it would be with another shared config, keeping the local entries
+    pub fn with_shared(&self, shared: Config) -> Result<Config> {
+        let mut config = self.local();
+        config.merge_read_only(Some(shared))?;
+        Ok(config)
+    }
+
+    /// Merge a read-only config beneath this config. Entries of this config take precedence over
+    /// the entries of the base.
+    fn merge_base(&mut self, base: Config) {
+        fn merge_entries<T: Clone>(
+            entries: &mut HashMap<String, T>,
+            base_entries: &HashMap<String, T>,
+        ) {
+            for (name, entry) in base_entries {
+                entries.entry(name.clone()).or_insert_with(|| entry.clone());
+            }
+        }
+
+        merge_entries(&mut self.key_aliases, &base.key_aliases);
+        merge_entries(&mut self.hosts, &base.hosts);
+        merge_entries(&mut self.templates, &base.templates);
+        merge_entries(&mut self.groups, &base.groups);
+        merge_entries(&mut self.databases, &base.databases);
+        merge_entries(&mut self.networks, &base.networks);
+        if self.default_alias.is_none() {
+            self.default_alias = base.default_alias.clone();
+        }
+        // Local patterns are tried first, shared patterns with the same pattern are overridden
+        for pattern in &base.patterns {
+            if !self
+                .patterns
+                .iter()
+                .any(|own| own.pattern == pattern.pattern)
+            {
+                self.patterns.push(pattern.clone());
+            }
+        }
+        // The audit log of a base config can't be replaced by the config on top of it, so that
+        // users can't redirect the records the shared or system config asks for
+        if base.audit_log.is_some() {
+            self.audit_log = base.audit_log.clone();
+        }
+        if self.max_session.is_none() {
+            self.max_session = base.max_session.clone();
+        }
+        if self.fetch_timeout.is_none() {
+            self.fetch_timeout = base.fetch_timeout.clone();
+        }
+        if self.offline_cache.is_none() {
+            self.offline_cache = base.offline_cache.clone();
+        }
+        if self.notify_drops.is_none() {
+            self.notify_drops = base.notify_drops.clone();
+        }
+        if self.confirm_tags.is_empty() {
+            self.confirm_tags = base.confirm_tags.clone();
+        }
+        if self.forward_agent.is_none() {
+            self.forward_agent = base.forward_agent;
+        }
+        self.base = Some(Box::new(base));
+    }
+
+    /// Whether a shell command, like a hook, an ssh option running a command, the up command of a
+    /// network or a second factor command, is set by this config rather than only by the shared
+    /// or system config beneath it
+    pub fn sets_command_locally(&self, command: &str) -> bool {
+        if self.base.is_none() {
+            return true;
+        }
+        let local = self.local();
+        let command = Some(command);
+        local
+            .hosts
+            .values()
+            .chain(local.templates.values())
+            .any(|host| {
+                host.pre_connect.as_deref() == command
+                    || host.post_disconnect.as_deref() == command
+                    || ssh_config::command_args(&host.args)
+                        .iter()
+                        .any(|arg| Some(arg.as_str()) == command)
+            })
+            || local
+                .networks
+                .values()
+                .any(|network| network.up.as_deref() == command)
+            || local.key_aliases.values().any(|alias| {
+                matches!(
+                    alias.second_factor(),
+                    Some(SecondFactorConfig::Command { command: local }) if Some(local.as_str()) == command
+                )
+            })
+    }
+
+    /// The config without the entries inherited unchanged from the base config.
+    fn local(&self) -> Config {
+        fn local_entries<T: Clone + PartialEq>(
+            entries: &HashMap<String, T>,
+            base_entries: &HashMap<String, T>,
+        ) -> HashMap<String, T> {
+            entries
+                .iter()
+                .filter(|(name, entry)| base_entries.get(*name) != Some(*entry))
+                .map(|(name, entry)| (name.clone(), entry.clone()))
+                .collect()
+        }
+
+        let Some(base) = &self.base else {
+            return self.clone();
+        };
+        Config {
+            key_aliases: local_entries(&self.key_aliases, &base.key_aliases),
+            hosts: local_entries(&self.hosts, &base.hosts),
+            templates: local_entries(&self.templates, &base.templates),
+            groups: local_entries(&self.groups, &base.groups),
+            databases: local_entries(&self.databases, &base.databases),
+            networks: local_entries(&self.networks, &base.networks),
+            default_alias: self
+                .default_alias
+                .clone()
+                .filter(|alias| base.default_alias.as_ref() != Some(alias)),
+            patterns: self
+                .patterns
+                .iter()
+                .filter(|pattern| !base.patterns.contains(pattern))
+                .cloned()
+                .collect(),
+            audit_log: self
+                .audit_log
+                .clone()
+                .filter(|audit_log| base.audit_log.as_ref() != Some(audit_log)),
+            max_session: self
+                .max_session
+                .clone()
+                .filter(|max_session| base.max_session.as_ref() != Some(max_session)),
+            fetch_timeout: self
+                .fetch_timeout
+                .clone()
+                .filter(|fetch_timeout| base.fetch_timeout.as_ref() != Some(fetch_timeout)),
+            offline_cache: self
+                .offline_cache
+                .clone()
+                .filter(|offline_cache| base.offline_cache.as_ref() != Some(offline_cache)),
+            notify_drops: self
+                .notify_drops
+                .clone()
+                .filter(|notify_drops| base.notify_drops.as_ref() != Some(notify_drops)),
+            confirm_tags: if self.confirm_tags == base.confirm_tags {
+                Vec::new()
+            } else {
+                self.confirm_tags.clone()
+            },
+            forward_agent: self
+                .forward_agent
+                .filter(|forward_agent| base.forward_agent != Some(*forward_agent)),
+            base: None,
+            ..self.clone()
+        }
+    }
+
+    /// Directory of the config backups
+    pub fn backup_dir() -> PathBuf {
+        Self::config_dir().join(BACKUP_DIR_NAME)
+    }
+
+    /// Write the config atomically, keeping the previous version of the config as a backup.
+    /// Entries inherited unchanged from the shared or system config are not stored. Changes stored by
+    /// another smssh since the config was loaded are merged in, with the changes of this config
+    /// winning for the entries changed by both.
+    pub fn store(&self) -> Result<()> {
+        let path = Self::config_path();
+        Self::create_config_dir()?;
+        // Held until the config is replaced, the next store then sees the changes
+        let _lock = Self::lock()?;
+
+        let local = self.local();
+        if self.passphrase.is_none()
+            && let Some(alias) = local.totp_alias()
+        {
+            return Err(Error::Config(format!(
+                "The TOTP secret of '{alias}' is only stored in an encrypted config, encrypt it \
+                 with `smssh config encrypt` or remove the second factor of '{alias}'"
+            ))
+            .into());
+        }
+        let mut value = serde_yml::to_value(local)?;
+        if let Some(loaded) = &self.loaded
+            && path.exists()
+        {
+            let stored = self.stored_value(&path)?;
+            if stored != *loaded {
+                debug!("The stored config changed since it was loaded, merging the changes");
+                value = merge_values(Some(loaded), Some(value), Some(stored), MERGE_DEPTH)
+                    .unwrap_or_default();
+            }
+        }
+        let yaml = serde_yml::to_string(&value)?;
+        let contents = match &self.passphrase {
+            Some(passphrase) => encryption::encrypt(&yaml, passphrase)?,
+            None => yaml,
+        };
+
+        if path.exists() {
+            Self::backup().wrap_err("Failed to back up the config file")?;
+        }
+        Self::write_atomic(&path, &contents)
+    }
+
+    /// Take the advisory lock on the config, waiting for other smssh processes storing it
+    fn lock() -> Result<File> {
+        let path = Self::config_dir().join(LOCK_FILE_NAME);
+        let file = permissions::create_with_mode(&mut OpenOptions::new(), CONFIG_FILE_MODE)
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .wrap_err_with(|| format!("Failed to open the config lock {path:?}"))?;
+        file.lock().wrap_err("Failed to lock the config")?;
+        Ok(file)
+    }
+
+    /// The config file as stored now, in the form `store` writes it
+    fn stored_value(&self, path: &Path) -> Result<serde_yml::Value> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read config file at {path:?}"))?;
+        let yaml = match (encryption::is_encrypted(&contents), &self.passphrase) {
+            (false, _) => contents,
+            (true, Some(passphrase)) => encryption::decrypt(&contents, passphrase)?,
+            (true, None) => {
+                return Err(eyre!(
+                    "The config was encrypted since it was loaded, run the command again"
+                ));
+            }
+        };
+        let mut value: serde_yml::Value = serde_yml::from_str(&yaml)?;
+        migrations::migrate(&mut value)?;
+        let stored: Self = serde_yml::from_value(value)?;
+        Ok(serde_yml::to_value(stored)?)
+    }
+
+    /// Write a file readable only by the owner by writing a temporary file next to it and
+    /// renaming it over the original.
+    pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+        let dir = Self::config_dir();
+        let mut file = tempfile::NamedTempFile::new_in(&dir)
+            .wrap_err_with(|| format!("Failed to create a temporary file in {dir:?}"))?;
+        permissions::set_file_mode(file.as_file(), CONFIG_FILE_MODE)?;
+        file.write_all(contents.as_bytes())
+            .wrap_err_with(|| format!("Failed to write {path:?}"))?;
+        file.as_file().sync_all()?;
+        file.persist(path)
+            .wrap_err_with(|| format!("Failed to replace {path:?}"))?;
+        Ok(())
+    }
+
+    /// Copy the current config file into the backup directory and prune old backups.
+    fn backup() -> Result<()> {
+        let backup_dir = Self::backup_dir();
+        std::fs::create_dir_all(&backup_dir)?;
+        permissions::set_mode(&backup_dir, PRIVATE_DIR_MODE)?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let backup_path = backup_dir.join(format!("{CONFIG_FILE_NAME}.{timestamp}"));
+        std::fs::copy(Self::config_path(), &backup_path)?;
+        permissions::set_mode(&backup_path, CONFIG_FILE_MODE)?;
+
+        let backups = Self::backups()?;
+        let excess = backups.len().saturating_sub(MAX_BACKUPS);
+        for (_, path) in backups.into_iter().take(excess) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt the plaintext backups with the passphrase of the config. Their contents are
+    /// overwritten before they are replaced, so that no plaintext copy stays behind on
+    /// filesystems writing in place.
+    pub fn encrypt_backups(passphrase: &SecretString) -> Result<()> {
+        for (_, path) in Self::backups()? {
+            let contents = Zeroizing::new(std::fs::read_to_string(&path)?);
+            if encryption::is_encrypted(&contents) {
+                continue;
+            }
+            let encrypted = encryption::encrypt(&contents, passphrase)?;
+            let mut file = OpenOptions::new().write(true).open(&path)?;
+            file.write_all(&vec![0; contents.len()])?;
+            file.sync_all()?;
+            Self::write_atomic(&path, &encrypted)
+                .wrap_err_with(|| format!("Failed to encrypt the backup at {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// List the available config backups as (timestamp in milliseconds, path), oldest first.
+    pub fn backups() -> Result<Vec<(u128, PathBuf)>> {
+        let backup_dir = Self::backup_dir();
+        if !backup_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let prefix = format!("{CONFIG_FILE_NAME}.");
+        let mut backups = Vec::new();
+        for entry in std::fs::read_dir(&backup_dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let timestamp = file_name
+                .strip_prefix(&prefix)
+                .and_then(|timestamp| timestamp.parse::<u128>().ok());
+            if let Some(timestamp) = timestamp {
+                backups.push((timestamp, entry.path()));
+            }
+        }
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// Restore the config from the backup with the given timestamp, or the latest backup if no
+    /// timestamp is given. The current config is backed up before being replaced.
+    pub fn restore(timestamp: Option<u128>) -> Result<u128> {
+        let backups = Self::backups()?;
+        let (timestamp, path) = match timestamp {
+            Some(timestamp) => backups
+                .into_iter()
+                .find(|(backup_timestamp, _)| *backup_timestamp == timestamp)
+                .ok_or_else(|| eyre!("Backup '{timestamp}' not found"))?,
+            None => backups
+                .into_iter()
+                .last()
+                .ok_or_else(|| eyre!("No config backups found in {:?}", Self::backup_dir()))?,
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read backup at {path:?}"))?;
+        let (config, _) = Self::parse(&contents)
+            .wrap_err_with(|| format!("Failed to parse backup at {path:?}"))?;
+        config.store()?;
+        Ok(timestamp)
+    }
+
+    /// Return the mode of the config file if it is accessible by anyone other than the owner.
+    pub fn insecure_permissions() -> Result<Option<u32>> {
+        let path = Self::config_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mode = permissions::mode(&std::fs::metadata(&path)?);
+        Ok(mode.filter(|mode| mode & 0o077 != 0))
+    }
+
+    /// Restrict the config file, the backup directory and all backups to the owner.
+    pub fn fix_permissions() -> Result<()> {
+        let path = Self::config_path();
+        if path.exists() {
+            permissions::set_mode(&path, CONFIG_FILE_MODE)
+                .wrap_err_with(|| format!("Failed to set permissions of {path:?}"))?;
+        }
+
+        let backup_dir = Self::backup_dir();
+        if backup_dir.exists() {
+            permissions::set_mode(&backup_dir, PRIVATE_DIR_MODE)
+                .wrap_err_with(|| format!("Failed to set permissions of {backup_dir:?}"))?;
+        }
+        for (_, backup_path) in Self::backups()? {
+            permissions::set_mode(&backup_path, CONFIG_FILE_MODE)
+                .wrap_err_with(|| format!("Failed to set permissions of {backup_path:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Parse the config file contents, decrypting them first if the config is encrypted and
+    /// migrating them to the current schema version. Returns the config and its original
+    /// version if it was migrated.
+    fn parse(contents: &str) -> Result<(Self, Option<u64>)> {
+        let mut passphrase = None;
+        let mut yaml = contents.to_string();
+        if encryption::is_encrypted(contents) {
+            let unlock_passphrase = encryption::unlock_passphrase()?;
+            yaml = encryption::decrypt(contents, &unlock_passphrase)?;
+            passphrase = Some(unlock_passphrase);
+        }
+
+        let mut value: serde_yml::Value = serde_yml::from_str(&yaml)?;
+        let migrated_from = migrations::migrate(&mut value)?;
+        let mut config: Self = serde_yml::from_value(value)?;
+        config.passphrase = passphrase;
+        Ok((config, migrated_from))
+    }
+
+    /// Check whether the config file is encrypted at rest.
+    pub fn is_encrypted() -> Result<bool> {
+        let path = Self::config_path();
+        if !path.exists() {
+            return Ok(false);
+        }
+        let contents = std::fs::read_to_string(path)?;
+        Ok(encryption::is_encrypted(&contents))
+    }
+
+    /// Load the local copy of the shared config.
+    pub fn load_shared() -> Result<Self> {
+        let contents = std::fs::read_to_string(Self::shared_config_path())?;
+        Self::parse_shared(&contents)
+    }
+
+    /// Parse a shared or system config, which is never encrypted and only migrated in memory. They
+    /// can't hold TOTP secrets for that reason.
+    pub fn parse_shared(contents: &str) -> Result<Self> {
+        let mut value: serde_yml::Value = serde_yml::from_str(contents)?;
+        migrations::migrate(&mut value)?;
+        let config: Self = serde_yml::from_value(value)?;
+        if let Some(alias) = config.totp_alias() {
+            return Err(Error::Config(format!(
+                "The TOTP secret of '{alias}' is in plaintext in a shared config, TOTP second \
+                 factors can only be set in the encrypted local config"
+            ))
+            .into());
+        }
+        Ok(config)
+    }
+
+    /// A key alias with a TOTP second factor, whose secret must not be stored in plaintext
+    fn totp_alias(&self) -> Option<&str> {
+        self.key_aliases
+            .iter()
+            .find(|(_, alias)| {
+                matches!(alias.second_factor(), Some(SecondFactorConfig::Totp { .. }))
+            })
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Replace the local copy of the shared config.
+    pub fn store_shared(contents: &str) -> Result<()> {
+        Self::create_config_dir()?;
+        Self::write_atomic(&Self::shared_config_path(), contents)
+    }
+
+    /// Load the local config, migrating it to the current schema, and merge the shared and system
+    /// configs beneath it
+    pub fn load() -> Result<Self> {
+        Self::load_with(true)
+    }
+
+    /// Load the config like [`Config::load`] without changing anything on disk, for shell
+    /// completions. A config on an older schema is only migrated in memory and one in the legacy
+    /// location is not read.
+    pub fn load_read_only() -> Result<Self> {
+        Self::load_with(false)
+    }
+
+    fn load_with(store_migrated: bool) -> Result<Self> {
+        let mut config = Self::new();
+        debug!("Loading config from {:?}", Self::config_path());
+        if store_migrated {
+            Self::migrate_legacy_location()?;
+        }
+
+        let path = Self::config_path();
+        if let Some(mode) = Self::insecure_permissions()? {
+            warn!(
+                "Config file {path:?} has mode {mode:o} and is accessible by other users, run `smssh config fix-permissions` to restrict it"
+            );
+        }
+        if path.exists() {
+            let contents = std::fs::read_to_string(&path).wrap_err_with(|| {
+                Error::Config(format!("Failed to read config file at {path:?}"))
+            })?;
+            let migrated_from;
+            (config, migrated_from) = Self::parse(&contents)
+                .wrap_err_with(|| Error::Config(format!("Failed to parse config from {path:?}")))?;
+
+            // Store the migrated config, the original is kept as a backup
+            if let Some(version) = migrated_from
+                && store_migrated
+            {
+                config.store()?;
+                info!(
+                    "Config migrated from version {version} to {}",
+                    migrations::CURRENT_VERSION
+                );
+            }
+        }
+
+        let shared_path = Self::shared_config_path();
+        let shared = if shared_path.exists() {
+            debug!("Merging shared config from {shared_path:?}");
+            Some(Self::load_shared().wrap_err_with(|| {
+                Error::Config(format!(
+                    "Failed to parse shared config from {shared_path:?}"
+                ))
+            })?)
+        } else {
+            None
+        };
+        config.merge_read_only(shared)?;
+
+        config.loaded = Some(serde_yml::to_value(config.local())?);
+        Ok(config)
+    }
+}
+
+/// Merge the changes made since `base` in `ours` and in `theirs`, `None` standing for a missing
+/// entry. Mappings changed on both sides are merged per key down to `depth` levels, below that the
+/// changes of `ours` win.
+fn merge_values(
+    base: Option<&serde_yml::Value>,
+    ours: Option<serde_yml::Value>,
+    theirs: Option<serde_yml::Value>,
+    depth: usize,
+) -> Option<serde_yml::Value> {
+    if ours.as_ref() == base {
+        return theirs;
+    }
+    if theirs.as_ref() == base || depth == 0 {
+        return ours;
+    }
+    let (mut ours, theirs) = match (ours, theirs) {
+        (Some(serde_yml::Value::Mapping(ours)), Some(serde_yml::Value::Mapping(theirs))) => {
+            (ours, theirs)
+        }
+        (ours, _) => return ours,
+    };
+    let empty = serde_yml::Mapping::new();
+    let base = match base {
+        Some(serde_yml::Value::Mapping(base)) => base,
+        _ => &empty,
+    };
+    let mut merged = serde_yml::Mapping::new();
+    for (key, their_value) in theirs {
+        let our_value = ours.remove(&key);
+        if let Some(value) = merge_values(base.get(&key), our_value, Some(their_value), depth - 1) {
+            merged.insert(key, value);
+        }
+    }
+    // Entries missing from theirs were either added by us or removed by them
+    for (key, our_value) in ours {
+        if let Some(value) = merge_values(base.get(&key), Some(our_value), None, depth - 1) {
+            merged.insert(key, value);
+        }
+    }
+    Some(serde_yml::Value::Mapping(merged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(destination: &str) -> HostConfig {
+        HostConfig {
+            destination: Some(destination.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn wildcard(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        wildcard_match(&pattern, &text)
+    }
+
+    #[test]
+    fn wildcards_match_any_characters_or_a_single_one() {
+        assert!(wildcard("*", ""));
+        assert!(wildcard("*.staging", "web.staging"));
+        assert!(wildcard("web-?", "web-1"));
+        assert!(wildcard("*a*b", "xaxxab"));
+        assert!(!wildcard("web-?", "web-10"));
+        assert!(!wildcard("*.staging", "web.staging.old"));
+        assert!(!wildcard("", "web"));
+    }
+
+    #[test]
+    fn hostnames_are_matched_without_user_scheme_and_port() {
+        assert!(hostname_matches(
+            "*.prod.example.com",
+            "db.prod.example.com"
+        ));
+        assert!(hostname_matches(
+            "*.prod.example.com",
+            "deploy@WEB.prod.example.com"
+        ));
+        assert!(hostname_matches(
+            "web?.example.com",
+            "ssh://deploy@web1.example.com:2222"
+        ));
+        assert!(!hostname_matches("*.prod.example.com", "prod.example.com"));
+        assert!(!hostname_matches("web", "web.example.com"));
+    }
+
+    #[test]
+    fn hosts_are_merged_over_their_template() {
+        let template = HostConfig {
+            key_alias: Some("template".to_string()),
+            args: vec!["-o".to_string(), "Compression=yes".to_string()],
+            tags: vec!["prod".to_string()],
+            env: BTreeMap::from([
+                ("A".to_string(), "template".to_string()),
+                ("B".to_string(), "template".to_string()),
+            ]),
+            ..host("template.example.com")
+        };
+        let own = HostConfig {
+            extends: Some("template".to_string()),
+            args: vec!["-p".to_string(), "2222".to_string()],
+            tags: vec!["web".to_string(), "prod".to_string()],
+            env: BTreeMap::from([("B".to_string(), "own".to_string())]),
+            ..host("web.example.com")
+        };
+        let merged = own.merge_over(&template);
+        assert_eq!(merged.key_alias.as_deref(), Some("template"));
+        assert_eq!(merged.destination.as_deref(), Some("web.example.com"));
+        assert_eq!(merged.args, ["-p", "2222", "-o", "Compression=yes"]);
+        assert_eq!(merged.tags, ["prod", "web"]);
+        assert_eq!(merged.env["A"], "template");
+        assert_eq!(merged.env["B"], "own");
+    }
+
+    #[test]
+    fn local_entries_take_precedence_over_the_base() {
+        let mut local = Config::new();
+        local
+            .hosts
+            .insert("web".to_string(), host("local.example.com"));
+        local.max_session = Some("1h".to_string());
+        local.audit_log = Some(AuditLogConfig::Syslog);
+        let mut base = Config::new();
+        base.hosts
+            .insert("web".to_string(), host("shared.example.com"));
+        base.hosts.insert("db".to_string(), host("db.example.com"));
+        base.max_session = Some("8h".to_string());
+        base.fetch_timeout = Some("10s".to_string());
+        base.audit_log = Some(AuditLogConfig::File {
+            path: "/var/log/smssh.jsonl".to_string(),
+        });
+
+        local.merge_base(base);
+        assert_eq!(
+            local.hosts["web"].destination.as_deref(),
+            Some("local.example.com")
+        );
+        assert_eq!(
+            local.hosts["db"].destination.as_deref(),
+            Some("db.example.com")
+        );
+        assert_eq!(local.max_session.as_deref(), Some("1h"));
+        assert_eq!(local.fetch_timeout.as_deref(), Some("10s"));
+        // The audit log of the base can't be redirected
+        assert!(matches!(local.audit_log, Some(AuditLogConfig::File { .. })));
+        assert!(!local.local().hosts.contains_key("db"));
+    }
+
+    fn yaml(value: &str) -> serde_yml::Value {
+        serde_yml::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn merges_changes_to_different_keys() {
+        let base = yaml("hosts: {web: {destination: a}, db: {destination: b}}");
+        let ours = yaml("hosts: {web: {destination: ours}, db: {destination: b}}");
+        let theirs = yaml("hosts: {web: {destination: a}, db: {destination: theirs}, new: {}}");
+        let merged = merge_values(Some(&base), Some(ours), Some(theirs), MERGE_DEPTH).unwrap();
+        assert_eq!(
+            merged,
+            yaml("hosts: {web: {destination: ours}, db: {destination: theirs}, new: {}}")
+        );
+    }
+
+    #[test]
+    fn keeps_removals_and_prefers_ours_below_the_depth() {
+        let base = yaml("hosts: {web: {destination: a}, db: {destination: b}}");
+        let ours = yaml("hosts: {web: {destination: a}}");
+        let theirs = yaml("hosts: {web: {destination: theirs}, db: {destination: b}}");
+        let merged = merge_values(
+            Some(&base),
+            Some(ours.clone()),
+            Some(theirs.clone()),
+            MERGE_DEPTH,
+        );
+        assert_eq!(merged, Some(yaml("hosts: {web: {destination: theirs}}")));
+
+        let merged = merge_values(Some(&base), Some(ours.clone()), Some(theirs), 0);
+        assert_eq!(merged, Some(ours));
+    }
+}
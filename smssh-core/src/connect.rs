@@ -0,0 +1,2320 @@
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+use crossterm::ExecutableCommand;
+use crossterm::cursor;
+#[cfg(target_os = "linux")]
+use nix::sys::memfd::{MemFdCreateFlag, memfd_create};
+#[cfg(unix)]
+use nix::sys::signal;
+#[cfg(unix)]
+use nix::{
+    libc::{STDIN_FILENO, tcsetpgrp},
+    sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction},
+    unistd::{Pid, getpid, getuid, setpgid},
+};
+#[cfg(windows)]
+use signal_hook::consts::signal::SIGBREAK;
+#[cfg(unix)]
+use signal_hook::consts::signal::{SIGCHLD, SIGHUP, SIGQUIT};
+use signal_hook::consts::signal::{SIGINT, SIGTERM};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+#[cfg(unix)]
+use std::io::IsTerminal;
+use std::io::{Read, Write, stdout};
+#[cfg(unix)]
+use std::os::unix::{net::UnixStream, process::CommandExt};
+#[cfg(windows)]
+use std::os::windows::{
+    io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    process::CommandExt,
+};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use std::{
+    io::{self, ErrorKind},
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+#[cfg(windows)]
+use uds_windows::UnixStream;
+#[cfg(windows)]
+use windows_sys::Win32::System::{
+    JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, TerminateJobObject,
+    },
+    Threading::CREATE_NEW_PROCESS_GROUP,
+};
+
+#[cfg(target_os = "linux")]
+use nix::libc::O_TMPFILE;
+use secrecy::{ExposeSecret, SecretString};
+#[cfg(target_os = "linux")]
+use std::os::{
+    fd::{AsRawFd, OwnedFd},
+    unix::fs::OpenOptionsExt,
+};
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+};
+use tempfile::{NamedTempFile, TempDir};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    audit::{self, AuditRecord},
+    aws, batch,
+    config::{
+        Config, ConfirmPolicy, ConsoleLinks, KeyAliasConfig, KnockConfig, NetworkConfig,
+        ResolvedHost, ResolverConfig, SecondFactorConfig, StopPolicy, Transport, X11Forwarding,
+    },
+    console,
+    error::Error,
+    fingerprints::{self, KeyCheck, KnownKey},
+    history::{self, HistoryEntry},
+    instance_sessions::{self, SessionMarker},
+    key_cache, key_format, keygen, network, notification, permissions, plugin,
+    progress::Progress,
+    prompt::{self, PromptError},
+    reachability, recording, resolver, second_factor, transport, trusted_commands,
+};
+
+/// Exit status when ssh was terminated by a signal, like shells use for an interrupt
+pub(crate) const SIGNAL_EXIT_STATUS: i32 = 130;
+/// How long to wait for a private ssh-agent to create its socket
+#[cfg(all(unix, not(target_os = "linux")))]
+const AGENT_START_TIMEOUT: Duration = Duration::from_secs(5);
+/// Exit status of ssh when it fails to connect, or for other errors of ssh itself
+const SSH_CONNECTION_FAILED: i32 = 255;
+/// Delay before the first retry, doubled for each further one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long to wait for a stopped instance to start and accept connections
+const INSTANCE_START_TIMEOUT: Duration = Duration::from_secs(300);
+const INSTANCE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Grace period before stopping an instance with the `delay` stop policy
+const INSTANCE_STOP_DELAY: Duration = Duration::from_secs(30);
+/// How long ssh waits for hosts to answer when running commands on them without a terminal
+const REMOTE_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long waiting for a wakeup blocks on Windows, where neither signals nor exiting children
+/// can write to the wakeup socket
+#[cfg(windows)]
+const WAKEUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Stands in for the temporary key file in dry runs
+static DRY_RUN_KEY_PATH: &str = "KEY_FILE";
+
+#[cfg(unix)]
+fn create_key_directory() -> Result<TempDir> {
+    let dir = tempfile::Builder::new()
+        .permissions(Permissions::from_mode(0o700))
+        .tempdir_in("/dev/shm")
+        .or_else(|_| {
+            tempfile::Builder::new()
+                .permissions(Permissions::from_mode(0o700))
+                .tempdir()
+        })?;
+    Ok(dir)
+}
+
+/// The temporary directory of the user, in their profile that only they can read
+#[cfg(windows)]
+fn create_key_directory() -> Result<TempDir> {
+    Ok(tempfile::tempdir()?)
+}
+
+fn create_key_file(dir: &TempDir) -> Result<NamedTempFile> {
+    #[cfg(unix)]
+    let file = tempfile::Builder::new()
+        .permissions(Permissions::from_mode(0o600))
+        .tempfile_in(dir)?;
+    #[cfg(windows)]
+    let file = NamedTempFile::new_in(dir)?;
+    permissions::set_mode(file.path(), 0o400)?;
+    Ok(file)
+}
+
+/// The private keys stored where ssh can read them for the duration of the session, offered by
+/// ssh in the order they were added
+pub(crate) enum KeyFile {
+    /// Files without a name, either anonymous in-memory files or `O_TMPFILE` files that are
+    /// never linked into a directory. They are released as soon as smssh exits, even if it
+    /// crashes
+    #[cfg(target_os = "linux")]
+    Unlinked(Vec<OwnedFd>),
+    /// Held by an ssh-agent started for this session only, so that the keys never touch the
+    /// disk on systems without memfds or /dev/shm, like macOS
+    #[cfg(all(unix, not(target_os = "linux")))]
+    Agent {
+        agent: std::process::Child,
+        socket: PathBuf,
+        _dir: TempDir,
+    },
+    /// Temporary files in /dev/shm, or the system temporary directory if it isn't available. The
+    /// only storage on Windows, whose ssh-agent is a system service holding keys across sessions
+    Temp {
+        dir: TempDir,
+        files: Vec<NamedTempFile>,
+    },
+}
+
+impl KeyFile {
+    pub(crate) fn create(key: &SecretString) -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        if let Some(fd) = Self::create_unlinked(key) {
+            return Ok(Self::Unlinked(vec![fd]));
+        }
+
+        #[cfg(all(unix, not(target_os = "linux")))]
+        match Self::create_agent(key) {
+            Ok(key_file) => return Ok(key_file),
+            Err(e) => debug!("Failed to store the key in a private ssh-agent: {e}"),
+        }
+
+        let mut key_file = Self::Temp {
+            dir: create_key_directory()?,
+            files: Vec::new(),
+        };
+        key_file.add(key)?;
+        Ok(key_file)
+    }
+
+    /// Store another key the same way as the keys already stored, ssh offers it after them
+    pub(crate) fn add(&mut self, key: &SecretString) -> Result<()> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Unlinked(fds) => fds.push(
+                Self::create_unlinked(key)
+                    .ok_or_else(|| eyre!("Failed to store the key in an unlinked file"))?,
+            ),
+            #[cfg(all(unix, not(target_os = "linux")))]
+            Self::Agent { socket, .. } => add_to_agent(socket, key)?,
+            Self::Temp { dir, files } => {
+                let mut file = create_key_file(dir)?;
+                file.write_all(key.expose_secret().as_bytes())?;
+                files.push(file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store a key in a memfd, or an `O_TMPFILE` file where memfds are not allowed
+    #[cfg(target_os = "linux")]
+    fn create_unlinked(key: &SecretString) -> Option<OwnedFd> {
+        match Self::create_memfd(key) {
+            Ok(fd) => return Some(fd),
+            Err(e) => debug!("Failed to store the key in a memfd: {e}"),
+        }
+        for dir in [Path::new("/dev/shm"), &std::env::temp_dir()] {
+            match Self::create_tmpfile(dir, key) {
+                Ok(fd) => return Some(fd),
+                Err(e) => debug!("Failed to store the key in an O_TMPFILE file in {dir:?}: {e}"),
+            }
+        }
+        None
+    }
+
+    /// Start an ssh-agent listening in a private directory and add the key to it
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn create_agent(key: &SecretString) -> Result<Self> {
+        let dir = create_key_directory()?;
+        let socket = dir.path().join("agent.sock");
+        let agent = Command::new("ssh-agent")
+            .arg("-D")
+            .arg("-a")
+            .arg(&socket)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()?;
+        // Kills the agent if adding the key fails
+        let key_file = Self::Agent {
+            agent,
+            socket,
+            _dir: dir,
+        };
+        let Self::Agent { socket, .. } = &key_file else {
+            unreachable!()
+        };
+
+        let started = Instant::now();
+        while !socket.exists() {
+            if started.elapsed() > AGENT_START_TIMEOUT {
+                return Err(eyre!("ssh-agent did not start listening on {socket:?}"));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        add_to_agent(socket, key)?;
+        Ok(key_file)
+    }
+
+    /// Where the keys are stored, for the logs
+    fn storage(&self) -> &'static str {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Unlinked(_) => "an unlinked in-memory or temporary file",
+            #[cfg(all(unix, not(target_os = "linux")))]
+            Self::Agent { .. } => "a private ssh-agent",
+            Self::Temp { .. } => "a temporary file",
+        }
+    }
+
+    /// memfd_secret would also hide the key from the kernel, but its files can only be accessed
+    /// through mmap, and ssh reads the key with read()
+    #[cfg(target_os = "linux")]
+    fn create_memfd(key: &SecretString) -> Result<OwnedFd> {
+        let fd = memfd_create(c"smssh-key", MemFdCreateFlag::MFD_CLOEXEC)?;
+        let mut file = std::fs::File::from(fd);
+        // ssh refuses keys that are accessible by other users
+        file.set_permissions(Permissions::from_mode(0o400))?;
+        file.write_all(key.expose_secret().as_bytes())?;
+        Ok(file.into())
+    }
+
+    /// Create an unnamed file in `dir`, for systems where memfd_create is not allowed
+    #[cfg(target_os = "linux")]
+    fn create_tmpfile(dir: &Path, key: &SecretString) -> Result<OwnedFd> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .mode(0o400)
+            .custom_flags(O_TMPFILE)
+            .open(dir)?;
+        file.write_all(key.expose_secret().as_bytes())?;
+        Ok(file.into())
+    }
+
+    /// The ssh arguments pointing it at the keys
+    fn ssh_args(&self) -> Vec<OsString> {
+        match self {
+            // ssh closes inherited file descriptors on startup, so it has to open the files
+            // through this process, which outlives it
+            #[cfg(target_os = "linux")]
+            Self::Unlinked(fds) => fds
+                .iter()
+                .flat_map(|fd| {
+                    [
+                        "-i".into(),
+                        format!("/proc/{}/fd/{}", std::process::id(), fd.as_raw_fd()).into(),
+                    ]
+                })
+                .collect(),
+            #[cfg(all(unix, not(target_os = "linux")))]
+            Self::Agent { socket, .. } => {
+                let mut agent = OsString::from("IdentityAgent=");
+                agent.push(socket);
+                vec!["-o".into(), agent]
+            }
+            Self::Temp { files, .. } => files
+                .iter()
+                .flat_map(|file| ["-i".into(), file.path().into()])
+                .collect(),
+        }
+    }
+
+    /// The ssh arguments pointing it at the keys and keeping ssh from offering any other identity
+    pub(crate) fn exclusive_ssh_args(&self) -> Vec<OsString> {
+        let mut args = self.ssh_args();
+        // The private agent holds no other key, only the key files have to be skipped
+        #[cfg(all(unix, not(target_os = "linux")))]
+        if let Self::Agent { .. } = self {
+            args.extend(["-o".into(), "IdentityFile=none".into()]);
+            return args;
+        }
+        args.extend(["-o".into(), "IdentitiesOnly=yes".into()]);
+        args
+    }
+}
+
+/// Add a key to the private ssh-agent listening on `socket`
+#[cfg(all(unix, not(target_os = "linux")))]
+fn add_to_agent(socket: &Path, key: &SecretString) -> Result<()> {
+    let mut ssh_add = Command::new("ssh-add")
+        .arg("-q")
+        .arg("-")
+        .env("SSH_AUTH_SOCK", socket)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = ssh_add.stdin.take() {
+        stdin.write_all(key.expose_secret().as_bytes())?;
+    }
+    let status = ssh_add.wait()?;
+    if !status.success() {
+        return Err(eyre!("ssh-add failed with {status}"));
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+impl Drop for KeyFile {
+    fn drop(&mut self) {
+        if let Self::Agent { agent, .. } = self {
+            let _ = agent.kill();
+            let _ = agent.wait();
+        }
+    }
+}
+
+/// Where the key of a session came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeySource {
+    /// Fetched from Secrets Manager or a plugin by this process
+    Fetched,
+    /// Served by the daemon, which may have cached it
+    Daemon,
+    /// The copy in the offline cache, because the fetch failed
+    OfflineCache,
+}
+
+impl Display for KeySource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let source = match self {
+            Self::Fetched => "fetched fresh",
+            Self::Daemon => "served by the daemon",
+            Self::OfflineCache => "from the offline cache",
+        };
+        write!(f, "{source}")
+    }
+}
+
+/// Get a secret from the daemon if it is running, or from Secrets Manager
+fn pull_secret(
+    secret_arn: &str,
+    profile: Option<&str>,
+    timeout: Option<Duration>,
+    progress: &Progress,
+) -> Result<(SecretString, KeySource)> {
+    if let Some(secret) = crate::daemon::get_key(secret_arn, profile)? {
+        return Ok((secret, KeySource::Daemon));
+    }
+    let client = crate::aws::block_on_timeout(
+        crate::aws::secrets_manager(profile),
+        timeout,
+        "resolve the AWS credentials",
+    )?;
+    progress.phase("Fetching the secret");
+    let secret = crate::aws::block_on_timeout(
+        crate::aws::get_secret(&client, secret_arn),
+        timeout,
+        "reach Secrets Manager",
+    )?;
+    Ok((secret, KeySource::Fetched))
+}
+
+fn pull_key(
+    key_alias: &str,
+    alias: &KeyAliasConfig,
+    timeout: Option<Duration>,
+    progress: &Progress,
+) -> Result<(SecretString, KeySource)> {
+    match alias {
+        KeyAliasConfig::SecretsManager {
+            secret_arn,
+            profile,
+            passphrase_secret_arn,
+            ..
+        } => {
+            let (key, source) = pull_secret(secret_arn, profile.as_deref(), timeout, progress)?;
+            let Some(passphrase_secret_arn) = passphrase_secret_arn else {
+                return Ok((key, source));
+            };
+            let (passphrase, _) =
+                pull_secret(passphrase_secret_arn, profile.as_deref(), timeout, progress)?;
+            progress.phase("Decrypting the key");
+            Ok((keygen::decrypt(&key, &passphrase)?, source))
+        }
+        KeyAliasConfig::Plugin { name, params } => Ok((
+            plugin::get_key(name, key_alias, params, timeout)?,
+            KeySource::Fetched,
+        )),
+    }
+}
+
+/// Options for a connection given on the command line
+#[derive(Debug, Default)]
+pub struct ConnectOptions {
+    /// Print the ssh command instead of connecting
+    pub dry_run: bool,
+    /// Record the session, to the given path or the default one
+    pub record: Option<Option<PathBuf>>,
+    /// How many times to retry when ssh fails to connect
+    pub retries: u32,
+    /// Probe the ssh port before fetching the key
+    pub check_reachable: bool,
+    /// Start the EC2 instance of the host if it is stopped
+    pub start_instance: bool,
+    /// Key alias used instead of the one configured for the host, without its fallbacks
+    pub key_alias: Option<String>,
+    /// Shell command run on the host instead of a login shell
+    pub command: Option<String>,
+}
+
+impl ConnectOptions {
+    /// Where to record a session with `name`, if it should be recorded
+    fn recording_path(&self, name: &str, always_record: bool) -> Option<PathBuf> {
+        match &self.record {
+            Some(Some(path)) => Some(path.clone()),
+            Some(None) => Some(recording::default_path(name)),
+            None => always_record.then(|| recording::default_path(name)),
+        }
+    }
+}
+
+/// A configured host connected through on the way to the destination
+pub struct JumpHost {
+    /// Name of the host in the config
+    pub name: String,
+    /// The address ssh connects to
+    pub destination: String,
+    /// The configured args of the jump host, its own jump hosts are not used
+    pub args: Vec<String>,
+    /// The key alias of the jump host
+    pub key_alias: String,
+    /// The key alias config with its environment variables expanded
+    pub key_alias_config: KeyAliasConfig,
+}
+
+/// The jump hosts of a host, in the order they are connected through
+fn jump_hosts(config: &Config, host: &ResolvedHost) -> Result<Vec<JumpHost>> {
+    host.jump_hosts
+        .iter()
+        .map(|name| {
+            if *name == host.name {
+                return Err(Error::Config(format!(
+                    "Host '{name}' is configured as its own jump host"
+                ))
+                .into());
+            }
+            let jump = config.resolve_host(name).wrap_err_with(|| {
+                Error::Config(format!(
+                    "Jump host '{name}' configured in '{}' is invalid",
+                    host.name
+                ))
+            })?;
+            Ok(JumpHost {
+                key_alias_config: config.key_alias(&jump.key_alias)?,
+                name: jump.name,
+                destination: jump.destination,
+                args: jump.args,
+                key_alias: jump.key_alias,
+            })
+        })
+        .collect()
+}
+
+/// An ssh session to start
+pub struct Connection<'a> {
+    /// The configured host or plain destination connected to, unset for `connect-with-alias`
+    pub host: Option<&'a str>,
+    /// The key alias whose key is used
+    pub key_alias: &'a str,
+    /// The key alias config with its environment variables expanded
+    pub key_alias_config: KeyAliasConfig,
+    /// Key aliases with their expanded configs, fetched in order when ssh fails with the previous
+    /// key, so that hosts still trusting an old key stay reachable during a rotation
+    pub fallback_aliases: Vec<(&'a str, KeyAliasConfig)>,
+    /// Key aliases with their expanded configs, whose keys are offered after the key of the
+    /// current key alias
+    pub extra_aliases: Vec<(&'a str, KeyAliasConfig)>,
+    /// Hosts connected through in order, each with the key of its own key alias
+    pub jump_hosts: Vec<JumpHost>,
+    /// The address ssh connects to, unset when it is in the ssh args
+    pub destination: Option<&'a str>,
+    /// Shell command run on the host instead of a login shell
+    pub command: Option<&'a str>,
+    /// The ssh args given on the command line
+    pub ssh_args: &'a [String],
+    /// All args passed to ssh, including the configured ones
+    pub args: Vec<String>,
+    /// Environment variables sent to the host with `SetEnv`
+    pub env: BTreeMap<String, String>,
+    /// File the session is recorded to
+    pub recording: Option<PathBuf>,
+    /// The session is terminated after this long
+    pub max_session: Option<Duration>,
+    /// The connection is kept open this long for the next sessions to the host
+    pub control_persist: Option<Duration>,
+    /// How many times to retry when ssh fails to connect
+    pub retries: u32,
+    /// Whether to ask before connecting
+    pub confirm: ConfirmPolicy,
+    /// Probe the ssh port before fetching the key
+    pub check_reachable: bool,
+    /// EC2 instance running the host
+    pub instance_id: Option<&'a str>,
+    /// Start the instance if it is stopped
+    pub start_instance: bool,
+    /// What happens to the instance when the last session to it closes
+    pub stop_on_exit: StopPolicy,
+    /// What is done with the console links of the instance when the session starts
+    pub console_links: ConsoleLinks,
+    /// Local shell command run before connecting, the connection is aborted if it fails
+    pub pre_connect: Option<&'a str>,
+    /// Local shell command run after the session, once the key is removed
+    pub post_disconnect: Option<&'a str>,
+    /// Ports knocked on right before ssh is started
+    pub knock: &'a [KnockConfig],
+    /// Network that has to be connected before the key is fetched, by its name
+    pub network: Option<(&'a str, &'a NetworkConfig)>,
+    /// Looks up the hostname before connecting
+    pub resolver: Option<&'a ResolverConfig>,
+    /// Reaches the instance through an AWS tunnel
+    pub transport: Option<Transport>,
+}
+
+impl Connection<'_> {
+    /// Audit record of an event using the given key alias
+    fn audit_record<'b>(
+        &'b self,
+        event: &'static str,
+        key_alias: &'b str,
+        source: String,
+    ) -> AuditRecord<'b> {
+        AuditRecord::new(event, self.host, self.destination, key_alias, source.into())
+    }
+}
+
+/// Connect to a destination in the ssh args with the key of a key alias, returning the exit
+/// status of ssh
+pub fn connect_by_alias(
+    key_alias: &str,
+    config: &Config,
+    ssh_args: &[String],
+    options: &ConnectOptions,
+) -> Result<i32> {
+    let connection = Connection {
+        host: None,
+        key_alias,
+        key_alias_config: config.key_alias(key_alias)?,
+        fallback_aliases: Vec::new(),
+        extra_aliases: Vec::new(),
+        jump_hosts: Vec::new(),
+        destination: None,
+        command: options.command.as_deref(),
+        ssh_args,
+        args: [ssh_args, &forward_agent_args(config.forward_agent)].concat(),
+        env: BTreeMap::new(),
+        recording: options.recording_path(key_alias, false),
+        max_session: config.max_session(None)?,
+        control_persist: None,
+        confirm: ConfirmPolicy::Never,
+        retries: options.retries,
+        check_reachable: options.check_reachable,
+        instance_id: None,
+        start_instance: false,
+        stop_on_exit: StopPolicy::Never,
+        console_links: ConsoleLinks::Never,
+        pre_connect: None,
+        post_disconnect: None,
+        knock: &[],
+        network: None,
+        resolver: None,
+        transport: None,
+    };
+    connect(config, &connection, options.dry_run)
+}
+
+/// Connect to a configured host or a destination matching its wildcard entries, patterns or
+/// the default key alias, returning the exit status of ssh
+pub fn connect_by_host(
+    host_name: &str,
+    config: &Config,
+    ssh_args: &[String],
+    options: &ConnectOptions,
+) -> Result<i32> {
+    let host_name = config.host_name(host_name).unwrap_or(host_name);
+    let wildcard_hosts = config.wildcard_hosts(host_name, None);
+    if !config.hosts.contains_key(host_name) && wildcard_hosts.is_empty() {
+        // Treat unknown hosts as plain destinations if a pattern or the default alias gives
+        // them a key alias
+        let destination = host_name;
+        let key_alias = if let Some(key_alias) = &options.key_alias {
+            info!("Host '{destination}' is not configured, using key alias '{key_alias}'");
+            key_alias
+        } else if let Some(pattern) = config.matching_pattern(destination) {
+            info!(
+                "Host '{destination}' is not configured, using key alias '{}' of pattern '{}'",
+                pattern.key_alias, pattern.pattern
+            );
+            config
+                .key_aliases
+                .get(&pattern.key_alias)
+                .ok_or(Error::Config(format!(
+                    "Key alias '{}' of pattern '{}' does not exist",
+                    pattern.key_alias, pattern.pattern
+                )))?;
+            &pattern.key_alias
+        } else {
+            let default_alias = config.default_alias.as_ref().ok_or(Error::Config(format!(
+                "Host '{destination}' does not exist"
+            )))?;
+            config
+                .key_aliases
+                .get(default_alias)
+                .ok_or(Error::Config(format!(
+                    "Default key alias '{default_alias}' does not exist"
+                )))?;
+            info!(
+                "Host '{destination}' is not configured, using default key alias '{default_alias}'"
+            );
+            default_alias
+        };
+        let connection = Connection {
+            host: Some(destination),
+            key_alias,
+            key_alias_config: config.key_alias(key_alias)?,
+            fallback_aliases: Vec::new(),
+            extra_aliases: Vec::new(),
+            jump_hosts: Vec::new(),
+            destination: Some(destination),
+            command: options.command.as_deref(),
+            ssh_args,
+            args: [ssh_args, &forward_agent_args(config.forward_agent)].concat(),
+            env: BTreeMap::new(),
+            recording: options.recording_path(destination, false),
+            max_session: config.max_session(None)?,
+            control_persist: None,
+            confirm: ConfirmPolicy::Never,
+            retries: options.retries,
+            check_reachable: options.check_reachable,
+            instance_id: None,
+            start_instance: false,
+            stop_on_exit: StopPolicy::Never,
+            console_links: ConsoleLinks::Never,
+            pre_connect: None,
+            post_disconnect: None,
+            knock: &[],
+            network: None,
+            resolver: None,
+            transport: None,
+        };
+        return connect(config, &connection, options.dry_run);
+    }
+
+    if let Some(key_alias) = &options.key_alias
+        && !config.key_aliases.contains_key(key_alias)
+    {
+        return Err(Error::Config(format!("Key alias '{key_alias}' does not exist")).into());
+    }
+    let host = if config.hosts.contains_key(host_name) {
+        let mut host = config.resolve_host(host_name)?;
+        if let Some(key_alias) = &options.key_alias {
+            info!(
+                "Using key alias '{key_alias}' instead of '{}' configured in '{host_name}'",
+                host.key_alias
+            );
+            host.key_alias = key_alias.clone();
+            host.fallback_aliases.clear();
+        }
+        host
+    } else {
+        info!(
+            "Host '{host_name}' is not configured, using the settings of {}",
+            wildcard_hosts
+                .iter()
+                .map(|pattern| format!("'{pattern}'"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        config.resolve_destination(host_name, options.key_alias.as_deref())?
+    };
+    let key_alias_config = config
+        .key_aliases
+        .get(&host.key_alias)
+        .ok_or(Error::Config(format!(
+            "Key alias '{}' configured in '{}' does not exist",
+            host.key_alias, host.name
+        )))?;
+
+    let fallback_aliases = host
+        .fallback_aliases
+        .iter()
+        .map(|alias| {
+            let alias_config = config.key_aliases.get(alias).ok_or(Error::Config(format!(
+                "Fallback key alias '{alias}' configured in '{}' does not exist",
+                host.name
+            )))?;
+            Ok((alias.as_str(), alias_config.expanded()?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let extra_aliases = host
+        .extra_key_aliases
+        .iter()
+        .map(|alias| {
+            let alias_config = config.key_aliases.get(alias).ok_or(Error::Config(format!(
+                "Extra key alias '{alias}' configured in '{}' does not exist",
+                host.name
+            )))?;
+            Ok((alias.as_str(), alias_config.expanded()?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let jump_hosts = jump_hosts(config, &host)?;
+    // The knocks would have to come from the last jump host
+    if !jump_hosts.is_empty() && !host.knock.is_empty() {
+        return Err(Error::Config(format!(
+            "Host '{host_name}' knocks on ports, which is not supported through jump hosts"
+        ))
+        .into());
+    }
+    let network = match &host.network {
+        Some(network) => Some((
+            network.as_str(),
+            config.networks.get(network).ok_or(Error::Config(format!(
+                "Network '{network}' configured in '{}' does not exist",
+                host.name
+            )))?,
+        )),
+        None => None,
+    };
+    // ssh silently connects without forwarding when there is no display to forward
+    if matches!(
+        host.forward_x11,
+        Some(X11Forwarding::Yes | X11Forwarding::Trusted)
+    ) && std::env::var_os("DISPLAY").is_none_or(|display| display.is_empty())
+    {
+        warn!(
+            "Host '{host_name}' forwards X11, but DISPLAY is not set, so remote GUI programs won't open"
+        );
+    }
+
+    let connection = Connection {
+        host: Some(host_name),
+        key_alias: &host.key_alias,
+        key_alias_config: key_alias_config.expanded()?,
+        fallback_aliases,
+        extra_aliases,
+        jump_hosts,
+        destination: Some(&host.destination),
+        command: options.command.as_deref(),
+        ssh_args,
+        // Args given on the command line come first to take precedence over the configured ones
+        args: ssh_args.iter().cloned().chain(host_args(&host)).collect(),
+        env: host.env.clone(),
+        recording: options.recording_path(host_name, host.record),
+        max_session: host.max_session,
+        control_persist: host.control_persist,
+        confirm: host.confirm,
+        retries: options.retries,
+        check_reachable: options.check_reachable || host.check_reachable,
+        instance_id: host.instance_id.as_deref(),
+        start_instance: options.start_instance || host.auto_start,
+        stop_on_exit: host.stop_on_exit,
+        console_links: host.console_links,
+        pre_connect: host.pre_connect.as_deref(),
+        post_disconnect: host.post_disconnect.as_deref(),
+        knock: &host.knock,
+        network,
+        resolver: host.resolver.as_ref(),
+        transport: host.transport,
+    };
+    connect(config, &connection, options.dry_run)
+}
+
+/// Start the EC2 instance of a host if it is stopped, then wait until it is running and accepts
+/// ssh connections with the given args
+fn start_instance(connection: &Connection, args: &[String]) -> Result<()> {
+    let host = connection.host.unwrap_or_default();
+    let instance_id = connection
+        .instance_id
+        .ok_or(eyre!("Host '{host}' has no instance ID to start"))?;
+    let client = aws::block_on(aws::ec2(connection.key_alias_config.profile()))?;
+
+    let progress = Progress::start(&format!("Checking instance {instance_id}"));
+    let started = Instant::now();
+    let mut start_requested = false;
+    loop {
+        let state = aws::block_on(aws::instance_state(&client, instance_id))??;
+        match state.as_str() {
+            "running" => break,
+            "stopped" if !start_requested => {
+                progress.phase(&format!("Starting instance {instance_id}"));
+                aws::block_on(aws::start_instance(&client, instance_id))??;
+                start_requested = true;
+            }
+            // Stopping instances can only be started once they are stopped
+            "stopped" | "stopping" | "pending" => {
+                progress.phase(&format!("Waiting for instance {instance_id} ({state})"));
+            }
+            _ => {
+                return Err(eyre!(
+                    "Instance {instance_id} of host '{host}' is {state} and cannot be started"
+                ));
+            }
+        }
+        if started.elapsed() > INSTANCE_START_TIMEOUT {
+            return Err(eyre!(
+                "Instance {instance_id} did not start within {INSTANCE_START_TIMEOUT:?}"
+            ));
+        }
+        std::thread::sleep(INSTANCE_POLL_INTERVAL);
+    }
+    if start_requested || started.elapsed() > INSTANCE_POLL_INTERVAL {
+        progress.phase(&format!("Waiting for {host} to accept connections"));
+        let remaining = INSTANCE_START_TIMEOUT.saturating_sub(started.elapsed());
+        reachability::wait_until_reachable(connection.destination, args, remaining)?;
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// Stop the EC2 instance of a host after its last session closed, as its stop policy says
+fn stop_instance_on_exit(
+    connection: &Connection,
+    marker: SessionMarker,
+    term_flag: &AtomicBool,
+    wakeup: &Wakeup,
+) -> Result<()> {
+    let Some(instance_id) = connection.instance_id else {
+        return Ok(());
+    };
+    if marker.others_active()? {
+        info!("Other sessions to instance {instance_id} are still open, leaving it running");
+        return Ok(());
+    }
+    match connection.stop_on_exit {
+        StopPolicy::Never => return Ok(()),
+        StopPolicy::Confirm => {
+            if !prompt::can_ask() {
+                info!(
+                    "Leaving instance {instance_id} running, there is no one to confirm the stop"
+                );
+                return Ok(());
+            }
+            if !prompt::confirm(&format!("Stop instance {instance_id}?"), false)? {
+                return Ok(());
+            }
+        }
+        StopPolicy::Delay => {
+            // A termination of the session must not cancel the stop
+            term_flag.store(false, Ordering::Relaxed);
+            info!(
+                "Stopping instance {instance_id} in {INSTANCE_STOP_DELAY:?}, press Ctrl-C to keep it running"
+            );
+            wakeup.sleep(INSTANCE_STOP_DELAY, term_flag)?;
+            if term_flag.load(Ordering::Relaxed) {
+                info!("Leaving instance {instance_id} running");
+                return Ok(());
+            }
+            // A new session could have been opened in the meantime
+            if marker.others_active()? {
+                info!("A new session to instance {instance_id} was opened, leaving it running");
+                return Ok(());
+            }
+        }
+    }
+
+    let client = aws::block_on(aws::ec2(connection.key_alias_config.profile()))?;
+    aws::block_on(aws::stop_instance(&client, instance_id))??;
+    info!("Stopping instance {instance_id}");
+    Ok(())
+}
+
+/// Run a hook of a host with the local shell in the foreground. The hook gets the host, destination and
+/// key alias of the connection, and the exit status of ssh once the session is over.
+fn run_hook(
+    connection: &Connection,
+    kind: &str,
+    hook: &str,
+    exit_status: Option<i32>,
+) -> Result<()> {
+    let host = connection.host.unwrap_or_default();
+    let mut command = local_shell(hook);
+    command
+        .env("SMSSH_HOST", host)
+        .env(
+            "SMSSH_DESTINATION",
+            connection.destination.unwrap_or_default(),
+        )
+        .env("SMSSH_KEY_ALIAS", connection.key_alias);
+    if let Some(exit_status) = exit_status {
+        command.env("SMSSH_EXIT_STATUS", exit_status.to_string());
+    }
+    info!("Running the {kind} hook of '{host}'");
+    let status = command
+        .status()
+        .wrap_err_with(|| format!("Failed to run the {kind} hook of '{host}'"))?;
+    if !status.success() {
+        return Err(eyre!("The {kind} hook of '{host}' failed with {status}"));
+    }
+    Ok(())
+}
+
+/// Ask the user to confirm a connection to a sensitive host before its key is fetched
+fn confirm_connection(connection: &Connection) -> Result<()> {
+    let name = connection
+        .host
+        .or(connection.destination)
+        .unwrap_or(connection.key_alias);
+    if connection.confirm == ConfirmPolicy::Never {
+        return Ok(());
+    }
+    if !prompt::can_ask() {
+        return Err(PromptError::Unavailable(format!(
+            "Connecting to '{name}' has to be confirmed, which needs a terminal and no batch mode"
+        ))
+        .into());
+    }
+    let confirmed = match connection.confirm {
+        ConfirmPolicy::Never => true,
+        ConfirmPolicy::Prompt => prompt::confirm(&format!("Connect to '{name}'?"), false)?,
+        ConfirmPolicy::Name => {
+            prompt::input(&format!("Type '{name}' to connect"), Some(""))? == name
+        }
+    };
+    if !confirmed {
+        return Err(PromptError::Declined(format!("Connection to '{name}' aborted")).into());
+    }
+    Ok(())
+}
+
+/// Remember the key of an alias, asking before using a key that changed since the last
+/// connection. Keys should only change when they are rotated, anything else may be tampering.
+fn confirm_key(key_alias: &str, check: KeyCheck, known: KnownKey) -> Result<()> {
+    let change = match check {
+        KeyCheck::Unchanged => return Ok(()),
+        KeyCheck::New => None,
+        KeyCheck::Changed { previous } => Some(format!(
+            "The key of '{key_alias}' changed since the last connection\n  previous: {previous}\n  current:  {}",
+            known.fingerprint
+        )),
+        KeyCheck::SourceChanged { previous } => Some(format!(
+            "The key of '{key_alias}' now comes from a different secret\n  previous: {previous}\n  current:  {}",
+            known.secret_arn
+        )),
+    };
+    match change {
+        None => debug!("Remembering the key fingerprint of '{key_alias}'"),
+        Some(change) => {
+            warn!("{change}");
+            if !prompt::can_ask() {
+                return Err(PromptError::Unavailable(format!(
+                    "Refusing to use the changed key of '{key_alias}', connect interactively to accept it"
+                ))
+                .into());
+            }
+            if !prompt::confirm("Use the new key?", false)? {
+                return Err(PromptError::Declined("Connection aborted".to_string()).into());
+            }
+        }
+    }
+    if let Err(e) = fingerprints::store(key_alias, known) {
+        warn!("Failed to remember the key fingerprint: {e}");
+    }
+    Ok(())
+}
+
+/// Add a finished session to the connection history. Failing to do so does not fail the
+/// connection, which has already happened.
+fn record_session(connection: &Connection, session: &Session) {
+    let entry = HistoryEntry::new(
+        connection.host,
+        connection.key_alias,
+        connection.ssh_args,
+        session.started,
+        session.duration,
+        session.exit_code,
+    );
+    if let Err(e) = history::record(entry) {
+        warn!("Failed to record the connection history: {e}");
+    }
+}
+
+/// Wakes up the loop waiting for ssh when a termination is requested or a child exits, so that
+/// it can block instead of polling
+struct Wakeup {
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+impl Wakeup {
+    fn new() -> Result<Self> {
+        let (reader, writer) = UnixStream::pair()?;
+        // Signal handlers must not block, wakeups are dropped while earlier ones are unread
+        writer.set_nonblocking(true)?;
+        let wakeup = Self { reader, writer };
+        wakeup.reset_timeout()?;
+        Ok(wakeup)
+    }
+
+    /// Waiting blocks until a wakeup on Unix, and polls on Windows
+    fn reset_timeout(&self) -> Result<()> {
+        #[cfg(unix)]
+        self.reader.set_read_timeout(None)?;
+        #[cfg(windows)]
+        self.reader.set_read_timeout(Some(WAKEUP_POLL_INTERVAL))?;
+        Ok(())
+    }
+
+    fn waker(&self) -> Result<UnixStream> {
+        Ok(self.writer.try_clone()?)
+    }
+
+    /// Block until the next wakeup, consuming the pending ones, or until the poll interval
+    /// passed on Windows
+    fn wait(&self) -> Result<()> {
+        let mut buffer = [0; 64];
+        match (&self.reader).read(&mut buffer) {
+            Err(e)
+                if !matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                Err(e.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleep for `duration`, returning early if `term_flag` is set
+    fn sleep(&self, duration: Duration, term_flag: &AtomicBool) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        let mut buffer = [0; 64];
+        while !term_flag.load(Ordering::Relaxed) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            self.reader.set_read_timeout(Some(left))?;
+            match (&self.reader).read(&mut buffer) {
+                Err(e)
+                    if !matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) =>
+                {
+                    return Err(e.into());
+                }
+                _ => {}
+            }
+        }
+        self.reset_timeout()
+    }
+}
+
+#[cfg(unix)]
+fn register_termination_handlers(term_flag: Arc<AtomicBool>, wakeup: &Wakeup) -> Result<()> {
+    for signal in [SIGHUP, SIGINT, SIGTERM, SIGQUIT] {
+        signal_hook::flag::register(signal, term_flag.clone())?;
+        signal_hook::low_level::pipe::register(signal, wakeup.waker()?)?;
+    }
+    signal_hook::low_level::pipe::register(SIGCHLD, wakeup.waker()?)?;
+    Ok(())
+}
+
+/// Ctrl+C arrives as SIGINT and Ctrl+Break as SIGBREAK. The loops waiting for a wakeup see the
+/// flag when they poll, as the handlers can't write to the wakeup socket on Windows.
+#[cfg(windows)]
+fn register_termination_handlers(term_flag: Arc<AtomicBool>, _wakeup: &Wakeup) -> Result<()> {
+    for signal in [SIGINT, SIGTERM, SIGBREAK] {
+        signal_hook::flag::register(signal, term_flag.clone())?;
+    }
+    Ok(())
+}
+
+/// Warn before the maximum session duration is reached, then set `term_flag` to terminate ssh.
+/// The warning comes a minute before the end, or halfway through for short sessions.
+fn limit_session(max_session: Duration, term_flag: Arc<AtomicBool>, mut waker: UnixStream) {
+    let warn_before = Duration::from_secs(60).min(max_session / 2);
+    std::thread::spawn(move || {
+        std::thread::sleep(max_session - warn_before);
+        warn!(
+            "The session will be terminated in {}",
+            history::format_duration(warn_before.as_secs())
+        );
+        std::thread::sleep(warn_before);
+        warn!(
+            "Maximum session duration of {} reached",
+            history::format_duration(max_session.as_secs())
+        );
+        term_flag.store(true, Ordering::Relaxed);
+        let _ = waker.write(&[0]);
+    });
+}
+
+/// The ssh args forwarding the agent or not, none if it is left to the ssh config
+fn forward_agent_args(forward_agent: Option<bool>) -> Vec<String> {
+    match forward_agent {
+        Some(forward) => vec![
+            "-o".to_string(),
+            format!("ForwardAgent={}", if forward { "yes" } else { "no" }),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// The directory of the control sockets of the connections kept open for the next sessions.
+/// Next to the key directories in /dev/shm if it is available, else in /tmp rather than the
+/// temporary directory, which can be too long for a socket path on macOS.
+#[cfg(unix)]
+fn control_dir() -> PathBuf {
+    let parent = if Path::new("/dev/shm").is_dir() {
+        "/dev/shm"
+    } else {
+        "/tmp"
+    };
+    Path::new(parent).join(format!("smssh-control-{}", getuid()))
+}
+
+/// Create the control socket directory, or make sure that the existing one is only accessible by
+/// the user. Anyone able to reach the sockets can use the connections without a key.
+#[cfg(unix)]
+fn ensure_control_dir() -> Result<PathBuf> {
+    let dir = control_dir();
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => return Ok(dir),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            return Err(Report::new(e).wrap_err(format!("Failed to create {}", dir.display())));
+        }
+    }
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != getuid().as_raw()
+        || metadata.permissions().mode() & 0o077 != 0
+    {
+        return Err(eyre!(
+            "{} is not private to the user, remove it for smssh to create it again",
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+/// The ssh args of the settings of a host, given ahead of its own args as ssh uses the first
+/// value of an option. OpenSSH for Windows can't multiplex connections, so `control_persist` is
+/// left out there.
+pub fn host_args(host: &ResolvedHost) -> Vec<String> {
+    let mut args = forward_agent_args(host.forward_agent);
+    if let Some(forward_x11) = host.forward_x11 {
+        args.extend(forward_x11.ssh_args().iter().map(ToString::to_string));
+    }
+    #[cfg(unix)]
+    if let Some(control_persist) = host.control_persist {
+        args.extend([
+            "-o".to_string(),
+            "ControlMaster=auto".to_string(),
+            "-o".to_string(),
+            // %C is a hash of the destination, port and user, short enough for a socket path
+            format!("ControlPath={}/%C", control_dir().display()),
+            "-o".to_string(),
+            format!("ControlPersist={}", control_persist.as_secs()),
+        ]);
+    }
+    args.extend(host.args.iter().cloned());
+    args
+}
+
+fn ssh_command(
+    key_args: &[OsString],
+    destination: Option<&str>,
+    ssh_args: &[String],
+    env: &BTreeMap<String, String>,
+    remote_command: Option<&str>,
+) -> Command {
+    let mut command = Command::new("ssh");
+    command.args(key_args);
+    // Ahead of the args of the host, as ssh uses the first value of an option
+    if batch::enabled() {
+        command.args(["-o", "BatchMode=yes"]);
+    }
+    command.args(ssh_args);
+    command.envs(env);
+
+    if let Some(destination) = destination {
+        command.arg(destination);
+    }
+    command.args(remote_command);
+    command
+}
+
+/// How ssh reaches a host the way `connect` does, through its jump hosts with their own keys, at
+/// the address its resolver finds or through the tunnel of its transport, for the commands
+/// running scripts on hosts and copying files
+pub struct Route {
+    jump_hosts: Vec<JumpHost>,
+    /// The args of the jump hosts, followed by the host ones
+    args: Vec<OsString>,
+    /// The args of the resolver or the transport, which reach the host itself
+    host_args: Vec<String>,
+    /// Read by the ssh proxy commands of the jump hosts, so kept as long as the route
+    _jump_key_files: Vec<KeyFile>,
+}
+
+impl Route {
+    /// Fetch the keys of the jump hosts of a host, once per key alias, and resolve its address or
+    /// set up its transport. Fetches are audited like the ones of `connect`.
+    pub fn new(config: &Config, host: &ResolvedHost) -> Result<Self> {
+        let jump_hosts = jump_hosts(config, host)?;
+        trusted_commands::ensure_trusted_args(config, &host.name, &host.args)?;
+        for jump_host in &jump_hosts {
+            trusted_commands::ensure_trusted_args(config, &jump_host.name, &jump_host.args)?;
+        }
+        let key_files = load_jump_keys(
+            config,
+            Some(&host.name),
+            Some(&host.destination),
+            &jump_hosts,
+        )?;
+        let mut args = jump_args(&jump_hosts, &key_files);
+        let key_alias_config = || config.key_alias(&host.key_alias)?.expanded();
+        // Resolvers and transports exclude each other
+        let host_args = match (&host.resolver, host.transport, &host.instance_id) {
+            (Some(resolver), _, _) => resolver::resolved_args(
+                resolver,
+                Some(&host.destination),
+                &host.args,
+                key_alias_config()?.profile(),
+            )?,
+            (None, Some(transport), Some(instance_id)) => {
+                transport::proxy_args(transport, instance_id, key_alias_config()?.profile())?
+            }
+            _ => Vec::new(),
+        };
+        args.extend(host_args.iter().map(OsString::from));
+        let _jump_key_files = key_files.into_values().collect();
+        Ok(Self {
+            jump_hosts,
+            args,
+            host_args,
+            _jump_key_files,
+        })
+    }
+
+    /// Check that the host accepts connections, or the first jump host for hosts behind them
+    pub fn check_reachable(&self, host: &ResolvedHost) -> Result<()> {
+        match self.jump_hosts.first() {
+            Some(jump_host) => reachability::check(Some(&jump_host.destination), &jump_host.args),
+            None => {
+                let args: Vec<String> = self.host_args.iter().chain(&host.args).cloned().collect();
+                reachability::check(Some(&host.destination), &args)
+            }
+        }
+    }
+}
+
+/// The ssh command running a shell script on a host with the key in `key_file` and no other
+/// identity, without a terminal, prompts or long waits, routed like `connect` does
+pub(crate) fn remote_command(
+    host: &ResolvedHost,
+    route: &Route,
+    key_file: &KeyFile,
+    script: &str,
+) -> Command {
+    let mut key_args = key_file.exclusive_ssh_args();
+    key_args.extend(route.args.iter().cloned());
+    key_args.extend([
+        "-T".into(),
+        "-o".into(),
+        "BatchMode=yes".into(),
+        "-o".into(),
+        format!("ConnectTimeout={}", REMOTE_CONNECT_TIMEOUT.as_secs()).into(),
+    ]);
+    let mut command = ssh_command(
+        &key_args,
+        Some(&host.destination),
+        &host.args,
+        &host.env,
+        None,
+    );
+    // The login shell of the user might not be a POSIX one
+    command.arg(format!("sh -c {}", shell_quote(script)));
+    command
+}
+
+/// Run a shell script on a host with the given key and no other identity, without a terminal,
+/// prompts or long waits, passing `input` on its stdin. Returns the stdout of the script.
+pub fn run_remote(
+    host: &ResolvedHost,
+    route: &Route,
+    key: &SecretString,
+    script: &str,
+    input: &str,
+) -> Result<String> {
+    let key_file = KeyFile::create(key)?;
+    let mut command = remote_command(host, route, &key_file, script);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    debug!("Running {:?}", command);
+    let mut child = command.spawn()?;
+    // The input is written from another thread, as the script could fill the stdout or stderr
+    // pipe before it reads all of it
+    let (output, written) = std::thread::scope(|scope| {
+        let writer = child
+            .stdin
+            .take()
+            .map(|mut stdin| scope.spawn(move || stdin.write_all(input.as_bytes())));
+        let output = child.wait_with_output();
+        let written = writer.map_or(Ok(()), |writer| {
+            writer.join().expect("writing the input does not panic")
+        });
+        (output, written)
+    });
+    let output = output?;
+    // The script may exit without reading all of its input
+    if let Err(e) = written
+        && e.kind() != ErrorKind::BrokenPipe
+    {
+        return Err(e.into());
+    }
+    if !output.status.success() {
+        let status = output.status.code().unwrap_or(SIGNAL_EXIT_STATUS);
+        return Err(Report::new(Error::SshFailed { status }).wrap_err(format!(
+            "ssh to '{}' failed with {}: {}",
+            host.name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A command running `script` with the local shell, `sh -c` on Unix and `cmd /C` on Windows
+pub(crate) fn local_shell(script: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+    // cmd parses its command line itself, the script is passed without quoting it as an arg
+    #[cfg(windows)]
+    {
+        let mut command = Command::new("cmd");
+        command.arg("/C").raw_arg(script);
+        command
+    }
+}
+
+/// Quote an argument if a shell would split or expand it
+pub fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Print what `connect` would do without fetching the key or running ssh
+fn print_dry_run(key_alias: &str, key_alias_config: &KeyAliasConfig, command: &Command) {
+    println!("Key alias: {key_alias}");
+    match key_alias_config {
+        KeyAliasConfig::SecretsManager {
+            secret_arn,
+            profile,
+            passphrase_secret_arn,
+            ..
+        } => {
+            println!("Secret: {secret_arn}");
+            println!("Profile: {}", profile.as_deref().unwrap_or("default"));
+            if let Some(passphrase_secret_arn) = passphrase_secret_arn {
+                println!("Passphrase secret: {passphrase_secret_arn}");
+            }
+        }
+        KeyAliasConfig::Plugin { name, .. } => {
+            println!("Plugin: {}{name}", plugin::EXECUTABLE_PREFIX);
+        }
+    }
+    match key_alias_config.second_factor() {
+        Some(SecondFactorConfig::Totp { .. }) => println!("Second factor: TOTP"),
+        Some(SecondFactorConfig::Command { command }) => println!("Second factor: {command}"),
+        None => {}
+    }
+    for (name, value) in command.get_envs() {
+        let value = value
+            .map(|value| value.to_string_lossy())
+            .unwrap_or_default();
+        println!(
+            "Environment: {}={}",
+            name.to_string_lossy(),
+            shell_quote(&value)
+        );
+    }
+    let command_line: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect();
+    println!("Command: {}", command_line.join(" "));
+}
+
+/// A finished ssh session
+struct Session {
+    started: SystemTime,
+    duration: Duration,
+    /// Exit code of ssh, unset if it was killed by a signal
+    exit_code: Option<i32>,
+}
+
+/// Log what a finished session connected to, for how long, how it ended and where its key came
+/// from
+fn print_summary(
+    connection: &Connection,
+    key_alias: &str,
+    key_source: KeySource,
+    session: &Session,
+) {
+    let name = connection
+        .host
+        .or(connection.destination)
+        .unwrap_or(key_alias);
+    let exit_status = match session.exit_code {
+        Some(exit_code) => format!("exit status {exit_code}"),
+        None => "killed by a signal".to_string(),
+    };
+    info!(
+        "Session to '{name}' ended after {}, {exit_status}, key alias '{key_alias}' {key_source}",
+        crate::duration::format(session.duration)
+    );
+}
+
+/// Verify the second factor of a key alias, if it has one
+fn verify_second_factor(
+    config: &Config,
+    key_alias: &str,
+    key_alias_config: &KeyAliasConfig,
+) -> Result<()> {
+    if let Some(second_factor) = key_alias_config.second_factor() {
+        second_factor::verify(config, key_alias, second_factor)?;
+    }
+    Ok(())
+}
+
+/// Keep a copy of a fetched key for when AWS is unreachable, if the offline cache is enabled
+fn cache_key(config: &Config, source: &str, key: &SecretString) {
+    if config.offline_cache.is_none() {
+        return;
+    }
+    let Some(passphrase) = &config.passphrase else {
+        warn!("Keys are only cached while the config is encrypted, see `smssh config encrypt`");
+        return;
+    };
+    if let Err(e) = key_cache::store(source, key, passphrase) {
+        warn!("Failed to cache the key for offline use: {e}");
+    }
+}
+
+/// Fall back to the cached copy of a key that could not be fetched because AWS could not be
+/// reached, failing with the fetch error if the offline cache is disabled or has no recent copy.
+/// Keys AWS refused, like ones of deleted secrets or revoked roles, are never taken from the
+/// cache.
+fn offline_key(
+    config: &Config,
+    key_alias: &str,
+    source: &str,
+    error: Report,
+) -> Result<SecretString> {
+    let (Some(max_age), Some(passphrase)) = (config.offline_cache()?, &config.passphrase) else {
+        return Err(error);
+    };
+    if !aws::is_unreachable(&error) {
+        return Err(error);
+    }
+    match key_cache::load(source, max_age, passphrase) {
+        Ok((key, age)) => {
+            warn!("Failed to fetch key alias '{key_alias}': {error}");
+            warn!(
+                "OFFLINE: using the cached copy of the key fetched {} ago",
+                history::format_duration(age.as_secs())
+            );
+            Ok(key)
+        }
+        Err(cache_error) => {
+            warn!("No usable offline copy of key alias '{key_alias}': {cache_error}");
+            Err(error)
+        }
+    }
+}
+
+/// Fetch the key of an alias, audit the fetch and check it against the key last seen
+fn load_key(
+    config: &Config,
+    host: Option<&str>,
+    destination: Option<&str>,
+    key_alias: &str,
+    key_alias_config: &KeyAliasConfig,
+) -> Result<(SecretString, KeySource)> {
+    let fetch_timeout = config.fetch_timeout(key_alias_config)?;
+    let progress = match key_alias_config {
+        KeyAliasConfig::SecretsManager { .. } => Progress::start("Resolving AWS credentials"),
+        KeyAliasConfig::Plugin { name, .. } => Progress::start(&format!("Running plugin {name}")),
+    };
+    let key = pull_key(key_alias, key_alias_config, fetch_timeout, &progress);
+    let key_source = config.key_source(key_alias)?;
+    if let Some(audit_log) = &config.audit_log {
+        let mut record = AuditRecord::new(
+            "fetch",
+            host,
+            destination,
+            key_alias,
+            key_source.as_str().into(),
+        );
+        record.error = key.as_ref().err().map(|e| e.to_string());
+        // Don't use keys that could not be audited
+        audit::record(audit_log, &record)?;
+    }
+    progress.finish();
+    let (key, source) = match key {
+        Ok((key, source)) => {
+            let key = key_format::normalize(&key)
+                .wrap_err_with(|| format!("Invalid key in key alias '{key_alias}'"))?;
+            cache_key(config, &key_source, &key);
+            (key, source)
+        }
+        Err(e) => (
+            offline_key(config, key_alias, &key_source, e).wrap_err_with(|| {
+                Error::Provider(format!("Failed to fetch the key of '{key_alias}'"))
+            })?,
+            KeySource::OfflineCache,
+        ),
+    };
+    let known_key = KnownKey {
+        secret_arn: key_source,
+        fingerprint: fingerprints::fingerprint(&key)?,
+    };
+    let key_check = fingerprints::check(key_alias, &known_key, &key)?;
+    confirm_key(key_alias, key_check, known_key)?;
+    Ok((key, source))
+}
+
+/// Fetch the keys of the jump hosts, once per key alias
+fn load_jump_keys<'a>(
+    config: &Config,
+    host: Option<&str>,
+    destination: Option<&str>,
+    jump_hosts: &'a [JumpHost],
+) -> Result<BTreeMap<&'a str, KeyFile>> {
+    let mut key_files = BTreeMap::new();
+    for jump_host in jump_hosts {
+        let key_alias = jump_host.key_alias.as_str();
+        if key_files.contains_key(key_alias) {
+            continue;
+        }
+        verify_second_factor(config, key_alias, &jump_host.key_alias_config)?;
+        let (key, _) = load_key(
+            config,
+            host,
+            destination,
+            key_alias,
+            &jump_host.key_alias_config,
+        )?;
+        key_files.insert(key_alias, KeyFile::create(&key)?);
+    }
+    Ok(key_files)
+}
+
+/// The ssh args reaching the destination through the jump hosts, as nested proxy commands since
+/// `-J` can't give each hop its own key. Each hop is reached through the proxy command of the
+/// hop before it.
+fn jump_args(jump_hosts: &[JumpHost], key_files: &BTreeMap<&str, KeyFile>) -> Vec<OsString> {
+    let mut proxy_command: Option<String> = None;
+    for jump_host in jump_hosts {
+        let mut words = vec!["ssh".to_string()];
+        if batch::enabled() {
+            words.extend(["-o".to_string(), "BatchMode=yes".to_string()]);
+        }
+        words.extend(
+            key_files[jump_host.key_alias.as_str()]
+                .ssh_args()
+                .iter()
+                .map(|arg| shell_quote(&arg.to_string_lossy())),
+        );
+        if let Some(inner) = &proxy_command {
+            // Every ssh on the way expands the % tokens of its proxy command, so the tokens of
+            // the inner hops are escaped once more per level
+            words.push("-o".to_string());
+            words.push(shell_quote(&format!(
+                "ProxyCommand={}",
+                inner.replace('%', "%%")
+            )));
+        }
+        words.extend(jump_host.args.iter().map(|arg| shell_quote(arg)));
+        words.extend([
+            "-W".to_string(),
+            "%h:%p".to_string(),
+            shell_quote(&jump_host.destination),
+        ]);
+        proxy_command = Some(words.join(" "));
+    }
+    match proxy_command {
+        Some(proxy_command) => vec!["-o".into(), format!("ProxyCommand={proxy_command}").into()],
+        None => Vec::new(),
+    }
+}
+
+/// Store the key of the session where ssh can read it, followed by the keys of the extra key
+/// aliases
+fn store_keys(key: &SecretString, extra_keys: &[SecretString]) -> Result<KeyFile> {
+    let mut key_file = KeyFile::create(key)?;
+    for extra_key in extra_keys {
+        key_file.add(extra_key)?;
+    }
+    let stored = if extra_keys.is_empty() {
+        "the key".to_string()
+    } else {
+        format!("{} keys", extra_keys.len() + 1)
+    };
+    info!("Stored {stored} in {}", key_file.storage());
+    Ok(key_file)
+}
+
+/// Show a desktop notification if a session that ran long enough lost its connection or was
+/// killed, which the user could miss for sessions running in the background
+fn notify_drop(
+    config: &Config,
+    connection: &Connection,
+    exit_code: Option<i32>,
+    duration: Duration,
+) {
+    let min_duration = match config.notify_drops() {
+        Ok(Some(min_duration)) => min_duration,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Invalid drop notification setting: {e}");
+            return;
+        }
+    };
+    let dropped = matches!(exit_code, Some(SSH_CONNECTION_FAILED) | None);
+    if !dropped || duration < min_duration {
+        return;
+    }
+    let name = connection
+        .host
+        .or(connection.destination)
+        .unwrap_or(connection.key_alias);
+    let body = format!(
+        "The session to {name} dropped after {}",
+        history::format_duration(duration.as_secs())
+    );
+    if let Err(e) = notification::send("smssh session dropped", &body) {
+        warn!("{e}");
+    }
+}
+
+/// Print or open the console links of the instance of a host. Failures only warn, the session
+/// starts either way.
+fn show_console_links(connection: &Connection, instance_id: &str) {
+    // The instance is started and stopped in the region of the profile, not of the secret
+    let region = aws::block_on(aws::region(connection.key_alias_config.profile()))
+        .ok()
+        .flatten();
+    let Some(region) = region else {
+        warn!("No AWS region is configured for the console links of instance {instance_id}");
+        return;
+    };
+    let mut links = console::links(instance_id, &region);
+    // Only the EC2 page is opened, the other links are printed. All of them are printed when
+    // there is no browser, like over ssh.
+    if connection.console_links == ConsoleLinks::Open {
+        let (label, url) = links.remove(0);
+        if let Err(e) = console::open(&url) {
+            warn!("Failed to open the console links: {e}");
+            links.insert(0, (label, url));
+        }
+    }
+    for (label, url) in links {
+        info!("{label}: {url}");
+    }
+}
+
+/// Connect and return the exit status of ssh
+pub fn connect(config: &Config, connection: &Connection, dry_run: bool) -> Result<i32> {
+    let Connection {
+        destination,
+        command: remote_command,
+        args,
+        env,
+        ..
+    } = connection;
+    if dry_run {
+        let key_args = ["-i".into(), DRY_RUN_KEY_PATH.into()];
+        let command = ssh_command(&key_args, *destination, args, env, *remote_command);
+        print_dry_run(connection.key_alias, &connection.key_alias_config, &command);
+        for (fallback, _) in &connection.fallback_aliases {
+            println!("Fallback key alias: {fallback}");
+        }
+        for (extra, _) in &connection.extra_aliases {
+            println!("Extra key alias: {extra}");
+        }
+        for jump_host in &connection.jump_hosts {
+            println!(
+                "Jump host: {} ({}, key alias {})",
+                jump_host.name, jump_host.destination, jump_host.key_alias
+            );
+        }
+        if let Some(recording) = &connection.recording {
+            println!("Recording: {}", recording.display());
+        }
+        if let Some(max_session) = connection.max_session {
+            println!(
+                "Max session: {}",
+                history::format_duration(max_session.as_secs())
+            );
+        }
+        if connection.confirm != ConfirmPolicy::Never {
+            println!(
+                "Confirm: {}",
+                format!("{:?}", connection.confirm).to_lowercase()
+            );
+        }
+        if connection.check_reachable {
+            println!("Reachability check: enabled");
+        }
+        if let Some(instance_id) = connection.instance_id {
+            let start = if connection.start_instance {
+                " (started if stopped)"
+            } else {
+                ""
+            };
+            println!("Instance: {instance_id}{start}");
+            if connection.stop_on_exit != StopPolicy::Never {
+                println!(
+                    "Stop on exit: {}",
+                    format!("{:?}", connection.stop_on_exit).to_lowercase()
+                );
+            }
+            if connection.console_links != ConsoleLinks::Never {
+                println!(
+                    "Console links: {}",
+                    format!("{:?}", connection.console_links).to_lowercase()
+                );
+            }
+        }
+        if let Some(fetch_timeout) = config.fetch_timeout(&connection.key_alias_config)? {
+            println!(
+                "Fetch timeout: {}",
+                history::format_duration(fetch_timeout.as_secs())
+            );
+        }
+        if let Some(notify_drops) = config.notify_drops()? {
+            println!(
+                "Drop notification: after {}",
+                history::format_duration(notify_drops.as_secs())
+            );
+        }
+        if let Some(hook) = connection.pre_connect {
+            println!("Pre-connect hook: {hook}");
+        }
+        if let Some(hook) = connection.post_disconnect {
+            println!("Post-disconnect hook: {hook}");
+        }
+        if let Some((network, _)) = connection.network {
+            println!("Network: {network}");
+        }
+        if let Some(resolver) = connection.resolver {
+            println!("Resolver: {resolver}");
+        }
+        if let Some(transport) = connection.transport {
+            println!("Transport: {transport}");
+        }
+        if !connection.knock.is_empty() {
+            let knocks: Vec<String> = connection.knock.iter().map(|k| k.to_string()).collect();
+            println!("Knock: {}", knocks.join(" "));
+        }
+        return Ok(0);
+    }
+
+    // Confirmed before the hooks run, which can have side effects of their own
+    confirm_connection(connection)?;
+    for (kind, hook) in [
+        ("pre-connect", connection.pre_connect),
+        ("post-disconnect", connection.post_disconnect),
+    ] {
+        if let Some(hook) = hook {
+            let host = connection.host.unwrap_or_default();
+            trusted_commands::ensure_trusted(config, &format!("{kind} hook of '{host}'"), hook)?;
+        }
+    }
+    // The configured args follow the ones given on the command line
+    if let Some(host) = connection.host {
+        trusted_commands::ensure_trusted_args(
+            config,
+            host,
+            &connection.args[connection.ssh_args.len()..],
+        )?;
+    }
+    for jump_host in &connection.jump_hosts {
+        trusted_commands::ensure_trusted_args(config, &jump_host.name, &jump_host.args)?;
+    }
+    // The pre-connect hook runs before anything that could need it, like a VPN for the
+    // reachability check or refreshed credentials for the key fetch
+    if let Some(hook) = connection.pre_connect {
+        run_hook(connection, "pre-connect", hook, None)?;
+    }
+    let result = run_session(config, connection);
+    // The key is removed by now. The hook also runs when the session failed, to clean up after
+    // the pre-connect hook.
+    if let Some(hook) = connection.post_disconnect
+        && let Err(e) = run_hook(
+            connection,
+            "post-disconnect",
+            hook,
+            result.as_ref().ok().copied(),
+        )
+    {
+        warn!("{e}");
+    }
+    result
+}
+
+/// How long to wait before the given retry, doubling from [`RETRY_DELAY`] up to
+/// [`MAX_RETRY_DELAY`]
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Fetch the key, run ssh with it and return the exit status of ssh
+fn run_session(config: &Config, connection: &Connection) -> Result<i32> {
+    let Connection {
+        destination,
+        command: remote_command,
+        args,
+        env,
+        ..
+    } = connection;
+    let unreachable = || {
+        Error::Transport(match connection.host.or(*destination) {
+            Some(name) => format!("Cannot reach '{name}'"),
+            None => "Cannot reach the host".to_string(),
+        })
+    };
+    // Before anything is started for a host that can't be resolved or reached without it
+    if matches!(connection.resolver, Some(ResolverConfig::Route53 { .. }))
+        || connection.transport.is_some()
+    {
+        aws::ensure_cli()?;
+    }
+    // Checked first, the instance, the reachability check and the key fetch can all need it
+    if let Some((network_name, network)) = connection.network {
+        network::ensure_connected(
+            config,
+            network_name,
+            network,
+            connection.host.unwrap_or_default(),
+        )
+        .wrap_err_with(unreachable)?;
+    }
+    // Given before the other args, since ssh uses the first value of each option. The tunnel
+    // is set up before the instance is started, as waiting for the instance probes through it.
+    let profile = connection.key_alias_config.profile();
+    let transport_args: Vec<String>;
+    let args = match (connection.transport, connection.instance_id) {
+        (Some(transport), Some(instance_id)) => {
+            transport_args = transport::proxy_args(transport, instance_id, profile)?
+                .into_iter()
+                .chain(args.iter().cloned())
+                .collect();
+            &transport_args
+        }
+        _ => args,
+    };
+    if connection.start_instance {
+        start_instance(connection, args).wrap_err_with(unreachable)?;
+    }
+    let resolved_args: Vec<String>;
+    let args = match connection.resolver {
+        Some(resolver) => {
+            resolved_args = resolver::resolved_args(resolver, *destination, args, profile)
+                .wrap_err_with(unreachable)?
+                .into_iter()
+                .chain(args.iter().cloned())
+                .collect();
+            &resolved_args
+        }
+        None => args,
+    };
+    if connection.check_reachable {
+        let probe = || {
+            // Hosts behind a port knocking daemon only accept the probe once knocked on
+            if !connection.knock.is_empty() {
+                reachability::knock(*destination, args, connection.knock)?;
+            }
+            // Hosts behind jump hosts can only be reached through them, so the first one is
+            // probed
+            match connection.jump_hosts.first() {
+                Some(jump_host) => {
+                    reachability::check(Some(&jump_host.destination), &jump_host.args)
+                }
+                None => reachability::check(*destination, args),
+            }
+        };
+        // A host that is still coming up is probed again as many times as ssh would be retried
+        let mut attempt = 0;
+        while let Err(e) = probe() {
+            if attempt >= connection.retries {
+                return Err(e).wrap_err_with(unreachable);
+            }
+            attempt += 1;
+            let delay = retry_delay(attempt);
+            warn!("{e}, probing again in {delay:?}");
+            std::thread::sleep(delay);
+        }
+    }
+    #[cfg(unix)]
+    if connection.control_persist.is_some() {
+        ensure_control_dir()?;
+    }
+    #[cfg(windows)]
+    if connection.control_persist.is_some() {
+        warn!("OpenSSH for Windows can't keep connections open, ignoring control_persist");
+    }
+    verify_second_factor(config, connection.key_alias, &connection.key_alias_config)?;
+    let term_flag = Arc::new(AtomicBool::new(false));
+    let wakeup = Wakeup::new()?;
+    register_termination_handlers(term_flag.clone(), &wakeup)?;
+
+    let mut key_alias = connection.key_alias;
+    let (key, mut key_source) = load_key(
+        config,
+        connection.host,
+        connection.destination,
+        key_alias,
+        &connection.key_alias_config,
+    )?;
+    // Kept for the session, the key file is recreated with them when falling back to another key
+    let extra_keys = connection
+        .extra_aliases
+        .iter()
+        .map(|(extra, extra_config)| {
+            verify_second_factor(config, extra, extra_config)?;
+            Ok(load_key(
+                config,
+                connection.host,
+                connection.destination,
+                extra,
+                extra_config,
+            )?
+            .0)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut key_file = store_keys(&key, &extra_keys)?;
+    drop(key);
+    let jump_key_files = load_jump_keys(
+        config,
+        connection.host,
+        connection.destination,
+        &connection.jump_hosts,
+    )?;
+    let jump_args = jump_args(&connection.jump_hosts, &jump_key_files);
+    let ssh_args = |key_file: &KeyFile| {
+        let mut ssh_args = key_file.ssh_args();
+        ssh_args.extend(jump_args.iter().cloned());
+        ssh_args
+    };
+    let mut fallback_aliases = connection.fallback_aliases.iter();
+    let command = ssh_command(
+        &ssh_args(&key_file),
+        *destination,
+        args,
+        env,
+        *remote_command,
+    );
+
+    info!("Running {:?}", command);
+    // Only log the variable names, the values can hold secrets
+    debug!(
+        "ssh environment variables: {:?}",
+        env.keys().collect::<Vec<_>>()
+    );
+    if let Some(max_session) = connection.max_session {
+        limit_session(max_session, term_flag.clone(), wakeup.waker()?);
+    }
+    if let Some(instance_id) = connection.instance_id
+        && connection.console_links != ConsoleLinks::Never
+    {
+        show_console_links(connection, instance_id);
+    }
+    let session_marker = match connection.instance_id {
+        Some(instance_id) if connection.stop_on_exit != StopPolicy::Never => {
+            Some(instance_sessions::register(instance_id)?)
+        }
+        _ => None,
+    };
+    let started = SystemTime::now();
+    let start = Instant::now();
+    let mut command = Some(command);
+    let mut attempt = 0;
+    let result = loop {
+        // The key is kept for all attempts, only the command is rebuilt
+        let command = command.take().unwrap_or_else(|| {
+            ssh_command(
+                &ssh_args(&key_file),
+                *destination,
+                args,
+                env,
+                *remote_command,
+            )
+        });
+        // Knocks open the port for a short while, so they are sent right before each attempt
+        if !connection.knock.is_empty()
+            && let Err(e) = reachability::knock(*destination, args, connection.knock)
+        {
+            break Err(e.wrap_err(unreachable()));
+        }
+        let result = match &connection.recording {
+            Some(path) => recording::run_recorded(command, term_flag.clone(), path),
+            None => run_command_in_foreground(command, term_flag.clone(), &wakeup),
+        };
+        let failed_to_connect = matches!(result, Ok(Some(SSH_CONNECTION_FAILED)));
+        if term_flag.load(Ordering::Relaxed) || !failed_to_connect {
+            break result;
+        }
+
+        // ssh fails the same way when the host does not trust the key, so try the fallback keys
+        // before retrying with the last one
+        if let Some((fallback, fallback_config)) = fallback_aliases.next() {
+            warn!(
+                "ssh failed with key alias '{key_alias}', trying fallback key alias '{fallback}'"
+            );
+            let loaded = verify_second_factor(config, fallback, fallback_config)
+                .and_then(|_| {
+                    load_key(
+                        config,
+                        connection.host,
+                        connection.destination,
+                        fallback,
+                        fallback_config,
+                    )
+                })
+                .and_then(|(key, source)| Ok((store_keys(&key, &extra_keys)?, source)));
+            match loaded {
+                Ok((fallback_key_file, fallback_source)) => {
+                    (key_file, key_source) = (fallback_key_file, fallback_source)
+                }
+                Err(e) => break Err(e),
+            }
+            key_alias = fallback;
+            if let Some(path) = &connection.recording {
+                let _ = std::fs::remove_file(path);
+            }
+            continue;
+        }
+        if attempt >= connection.retries {
+            break result;
+        }
+
+        attempt += 1;
+        let delay = retry_delay(attempt);
+        if connection.retries == u32::MAX {
+            warn!("ssh failed to connect, retrying in {delay:?}");
+        } else {
+            warn!(
+                "ssh failed to connect, retrying in {delay:?} ({attempt} of {})",
+                connection.retries
+            );
+        }
+        // Make room for the recording of the next attempt
+        if let Some(path) = &connection.recording {
+            let _ = std::fs::remove_file(path);
+        }
+        wakeup.sleep(delay, &term_flag)?;
+    };
+    let duration = start.elapsed();
+
+    if let Some(audit_log) = &config.audit_log {
+        let source = config.key_source(key_alias)?;
+        let mut record = connection.audit_record("connect", key_alias, source);
+        match &result {
+            Ok(exit_code) => record.exit_code = *exit_code,
+            Err(e) => record.error = Some(e.to_string()),
+        }
+        if let Err(e) = audit::record(audit_log, &record) {
+            warn!("Failed to audit the connection: {e}");
+        }
+    }
+    if let Some(marker) = session_marker
+        && let Err(e) = stop_instance_on_exit(connection, marker, &term_flag, &wakeup)
+    {
+        warn!("Failed to stop the instance: {e}");
+    }
+    let exit_code = result?;
+    debug!("ssh exited with {exit_code:?} after {duration:?}");
+    if !term_flag.load(Ordering::Relaxed) {
+        notify_drop(config, connection, exit_code, duration);
+    }
+    let session = Session {
+        started,
+        duration,
+        exit_code,
+    };
+    print_summary(connection, key_alias, key_source, &session);
+    record_session(connection, &session);
+    Ok(exit_code.unwrap_or(SIGNAL_EXIT_STATUS))
+}
+
+/// Run a command in the foreground and bring back the parent after it exits. Terminates early if
+/// `term_flag` is set to true, which has to be followed by a wakeup. Returns the exit code of the
+/// command, if it exited normally. Without a terminal, as from pipelines and cron, the command
+/// just runs in its own process group.
+#[cfg(unix)]
+fn run_command_in_foreground(
+    mut command: Command,
+    term_flag: Arc<AtomicBool>,
+    wakeup: &Wakeup,
+) -> Result<Option<i32>> {
+    let mut child = unsafe {
+        command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .pre_exec(|| {
+                // Detach from the parent PGID
+                let pid = getpid();
+                setpgid(pid, pid)?;
+                Ok(())
+            })
+            .spawn()?
+    };
+
+    let terminal = io::stdin().is_terminal();
+    // Ignore SIGTTOU to allow moving the parent to the foreground after the child exits
+    // and to allow background logging if `tostop` is set. A custom handler would not work,
+    // the signal needs to be ignored or blocked for the background tcsetpgrp call to succeed
+    let ignore_action = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    let old_action = unsafe { sigaction(Signal::SIGTTOU, &ignore_action)? };
+
+    // Set the foreground PGID to the child's PGID
+    let child_pid = Pid::from_raw(child.id() as i32);
+    if terminal && unsafe { tcsetpgrp(STDIN_FILENO, child_pid.as_raw()) } != 0 {
+        Err(io::Error::last_os_error())?
+    }
+
+    let exit_code = wait_for_child(&mut child, &term_flag, wakeup, |child| {
+        Ok(signal::kill(child_pid, Signal::SIGTERM).or_else(|_| child.kill())?)
+    })?;
+
+    // Set the foreground PGID to the parent's PGID
+    // The parent process is in the background - this requires ignoring or blocking SIGTTOU
+    let parent_pid = getpid();
+    if terminal && unsafe { tcsetpgrp(STDIN_FILENO, parent_pid.as_raw()) } != 0 {
+        Err(io::Error::last_os_error())?
+    }
+
+    // Restore the SIGTTOU handler now that we're in the foreground again
+    unsafe { sigaction(Signal::SIGTTOU, &old_action)? };
+
+    Ok(exit_code)
+}
+
+/// Run a command on the console in its own process group, so that console control events are
+/// left to smssh, and in a Job Object that takes down the command with everything it started,
+/// like proxy commands, when terminated or when smssh dies. Terminates early if `term_flag` is
+/// set to true. Returns the exit code of the command.
+#[cfg(windows)]
+fn run_command_in_foreground(
+    mut command: Command,
+    term_flag: Arc<AtomicBool>,
+    wakeup: &Wakeup,
+) -> Result<Option<i32>> {
+    let job = Job::new()?;
+    let mut child = command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .creation_flags(CREATE_NEW_PROCESS_GROUP)
+        .spawn()?;
+    if let Err(e) = job.assign(&child) {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e.into());
+    }
+    wait_for_child(&mut child, &term_flag, wakeup, |_| {
+        Ok(job.terminate(SIGNAL_EXIT_STATUS as u32)?)
+    })
+}
+
+/// Wait for a child to exit, or terminate it with `terminate` once `term_flag` is set. Returns
+/// its exit code, if it exited normally.
+fn wait_for_child(
+    child: &mut Child,
+    term_flag: &AtomicBool,
+    wakeup: &Wakeup,
+    terminate: impl FnOnce(&mut Child) -> Result<()>,
+) -> Result<Option<i32>> {
+    loop {
+        // Termination requested
+        if term_flag.load(Ordering::Relaxed) {
+            let mut stdout = stdout();
+            stdout.flush()?;
+            stdout.execute(cursor::MoveToNextLine(1))?;
+            info!("Termination signal received, exiting...");
+
+            terminate(child)?;
+            let exit_code = child.wait()?.code();
+
+            stdout.flush()?;
+            stdout.execute(cursor::MoveToNextLine(1))?;
+            return Ok(exit_code);
+        }
+
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status.code()),
+            // A wakeup that arrives between the check and the wait is kept in the pipe
+            Ok(None) => wakeup.wait()?,
+            Err(e) => {
+                error!("Error waiting for child: {:?}", e);
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// A Job Object killing the processes assigned to it once it is closed
+#[cfg(windows)]
+struct Job(OwnedHandle);
+
+#[cfg(windows)]
+impl Job {
+    fn new() -> io::Result<Self> {
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let job = Self(unsafe { OwnedHandle::from_raw_handle(handle) });
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let set = unsafe {
+            SetInformationJobObject(
+                job.0.as_raw_handle(),
+                JobObjectExtendedLimitInformation,
+                (&raw const limits).cast(),
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if set == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(job)
+    }
+
+    fn assign(&self, child: &Child) -> io::Result<()> {
+        if unsafe { AssignProcessToJobObject(self.0.as_raw_handle(), child.as_raw_handle()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Kill every process of the job, which exit with `exit_code`
+    fn terminate(&self, exit_code: u32) -> io::Result<()> {
+        if unsafe { TerminateJobObject(self.0.as_raw_handle(), exit_code) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
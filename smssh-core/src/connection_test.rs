@@ -0,0 +1,107 @@
+use std::fmt::{Display, Formatter};
+
+use secrecy::SecretString;
+
+use crate::{
+    config::ResolvedHost,
+    connect::{self, Route},
+};
+
+/// The step of a connection test that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Fetching the key of the key alias
+    KeyFetch,
+    /// Reaching the ssh port, through the jump hosts if any
+    Network,
+    /// Verifying the host key against the known hosts
+    HostKey,
+    /// Logging in with the key
+    Authentication,
+}
+
+impl Stage {
+    /// The stages in the order they are run
+    pub const ALL: [Stage; 4] = [
+        Self::KeyFetch,
+        Self::Network,
+        Self::HostKey,
+        Self::Authentication,
+    ];
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::KeyFetch => "key fetch",
+            Self::Network => "network",
+            Self::HostKey => "host key",
+            Self::Authentication => "authentication",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A failed connection test, the stages before `stage` succeeded
+#[derive(Debug)]
+pub struct Failure {
+    /// The stage that failed
+    pub stage: Stage,
+    /// Why it failed
+    pub message: String,
+}
+
+/// Tell from the errors ssh printed whether it failed before or after verifying the host key
+fn ssh_failure_stage(stderr: &str) -> Stage {
+    let host_key_errors = [
+        "Host key verification failed",
+        "REMOTE HOST IDENTIFICATION HAS CHANGED",
+        "No matching host key type found",
+    ];
+    let network_errors = [
+        "Could not resolve hostname",
+        "Connection refused",
+        "Connection timed out",
+        "No route to host",
+        "Network is unreachable",
+        "Connection closed by remote host",
+    ];
+    if host_key_errors.iter().any(|error| stderr.contains(error)) {
+        Stage::HostKey
+    } else if network_errors.iter().any(|error| stderr.contains(error)) {
+        Stage::Network
+    } else {
+        Stage::Authentication
+    }
+}
+
+/// Log into a host with a fetched key and exit right away, without prompting for anything.
+/// `key` is the outcome of fetching the key of the host, so that its failure is reported as the
+/// first stage. `route` is the outcome of fetching the keys of its jump hosts and resolving it,
+/// reported as a network failure.
+pub fn test(
+    host: &ResolvedHost,
+    key: Result<&SecretString, String>,
+    route: Result<&Route, String>,
+) -> Result<(), Failure> {
+    let key = key.map_err(|message| Failure {
+        stage: Stage::KeyFetch,
+        message,
+    })?;
+    let route = route.map_err(|message| Failure {
+        stage: Stage::Network,
+        message,
+    })?;
+    route.check_reachable(host).map_err(|e| Failure {
+        stage: Stage::Network,
+        message: e.to_string(),
+    })?;
+    connect::run_remote(host, route, key, "exit 0", "").map_err(|e| {
+        let message = e.to_string();
+        Failure {
+            stage: ssh_failure_stage(&message),
+            message,
+        }
+    })?;
+    Ok(())
+}
@@ -0,0 +1,81 @@
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::process::{Command, Stdio};
+
+#[cfg(windows)]
+use windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP;
+
+use color_eyre::{Result, eyre::Context};
+
+/// Opens URLs in the default browser
+#[cfg(target_os = "macos")]
+const OPEN_COMMAND: &str = "open";
+#[cfg(all(unix, not(target_os = "macos")))]
+const OPEN_COMMAND: &str = "xdg-open";
+#[cfg(windows)]
+const OPEN_COMMAND: &str = "explorer";
+
+/// Links to the AWS console pages of an EC2 instance, with what they show. CloudWatch Logs lists
+/// the log groups named after the instance, as the CloudWatch agent has no page per instance.
+pub fn links(instance_id: &str, region: &str) -> Vec<(&'static str, String)> {
+    let base = format!("https://{region}.console.aws.amazon.com");
+    vec![
+        (
+            "EC2",
+            format!("{base}/ec2/home?region={region}#InstanceDetails:instanceId={instance_id}"),
+        ),
+        (
+            "Session Manager",
+            format!("{base}/systems-manager/session-manager/{instance_id}?region={region}"),
+        ),
+        (
+            "CloudWatch Logs",
+            format!(
+                "{base}/cloudwatch/home?region={region}#logsV2:log-groups$3FlogGroupNameFilter$3D{instance_id}"
+            ),
+        ),
+    ]
+}
+
+/// Open a URL in the default browser without waiting for it. The opener runs in its own process
+/// group, so that Ctrl-C in the session that follows does not reach it.
+pub fn open(url: &str) -> Result<()> {
+    let mut command = Command::new(OPEN_COMMAND);
+    command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    command.process_group(0);
+    #[cfg(windows)]
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    let mut child = command
+        .spawn()
+        .wrap_err_with(|| format!("Failed to run {OPEN_COMMAND}"))?;
+    // Reaped in the background, its exit status is not waited for
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_point_at_the_instance_in_its_region() {
+        let links = links("i-0123456789abcdef0", "eu-west-1");
+        let names: Vec<_> = links.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["EC2", "Session Manager", "CloudWatch Logs"]);
+        for (name, url) in &links {
+            assert!(
+                url.starts_with("https://eu-west-1.console.aws.amazon.com/"),
+                "{name}: {url}"
+            );
+            assert!(url.contains("i-0123456789abcdef0"), "{name}: {url}");
+            assert!(url.contains("region=eu-west-1"), "{name}: {url}");
+        }
+    }
+}
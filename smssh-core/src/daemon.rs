@@ -0,0 +1,413 @@
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+#[cfg(unix)]
+use nix::{
+    sys::mman::{MlockAllFlags, mlockall},
+    unistd::getuid,
+};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+#[cfg(windows)]
+use uds_windows::{UnixListener, UnixStream};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{aws, config::Config, encryption, metrics::Metrics, permissions, systemd};
+
+static SOCKET_FILE_NAME: &str = "daemon.sock";
+/// Points the clients at another socket, like the private one of `smssh open`
+pub static SOCKET_ENV: &str = "SMSSH_DAEMON_SOCKET";
+const SOCKET_MODE: u32 = 0o600;
+/// Credentials expiring sooner than this are resolved again
+const CREDENTIALS_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+/// How long the client waits for the daemon before giving up
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a socket is checked for requests
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a client gets to send its request and to read the answer, so that a stuck client
+/// can't hold up the daemon
+const STREAM_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longer lines are not requests a client would send
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Request {
+    secret_arn: String,
+    profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Key(String),
+    /// A key the daemon keeps in memory for the next requests
+    CachedKey(String),
+    Error(String),
+    /// AWS refused the request, with the error code it gave
+    AwsError {
+        code: String,
+        message: String,
+    },
+    /// AWS could not be reached, so the client may fall back to its offline cache
+    Unreachable(String),
+}
+
+/// Path of the daemon socket, in the config directory unless `SMSSH_DAEMON_SOCKET` is set
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Config::config_dir().join(SOCKET_FILE_NAME))
+}
+
+/// Fetch a key through a running daemon. Returns `None` if no daemon is listening, so that the
+/// caller can fetch the key itself.
+pub fn get_key(secret_arn: &str, profile: Option<&str>) -> Result<Option<SecretString>> {
+    Ok(request(secret_arn, profile)?.map(|(key, _)| key))
+}
+
+/// Have a running daemon fetch a key ahead of the next connects. Returns whether the daemon keeps
+/// the key, it only keeps the AWS credentials without `--cache-keys`, or `None` if no daemon is
+/// listening.
+pub fn warm_key(secret_arn: &str, profile: Option<&str>) -> Result<Option<bool>> {
+    Ok(request(secret_arn, profile)?.map(|(_, cached)| cached))
+}
+
+/// Request a key from a running daemon, along with whether the daemon keeps it
+fn request(secret_arn: &str, profile: Option<&str>) -> Result<Option<(SecretString, bool)>> {
+    let path = socket_path();
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            if e.kind() != ErrorKind::NotFound {
+                debug!("Not using the daemon at {path:?}: {e}");
+            }
+            return Ok(None);
+        }
+    };
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let request = Request {
+        secret_arn: secret_arn.to_string(),
+        profile: profile.map(str::to_string),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+
+    let mut line = Zeroizing::new(String::new());
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .wrap_err("Failed to read the response of the daemon")?;
+    match serde_json::from_str(&line).wrap_err("Invalid response from the daemon")? {
+        Response::Key(key) => Ok(Some((encryption::into_secret(key), false))),
+        Response::CachedKey(key) => Ok(Some((encryption::into_secret(key), true))),
+        Response::Error(e) => Err(eyre!("The daemon failed to fetch the key: {e}")),
+        Response::AwsError { code, message } => Err(Report::new(aws::Relayed { code, message })
+            .wrap_err("The daemon failed to fetch the key")),
+        Response::Unreachable(e) => {
+            Err(Report::new(aws::Unreachable(e)).wrap_err("The daemon failed to fetch the key"))
+        }
+    }
+}
+
+/// A client with resolved AWS credentials of a profile
+struct Session {
+    client: SecretsManagerClient,
+    expiry: Option<SystemTime>,
+}
+
+impl Session {
+    fn is_fresh(&self) -> bool {
+        self.expiry.is_none_or(|expiry| {
+            expiry
+                .duration_since(SystemTime::now())
+                .is_ok_and(|left| left > CREDENTIALS_REFRESH_MARGIN)
+        })
+    }
+}
+
+struct Daemon {
+    sessions: HashMap<Option<String>, Session>,
+    /// How long fetched keys are kept, keys are not cached if unset
+    key_ttl: Option<Duration>,
+    keys: HashMap<(String, Option<String>), (SecretString, Instant)>,
+    metrics: Arc<Metrics>,
+}
+
+impl Daemon {
+    /// Drop the keys kept for longer than the key TTL, which scrubs them from memory
+    fn purge_expired_keys(&mut self) {
+        let key_ttl = self.key_ttl;
+        self.keys
+            .retain(|_, (_, fetched)| key_ttl.is_some_and(|ttl| fetched.elapsed() < ttl));
+    }
+
+    fn session(&mut self, profile: Option<&str>) -> Result<&Session> {
+        let profile = profile.map(str::to_string);
+        let is_fresh = self.sessions.get(&profile).is_some_and(Session::is_fresh);
+        if !is_fresh {
+            // Not using the clients cached by the aws module, which never expire
+            let sdk_config = aws::block_on(aws::load_credentials(profile.as_deref()))??;
+            let expiry = aws::block_on(aws::credentials_expiry(&sdk_config))??;
+            info!("Resolved AWS credentials for profile {profile:?}, expiring at {expiry:?}");
+            let client = SecretsManagerClient::new(&sdk_config);
+            self.sessions
+                .insert(profile.clone(), Session { client, expiry });
+            self.metrics.set_sessions(self.sessions.len());
+        }
+        Ok(&self.sessions[&profile])
+    }
+
+    fn get_key(&mut self, request: &Request) -> Result<SecretString> {
+        let id = (request.secret_arn.clone(), request.profile.clone());
+        if let (Some(ttl), Some((key, fetched))) = (self.key_ttl, self.keys.get(&id))
+            && fetched.elapsed() < ttl
+        {
+            debug!("Using the cached key of '{}'", request.secret_arn);
+            self.metrics.cache_hit();
+            return Ok(key.clone());
+        }
+
+        let started = Instant::now();
+        let key = self
+            .session(request.profile.as_deref())
+            .map(|session| session.client.clone())
+            .and_then(|client| aws::block_on(aws::get_secret(&client, &request.secret_arn))?);
+        self.metrics.fetched(started.elapsed(), key.is_ok());
+        let key = key?;
+        if self.key_ttl.is_some() {
+            self.keys.insert(id, (key.clone(), Instant::now()));
+        }
+        Ok(key)
+    }
+
+    /// Answer a request, returns whether a key was requested. Only processes of the user running
+    /// the daemon are answered, in case others can reach the socket despite its mode, like
+    /// through a shared runtime directory.
+    fn handle(&mut self, stream: UnixStream) -> Result<bool> {
+        check_peer(&stream)?;
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(STREAM_TIMEOUT))?;
+        stream.set_write_timeout(Some(STREAM_TIMEOUT))?;
+        let mut line = String::new();
+        let read = BufReader::new(&stream)
+            .take(MAX_REQUEST_LEN as u64)
+            .read_line(&mut line)?;
+        if read == 0 {
+            // Another daemon checking whether this one is alive
+            return Ok(false);
+        }
+        if read == MAX_REQUEST_LEN && !line.ends_with('\n') {
+            return Err(eyre!("Request longer than {MAX_REQUEST_LEN} bytes"));
+        }
+        let request: Request = serde_json::from_str(&line)?;
+        info!("Key requested for '{}'", request.secret_arn);
+
+        let mut response = match self.get_key(&request) {
+            Ok(key) if self.key_ttl.is_some() => {
+                Response::CachedKey(key.expose_secret().to_string())
+            }
+            Ok(key) => Response::Key(key.expose_secret().to_string()),
+            Err(e) => {
+                warn!("Failed to fetch the key of '{}': {e}", request.secret_arn);
+                if aws::is_unreachable(&e) {
+                    Response::Unreachable(format!("{e:#}"))
+                } else if let Some(code) = aws::error_code(&e) {
+                    Response::AwsError {
+                        code,
+                        message: format!("{e:#}"),
+                    }
+                } else {
+                    Response::Error(format!("{e:#}"))
+                }
+            }
+        };
+        let mut line = Zeroizing::new(serde_json::to_string(&response)?);
+        if let Response::Key(key) | Response::CachedKey(key) = &mut response {
+            key.zeroize();
+        }
+        line.push('\n');
+        (&stream).write_all(line.as_bytes())?;
+        Ok(true)
+    }
+}
+
+/// Fail unless the process connected to a socket runs as the same user
+#[cfg(unix)]
+fn check_peer(stream: &UnixStream) -> Result<()> {
+    let uid = peer_uid(stream)?;
+    if uid != getuid().as_raw() {
+        return Err(eyre!("Refusing a request from user {uid}"));
+    }
+    Ok(())
+}
+
+/// Windows has no peer credentials on Unix sockets. The socket file is in the profile of the
+/// user, its ACL already keeps other users from connecting.
+#[cfg(windows)]
+fn check_peer(_stream: &UnixStream) -> Result<()> {
+    Ok(())
+}
+
+/// Keep the memory of the process out of swap, including the keys it will hold
+#[cfg(unix)]
+fn lock_memory() -> Result<()> {
+    Ok(mlockall(
+        MlockAllFlags::MCL_CURRENT | MlockAllFlags::MCL_FUTURE,
+    )?)
+}
+
+/// Windows can only lock single pages, not all the memory a process will use
+#[cfg(windows)]
+fn lock_memory() -> Result<()> {
+    Err(eyre!("locking all memory is not supported on Windows"))
+}
+
+/// The user ID of the process connected to a socket
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> Result<u32> {
+    use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+    Ok(getsockopt(stream, PeerCredentials)?.uid())
+}
+
+/// The user ID of the process connected to a socket
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(stream: &UnixStream) -> Result<u32> {
+    Ok(nix::unistd::getpeereid(stream)?.0.as_raw())
+}
+
+/// Serve keys on the daemon socket until the process is terminated. AWS credentials are resolved
+/// once per profile and reused until they are about to expire. Keys are kept in memory for
+/// `key_ttl` if set, in which case the memory of the daemon is locked to keep them out of swap.
+/// Prometheus metrics are served on `metrics_address` if set. Listens on the socket passed by
+/// systemd when started by socket activation, and exits after `idle_timeout` without requests if
+/// set. The daemon does not read the config, the clients asking for keys audit their fetches.
+pub fn serve(
+    key_ttl: Option<Duration>,
+    metrics_address: Option<&str>,
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
+    let path = socket_path();
+    let activated = systemd::activated_listener()?;
+    let is_activated = activated.is_some();
+    let listener = match activated {
+        Some(listener) => listener,
+        None => bind(&path)?,
+    };
+
+    if key_ttl.is_some()
+        && let Err(e) = lock_memory()
+    {
+        warn!("Failed to lock the daemon memory, cached keys could be swapped to disk: {e}");
+    }
+
+    let metrics = Arc::new(Metrics::new(
+        "smssh_daemon_credential_sessions",
+        "Profiles with resolved AWS credentials held by the daemon",
+    ));
+    if let Some(address) = metrics_address {
+        crate::metrics::serve(address, metrics.clone())?;
+    }
+    let mut daemon = Daemon {
+        sessions: HashMap::new(),
+        key_ttl,
+        keys: HashMap::new(),
+        metrics,
+    };
+    let address = listener.local_addr()?;
+    let listening = address.as_pathname().unwrap_or(&path);
+    info!("Listening on {listening:?}");
+    listener.set_nonblocking(true)?;
+    let mut last_request = Instant::now();
+    loop {
+        daemon.purge_expired_keys();
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if let Some(idle_timeout) = idle_timeout
+                    && last_request.elapsed() >= idle_timeout
+                {
+                    info!("Exiting after {idle_timeout:?} without requests");
+                    break;
+                }
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to accept a request: {e}");
+                continue;
+            }
+        };
+        last_request = Instant::now();
+        if let Err(e) = daemon.handle(stream) {
+            warn!("Failed to handle a request: {e}");
+        }
+    }
+    // The socket of systemd stays, to start the daemon again on the next request
+    if !is_activated {
+        let _ = std::fs::remove_file(&path);
+    }
+    Ok(())
+}
+
+/// Listen on the daemon socket, replacing the one left behind by a daemon that was killed
+fn bind(path: &Path) -> Result<UnixListener> {
+    Config::create_config_dir()?;
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(eyre!("A daemon is already listening on {path:?}"));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener =
+        UnixListener::bind(path).wrap_err_with(|| format!("Failed to listen on {path:?}"))?;
+    permissions::set_mode(path, SOCKET_MODE)?;
+    Ok(listener)
+}
+
+/// Serve keys on a private socket until `requests` keys were requested or the deadline passed.
+/// Keys are kept in memory meanwhile, so that each secret is fetched once however many clients
+/// request it.
+pub fn serve_private(listener: UnixListener, requests: usize, deadline: Instant) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    if let Err(e) = lock_memory() {
+        warn!("Failed to lock the memory, cached keys could be swapped to disk: {e}");
+    }
+    let mut daemon = Daemon {
+        sessions: HashMap::new(),
+        key_ttl: Some(deadline.saturating_duration_since(Instant::now())),
+        keys: HashMap::new(),
+        metrics: Arc::new(Metrics::new(
+            "smssh_daemon_credential_sessions",
+            "Profiles with resolved AWS credentials held by the daemon",
+        )),
+    };
+    let mut served = 0;
+    while served < requests && Instant::now() < deadline {
+        daemon.purge_expired_keys();
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match daemon.handle(stream) {
+            Ok(true) => served += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Failed to handle a request: {e}"),
+        }
+    }
+    Ok(())
+}
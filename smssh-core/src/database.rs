@@ -0,0 +1,213 @@
+use std::net::TcpListener;
+
+use color_eyre::{Result, eyre::eyre};
+use serde::Deserialize;
+
+use crate::{aws, config::DatabaseSource};
+
+/// The endpoint of a database in its VPC and what its connection string needs
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    /// Hostname of the database, resolved inside its VPC
+    pub address: String,
+    /// Port the database listens on
+    pub port: u16,
+    /// Engine as named by AWS, like "postgres", "aurora-mysql" or "redis"
+    pub engine: String,
+    /// The master user, if AWS reports one
+    pub user: Option<String>,
+    /// The name of the initial database, if AWS reports one
+    pub database: Option<String>,
+    /// Whether clients have to use TLS, only known for ElastiCache
+    pub tls: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Address {
+    address: String,
+    port: u16,
+}
+
+#[derive(Deserialize)]
+struct DbInstances {
+    #[serde(rename = "DBInstances")]
+    instances: Vec<DbInstance>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DbInstance {
+    /// Unset while the instance is being created
+    endpoint: Option<Address>,
+    engine: String,
+    master_username: Option<String>,
+    #[serde(rename = "DBName")]
+    db_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DbClusters {
+    #[serde(rename = "DBClusters")]
+    clusters: Vec<DbCluster>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DbCluster {
+    endpoint: Option<String>,
+    port: Option<u16>,
+    engine: String,
+    master_username: Option<String>,
+    database_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReplicationGroups {
+    replication_groups: Vec<ReplicationGroup>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReplicationGroup {
+    /// Only set for groups with cluster mode enabled
+    configuration_endpoint: Option<Address>,
+    #[serde(default)]
+    node_groups: Vec<NodeGroup>,
+    engine: Option<String>,
+    #[serde(default)]
+    transit_encryption_enabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NodeGroup {
+    primary_endpoint: Option<Address>,
+}
+
+/// Look up the endpoint of a database. There is no SDK client for RDS and ElastiCache here, so
+/// the endpoints are looked up with the AWS CLI.
+pub fn resolve(source: &DatabaseSource, profile: Option<&str>) -> Result<Endpoint> {
+    match source {
+        DatabaseSource::Rds { instance } => {
+            let response: DbInstances = aws::cli(
+                &[
+                    "rds",
+                    "describe-db-instances",
+                    "--db-instance-identifier",
+                    instance,
+                ],
+                profile,
+            )?;
+            let db = response
+                .instances
+                .into_iter()
+                .next()
+                .ok_or(eyre!("RDS instance '{instance}' does not exist"))?;
+            let endpoint = db
+                .endpoint
+                .ok_or(eyre!("RDS instance '{instance}' has no endpoint yet"))?;
+            Ok(Endpoint {
+                address: endpoint.address,
+                port: endpoint.port,
+                engine: db.engine,
+                user: db.master_username,
+                database: db.db_name,
+                tls: false,
+            })
+        }
+        DatabaseSource::RdsCluster { cluster } => {
+            let response: DbClusters = aws::cli(
+                &[
+                    "rds",
+                    "describe-db-clusters",
+                    "--db-cluster-identifier",
+                    cluster,
+                ],
+                profile,
+            )?;
+            let db = response
+                .clusters
+                .into_iter()
+                .next()
+                .ok_or(eyre!("RDS cluster '{cluster}' does not exist"))?;
+            let (Some(address), Some(port)) = (db.endpoint, db.port) else {
+                return Err(eyre!("RDS cluster '{cluster}' has no endpoint yet"));
+            };
+            Ok(Endpoint {
+                address,
+                port,
+                engine: db.engine,
+                user: db.master_username,
+                database: db.database_name,
+                tls: false,
+            })
+        }
+        DatabaseSource::ElastiCache { replication_group } => {
+            let response: ReplicationGroups = aws::cli(
+                &[
+                    "elasticache",
+                    "describe-replication-groups",
+                    "--replication-group-id",
+                    replication_group,
+                ],
+                profile,
+            )?;
+            let group = response.replication_groups.into_iter().next().ok_or(eyre!(
+                "ElastiCache replication group '{replication_group}' does not exist"
+            ))?;
+            let endpoint = group
+                .configuration_endpoint
+                .or_else(|| {
+                    group
+                        .node_groups
+                        .into_iter()
+                        .find_map(|node_group| node_group.primary_endpoint)
+                })
+                .ok_or(eyre!(
+                    "ElastiCache replication group '{replication_group}' has no endpoint yet"
+                ))?;
+            Ok(Endpoint {
+                address: endpoint.address,
+                port: endpoint.port,
+                engine: group.engine.unwrap_or_else(|| "redis".to_string()),
+                user: None,
+                database: None,
+                tls: group.transit_encryption_enabled,
+            })
+        }
+    }
+}
+
+/// A free port on the loopback interface, which the forward can listen on
+pub fn free_local_port() -> Result<u16> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// A connection string to the database through the local forward, in the URL format of its
+/// engine, or just the address for engines without a common one
+pub fn connection_string(
+    endpoint: &Endpoint,
+    user: Option<&str>,
+    database: Option<&str>,
+    local_port: u16,
+) -> String {
+    let scheme = match endpoint.engine.as_str() {
+        engine if engine.contains("postgres") => "postgresql",
+        engine if engine.contains("mysql") || engine == "mariadb" || engine == "aurora" => "mysql",
+        "redis" | "valkey" if endpoint.tls => "rediss",
+        "redis" | "valkey" => "redis",
+        _ => return format!("127.0.0.1:{local_port}"),
+    };
+    let user = user
+        .or(endpoint.user.as_deref())
+        .map(|user| format!("{user}@"))
+        .unwrap_or_default();
+    let database = database
+        .or(endpoint.database.as_deref())
+        .map(|database| format!("/{database}"))
+        .unwrap_or_default();
+    format!("{scheme}://{user}127.0.0.1:{local_port}{database}")
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use color_eyre::{Result, eyre::eyre};
+
+/// Parse a duration made of numbers with `d`, `h`, `m` or `s` units, like "8h" or "1h30m".
+pub fn parse(value: &str) -> Result<Duration> {
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(eyre!("Invalid unit '{c}' in duration '{value}'")),
+        };
+        if number.is_empty() {
+            return Err(eyre!("Missing number before '{c}' in duration '{value}'"));
+        }
+        let too_long = || eyre!("Duration '{value}' is too long");
+        let amount: u64 = number.parse().map_err(|_| too_long())?;
+        seconds = amount
+            .checked_mul(unit)
+            .and_then(|amount| seconds.checked_add(amount))
+            .ok_or_else(too_long)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(eyre!(
+            "Missing unit after '{number}' in duration '{value}', use one of d, h, m or s"
+        ));
+    }
+    if seconds == 0 {
+        return Err(eyre!("Duration '{value}' must be longer than zero"));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Format a duration with all of its units, like "1h30m5s", in the format read by [`parse`].
+pub fn format(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    let mut formatted = String::new();
+    for (unit, name) in [(86400, 'd'), (3600, 'h'), (60, 'm')] {
+        if seconds >= unit {
+            formatted.push_str(&format!("{}{name}", seconds / unit));
+            seconds %= unit;
+        }
+    }
+    if seconds > 0 || formatted.is_empty() {
+        formatted.push_str(&format!("{seconds}s"));
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_combined_units() {
+        assert_eq!(parse("8h").unwrap(), Duration::from_secs(8 * 3600));
+        assert_eq!(parse("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse(" 1d2h3m4s ").unwrap(), Duration::from_secs(93784));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for value in ["", "0s", "10", "h", "5x", "1h30"] {
+            assert!(parse(value).is_err(), "{value:?} should be invalid");
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        assert!(parse("18446744073709551615d").is_err());
+        assert!(parse("18446744073709551615s1s").is_err());
+        assert!(parse("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        for value in ["1h30m5s", "2d", "45s", "1d1s"] {
+            assert_eq!(format(parse(value).unwrap()), value);
+        }
+        assert_eq!(format(Duration::ZERO), "0s");
+    }
+}
@@ -0,0 +1,123 @@
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use keyring::Entry;
+use secrecy::{ExposeSecret, SecretString};
+use tracing::debug;
+use zeroize::Zeroize;
+
+use crate::{batch, prompt::PromptError};
+
+static ARMOR_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+static PASSPHRASE_ENV_VAR: &str = "SMSSH_CONFIG_PASSPHRASE";
+/// Service and user of the config passphrase in the OS keyring
+static KEYRING_SERVICE: &str = "smssh";
+static KEYRING_USER: &str = "config-passphrase";
+
+/// Move a string into a `SecretString`, which is scrubbed when dropped and redacted when
+/// debug-printed. The string is copied and scrubbed rather than converted, since the conversion
+/// could reallocate and leave the original buffer behind unscrubbed.
+pub fn into_secret(mut value: String) -> SecretString {
+    let secret = SecretString::from(value.as_str());
+    value.zeroize();
+    secret
+}
+
+/// Check whether the contents are an armored age file.
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.trim_start().starts_with(ARMOR_HEADER)
+}
+
+/// Encrypt the config with a passphrase into an armored age file
+pub fn encrypt(plaintext: &str, passphrase: &SecretString) -> Result<String> {
+    let recipient = age::scrypt::Recipient::new(passphrase.clone());
+    let ciphertext = age::encrypt_and_armor(&recipient, plaintext.as_bytes())?;
+    Ok(ciphertext)
+}
+
+/// Decrypt an armored age file with a passphrase
+pub fn decrypt(ciphertext: &str, passphrase: &SecretString) -> Result<String> {
+    let identity = age::scrypt::Identity::new(passphrase.clone());
+    let plaintext = age::decrypt(&identity, ciphertext.as_bytes())
+        .wrap_err("Failed to decrypt the config, is the passphrase correct?")?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// Check whether the passphrase is provided through the environment.
+pub fn has_env_passphrase() -> bool {
+    std::env::var_os(PASSPHRASE_ENV_VAR).is_some()
+}
+
+fn keyring_entry() -> keyring::Result<Entry> {
+    Entry::new(KEYRING_SERVICE, KEYRING_USER)
+}
+
+/// The passphrase stored in the OS keyring, if there is one and the keyring can be reached
+fn keyring_passphrase() -> Option<SecretString> {
+    match keyring_entry().and_then(|entry| entry.get_password()) {
+        Ok(passphrase) => Some(into_secret(passphrase)),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            debug!("Not using the OS keyring: {e}");
+            None
+        }
+    }
+}
+
+/// Store the passphrase in the OS keyring, so that the config is unlocked without prompting
+pub fn store_in_keyring(passphrase: &SecretString) -> Result<()> {
+    keyring_entry()
+        .and_then(|entry| entry.set_password(passphrase.expose_secret()))
+        .wrap_err("Failed to store the passphrase in the OS keyring")
+}
+
+/// Remove the passphrase from the OS keyring. Keyrings that can't be reached can't hold it.
+pub fn remove_from_keyring() -> Result<()> {
+    match keyring_entry().and_then(|entry| entry.delete_credential()) {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e @ (keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_))) => {
+            debug!("Not using the OS keyring: {e}");
+            Ok(())
+        }
+        Err(e) => Err(e).wrap_err("Failed to remove the passphrase from the OS keyring"),
+    }
+}
+
+/// Get the passphrase used to unlock an encrypted config, from the environment, the OS keyring
+/// or by prompting the user.
+pub fn unlock_passphrase() -> Result<SecretString> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV_VAR) {
+        return Ok(into_secret(passphrase));
+    }
+    if let Some(passphrase) = keyring_passphrase() {
+        return Ok(passphrase);
+    }
+    if batch::enabled() {
+        return Err(PromptError::Unavailable(format!(
+            "The config is encrypted, set {PASSPHRASE_ENV_VAR} to unlock it in batch mode"
+        ))
+        .into());
+    }
+    let passphrase = rpassword::prompt_password("Config passphrase: ")?;
+    Ok(into_secret(passphrase))
+}
+
+/// Prompt the user for a new passphrase, asking for it twice to catch typos.
+pub fn new_passphrase() -> Result<SecretString> {
+    if batch::enabled() {
+        return Err(PromptError::Unavailable(
+            "Cannot ask for a new config passphrase in batch mode".to_string(),
+        )
+        .into());
+    }
+    let passphrase = into_secret(rpassword::prompt_password("New config passphrase: ")?);
+    if passphrase.expose_secret().is_empty() {
+        return Err(eyre!("The passphrase cannot be empty"));
+    }
+    let confirmation = into_secret(rpassword::prompt_password("Confirm passphrase: ")?);
+    if passphrase.expose_secret() != confirmation.expose_secret() {
+        return Err(eyre!("The passphrases do not match"));
+    }
+    Ok(passphrase)
+}
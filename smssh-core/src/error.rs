@@ -0,0 +1,90 @@
+use std::fmt::{Display, Formatter};
+
+use color_eyre::Report;
+
+/// Exit status for an invalid config or one missing the entries used, `EX_CONFIG` of sysexits.h
+pub const CONFIG_EXIT_STATUS: u8 = 78;
+/// Exit status for a key that could not be fetched, `EX_UNAVAILABLE` of sysexits.h
+pub const PROVIDER_EXIT_STATUS: u8 = 69;
+/// Exit status for a host that could not be reached before running ssh, `EX_NOHOST` of sysexits.h
+pub const TRANSPORT_EXIT_STATUS: u8 = 68;
+/// Exit status for a confirmation the user declined, like removing an entry in use,
+/// `EX_NOPERM` of sysexits.h
+pub const DECLINED_EXIT_STATUS: u8 = 77;
+/// Exit status for ssh exiting with one of the statuses above or with this one, see
+/// [`ssh_exit_status`]
+pub const SSH_COLLISION_EXIT_STATUS: u8 = 79;
+
+/// The exit status of smssh when ssh exited with `status`. The statuses smssh uses for its own
+/// failures are replaced with [`SSH_COLLISION_EXIT_STATUS`], so that a remote command exiting
+/// with 69 is not taken for a key that could not be fetched. `--error-format json` still reports
+/// the status of ssh.
+pub fn ssh_exit_status(status: i32) -> u8 {
+    // Exit statuses are a single byte, ssh never returns more than 255
+    match status as u8 {
+        CONFIG_EXIT_STATUS
+        | PROVIDER_EXIT_STATUS
+        | TRANSPORT_EXIT_STATUS
+        | DECLINED_EXIT_STATUS
+        | SSH_COLLISION_EXIT_STATUS => SSH_COLLISION_EXIT_STATUS,
+        status => status,
+    }
+}
+
+/// The kinds of failures that automation around smssh can tell apart by the exit status. They are
+/// returned as the error, or attached to it with `wrap_err`, and found again with [`Error::of`].
+#[derive(Debug)]
+pub enum Error {
+    /// The config is invalid or refers to entries that do not exist
+    Config(String),
+    /// The key of a key alias could not be fetched from AWS or a plugin
+    Provider(String),
+    /// The host could not be reached, resolved or woken up before running ssh
+    Transport(String),
+    /// ssh failed, for example to authenticate, or the remote command failed
+    SshFailed {
+        /// Exit status of ssh
+        status: i32,
+    },
+}
+
+impl Error {
+    /// The kind of failure behind a report, if it has one
+    pub fn of(report: &Report) -> Option<&Self> {
+        report.downcast_ref()
+    }
+
+    /// The name of the kind, like "config"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Config(_) => "config",
+            Self::Provider(_) => "provider",
+            Self::Transport(_) => "transport",
+            Self::SshFailed { .. } => "ssh_failed",
+        }
+    }
+
+    /// The exit status of smssh for the failure. ssh failures pass on the status of ssh, which is
+    /// 255 when ssh itself failed, see [`ssh_exit_status`].
+    pub fn exit_status(&self) -> u8 {
+        match self {
+            Self::Config(_) => CONFIG_EXIT_STATUS,
+            Self::Provider(_) => PROVIDER_EXIT_STATUS,
+            Self::Transport(_) => TRANSPORT_EXIT_STATUS,
+            Self::SshFailed { status } => ssh_exit_status(*status),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(message) | Self::Provider(message) | Self::Transport(message) => {
+                write!(f, "{message}")
+            }
+            Self::SshFailed { status } => write!(f, "ssh exited with status {status}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+};
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, keygen};
+
+static FINGERPRINTS_FILE_NAME: &str = "key_fingerprints.json";
+
+/// The key last seen for a key alias
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KnownKey {
+    /// Where the key came from, see [`crate::config::Config::key_source`]
+    pub secret_arn: String,
+    /// SHA256 fingerprint of the public key
+    pub fingerprint: String,
+}
+
+/// How a fetched key compares to the one last seen for its alias
+pub enum KeyCheck {
+    /// No key was seen for the alias or its secret
+    New,
+    /// The key is the one last seen
+    Unchanged,
+    /// The key differs from the one last seen
+    Changed {
+        /// Fingerprint of the key last seen
+        previous: String,
+    },
+    /// The alias points at a different secret than when its key was last seen, and the key
+    /// differs
+    SourceChanged {
+        /// Where the key last seen came from
+        previous: String,
+    },
+}
+
+/// Path of the fingerprints of the keys last seen, in the config directory
+pub fn fingerprints_path() -> PathBuf {
+    Config::config_dir().join(FINGERPRINTS_FILE_NAME)
+}
+
+/// SHA256 fingerprint of the public key of a private key, the same as `ssh-keygen -l` and
+/// `smssh fingerprint alias` print, so that changes can be detected without storing the key
+pub fn fingerprint(key: &SecretString) -> Result<String> {
+    let public_key = keygen::public_key(key)?;
+    let blob = public_key
+        .split_whitespace()
+        .nth(1)
+        .and_then(|blob| STANDARD.decode(blob).ok())
+        .ok_or_else(|| eyre!("ssh-keygen printed an invalid public key"))?;
+    Ok(format!(
+        "SHA256:{}",
+        STANDARD_NO_PAD.encode(Sha256::digest(blob))
+    ))
+}
+
+/// SHA-256 of the private key itself in hex, which is what was stored as the fingerprint before
+/// fingerprints were taken of the public key. Also checks the keys of the offline cache.
+pub(crate) fn key_digest(key: &SecretString) -> String {
+    let digest = Sha256::digest(key.expose_secret().as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("SHA256:{hex}")
+}
+
+/// Load the keys last seen per key alias
+pub fn load() -> Result<BTreeMap<String, KnownKey>> {
+    let path = fingerprints_path();
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read key fingerprints {path:?}"))?;
+    serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Failed to parse key fingerprints {path:?}"))
+}
+
+/// Compare a fetched key with the one last seen for the alias. The alias being pointed at a
+/// different secret is reported as well, since the config can be changed by a synced shared
+/// config as much as by the user.
+pub fn check(key_alias: &str, known: &KnownKey, key: &SecretString) -> Result<KeyCheck> {
+    let check = match load()?.get(key_alias) {
+        Some(previous) if previous.fingerprint == known.fingerprint => KeyCheck::Unchanged,
+        // Fingerprints stored as the digest of the private key are replaced by the fingerprint
+        // of its public key
+        Some(previous) if previous.fingerprint == key_digest(key) => {
+            store(key_alias, known.clone())?;
+            KeyCheck::Unchanged
+        }
+        Some(previous) if previous.secret_arn != known.secret_arn => KeyCheck::SourceChanged {
+            previous: previous.secret_arn.clone(),
+        },
+        Some(previous) => KeyCheck::Changed {
+            previous: previous.fingerprint.clone(),
+        },
+        None => KeyCheck::New,
+    };
+    Ok(check)
+}
+
+/// Remember the key seen for the alias
+pub fn store(key_alias: &str, known: KnownKey) -> Result<()> {
+    let mut fingerprints = load()?;
+    fingerprints.insert(key_alias.to_string(), known);
+    Config::create_config_dir()?;
+    Config::write_atomic(
+        &fingerprints_path(),
+        &serde_json::to_string_pretty(&fingerprints)?,
+    )
+}
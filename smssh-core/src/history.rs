@@ -0,0 +1,118 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{Result, eyre::Context};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+static HISTORY_FILE_NAME: &str = "history.jsonl";
+const MAX_HISTORY: usize = 1000;
+
+/// A finished ssh session
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// The host or destination connected to, unset for `connect-with-alias`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// The key alias whose key was used
+    pub key_alias: String,
+    /// The ssh args given on the command line
+    #[serde(default)]
+    pub ssh_args: Vec<String>,
+    /// Seconds since the Unix epoch when the session started
+    pub timestamp: u64,
+    /// How long the session lasted
+    pub duration_secs: u64,
+    /// Exit status of ssh, unset if it was killed by a signal
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+}
+
+impl HistoryEntry {
+    /// An entry for a session that started at `started` and lasted `duration`
+    pub fn new(
+        host: Option<&str>,
+        key_alias: &str,
+        ssh_args: &[String],
+        started: SystemTime,
+        duration: Duration,
+        exit_code: Option<i32>,
+    ) -> Self {
+        Self {
+            host: host.map(str::to_string),
+            key_alias: key_alias.to_string(),
+            ssh_args: ssh_args.to_vec(),
+            timestamp: started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration_secs: duration.as_secs(),
+            exit_code,
+        }
+    }
+}
+
+/// Path of the connection history, in the config directory
+pub fn history_path() -> PathBuf {
+    Config::config_dir().join(HISTORY_FILE_NAME)
+}
+
+/// Load the connection history, oldest entry first. Lines that fail to parse are skipped.
+pub fn load() -> Result<Vec<HistoryEntry>> {
+    let path = history_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read history {path:?}"))?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Append an entry to the history, dropping the oldest entries over the limit
+pub fn record(entry: HistoryEntry) -> Result<()> {
+    let mut entries = load()?;
+    entries.push(entry);
+    let skip = entries.len().saturating_sub(MAX_HISTORY);
+
+    let mut contents = String::new();
+    for entry in &entries[skip..] {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+    Config::create_config_dir()?;
+    Config::write_atomic(&history_path(), &contents)
+}
+
+/// The last time the host was connected to, in seconds since the Unix epoch
+pub fn last_used(entries: &[HistoryEntry], host: &str) -> Option<u64> {
+    entries
+        .iter()
+        .rev()
+        .find(|entry| entry.host.as_deref() == Some(host))
+        .map(|entry| entry.timestamp)
+}
+
+/// Describe how long ago a timestamp was, e.g. "5m ago"
+pub fn format_ago(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("{} ago", format_duration(now.saturating_sub(timestamp)))
+}
+
+/// Format a number of seconds using its largest unit, e.g. "3h"
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use color_eyre::{Result, eyre::Context};
+#[cfg(unix)]
+use nix::{sys::signal, unistd::Pid};
+use tracing::debug;
+#[cfg(windows)]
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, STILL_ACTIVE},
+    System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+};
+
+use crate::config::Config;
+
+static INSTANCE_SESSIONS_DIR_NAME: &str = "instance_sessions";
+
+/// Marks a running smssh session to an EC2 instance, so that the last session to close can tell
+/// that no others are left. Removed when dropped.
+pub struct SessionMarker {
+    path: PathBuf,
+}
+
+fn instance_dir(instance_id: &str) -> PathBuf {
+    Config::config_dir()
+        .join(INSTANCE_SESSIONS_DIR_NAME)
+        .join(instance_id)
+}
+
+/// Mark a session of this process to the instance
+pub fn register(instance_id: &str) -> Result<SessionMarker> {
+    let dir = instance_dir(instance_id);
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Failed to create the session directory {dir:?}"))?;
+    let path = dir.join(std::process::id().to_string());
+    std::fs::write(&path, "")
+        .wrap_err_with(|| format!("Failed to mark the session in {path:?}"))?;
+    Ok(SessionMarker { path })
+}
+
+impl SessionMarker {
+    /// Whether other smssh processes still have sessions to the instance. Markers left behind by
+    /// processes that no longer run are removed.
+    pub fn others_active(&self) -> Result<bool> {
+        let Some(dir) = self.path.parent() else {
+            return Ok(false);
+        };
+        let mut active = false;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path == self.path {
+                continue;
+            }
+            let pid = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse().ok());
+            match pid {
+                Some(pid) if is_running(pid) => active = true,
+                _ => {
+                    debug!("Removing the stale session marker {path:?}");
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        Ok(active)
+    }
+}
+
+/// Whether a process with the pid runs, and may be signalled by this process
+#[cfg(unix)]
+fn is_running(pid: i32) -> bool {
+    signal::kill(Pid::from_raw(pid), None).is_ok()
+}
+
+/// Whether a process with the pid runs, and may be queried by this process
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if process.is_null() {
+        return false;
+    }
+    let mut exit_code = 0;
+    let queried = unsafe { GetExitCodeProcess(process, &mut exit_code) } != 0;
+    unsafe { CloseHandle(process) };
+    queried && exit_code == STILL_ACTIVE as u32
+}
+
+impl Drop for SessionMarker {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
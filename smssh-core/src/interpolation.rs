@@ -0,0 +1,46 @@
+use color_eyre::{Result, eyre::eyre};
+
+/// Expand `${VAR}` and `${VAR:-default}` references to environment variables. `$${` produces a
+/// literal `${`.
+pub fn expand(value: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        // Escaped reference
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start]);
+            expanded.push('{');
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        expanded.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let end = reference
+            .find('}')
+            .ok_or_else(|| eyre!("Unterminated variable reference in '{value}'"))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+
+        // Like the shell, the default is also used for empty variables
+        let variable = std::env::var(name)
+            .ok()
+            .filter(|variable| !variable.is_empty() || default.is_none());
+        match (variable, default) {
+            (Some(variable), _) => expanded.push_str(&variable),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => {
+                return Err(eyre!(
+                    "Environment variable '{name}' referenced in '{value}' is not set"
+                ));
+            }
+        }
+        rest = &reference[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
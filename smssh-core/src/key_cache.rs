@@ -0,0 +1,106 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, encryption, fingerprints};
+
+static KEY_CACHE_FILE_NAME: &str = "key_cache.json";
+
+/// The last key fetched from a secret, encrypted with the config passphrase
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedKey {
+    /// Digest of the key, see [`fingerprints::key_digest`]
+    fingerprint: String,
+    /// Unix timestamp of the last fetch that returned this key
+    fetched_at: u64,
+    /// Armored age file holding the key
+    key: String,
+}
+
+fn key_cache_path() -> PathBuf {
+    Config::config_dir().join(KEY_CACHE_FILE_NAME)
+}
+
+/// Remove all cached keys
+pub fn clear() -> Result<()> {
+    let path = key_cache_path();
+    if path.exists() {
+        std::fs::remove_file(&path)
+            .wrap_err_with(|| format!("Failed to remove the key cache {path:?}"))?;
+    }
+    Ok(())
+}
+
+fn load_all() -> Result<BTreeMap<String, CachedKey>> {
+    let path = key_cache_path();
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read the key cache {path:?}"))?;
+    serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Failed to parse the key cache {path:?}"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Cache a freshly fetched key. The key is only encrypted again when it changed, otherwise just
+/// its fetch time is updated, since encrypting with a passphrase is deliberately slow.
+pub fn store(secret_arn: &str, key: &SecretString, passphrase: &SecretString) -> Result<()> {
+    let mut cache = load_all()?;
+    let fingerprint = fingerprints::key_digest(key);
+    match cache.get_mut(secret_arn) {
+        Some(cached) if cached.fingerprint == fingerprint => cached.fetched_at = now(),
+        _ => {
+            let cached = CachedKey {
+                fingerprint,
+                fetched_at: now(),
+                key: encryption::encrypt(key.expose_secret(), passphrase)?,
+            };
+            cache.insert(secret_arn.to_string(), cached);
+        }
+    }
+    Config::create_config_dir()?;
+    Config::write_atomic(&key_cache_path(), &serde_json::to_string_pretty(&cache)?)
+}
+
+/// Load the cached key of a secret if it was fetched within `max_age`, returning it with its age
+pub fn load(
+    secret_arn: &str,
+    max_age: Duration,
+    passphrase: &SecretString,
+) -> Result<(SecretString, Duration)> {
+    let cache = load_all()?;
+    let cached = cache
+        .get(secret_arn)
+        .ok_or_else(|| eyre!("No cached copy of the key"))?;
+    let age = Duration::from_secs(now().saturating_sub(cached.fetched_at));
+    if age > max_age {
+        return Err(eyre!(
+            "The cached copy of the key is older than {}",
+            crate::history::format_duration(max_age.as_secs())
+        ));
+    }
+    let key = encryption::into_secret(
+        encryption::decrypt(&cached.key, passphrase)
+            .wrap_err("Failed to decrypt the cached key")?,
+    );
+    if fingerprints::key_digest(&key) != cached.fingerprint {
+        return Err(eyre!("The cached key does not match its fingerprint"));
+    }
+    Ok((key, age))
+}
@@ -0,0 +1,92 @@
+//! The config model, key providers and connection orchestration behind the `smssh` CLI.
+//!
+//! Load the config with [`config::Config::load`], resolve a host with
+//! [`config::Config::resolve_host`] or its key alias with [`config::Config::host_key_alias`] and
+//! fetch the key on your own runtime with [`provider::fetch_key`], or connect through
+//! [`connect::connect_by_host`] to run ssh the way `smssh connect` does. Other secret stores can
+//! be added by implementing [`provider::KeyProvider`].
+
+#![warn(missing_docs)]
+
+/// An ssh agent fetching the keys of key aliases when they are first used
+pub mod agent;
+/// Exporting the hosts as an Ansible inventory
+pub mod ansible;
+/// Audit records of key fetches and connections
+pub mod audit;
+/// Adding and removing public keys in the authorized_keys of hosts
+pub mod authorized_keys;
+/// Secrets Manager, S3 and EC2 calls, with blocking variants for the CLI
+pub mod aws;
+/// Running unattended from pipelines and cron
+pub mod batch;
+/// The config file, its hosts, templates, groups and key aliases
+pub mod config;
+/// Fetching keys and running ssh with them
+pub mod connect;
+/// Non-interactive logins checking each step of a connection
+pub mod connection_test;
+/// Links to the AWS console pages of EC2 instances
+pub mod console;
+/// The background process that keeps credentials and keys warm, and its client
+pub mod daemon;
+/// Looking up RDS and ElastiCache endpoints for the database forwards
+pub mod database;
+/// Durations like "1h30m" used in the config
+pub mod duration;
+/// Passphrase encryption of the config and the offline key cache
+pub mod encryption;
+/// The kinds of failures with their exit statuses
+pub mod error;
+/// Fingerprints of the keys last seen per key alias
+pub mod fingerprints;
+/// The connection history
+pub mod history;
+/// Markers of the sessions open to EC2 instances
+pub mod instance_sessions;
+/// Environment variable expansion in config values
+pub mod interpolation;
+/// Encrypted copies of keys used when AWS is unreachable
+pub mod key_cache;
+/// Normalization and validation of fetched private keys
+pub mod key_format;
+/// Generating new keypairs
+pub mod keygen;
+/// Prometheus metrics of the daemon and the agent
+pub mod metrics;
+/// Upgrades of older config versions
+pub mod migrations;
+/// Checking that the networks required by hosts are connected
+pub mod network;
+/// Desktop notifications about dropped sessions
+pub mod notification;
+/// File modes keeping the files of smssh private to the user
+pub mod permissions;
+/// External key providers speaking JSON over stdin and stdout
+pub mod plugin;
+/// Spinner for long running operations
+pub mod progress;
+/// Interactive questions on the terminal
+pub mod prompt;
+/// Key providers behind an async API, pluggable through the `KeyProvider` trait
+pub mod provider;
+/// Probing the ssh port of a host
+pub mod reachability;
+/// Session recordings
+pub mod recording;
+/// Looking up hostnames through AWS for names the local DNS can't resolve
+pub mod resolver;
+/// Second factors verified before fetching keys
+pub mod second_factor;
+/// Parsing the hosts of OpenSSH config files
+pub mod ssh_config;
+/// Fetching the shared config
+pub mod sync;
+/// Socket activation and unit files of systemd
+pub mod systemd;
+/// Copying files to and from hosts through ssh
+pub mod transfer;
+/// Tunnels to EC2 instances through SSM and EC2 Instance Connect Endpoints
+pub mod transport;
+/// Approvals of the shell commands set by the shared and system configs
+pub mod trusted_commands;
@@ -0,0 +1,193 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use color_eyre::{Result, eyre::Context};
+use tracing::{info, warn};
+
+/// Upper bounds of the buckets of the fetch durations, in seconds
+const FETCH_DURATION_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// How long a scraper gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters of the key fetches of a long running smssh process, rendered in the Prometheus text
+/// format
+pub struct Metrics {
+    /// Name and help of the gauge of the sessions the process holds
+    sessions_gauge: (&'static str, &'static str),
+    sessions: AtomicI64,
+    /// Counted apart, so that a scrape between the two increments of a fetch can't see more
+    /// errors than fetches
+    fetch_successes: AtomicU64,
+    fetch_errors: AtomicU64,
+    cache_hits: AtomicU64,
+    fetch_duration_buckets: [AtomicU64; FETCH_DURATION_BUCKETS.len()],
+    fetch_duration_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Metrics with a gauge of the sessions the process holds, with the given name and help
+    pub fn new(sessions_name: &'static str, sessions_help: &'static str) -> Self {
+        Self {
+            sessions_gauge: (sessions_name, sessions_help),
+            sessions: AtomicI64::new(0),
+            fetch_successes: AtomicU64::new(0),
+            fetch_errors: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            fetch_duration_buckets: Default::default(),
+            fetch_duration_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a key fetched from its provider, or failing to be fetched
+    pub fn fetched(&self, elapsed: Duration, succeeded: bool) {
+        if succeeded {
+            self.fetch_successes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.fetch_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self
+            .fetch_duration_buckets
+            .iter()
+            .zip(FETCH_DURATION_BUCKETS)
+        {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.fetch_duration_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Record a key served from the cache instead of its provider
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set the number of sessions the process holds
+    pub fn set_sessions(&self, sessions: usize) {
+        self.sessions.store(sessions as i64, Ordering::Relaxed);
+    }
+
+    /// Add to or subtract from the number of sessions the process holds
+    pub fn adjust_sessions(&self, delta: i64) {
+        self.sessions.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let successes = self.fetch_successes.load(Ordering::Relaxed);
+        let errors = self.fetch_errors.load(Ordering::Relaxed);
+        let fetches = successes + errors;
+        let (sessions_name, sessions_help) = self.sessions_gauge;
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP smssh_key_fetches_total Keys fetched from their provider, by result"
+        );
+        let _ = writeln!(out, "# TYPE smssh_key_fetches_total counter");
+        let _ = writeln!(out, "smssh_key_fetches_total{{result=\"ok\"}} {successes}");
+        let _ = writeln!(out, "smssh_key_fetches_total{{result=\"error\"}} {errors}");
+        let _ = writeln!(
+            out,
+            "# HELP smssh_key_cache_hits_total Keys served without fetching them"
+        );
+        let _ = writeln!(out, "# TYPE smssh_key_cache_hits_total counter");
+        let _ = writeln!(
+            out,
+            "smssh_key_cache_hits_total {}",
+            self.cache_hits.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP smssh_key_fetch_duration_seconds How long fetching a key from its provider took"
+        );
+        let _ = writeln!(out, "# TYPE smssh_key_fetch_duration_seconds histogram");
+        for (bucket, bound) in self
+            .fetch_duration_buckets
+            .iter()
+            .zip(FETCH_DURATION_BUCKETS)
+        {
+            let _ = writeln!(
+                out,
+                "smssh_key_fetch_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "smssh_key_fetch_duration_seconds_bucket{{le=\"+Inf\"}} {fetches}"
+        );
+        let _ = writeln!(
+            out,
+            "smssh_key_fetch_duration_seconds_sum {}",
+            self.fetch_duration_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "smssh_key_fetch_duration_seconds_count {fetches}");
+        let _ = writeln!(out, "# HELP {sessions_name} {sessions_help}");
+        let _ = writeln!(out, "# TYPE {sessions_name} gauge");
+        let _ = writeln!(
+            out,
+            "{sessions_name} {}",
+            self.sessions.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+/// Answer a scrape, with the metrics on `/metrics` and 404 elsewhere
+fn answer(stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are not needed, but are read so that the scraper is not cut off mid-request
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", metrics.render())
+    } else {
+        (
+            "404 Not Found",
+            "Metrics are served on /metrics\n".to_string(),
+        )
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// Serve the metrics over HTTP on an address like "127.0.0.1:9464" from a background thread
+pub fn serve(address: &str, metrics: Arc<Metrics>) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .wrap_err_with(|| format!("Failed to serve metrics on {address}"))?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(Into::into)
+                .and_then(|stream| answer(stream, &metrics));
+            if let Err(e) = result {
+                warn!("Failed to answer a metrics scrape: {e}");
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use color_eyre::{Result, eyre::eyre};
+use serde_yml::Value;
+
+/// Version of the config schema written by this build
+pub const CURRENT_VERSION: u64 = 1;
+
+/// A migration upgrades a config from the version at its index to the next version
+type Migration = fn(&mut Value) -> Result<()>;
+
+static MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: Configs written before versioning was introduced, the schema is unchanged
+    |_| Ok(()),
+];
+
+/// Upgrade a config to the current schema version. Returns the original version if any
+/// migrations were applied.
+pub fn migrate(config: &mut Value) -> Result<Option<u64>> {
+    let mapping = config
+        .as_mapping_mut()
+        .ok_or_else(|| eyre!("The config is not a YAML mapping"))?;
+    let version = match mapping.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| eyre!("The config version {version:?} is not a number"))?,
+        None => 0,
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(eyre!(
+            "The config version {version} is newer than the supported version {CURRENT_VERSION}, please upgrade smssh"
+        ));
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(config)
+            .map_err(|e| eyre!("Failed to migrate the config from version {from_version}: {e}"))?;
+    }
+
+    let mapping = config
+        .as_mapping_mut()
+        .ok_or_else(|| eyre!("The config is not a YAML mapping"))?;
+    mapping.insert("version".into(), CURRENT_VERSION.into());
+    Ok(Some(version))
+}
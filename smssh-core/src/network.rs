@@ -0,0 +1,129 @@
+#[cfg(not(target_os = "linux"))]
+use std::process::Command;
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use tracing::{debug, info};
+
+use crate::{
+    config::{Config, NetworkConfig},
+    connect,
+    progress::Progress,
+    trusted_commands,
+};
+
+/// How long to wait for the probe of a network to accept the connection
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a network may take to connect after its up command
+const UP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Delay between the checks of a network that is coming up
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether the interface exists and is not down. Tunnels report an unknown state while up.
+#[cfg(target_os = "linux")]
+fn interface_up(interface: &str) -> bool {
+    std::fs::read_to_string(format!("/sys/class/net/{interface}/operstate"))
+        .is_ok_and(|state| state.trim() != "down")
+}
+
+/// Whether the interface exists and has the UP flag
+#[cfg(all(unix, not(target_os = "linux")))]
+fn interface_up(interface: &str) -> bool {
+    Command::new("ifconfig")
+        .arg(interface)
+        .output()
+        .is_ok_and(|output| {
+            output.status.success() && String::from_utf8_lossy(&output.stdout).contains("<UP")
+        })
+}
+
+/// Whether the adapter exists and is up, asking PowerShell as netsh prints localized states
+#[cfg(windows)]
+fn interface_up(interface: &str) -> bool {
+    let name = interface.replace('\'', "''");
+    Command::new("powershell")
+        .arg("-NoProfile")
+        .arg("-Command")
+        .arg(format!("(Get-NetAdapter -Name '{name}').Status"))
+        .output()
+        .is_ok_and(|output| {
+            output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "Up"
+        })
+}
+
+/// Whether the probe address accepts TCP connections on any of its addresses
+fn probe_reachable(probe: &str) -> bool {
+    let Ok(addresses) = probe.to_socket_addrs() else {
+        return false;
+    };
+    addresses
+        .into_iter()
+        .any(|address| TcpStream::connect_timeout(&address, PROBE_TIMEOUT).is_ok())
+}
+
+/// Whether the network is connected, all of its configured checks have to pass
+pub fn is_connected(network: &NetworkConfig) -> bool {
+    if let Some(interface) = &network.interface
+        && !interface_up(interface)
+    {
+        debug!("Interface {interface} is not up");
+        return false;
+    }
+    if let Some(probe) = &network.probe
+        && !probe_reachable(probe)
+    {
+        debug!("{probe} is not reachable");
+        return false;
+    }
+    true
+}
+
+/// Make sure the network required by a host is connected, running its up command and waiting for
+/// it to connect if it is not. An up command only the shared or system config sets has to be
+/// approved first.
+pub fn ensure_connected(
+    config: &Config,
+    name: &str,
+    network: &NetworkConfig,
+    host: &str,
+) -> Result<()> {
+    if is_connected(network) {
+        return Ok(());
+    }
+    let Some(up) = &network.up else {
+        return Err(eyre!(
+            "Network '{name}' required by '{host}' is not connected, connect it and try again"
+        ));
+    };
+    trusted_commands::ensure_trusted(config, &format!("up command of network '{name}'"), up)?;
+
+    info!("Network '{name}' is not connected, bringing it up");
+    let status = connect::local_shell(up)
+        .status()
+        .wrap_err_with(|| format!("Failed to run the up command of network '{name}'"))?;
+    if !status.success() {
+        return Err(eyre!(
+            "The up command of network '{name}' failed with {status}"
+        ));
+    }
+
+    let progress = Progress::start(&format!("Waiting for network '{name}'"));
+    let started = Instant::now();
+    while !is_connected(network) {
+        if started.elapsed() >= UP_TIMEOUT {
+            return Err(eyre!(
+                "Network '{name}' did not connect within {UP_TIMEOUT:?} of its up command"
+            ));
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    }
+    progress.finish();
+    info!("Network '{name}' is connected");
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::process::{Command, Stdio};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+
+/// Show a desktop notification with `osascript`
+#[cfg(target_os = "macos")]
+fn notification_command(summary: &str, body: &str) -> Command {
+    // AppleScript strings only need quotes and backslashes escaped
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification \"{}\" with title \"{}\"",
+        escape(body),
+        escape(summary)
+    ));
+    command
+}
+
+/// Show a desktop notification with `notify-send` from libnotify
+#[cfg(not(target_os = "macos"))]
+fn notification_command(summary: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name", "smssh", summary, body]);
+    command
+}
+
+/// Show a desktop notification, failing if there is no notification tool or desktop session
+pub fn send(summary: &str, body: &str) -> Result<()> {
+    let output = notification_command(summary, body)
+        .stdin(Stdio::null())
+        .output()
+        .wrap_err("Failed to run the desktop notification command")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!(
+            "Failed to show a desktop notification: {}",
+            stderr.trim()
+        ));
+    }
+    Ok(())
+}
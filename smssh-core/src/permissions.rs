@@ -0,0 +1,62 @@
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+use std::{
+    fs::{File, Metadata, OpenOptions},
+    io,
+    path::Path,
+};
+
+// Windows has no modes. The files of smssh live in the profile of the user there, whose ACL
+// already grants access only to the user, the administrators and SYSTEM, and every file created
+// in it inherits that ACL. The helpers are no-ops on Windows for that reason.
+
+/// Set the mode of a file or directory
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    std::fs::set_permissions(path, Permissions::from_mode(mode))
+}
+
+/// Set the mode of a file or directory
+#[cfg(windows)]
+pub fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Set the mode of an open file
+#[cfg(unix)]
+pub fn set_file_mode(file: &File, mode: u32) -> io::Result<()> {
+    file.set_permissions(Permissions::from_mode(mode))
+}
+
+/// Set the mode of an open file
+#[cfg(windows)]
+pub fn set_file_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Create files with `mode` when opening them with `options`
+#[cfg(unix)]
+pub fn create_with_mode(options: &mut OpenOptions, mode: u32) -> &mut OpenOptions {
+    options.mode(mode)
+}
+
+/// Create files with `mode` when opening them with `options`
+#[cfg(windows)]
+pub fn create_with_mode(options: &mut OpenOptions, _mode: u32) -> &mut OpenOptions {
+    options
+}
+
+/// The permission bits of a file, `None` where files have no modes
+#[cfg(unix)]
+pub fn mode(metadata: &Metadata) -> Option<u32> {
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+/// The permission bits of a file, `None` where files have no modes
+#[cfg(windows)]
+pub fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, Read, Write},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{encryption, error::Error, history};
+
+/// Prefix of the executables of plugins, followed by the plugin name
+pub static EXECUTABLE_PREFIX: &str = "smssh-provider-";
+const PROTOCOL_VERSION: u32 = 1;
+/// How often a plugin with a timeout is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Serialize)]
+struct Request<'a> {
+    version: u32,
+    action: &'static str,
+    key_alias: &'a str,
+    params: &'a BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Key(String),
+    Error(String),
+}
+
+/// Check that a plugin name is only letters, digits, `_` and `-`, so that it can't point the
+/// executable lookup at a path
+pub fn validate_name(name: &str) -> Result<()> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !is_valid {
+        return Err(Error::Config(format!(
+            "Invalid plugin name '{name}', only letters, digits, '_' and '-' are allowed"
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Fetch the key of an alias through a plugin, killing the plugin if it runs longer than `timeout`.
+///
+/// The plugin gets a single line of JSON on its stdin, like
+/// `{"version":1,"action":"get_key","key_alias":"prod","params":{"path":"secret/prod"}}`, and
+/// answers with `{"key":"<private key>"}` or `{"error":"<message>"}` on its stdout. Its stderr is
+/// shown to the user.
+pub fn get_key(
+    name: &str,
+    key_alias: &str,
+    params: &BTreeMap<String, String>,
+    timeout: Option<Duration>,
+) -> Result<SecretString> {
+    validate_name(name)?;
+    let executable = format!("{EXECUTABLE_PREFIX}{name}");
+    let mut child = Command::new(&executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => {
+                eyre!("Plugin '{name}' is not installed, {executable} is not in the PATH")
+            }
+            _ => eyre!("Failed to run plugin '{name}': {e}"),
+        })?;
+
+    let request = Request {
+        version: PROTOCOL_VERSION,
+        action: "get_key",
+        key_alias,
+        params,
+    };
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // A plugin that doesn't need the request can exit before reading it
+    if let Err(e) = writeln!(stdin, "{}", serde_json::to_string(&request)?)
+        && e.kind() != ErrorKind::BrokenPipe
+    {
+        return Err(e).wrap_err_with(|| format!("Failed to send the request to plugin '{name}'"));
+    }
+    drop(stdin);
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = Zeroizing::new(String::new());
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(timeout) = timeout
+            && started.elapsed() >= timeout
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(eyre!(
+                "Plugin '{name}' did not return the key within {}",
+                history::format_duration(timeout.as_secs())
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let output = reader
+        .join()
+        .map_err(|_| eyre!("Failed to read the output of plugin '{name}'"))?
+        .wrap_err_with(|| format!("Failed to read the output of plugin '{name}'"))?;
+
+    let response = serde_json::from_str(&output);
+    match response {
+        Ok(Response::Key(key)) => Ok(encryption::into_secret(key)),
+        Ok(Response::Error(e)) => Err(eyre!("Plugin '{name}' failed: {e}")),
+        Err(_) if !status.success() => Err(eyre!("Plugin '{name}' failed with {status}")),
+        Err(e) => Err(eyre!("Invalid response from plugin '{name}': {e}")),
+    }
+}
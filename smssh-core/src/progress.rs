@@ -0,0 +1,125 @@
+use std::{
+    io::{IsTerminal, Write, stderr, stdout},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveToColumn,
+    terminal::{Clear, ClearType},
+};
+use tracing::{Level, info};
+
+use crate::batch;
+
+static SPINNER_FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+static SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reports the phases of a long running operation. Draws a spinner with the current phase and the
+/// elapsed time on stderr when running in a terminal, otherwise logs each phase as plain text. In
+/// batch mode, each phase and the end are written as tab separated lines for scripts instead, like
+/// `progress<TAB>1.2<TAB>Fetching the key` with the elapsed seconds.
+pub struct Progress {
+    started: Instant,
+    phase: Arc<Mutex<String>>,
+    done: Arc<AtomicBool>,
+    spinner: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Show that a phase started, with a spinner on terminals or a line in batch mode
+    pub fn start(phase: &str) -> Self {
+        let mut progress = Self {
+            started: Instant::now(),
+            phase: Arc::new(Mutex::new(phase.to_string())),
+            done: Arc::new(AtomicBool::new(false)),
+            spinner: None,
+        };
+
+        // The spinner is informational output, so it is hidden along with it by `--quiet`
+        let interactive = stdout().is_terminal() && stderr().is_terminal();
+        if batch::enabled() {
+            progress.report(phase);
+        } else if interactive && tracing::enabled!(Level::INFO) {
+            progress.spinner = Some(progress.spawn_spinner());
+        } else {
+            info!("{phase}");
+        }
+        progress
+    }
+
+    /// Write a progress line for scripts, hidden by `--quiet` like the rest of the progress
+    fn report(&self, phase: &str) {
+        if tracing::enabled!(Level::INFO) {
+            eprintln!(
+                "progress\t{:.1}\t{phase}",
+                self.started.elapsed().as_secs_f32()
+            );
+        }
+    }
+
+    fn spawn_spinner(&self) -> JoinHandle<()> {
+        let started = self.started;
+        let phase = self.phase.clone();
+        let done = self.done.clone();
+        std::thread::spawn(move || {
+            let mut stderr = stderr();
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                let phase = phase.lock().map(|phase| phase.clone()).unwrap_or_default();
+                let elapsed = started.elapsed().as_secs_f32();
+                let _ = stderr
+                    .queue(MoveToColumn(0))
+                    .and_then(|stderr| stderr.queue(Clear(ClearType::CurrentLine)));
+                let _ = write!(stderr, "{frame} {phase} ({elapsed:.1}s)");
+                let _ = stderr.flush();
+                std::thread::sleep(SPINNER_INTERVAL);
+            }
+            let _ = stderr
+                .queue(MoveToColumn(0))
+                .and_then(|stderr| stderr.queue(Clear(ClearType::CurrentLine)));
+            let _ = stderr.flush();
+        })
+    }
+
+    /// Move on to the next phase
+    pub fn phase(&self, phase: &str) {
+        if batch::enabled() {
+            self.report(phase);
+        } else if self.spinner.is_none() {
+            info!("{phase}");
+        }
+        if let Ok(mut current) = self.phase.lock() {
+            *current = phase.to_string();
+        }
+    }
+
+    /// Stop the spinner and clear its line
+    pub fn finish(mut self) {
+        if batch::enabled() {
+            self.report("done");
+        }
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(spinner) = self.spinner.take() {
+            let _ = spinner.join();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Clear the spinner if the operation failed part way through
+        self.stop();
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+use color_eyre::{Report, Result, eyre::eyre};
+
+use crate::batch;
+
+/// A question that could not be answered, or was answered with no
+#[derive(Debug)]
+pub enum PromptError {
+    /// The question needs a terminal and batch mode to be off
+    Unavailable(String),
+    /// The user declined
+    Declined(String),
+}
+
+impl PromptError {
+    /// The prompt error behind a report, if it has one
+    pub fn of(report: &Report) -> Option<&Self> {
+        report.chain().find_map(|error| error.downcast_ref())
+    }
+}
+
+impl Display for PromptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(message) | Self::Declined(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl StdError for PromptError {}
+
+/// Whether questions can be asked, which needs a terminal and batch mode to be off
+pub fn can_ask() -> bool {
+    !batch::enabled() && io::stdin().is_terminal()
+}
+
+fn ensure_not_batch(question: &str) -> Result<()> {
+    if batch::enabled() {
+        return Err(
+            PromptError::Unavailable(format!("Cannot ask '{question}' in batch mode")).into(),
+        );
+    }
+    Ok(())
+}
+
+/// Ask a question on stdout and read a line from stdin. An empty answer returns the default, if
+/// there is one.
+pub fn input(question: &str, default: Option<&str>) -> Result<String> {
+    ensure_not_batch(question)?;
+    loop {
+        match default {
+            Some(default) if !default.is_empty() => print!("{question} [{default}]: "),
+            _ => print!("{question}: "),
+        }
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err(eyre!("No input received"));
+        }
+        let answer = answer.trim();
+
+        match (answer.is_empty(), default) {
+            (false, _) => return Ok(answer.to_string()),
+            (true, Some(default)) => return Ok(default.to_string()),
+            (true, None) => println!("An answer is required"),
+        }
+    }
+}
+
+/// Ask a yes/no question.
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        let answer = input(&format!("{question} ({hint})"), Some(""))?;
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer 'y' or 'n'"),
+        }
+    }
+}
+
+/// Print a numbered list of options and let the user pick one of them by number. Returns `None`
+/// if the user skipped the selection with an empty answer.
+pub fn select(question: &str, options: &[String]) -> Result<Option<usize>> {
+    ensure_not_batch(question)?;
+    for (index, option) in options.iter().enumerate() {
+        println!("  {}) {option}", index + 1);
+    }
+    loop {
+        let answer = input(question, Some(""))?;
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=options.len()).contains(&number) => return Ok(Some(number - 1)),
+            _ => println!("Please enter a number between 1 and {}", options.len()),
+        }
+    }
+}
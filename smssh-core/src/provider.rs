@@ -0,0 +1,139 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use color_eyre::{Report, Result, eyre::eyre};
+use secrecy::SecretString;
+
+use crate::{
+    audit::{self, AuditRecord},
+    aws,
+    config::{Config, KeyAliasConfig},
+    history, key_format, keygen, plugin,
+};
+
+/// A backend fetching the private keys of key aliases. Providers can be chained as tuples,
+/// `(MyProvider, BuiltinProvider::default())` tries `MyProvider` first.
+pub trait KeyProvider {
+    /// Fetch the key of an alias, `None` if this provider does not handle the kind of the alias
+    fn fetch_key(
+        &self,
+        key_alias: &str,
+        alias: &KeyAliasConfig,
+    ) -> impl Future<Output = Result<Option<SecretString>>> + Send;
+}
+
+/// Fetches `SecretsManager` aliases, resolving the credentials of their profile on first use.
+/// Keys with a passphrase secret are decrypted on a blocking thread.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SecretsManagerProvider;
+
+impl KeyProvider for SecretsManagerProvider {
+    async fn fetch_key(
+        &self,
+        _key_alias: &str,
+        alias: &KeyAliasConfig,
+    ) -> Result<Option<SecretString>> {
+        match alias {
+            KeyAliasConfig::SecretsManager {
+                secret_arn,
+                profile,
+                passphrase_secret_arn,
+                ..
+            } => {
+                let key = aws::get_key(secret_arn, profile.as_deref()).await?;
+                let Some(passphrase_secret_arn) = passphrase_secret_arn else {
+                    return Ok(Some(key));
+                };
+                let passphrase = aws::get_key(passphrase_secret_arn, profile.as_deref()).await?;
+                let key = tokio::task::spawn_blocking(move || keygen::decrypt(&key, &passphrase))
+                    .await??;
+                Ok(Some(key))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Fetches `Plugin` aliases by running their plugin on a blocking thread
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PluginProvider {
+    /// Plugins running longer than this are killed
+    pub timeout: Option<Duration>,
+}
+
+impl KeyProvider for PluginProvider {
+    async fn fetch_key(
+        &self,
+        key_alias: &str,
+        alias: &KeyAliasConfig,
+    ) -> Result<Option<SecretString>> {
+        let KeyAliasConfig::Plugin { name, params } = alias else {
+            return Ok(None);
+        };
+        let (name, key_alias, params): (String, String, BTreeMap<_, _>) =
+            (name.clone(), key_alias.to_string(), params.clone());
+        let timeout = self.timeout;
+        let key = tokio::task::spawn_blocking(move || {
+            plugin::get_key(&name, &key_alias, &params, timeout)
+        })
+        .await??;
+        Ok(Some(key))
+    }
+}
+
+/// All the providers built into smssh
+pub type BuiltinProvider = (SecretsManagerProvider, PluginProvider);
+
+impl<A: KeyProvider + Sync, B: KeyProvider + Sync> KeyProvider for (A, B) {
+    async fn fetch_key(
+        &self,
+        key_alias: &str,
+        alias: &KeyAliasConfig,
+    ) -> Result<Option<SecretString>> {
+        match self.0.fetch_key(key_alias, alias).await? {
+            Some(key) => Ok(Some(key)),
+            None => self.1.fetch_key(key_alias, alias).await,
+        }
+    }
+}
+
+/// Fetch the key of an alias of the config with the built-in providers, see [`fetch_key_with`].
+pub async fn fetch_key(config: &Config, key_alias: &str) -> Result<SecretString> {
+    fetch_key_with(&BuiltinProvider::default(), config, key_alias).await
+}
+
+/// Fetch the key of an alias of the config with `provider`, expanding the environment variables
+/// of the alias first and normalizing the key like `smssh connect` does, within the fetch timeout
+/// of the alias. The fetch is written to the audit log of the config, and the key is not returned
+/// if that fails. Does not use the daemon or the offline cache.
+pub async fn fetch_key_with(
+    provider: &impl KeyProvider,
+    config: &Config,
+    key_alias: &str,
+) -> Result<SecretString> {
+    let alias = config.key_alias(key_alias)?;
+    let fetch = async {
+        provider
+            .fetch_key(key_alias, &alias)
+            .await?
+            .ok_or_else(|| eyre!("No provider handles key alias '{key_alias}'"))
+    };
+    let key = match config.fetch_timeout(&alias)? {
+        Some(timeout) => tokio::time::timeout(timeout, fetch)
+            .await
+            .map_err(|elapsed| {
+                Report::new(elapsed).wrap_err(format!(
+                    "Could not fetch the key of '{key_alias}' within {}",
+                    history::format_duration(timeout.as_secs())
+                ))
+            })
+            .and_then(|key| key),
+        None => fetch.await,
+    };
+    if let Some(audit_log) = &config.audit_log {
+        let source = config.key_source(key_alias)?;
+        let mut record = AuditRecord::new("fetch", None, None, key_alias, source.into());
+        record.error = key.as_ref().err().map(|e| e.to_string());
+        audit::record(audit_log, &record)?;
+    }
+    key_format::normalize(&key?)
+}
@@ -0,0 +1,204 @@
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use tracing::debug;
+
+use crate::{
+    config::{KnockConfig, KnockProtocol},
+    ssh_config,
+};
+
+/// How long to wait for the destination to accept the probe connection
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a TCP knock waits for the SYN to go out, knocked ports don't answer
+const KNOCK_TIMEOUT: Duration = Duration::from_millis(100);
+/// Delay after knocks without their own, so that they arrive in order
+const DEFAULT_KNOCK_DELAY: Duration = Duration::from_millis(50);
+/// Delay between the probes of a host that is still booting
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where ssh would connect to, as resolved by `ssh -G` from the ssh config and the args
+struct Target {
+    hostname: String,
+    port: u16,
+    /// Connections go through a jump host or a proxy command, so the host can't be probed directly
+    proxied: bool,
+}
+
+fn resolve_target(destination: Option<&str>, ssh_args: &[String]) -> Result<Target> {
+    let mut hostname = None;
+    let mut port = 22;
+    let mut proxied = false;
+    for (option, value) in ssh_config::effective_options(destination, ssh_args)? {
+        match option.as_str() {
+            "hostname" => hostname = Some(value),
+            "port" => port = value.parse()?,
+            "proxyjump" | "proxycommand" if value != "none" => proxied = true,
+            _ => {}
+        }
+    }
+    Ok(Target {
+        hostname: hostname.ok_or(eyre!("`ssh -G` did not print the hostname"))?,
+        port,
+        proxied,
+    })
+}
+
+/// Probe the TCP port ssh would connect to, failing with a message that tells a DNS failure, a
+/// closed port and an unreachable host apart
+pub fn check(destination: Option<&str>, ssh_args: &[String]) -> Result<()> {
+    let Target {
+        hostname,
+        port,
+        proxied,
+    } = resolve_target(destination, ssh_args)?;
+    if proxied {
+        debug!("Skipping the reachability check of '{hostname}', it is reached through a proxy");
+        return Ok(());
+    }
+
+    let addresses: Vec<_> = (hostname.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| eyre!("DNS failure: could not resolve '{hostname}': {e}"))?
+        .collect();
+    let mut last_error = None;
+    for address in &addresses {
+        match TcpStream::connect_timeout(address, PROBE_TIMEOUT) {
+            Ok(_) => {
+                debug!("'{hostname}' accepts connections on {address}");
+                return Ok(());
+            }
+            Err(e) => last_error = Some((address, e)),
+        }
+    }
+
+    let Some((address, e)) = last_error else {
+        return Err(eyre!("DNS failure: '{hostname}' has no addresses"));
+    };
+    Err(match e.kind() {
+        ErrorKind::ConnectionRefused => {
+            eyre!("Port closed: '{hostname}' ({address}) refused the connection on port {port}")
+        }
+        ErrorKind::TimedOut => eyre!(
+            "Host unreachable: '{hostname}' ({address}) did not answer on port {port} within {PROBE_TIMEOUT:?}"
+        ),
+        _ => eyre!("Host unreachable: '{hostname}' ({address}): {e}"),
+    })
+}
+
+/// Fetch the host keys a host offers with ssh-keyscan, in the known_hosts format. Hosts reached
+/// through a jump host or a proxy command can't be scanned directly.
+pub fn scan_host_keys(destination: &str, ssh_args: &[String]) -> Result<String> {
+    let Target {
+        hostname,
+        port,
+        proxied,
+    } = resolve_target(Some(destination), ssh_args)?;
+    if proxied {
+        return Err(eyre!(
+            "'{hostname}' is reached through a proxy, its host keys can't be scanned directly"
+        ));
+    }
+    let output = Command::new("ssh-keyscan")
+        .arg("-T")
+        .arg(PROBE_TIMEOUT.as_secs().to_string())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg(&hostname)
+        .stdin(Stdio::null())
+        .output()
+        .wrap_err("Failed to run ssh-keyscan")?;
+    let keys = String::from_utf8(output.stdout)?;
+    if keys.trim().is_empty() {
+        // ssh-keyscan only explains some failures, like DNS errors
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.trim() {
+            "" => String::new(),
+            reason => format!(": {reason}"),
+        };
+        return Err(eyre!(
+            "'{hostname}' did not offer any host keys on port {port}{reason}"
+        ));
+    }
+    Ok(keys)
+}
+
+/// Probe the ssh port until it accepts connections, like after starting the host
+pub fn wait_until_reachable(
+    destination: Option<&str>,
+    ssh_args: &[String],
+    timeout: Duration,
+) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        match check(destination, ssh_args) {
+            Ok(()) => return Ok(()),
+            Err(e) if started.elapsed() >= timeout => {
+                return Err(e.wrap_err(format!(
+                    "The host did not accept connections within {timeout:?}"
+                )));
+            }
+            Err(e) => debug!("Waiting for the host to accept connections: {e}"),
+        }
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+/// Knock on a sequence of ports of the host ssh would connect to, opening it on a port knocking
+/// daemon like knockd. The knocked ports are closed, so TCP knocks only send their SYN and UDP
+/// knocks an empty datagram.
+pub fn knock(
+    destination: Option<&str>,
+    ssh_args: &[String],
+    sequence: &[KnockConfig],
+) -> Result<()> {
+    let Target {
+        hostname, proxied, ..
+    } = resolve_target(destination, ssh_args)?;
+    if proxied {
+        return Err(eyre!(
+            "'{hostname}' is reached through a proxy, its ports can't be knocked on directly"
+        ));
+    }
+    // All knocks have to come from the same address, so they go to the first one resolved
+    let address = (hostname.as_str(), 0)
+        .to_socket_addrs()
+        .map_err(|e| eyre!("DNS failure: could not resolve '{hostname}': {e}"))?
+        .next()
+        .ok_or(eyre!("DNS failure: '{hostname}' has no addresses"))?;
+
+    for knock in sequence {
+        let target = SocketAddr::new(address.ip(), knock.port);
+        debug!("Knocking on {target} over {:?}", knock.protocol);
+        match knock.protocol {
+            KnockProtocol::Tcp => {
+                let _ = TcpStream::connect_timeout(&target, KNOCK_TIMEOUT);
+            }
+            KnockProtocol::Udp => {
+                let local: SocketAddr = if target.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                UdpSocket::bind(local)
+                    .and_then(|socket| socket.send_to(&[], target))
+                    .wrap_err_with(|| format!("Failed to knock on {target}/udp"))?;
+            }
+        }
+        std::thread::sleep(
+            knock
+                .delay_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_KNOCK_DELAY),
+        );
+    }
+    Ok(())
+}
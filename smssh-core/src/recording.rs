@@ -0,0 +1,304 @@
+#[cfg(unix)]
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, IsTerminal, Read, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::{fs::OpenOptionsExt, process::CommandExt},
+    },
+    process::{Child, Stdio},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, atomic::AtomicBool},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::Result;
+#[cfg(unix)]
+use color_eyre::eyre::Context;
+#[cfg(windows)]
+use color_eyre::eyre::eyre;
+#[cfg(unix)]
+use crossterm::terminal;
+#[cfg(unix)]
+use nix::{
+    libc::{self, STDIN_FILENO, TIOCSCTTY, TIOCSWINSZ},
+    pty::{Winsize, openpty},
+    sys::signal::{self, Signal},
+    unistd::{Pid, setsid},
+};
+#[cfg(unix)]
+use signal_hook::consts::SIGWINCH;
+#[cfg(unix)]
+use tracing::info;
+
+use crate::config::Config;
+
+static RECORDINGS_DIR_NAME: &str = "recordings";
+static ASCIICAST_EXTENSION: &str = "cast";
+#[cfg(unix)]
+const RECORDING_FILE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const DEFAULT_TERMINAL_SIZE: (u16, u16) = (80, 24);
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Default path of a new recording of a session with `name`
+pub fn default_path(name: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Destinations can contain characters that don't belong in file names
+    let name: String = name
+        .chars()
+        .map(|c| if c == '/' { '_' } else { c })
+        .collect();
+    Config::config_dir()
+        .join(RECORDINGS_DIR_NAME)
+        .join(format!("{name}-{timestamp}.{ASCIICAST_EXTENSION}"))
+}
+
+#[cfg(unix)]
+enum Format {
+    /// asciinema's asciicast v2, JSON lines with timed output events
+    Asciicast,
+    /// The raw output with a header and footer like script(1) writes
+    Typescript,
+}
+
+/// Writes the output of a session to a file
+#[cfg(unix)]
+struct Recorder {
+    file: BufWriter<File>,
+    format: Format,
+    started: Instant,
+    /// Trailing bytes of an incomplete UTF-8 sequence, completed by the next output
+    incomplete: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl Recorder {
+    fn create(path: &Path, (width, height): (u16, u16)) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Failed to create directory {dir:?}"))?;
+        }
+        // Sessions can contain secrets, so recordings are only readable by the current user
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(RECORDING_FILE_MODE)
+            .open(path)
+            .wrap_err_with(|| format!("Failed to create recording {path:?}"))?;
+        let format = match path.extension() {
+            Some(extension) if extension == ASCIICAST_EXTENSION => Format::Asciicast,
+            _ => Format::Typescript,
+        };
+
+        let mut recorder = Self {
+            file: BufWriter::new(file),
+            format,
+            started: Instant::now(),
+            incomplete: Vec::new(),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match recorder.format {
+            Format::Asciicast => {
+                let header = serde_json::json!({
+                    "version": 2,
+                    "width": width,
+                    "height": height,
+                    "timestamp": timestamp,
+                    "env": { "TERM": std::env::var("TERM").unwrap_or_default() },
+                });
+                writeln!(recorder.file, "{header}")?;
+            }
+            Format::Typescript => writeln!(
+                recorder.file,
+                "Script started by smssh at {timestamp} [COLUMNS=\"{width}\" LINES=\"{height}\"]"
+            )?,
+        }
+        Ok(recorder)
+    }
+
+    fn output(&mut self, data: &[u8]) -> Result<()> {
+        match self.format {
+            Format::Asciicast => {
+                // Events hold strings, so split multi-byte characters are kept until complete
+                self.incomplete.extend_from_slice(data);
+                let valid = match std::str::from_utf8(&self.incomplete) {
+                    Ok(_) => self.incomplete.len(),
+                    Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                    Err(_) => self.incomplete.len(),
+                };
+                let text = String::from_utf8_lossy(&self.incomplete[..valid]).into_owned();
+                self.incomplete.drain(..valid);
+                if !text.is_empty() {
+                    let event =
+                        serde_json::json!([self.started.elapsed().as_secs_f64(), "o", text]);
+                    writeln!(self.file, "{event}")?;
+                }
+            }
+            Format::Typescript => self.file.write_all(data)?,
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Format::Typescript = self.format {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            writeln!(self.file, "\nScript done at {timestamp}")?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Size of the terminal of smssh, pseudo terminals without a configured size report zero
+#[cfg(unix)]
+fn terminal_size() -> (u16, u16) {
+    match terminal::size() {
+        Ok((width, height)) if width > 0 && height > 0 => (width, height),
+        _ => DEFAULT_TERMINAL_SIZE,
+    }
+}
+
+#[cfg(unix)]
+fn set_window_size(pty: &OwnedFd, (width, height): (u16, u16)) {
+    let size = Winsize {
+        ws_row: height,
+        ws_col: width,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { libc::ioctl(pty.as_raw_fd(), TIOCSWINSZ, &size) };
+}
+
+/// Run a command in a new pseudo terminal, relaying it to the terminal of smssh and recording its
+/// output to `path`. Terminates early if `term_flag` is set to true. Returns the exit code of the
+/// command, if it exited normally.
+#[cfg(unix)]
+pub fn run_recorded(
+    mut command: Command,
+    term_flag: Arc<AtomicBool>,
+    path: &Path,
+) -> Result<Option<i32>> {
+    let size = terminal_size();
+    let mut recorder = Recorder::create(path, size)?;
+    let pty = openpty(None, None)?;
+    set_window_size(&pty.master, size);
+
+    let mut child = unsafe {
+        command
+            .stdin(Stdio::from(pty.slave.try_clone()?))
+            .stdout(Stdio::from(pty.slave.try_clone()?))
+            .stderr(Stdio::from(pty.slave))
+            .pre_exec(|| {
+                // Make the pseudo terminal the controlling terminal of a new session
+                setsid()?;
+                if libc::ioctl(STDIN_FILENO, TIOCSCTTY, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            })
+            .spawn()?
+    };
+    // Close the parent's copies of the slave, so reading the master fails once the child exits
+    drop(command);
+    info!("Recording the session to {path:?}");
+
+    let resized = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGWINCH, resized.clone())?;
+
+    // Input is relayed in the background. The thread stays blocked on stdin after the session
+    // ends, until smssh exits
+    let mut pty_input = File::from(pty.master.try_clone()?);
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin(), &mut pty_input);
+    });
+    let (output_sender, output) = mpsc::channel();
+    let mut pty_output = File::from(pty.master.try_clone()?);
+    std::thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(read) = pty_output.read(&mut buffer) {
+            if read == 0 || output_sender.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Keys are handled by the terminal inside the pseudo terminal, including Ctrl+C
+    let raw_mode = std::io::stdin().is_terminal();
+    if raw_mode {
+        terminal::enable_raw_mode()?;
+    }
+    let result = relay_output(
+        &mut child,
+        &pty.master,
+        output,
+        &mut recorder,
+        &term_flag,
+        &resized,
+    );
+    if raw_mode {
+        terminal::disable_raw_mode()?;
+    }
+    recorder.finish()?;
+    result
+}
+
+/// Sessions are recorded through a pseudo terminal that ssh runs in, which Windows doesn't have
+#[cfg(windows)]
+pub fn run_recorded(
+    _command: Command,
+    _term_flag: Arc<AtomicBool>,
+    _path: &Path,
+) -> Result<Option<i32>> {
+    Err(eyre!("Recording sessions is not supported on Windows"))
+}
+
+/// Copy the output of the child to stdout and the recording until the child exits
+#[cfg(unix)]
+fn relay_output(
+    child: &mut Child,
+    pty: &OwnedFd,
+    output: Receiver<Vec<u8>>,
+    recorder: &mut Recorder,
+    term_flag: &AtomicBool,
+    resized: &AtomicBool,
+) -> Result<Option<i32>> {
+    let mut stdout = std::io::stdout();
+    loop {
+        if term_flag.load(Ordering::Relaxed) {
+            let child_pid = Pid::from_raw(child.id() as i32);
+            signal::kill(child_pid, Signal::SIGTERM).or_else(|_| child.kill())?;
+        }
+        if resized.swap(false, Ordering::Relaxed) {
+            set_window_size(pty, terminal_size());
+        }
+
+        match output.recv_timeout(POLL_INTERVAL) {
+            Ok(data) => {
+                stdout.write_all(&data)?;
+                stdout.flush()?;
+                recorder.output(&data)?;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(child.wait()?.code()),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use color_eyre::{Result, eyre::eyre};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{aws, config::ResolverConfig, progress::Progress, ssh_config};
+
+/// How many record sets are listed from the hostname on, enough for all the types of one name
+const ROUTE53_MAX_ITEMS: &str = "10";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RecordSets {
+    resource_record_sets: Vec<RecordSet>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RecordSet {
+    name: String,
+    #[serde(rename = "Type")]
+    record_type: String,
+    #[serde(default)]
+    resource_records: Vec<Record>,
+    /// Set instead of the records for alias records
+    alias_target: Option<AliasTarget>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Record {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct AliasTarget {
+    #[serde(rename = "DNSName")]
+    dns_name: String,
+}
+
+/// Look up a hostname in a Route 53 hosted zone. The record sets are listed from the hostname on,
+/// so the ones of the hostname come first if it has any.
+fn route53(zone_id: &str, hostname: &str, profile: Option<&str>) -> Result<String> {
+    let response: RecordSets = aws::cli(
+        &[
+            "route53",
+            "list-resource-record-sets",
+            "--hosted-zone-id",
+            zone_id,
+            "--start-record-name",
+            hostname,
+            "--max-items",
+            ROUTE53_MAX_ITEMS,
+        ],
+        profile,
+    )?;
+    let name = hostname.trim_end_matches('.');
+    response
+        .resource_record_sets
+        .into_iter()
+        .filter(|set| set.name.trim_end_matches('.').eq_ignore_ascii_case(name))
+        .filter(|set| matches!(set.record_type.as_str(), "A" | "AAAA" | "CNAME"))
+        .find_map(|set| {
+            set.resource_records
+                .into_iter()
+                .next()
+                .map(|record| record.value)
+                .or(set.alias_target.map(|target| target.dns_name))
+        })
+        .map(|address| address.trim_end_matches('.').to_string())
+        .ok_or_else(|| {
+            eyre!("'{hostname}' has no A, AAAA or CNAME record in hosted zone {zone_id}")
+        })
+}
+
+/// Look up the private IP of the running instance with the hostname as its private DNS name
+fn ec2(hostname: &str, profile: Option<&str>) -> Result<String> {
+    let client = aws::block_on(aws::ec2(profile))?;
+    aws::block_on(aws::private_ip_by_dns_name(&client, hostname))??
+        .ok_or_else(|| eyre!("No running instance has the private DNS name '{hostname}'"))
+}
+
+/// Look up the address of a hostname with a resolver, using the credentials of `profile`
+pub fn resolve(resolver: &ResolverConfig, hostname: &str, profile: Option<&str>) -> Result<String> {
+    match resolver {
+        ResolverConfig::Route53 { zone_id } => route53(zone_id, hostname, profile),
+        ResolverConfig::Ec2 => ec2(hostname, profile),
+    }
+}
+
+/// The ssh args connecting to the address the resolver finds for the hostname ssh would use. The
+/// host key stays recorded under the hostname rather than under the address.
+pub fn resolved_args(
+    resolver: &ResolverConfig,
+    destination: Option<&str>,
+    ssh_args: &[String],
+    profile: Option<&str>,
+) -> Result<Vec<String>> {
+    let hostname = ssh_config::effective_options(destination, ssh_args)?
+        .into_iter()
+        .find(|(option, _)| option == "hostname")
+        .map(|(_, hostname)| hostname)
+        .ok_or(eyre!("`ssh -G` did not print the hostname"))?;
+    let progress = Progress::start(&format!("Resolving {hostname}"));
+    let address = resolve(resolver, &hostname, profile)?;
+    progress.finish();
+    info!("Resolved {hostname} to {address} with {resolver}");
+    Ok(vec![
+        "-o".to_string(),
+        format!("HostName={address}"),
+        "-o".to_string(),
+        format!("HostKeyAlias={hostname}"),
+    ])
+}
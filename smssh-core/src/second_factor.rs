@@ -0,0 +1,171 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tracing::info;
+
+use crate::{
+    config::{Config, SecondFactorConfig},
+    connect,
+    error::Error,
+    prompt, trusted_commands,
+};
+
+/// Length of a TOTP time step in seconds
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+/// Require the second factor of a key alias before its key is fetched. A command only the shared
+/// or system config sets has to be approved first, like hooks.
+pub fn verify(config: &Config, key_alias: &str, second_factor: &SecondFactorConfig) -> Result<()> {
+    match second_factor {
+        SecondFactorConfig::Totp { secret } => {
+            // The secret is as good as the code if it can be read from disk
+            if !Config::is_encrypted()? {
+                return Err(Error::Config(format!(
+                    "The TOTP secret of '{key_alias}' is stored in plaintext, encrypt the config \
+                     with `smssh config encrypt` to use it"
+                ))
+                .into());
+            }
+            let key = decode_base32(secret)
+                .ok_or_else(|| eyre!("The TOTP secret of '{key_alias}' is not valid base32"))?;
+            let code = prompt::input(&format!("TOTP code for '{key_alias}'"), None)?;
+            if !verify_totp(&key, code.trim(), unix_time()) {
+                return Err(eyre!("Invalid TOTP code for '{key_alias}'"));
+            }
+        }
+        SecondFactorConfig::Command { command } => {
+            trusted_commands::ensure_trusted(
+                config,
+                &format!("second factor command of '{key_alias}'"),
+                command,
+            )?;
+            info!("Waiting for the second factor of '{key_alias}'");
+            let status = connect::local_shell(command)
+                .status()
+                .wrap_err_with(|| format!("Failed to run the second factor command '{command}'"))?;
+            if !status.success() {
+                return Err(eyre!(
+                    "The second factor command of '{key_alias}' failed with {status}"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Check a code against the current time step and its neighbours, to allow for clock drift and
+/// codes typed just before the step changed.
+fn verify_totp(key: &[u8], code: &str, time: u64) -> bool {
+    let step = time / TOTP_STEP;
+    [step.saturating_sub(1), step, step + 1]
+        .iter()
+        .any(|&step| totp(key, step) == code)
+}
+
+/// RFC 6238 time-based one-time password with HMAC-SHA1, as used by authenticator apps
+fn totp(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+/// Decode RFC 4648 base32, ignoring case, spaces and padding as authenticator apps do
+fn decode_base32(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for c in value.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let digit = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | digit;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    (!bytes.is_empty()).then_some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 secret of the RFC 6238 test vectors
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_the_rfc_6238_sha1_vectors() {
+        // Appendix B lists 8 digit codes, 6 digit codes are their last digits
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(totp(RFC_KEY, time / TOTP_STEP), code, "time {time}");
+        }
+    }
+
+    #[test]
+    fn accepts_codes_of_the_neighbouring_steps_only() {
+        let code = totp(RFC_KEY, 59 / TOTP_STEP);
+        assert!(verify_totp(RFC_KEY, &code, 59));
+        assert!(verify_totp(RFC_KEY, &code, 59 - TOTP_STEP));
+        assert!(verify_totp(RFC_KEY, &code, 59 + TOTP_STEP));
+        assert!(!verify_totp(RFC_KEY, &code, 59 + 2 * TOTP_STEP));
+        assert!(!verify_totp(RFC_KEY, "", 59));
+    }
+
+    #[test]
+    fn decodes_base32_like_authenticator_apps() {
+        assert_eq!(
+            decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").as_deref(),
+            Some(RFC_KEY)
+        );
+        assert_eq!(
+            decode_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").as_deref(),
+            Some(RFC_KEY)
+        );
+        assert_eq!(decode_base32("MZXW6===").as_deref(), Some(&b"foo"[..]));
+        assert_eq!(decode_base32("MZXW6YQ=").as_deref(), Some(&b"foob"[..]));
+    }
+
+    #[test]
+    fn rejects_invalid_base32() {
+        for value in ["", "====", "GEZ1", "GEZDGNBV!", "MZXW6===0"] {
+            assert_eq!(decode_base32(value), None, "{value:?} should be invalid");
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+
+use crate::config::{HostConfig, X11Forwarding};
+
+/// Options that are turned into the destination or dedicated ssh arguments
+static MAPPED_OPTIONS: [&str; 4] = ["hostname", "user", "port", "proxyjump"];
+/// Options that would make ssh use another key than the one of the key alias
+static KEY_OPTIONS: [&str; 4] = [
+    "identityfile",
+    "identitiesonly",
+    "identityagent",
+    "certificatefile",
+];
+/// Options that may be given several times, all other options only use their first value
+static REPEATED_OPTIONS: [&str; 5] = [
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "sendenv",
+    "setenv",
+];
+
+/// Options that make ssh run a local command or load a local library
+static COMMAND_OPTIONS: [&str; 6] = [
+    "proxycommand",
+    "localcommand",
+    "knownhostscommand",
+    "pkcs11provider",
+    "securitykeyprovider",
+    "xauthlocation",
+];
+/// ssh flags taking a value, given in the same arg or the next one
+static VALUE_FLAGS: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/// A host declared by name in an ssh config, with the options of the blocks naming it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshHost {
+    /// The name given after `Host`
+    pub name: String,
+    /// Options in the order they were given, with their keywords as written
+    pub options: Vec<(String, String)>,
+}
+
+impl SshHost {
+    /// The first value of an option, keywords are case-insensitive
+    fn option(&self, keyword: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(keyword))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The smssh host equivalent to this host, without a key alias. Options that ssh would use
+    /// once are only passed with their first value, and key options are dropped.
+    pub fn to_host_config(&self) -> HostConfig {
+        let hostname = self.option("hostname").unwrap_or(&self.name);
+        let destination = match self.option("user") {
+            Some(user) => format!("{user}@{hostname}"),
+            None => hostname.to_string(),
+        };
+
+        let mut args = Vec::new();
+        if let Some(port) = self.option("port") {
+            args.extend(["-p".to_string(), port.to_string()]);
+        }
+        if let Some(jump) = self.option("proxyjump") {
+            args.extend(["-J".to_string(), jump.to_string()]);
+        }
+        // A socket path to forward instead of the agent stays an ssh option
+        let forward_agent = match self.option("forwardagent") {
+            Some(value) if value.eq_ignore_ascii_case("yes") => Some(true),
+            Some(value) if value.eq_ignore_ascii_case("no") => Some(false),
+            _ => None,
+        };
+        let trusted = self
+            .option("forwardx11trusted")
+            .is_some_and(|value| value.eq_ignore_ascii_case("yes"));
+        let forward_x11 = match self.option("forwardx11") {
+            Some(value) if value.eq_ignore_ascii_case("yes") && trusted => {
+                Some(X11Forwarding::Trusted)
+            }
+            Some(value) if value.eq_ignore_ascii_case("yes") => Some(X11Forwarding::Yes),
+            Some(value) if value.eq_ignore_ascii_case("no") => Some(X11Forwarding::No),
+            _ => None,
+        };
+        let mut seen: Vec<String> = Vec::new();
+        for (keyword, value) in &self.options {
+            let lowercase = keyword.to_ascii_lowercase();
+            if MAPPED_OPTIONS.contains(&lowercase.as_str())
+                || KEY_OPTIONS.contains(&lowercase.as_str())
+                || (lowercase == "forwardagent" && forward_agent.is_some())
+                || (lowercase.starts_with("forwardx11") && forward_x11.is_some())
+            {
+                continue;
+            }
+            if !REPEATED_OPTIONS.contains(&lowercase.as_str()) {
+                if seen.contains(&lowercase) {
+                    continue;
+                }
+                seen.push(lowercase);
+            }
+            args.extend(["-o".to_string(), format!("{keyword}={value}")]);
+        }
+
+        HostConfig {
+            destination: Some(destination),
+            args,
+            forward_agent,
+            forward_x11,
+            ..Default::default()
+        }
+    }
+}
+
+/// The result of parsing an ssh config
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    /// Hosts declared by name, in the order they first appear
+    pub hosts: Vec<SshHost>,
+    /// Host patterns with wildcards or negations, which can't become smssh hosts
+    pub skipped_patterns: Vec<String>,
+    /// Files included with `Include`, which are not followed
+    pub includes: Vec<String>,
+}
+
+/// The default ssh config of the user, ~/.ssh/config
+pub fn default_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_default()
+        .join(".ssh")
+        .join("config")
+}
+
+/// Split a line into its keyword and value, which are separated by whitespace or an equals sign.
+/// Quotes around the value are removed.
+fn split_option(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let split = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(split);
+    let value = rest.trim_start().strip_prefix('=').unwrap_or(rest).trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((keyword, value))
+}
+
+/// Parse the `Host` blocks of an ssh config. Hosts named in several blocks collect the options of
+/// all of them in order. Options outside of `Host` blocks and inside `Match` blocks apply to
+/// many hosts, which ssh still reads from its own config, so they are not imported.
+pub fn parse(contents: &str) -> SshConfig {
+    let mut config = SshConfig::default();
+    // Indices of the hosts named by the current block
+    let mut current: Vec<usize> = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((keyword, value)) = split_option(line) else {
+            continue;
+        };
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                current.clear();
+                for pattern in value.split_whitespace() {
+                    if pattern.contains(['*', '?', '!']) {
+                        if !config.skipped_patterns.iter().any(|known| known == pattern) {
+                            config.skipped_patterns.push(pattern.to_string());
+                        }
+                        continue;
+                    }
+                    let index = match config.hosts.iter().position(|host| host.name == pattern) {
+                        Some(index) => index,
+                        None => {
+                            config.hosts.push(SshHost {
+                                name: pattern.to_string(),
+                                options: Vec::new(),
+                            });
+                            config.hosts.len() - 1
+                        }
+                    };
+                    current.push(index);
+                }
+            }
+            "match" => current.clear(),
+            "include" => config.includes.push(value.to_string()),
+            _ => {
+                for &index in &current {
+                    config.hosts[index]
+                        .options
+                        .push((keyword.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+    config
+}
+
+/// The options ssh would use to connect to a destination with the given args, as printed by
+/// `ssh -G` after applying the ssh configs, in the order it prints them
+pub fn effective_options(
+    destination: Option<&str>,
+    ssh_args: &[String],
+) -> Result<Vec<(String, String)>> {
+    let output = Command::new("ssh")
+        .arg("-G")
+        .args(ssh_args)
+        .args(destination)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .wrap_err("Failed to run `ssh -G`")?;
+    if !output.status.success() {
+        return Err(eyre!("`ssh -G` failed with {}", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(option, value)| (option.to_string(), value.to_string()))
+        .collect())
+}
+
+/// The ssh args that make ssh run local code: `-o` options like ProxyCommand, and the config
+/// files and PKCS#11 libraries given with `-F` and `-I`, which can run commands of their own.
+/// Options are returned as they were given, like "ProxyCommand=nc %h %p".
+pub fn command_args(args: &[String]) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flags) = arg.strip_prefix('-') else {
+            continue;
+        };
+        if flags == "-" {
+            break;
+        }
+        for (position, flag) in flags.char_indices() {
+            if !VALUE_FLAGS.contains(flag) {
+                continue;
+            }
+            let value = match &flags[position + flag.len_utf8()..] {
+                "" => args.next().map(String::as_str).unwrap_or_default(),
+                value => value,
+            };
+            match flag {
+                'o' => {
+                    let keyword = value
+                        .split(|c: char| c == '=' || c.is_whitespace())
+                        .next()
+                        .unwrap_or_default()
+                        .to_ascii_lowercase();
+                    if COMMAND_OPTIONS.contains(&keyword.as_str()) {
+                        commands.push(value.to_string());
+                    }
+                }
+                'F' | 'I' => commands.push(format!("-{flag} {value}")),
+                _ => {}
+            }
+            break;
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+# Options for every host are left to ssh
+ServerAliveInterval 30
+Include ~/.ssh/config.d/*
+
+Host web web-backup
+    HostName web.example.com
+    User deploy
+    Port=2222
+
+Host *.internal !bastion
+    ProxyJump bastion
+
+Host web
+    IdentityFile "~/.ssh/web key"
+
+Match host db
+    User postgres
+"#;
+
+    #[test]
+    fn collects_the_options_of_every_block_naming_a_host() {
+        let config = parse(CONFIG);
+        let names: Vec<_> = config.hosts.iter().map(|host| host.name.as_str()).collect();
+        assert_eq!(names, ["web", "web-backup"]);
+        assert_eq!(
+            config.hosts[0].options,
+            [
+                ("HostName".to_string(), "web.example.com".to_string()),
+                ("User".to_string(), "deploy".to_string()),
+                ("Port".to_string(), "2222".to_string()),
+                ("IdentityFile".to_string(), "~/.ssh/web key".to_string()),
+            ]
+        );
+        assert_eq!(config.hosts[1].options.len(), 3);
+    }
+
+    #[test]
+    fn skips_patterns_includes_and_match_blocks() {
+        let config = parse(CONFIG);
+        assert_eq!(config.skipped_patterns, ["*.internal", "!bastion"]);
+        assert_eq!(config.includes, ["~/.ssh/config.d/*"]);
+        assert!(
+            config
+                .hosts
+                .iter()
+                .all(|host| host.option("user") != Some("postgres"))
+        );
+    }
+
+    #[test]
+    fn converts_a_host_without_its_key_options() {
+        let host = parse(CONFIG).hosts.remove(0).to_host_config();
+        assert_eq!(host.destination.as_deref(), Some("deploy@web.example.com"));
+        assert_eq!(host.args, ["-p", "2222"]);
+        assert_eq!(host.key_alias, None);
+    }
+
+    #[test]
+    fn finds_the_args_running_local_commands() {
+        let args: Vec<String> = [
+            "-p",
+            "2222",
+            "-oProxyCommand=nc %h %p",
+            "-o",
+            "Compression=yes",
+            "-o",
+            "localcommand echo hi",
+            "-vF",
+            "/tmp/config",
+            "-I/tmp/pkcs11.so",
+            "-l",
+            "-oKnownHostsCommand=x",
+        ]
+        .map(String::from)
+        .into();
+        assert_eq!(
+            command_args(&args),
+            [
+                "ProxyCommand=nc %h %p",
+                "localcommand echo hi",
+                "-F /tmp/config",
+                "-I /tmp/pkcs11.so",
+            ]
+        );
+    }
+}
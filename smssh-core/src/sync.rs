@@ -0,0 +1,168 @@
+use std::{collections::HashMap, path::Path, process::Command};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// Path of the config file inside a git repository if the source doesn't specify one
+static DEFAULT_GIT_PATH: &str = "smssh.yaml";
+
+/// Fetch the contents of a shared config. Supported sources are `s3://bucket/key`, `https://`
+/// URLs, `git+<repository>[#path]` with an https, ssh or `user@host:path` repository and local
+/// file paths.
+pub fn fetch(source: &str) -> Result<String> {
+    if let Some(location) = source.strip_prefix("s3://") {
+        let (bucket, key) = location
+            .split_once('/')
+            .ok_or_else(|| eyre!("S3 sources must have the form s3://bucket/key"))?;
+        crate::aws::get_object_blocking(bucket, key)
+            .wrap_err_with(|| format!("Failed to download {source}"))
+    } else if source.starts_with("http://") {
+        Err(eyre!(
+            "Refusing to sync {source} over plain http, which anyone on the way can change"
+        ))
+    } else if source.starts_with("https://") {
+        let contents = ureq::get(source)
+            .call()
+            .wrap_err_with(|| format!("Failed to download {source}"))?
+            .into_string()?;
+        Ok(contents)
+    } else if let Some(repository) = source.strip_prefix("git+") {
+        fetch_git(repository)
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Failed to read {path}"))
+    }
+}
+
+/// Check that a repository is cloned over https or ssh. Other transports are unauthenticated,
+/// like git:// and http://, or run commands, like ext::.
+fn validate_repository(repository: &str) -> Result<()> {
+    let is_scp_like = !repository.contains("://")
+        && !repository.contains("::")
+        && !repository.starts_with('-')
+        && repository
+            .split_once(':')
+            .is_some_and(|(host, _)| !host.is_empty() && !host.contains('/'));
+    if repository.starts_with("https://") || repository.starts_with("ssh://") || is_scp_like {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Refusing to clone {repository}, git sources have to use https://, ssh:// or \
+             user@host:path"
+        ))
+    }
+}
+
+/// Shallow clone the repository into a temporary directory and read the config file from it.
+fn fetch_git(repository: &str) -> Result<String> {
+    let (repository, path) = repository
+        .split_once('#')
+        .unwrap_or((repository, DEFAULT_GIT_PATH));
+    validate_repository(repository)?;
+    if Path::new(path).is_absolute() || path.split('/').any(|part| part == "..") {
+        return Err(eyre!("The path {path} has to stay inside the repository"));
+    }
+    let dir = tempfile::tempdir()?;
+    let status = Command::new("git")
+        .args(["clone", "--quiet", "--depth", "1", "--", repository])
+        .arg(dir.path())
+        .status()
+        .wrap_err("Failed to run git")?;
+    if !status.success() {
+        return Err(eyre!(
+            "Failed to clone {repository}: git exited with {status}"
+        ));
+    }
+    let file = dir.path().join(path);
+    std::fs::read_to_string(&file)
+        .wrap_err_with(|| format!("Failed to read {path} from {repository}"))
+}
+
+/// The names of the settings that differ between two versions of an entry, sorted
+fn changed_fields<T: Serialize>(old: &T, new: &T) -> Vec<String> {
+    let (Ok(serde_yml::Value::Mapping(old)), Ok(serde_yml::Value::Mapping(new))) =
+        (serde_yml::to_value(old), serde_yml::to_value(new))
+    else {
+        return Vec::new();
+    };
+    let mut fields: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|field| old.get(*field) != new.get(*field))
+        .filter_map(|field| field.as_str().map(str::to_string))
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+/// Describe the differences between the entries of two maps as lines prefixed with `+` for
+/// added, `-` for removed and `~` for changed entries, followed by the changed settings.
+fn diff_section<T: PartialEq + Serialize>(
+    kind: &str,
+    old: &HashMap<String, T>,
+    new: &HashMap<String, T>,
+    lines: &mut Vec<String>,
+) {
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        match (old.get(name), new.get(name)) {
+            (None, Some(_)) => lines.push(format!("+ {kind} '{name}'")),
+            (Some(_), None) => lines.push(format!("- {kind} '{name}'")),
+            (Some(old), Some(new)) if old != new => {
+                let fields = changed_fields(old, new);
+                if fields.is_empty() {
+                    lines.push(format!("~ {kind} '{name}'"));
+                } else {
+                    lines.push(format!("~ {kind} '{name}': {}", fields.join(", ")));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Describe the entries added, removed and changed between two configs.
+pub fn diff(old: &Config, new: &Config) -> Vec<String> {
+    let mut lines = Vec::new();
+    diff_section("key alias", &old.key_aliases, &new.key_aliases, &mut lines);
+    diff_section("host", &old.hosts, &new.hosts, &mut lines);
+    diff_section("template", &old.templates, &new.templates, &mut lines);
+    diff_section("group", &old.groups, &new.groups, &mut lines);
+    diff_section("database", &old.databases, &new.databases, &mut lines);
+    diff_section("network", &old.networks, &new.networks, &mut lines);
+    if old.default_alias != new.default_alias {
+        lines.push(format!(
+            "~ default key alias {:?} -> {:?}",
+            old.default_alias, new.default_alias
+        ));
+    }
+    for pattern in &new.patterns {
+        match old
+            .patterns
+            .iter()
+            .find(|old| old.pattern == pattern.pattern)
+        {
+            None => lines.push(format!("+ pattern '{}'", pattern.pattern)),
+            Some(old) if old != pattern => lines.push(format!("~ pattern '{}'", pattern.pattern)),
+            _ => {}
+        }
+    }
+    for pattern in &old.patterns {
+        if !new
+            .patterns
+            .iter()
+            .any(|new| new.pattern == pattern.pattern)
+        {
+            lines.push(format!("- pattern '{}'", pattern.pattern));
+        }
+    }
+    lines
+}
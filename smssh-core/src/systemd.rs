@@ -0,0 +1,111 @@
+#[cfg(unix)]
+use std::os::{
+    fd::{FromRawFd, RawFd},
+    unix::net::UnixListener,
+};
+use std::path::Path;
+
+use color_eyre::{Result, eyre::Context};
+#[cfg(unix)]
+use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+#[cfg(windows)]
+use uds_windows::UnixListener;
+
+/// The first file descriptor passed by systemd, see sd_listen_fds(3)
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The socket passed by systemd when the unit was started by socket activation. The activation
+/// variables are removed, so that child processes don't take the socket for theirs.
+#[cfg(unix)]
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or(0);
+    // SAFETY: no other threads read the environment this early
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+    if !for_us || fds == 0 {
+        return Ok(None);
+    }
+    // Not passed on to the processes started later, like ssh-agent
+    fcntl(LISTEN_FDS_START, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+        .wrap_err("Invalid socket passed by systemd")?;
+    // SAFETY: systemd passes the listening socket as the first descriptor and nothing else in
+    // the process owns it
+    Ok(Some(unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) }))
+}
+
+/// There is no socket activation on Windows, the socket is always bound by smssh
+#[cfg(windows)]
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    Ok(None)
+}
+
+/// Quote an argument of `ExecStart`, which systemd splits itself rather than through a shell.
+/// Specifiers and variables are escaped, so that `%` and `$` are passed on as written.
+fn exec_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._:=@+,-".contains(c));
+    if is_plain {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A user level socket unit listening on `socket` and the service it activates, running the
+/// current executable with `args`, separated by comments naming the unit files
+pub fn units(name: &str, description: &str, socket: &Path, args: &[String]) -> Result<String> {
+    let exe = std::env::current_exe().wrap_err("Failed to find the smssh executable")?;
+    let command: Vec<String> = std::iter::once(exe.to_string_lossy().as_ref())
+        .chain(args.iter().map(String::as_str))
+        .map(exec_quote)
+        .collect();
+    Ok(format!(
+        "# ~/.config/systemd/user/{name}.socket
+[Unit]
+Description={description} socket
+
+[Socket]
+ListenStream={socket}
+SocketMode=0600
+DirectoryMode=0700
+
+[Install]
+WantedBy=sockets.target
+
+# ~/.config/systemd/user/{name}.service
+[Unit]
+Description={description}
+Requires={name}.socket
+
+[Service]
+ExecStart={command}
+",
+        // Paths are not split, only specifiers are expanded
+        socket = socket.display().to_string().replace('%', "%%"),
+        command = command.join(" "),
+    ))
+}
@@ -0,0 +1,174 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicU64, Ordering},
+    thread::JoinHandle,
+};
+
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+use secrecy::SecretString;
+
+use crate::{
+    config::ResolvedHost,
+    connect::{self, KeyFile, Route, shell_quote},
+    error::Error,
+};
+
+/// Size of the chunks copied between tar and ssh
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A remote path for the shell, relative to the home directory when it starts with `~/`, which
+/// would not be expanded once quoted
+fn remote_path(path: &str) -> String {
+    let path = path.strip_prefix("~/").unwrap_or(path);
+    if path.is_empty() || path == "~" {
+        ".".to_string()
+    } else {
+        shell_quote(path)
+    }
+}
+
+/// Copy everything from `from` to `to`, adding the copied bytes to `transferred` as they go
+fn copy_counted(mut from: impl Read, mut to: impl Write, transferred: &AtomicU64) -> Result<()> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = from.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        to.write_all(&buffer[..read])?;
+        transferred.fetch_add(read as u64, Ordering::Relaxed);
+    }
+}
+
+/// Collect the stderr of ssh while the transfer runs, so that a full pipe can't stall it
+fn read_stderr(child: &mut Child) -> JoinHandle<String> {
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output);
+        output
+    })
+}
+
+/// Fail with the errors ssh printed if it did not succeed, or if the local tar failed
+fn check_exit(
+    host: &ResolvedHost,
+    mut ssh: Child,
+    stderr: JoinHandle<String>,
+    mut tar: Child,
+) -> Result<()> {
+    let ssh_status = ssh.wait()?;
+    let tar_status = tar.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    if !ssh_status.success() {
+        let status = ssh_status.code().unwrap_or(connect::SIGNAL_EXIT_STATUS);
+        return Err(Report::new(Error::SshFailed { status }).wrap_err(format!(
+            "ssh to '{}' failed with {ssh_status}: {}",
+            host.name,
+            stderr.trim()
+        )));
+    }
+    if !tar_status.success() {
+        return Err(eyre!("The local tar failed with {tar_status}"));
+    }
+    Ok(())
+}
+
+/// Upload a file or directory into a directory on a host, created if missing, by streaming a tar
+/// archive through ssh. Adds the bytes sent to `sent` as they go.
+pub fn upload(
+    host: &ResolvedHost,
+    route: &Route,
+    key: &SecretString,
+    local: &Path,
+    remote_dir: &str,
+    sent: &AtomicU64,
+) -> Result<()> {
+    let local = std::path::absolute(local)?;
+    let (Some(parent), Some(name)) = (local.parent(), local.file_name()) else {
+        return Err(eyre!("Cannot upload {local:?}, it has no file name"));
+    };
+    let mut tar = Command::new("tar")
+        .arg("-C")
+        .arg(parent)
+        .arg("-cf")
+        .arg("-")
+        .arg(name)
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err("Failed to run tar")?;
+
+    let key_file = KeyFile::create(key)?;
+    let dir = remote_path(remote_dir);
+    let mut ssh = connect::remote_command(
+        host,
+        route,
+        &key_file,
+        &format!("mkdir -p {dir} && tar -C {dir} -xf -"),
+    )
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .spawn()?;
+    let stderr = read_stderr(&mut ssh);
+
+    let archive = tar.stdout.take().expect("stdout is piped");
+    let remote = ssh.stdin.take().expect("stdin is piped");
+    // A failed write means ssh exited, which its exit status explains better
+    let _ = copy_counted(archive, remote, sent);
+    check_exit(host, ssh, stderr, tar)
+}
+
+/// Download a file or directory from a host into a local directory, created if missing, by
+/// streaming a tar archive through ssh. Adds the bytes received to `received` as they go.
+pub fn download(
+    host: &ResolvedHost,
+    route: &Route,
+    key: &SecretString,
+    remote: &str,
+    local_dir: &Path,
+    received: &AtomicU64,
+) -> Result<()> {
+    let remote = remote.trim_end_matches('/');
+    let (parent, name) = match remote.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => ("~", remote),
+    };
+    if name.is_empty() || name == "~" {
+        return Err(eyre!("Cannot download '{remote}', it has no file name"));
+    }
+    std::fs::create_dir_all(local_dir)
+        .wrap_err_with(|| format!("Failed to create directory {local_dir:?}"))?;
+
+    let key_file = KeyFile::create(key)?;
+    let mut ssh = connect::remote_command(
+        host,
+        route,
+        &key_file,
+        &format!("tar -C {} -cf - {}", remote_path(parent), shell_quote(name)),
+    )
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+    let stderr = read_stderr(&mut ssh);
+    let mut tar = Command::new("tar")
+        .arg("-C")
+        .arg(local_dir)
+        .arg("-xf")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .wrap_err("Failed to run tar")?;
+
+    let archive = ssh.stdout.take().expect("stdout is piped");
+    let local = tar.stdin.take().expect("stdin is piped");
+    let _ = copy_counted(archive, local, received);
+    check_exit(host, ssh, stderr, tar)
+}
@@ -0,0 +1,77 @@
+use color_eyre::Result;
+use tracing::info;
+
+use crate::{aws, config::Transport, connect::shell_quote};
+
+/// An arg of the proxy command, quoted for the shell ssh runs it with, and with the `%` escaped
+/// that ssh would expand as the start of a token
+fn proxy_arg(arg: &str) -> String {
+    shell_quote(arg).replace('%', "%%")
+}
+
+/// The proxy command opening the tunnel of a transport to the port ssh connects to
+fn proxy_command(transport: Transport, instance_id: &str, profile: Option<&str>) -> String {
+    let instance_id = proxy_arg(instance_id);
+    let mut command = match transport {
+        Transport::Ssm => format!(
+            "{} ssm start-session --target {instance_id} --document-name AWS-StartSSHSession \
+             --parameters portNumber=%p",
+            aws::CLI_COMMAND
+        ),
+        Transport::InstanceConnect => format!(
+            "{} ec2-instance-connect open-tunnel --instance-id {instance_id} --remote-port %p",
+            aws::CLI_COMMAND
+        ),
+    };
+    if let Some(profile) = profile {
+        command.push_str(&format!(" --profile {}", proxy_arg(profile)));
+    }
+    command
+}
+
+/// The ssh args sending the connection to an instance through the tunnel of a transport, opened
+/// by the AWS CLI with the credentials of `profile`. Fails before anything is started when the
+/// AWS CLI or the plugin it needs for the transport is missing.
+pub fn proxy_args(
+    transport: Transport,
+    instance_id: &str,
+    profile: Option<&str>,
+) -> Result<Vec<String>> {
+    aws::ensure_cli()?;
+    if transport == Transport::Ssm {
+        aws::ensure_session_manager_plugin()?;
+    }
+    info!("Connecting to instance {instance_id} through {transport}");
+    Ok(vec![
+        "-o".to_string(),
+        format!(
+            "ProxyCommand={}",
+            proxy_command(transport, instance_id, profile)
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_proxy_commands_of_the_transports() {
+        assert_eq!(
+            proxy_command(Transport::Ssm, "i-0123456789abcdef0", None),
+            "aws ssm start-session --target i-0123456789abcdef0 --document-name \
+             AWS-StartSSHSession --parameters portNumber=%p"
+        );
+        assert_eq!(
+            proxy_command(Transport::InstanceConnect, "i-0123", Some("prod")),
+            "aws ec2-instance-connect open-tunnel --instance-id i-0123 --remote-port %p \
+             --profile prod"
+        );
+        // Neither the shell nor ssh may expand anything in the profile
+        assert_eq!(
+            proxy_command(Transport::InstanceConnect, "i-0123", Some("100% $HOME")),
+            "aws ec2-instance-connect open-tunnel --instance-id i-0123 --remote-port %p \
+             --profile '100%% $HOME'"
+        );
+    }
+}
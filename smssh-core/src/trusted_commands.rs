@@ -0,0 +1,81 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use color_eyre::{Result, eyre::Context};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::Config,
+    prompt::{self, PromptError},
+    ssh_config,
+};
+
+static TRUSTED_COMMANDS_FILE_NAME: &str = "trusted_commands.json";
+
+/// Path of the digests of the approved commands, in the config directory
+pub fn trusted_commands_path() -> PathBuf {
+    Config::config_dir().join(TRUSTED_COMMANDS_FILE_NAME)
+}
+
+/// SHA-256 of a command, so that an approval covers exactly the command that was shown
+fn digest(command: &str) -> String {
+    Sha256::digest(command.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Load the digests of the approved commands
+fn load() -> Result<BTreeSet<String>> {
+    let path = trusted_commands_path();
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read the trusted commands {path:?}"))?;
+    serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Failed to parse the trusted commands {path:?}"))
+}
+
+fn store(trusted: &BTreeSet<String>) -> Result<()> {
+    Config::create_config_dir()?;
+    Config::write_atomic(
+        &trusted_commands_path(),
+        &serde_json::to_string_pretty(trusted)?,
+    )
+}
+
+/// Make sure a shell command from the config may run. Commands set by the local config run as
+/// they are. Commands only the shared or system config sets are shown and have to be approved
+/// once, so that whoever controls the sync source can't run code on the machine unnoticed. A
+/// changed command has to be approved again.
+pub fn ensure_trusted(config: &Config, description: &str, command: &str) -> Result<()> {
+    if config.sets_command_locally(command) {
+        return Ok(());
+    }
+    let mut trusted = load()?;
+    if trusted.contains(&digest(command)) {
+        return Ok(());
+    }
+    if !prompt::can_ask() {
+        return Err(PromptError::Unavailable(format!(
+            "The {description} from the shared or system config has not been approved, run smssh \
+             in a terminal to review it"
+        ))
+        .into());
+    }
+    println!("The {description} comes from the shared or system config:\n  {command}");
+    if !prompt::confirm("Run it, now and from now on?", false)? {
+        return Err(PromptError::Declined(format!("The {description} was not approved")).into());
+    }
+    trusted.insert(digest(command));
+    store(&trusted)
+}
+
+/// Make sure the ssh args of a host that run local commands, like ProxyCommand options, may be
+/// passed to ssh, see [`ensure_trusted`]
+pub fn ensure_trusted_args(config: &Config, host: &str, args: &[String]) -> Result<()> {
+    for arg in ssh_config::command_args(args) {
+        ensure_trusted(config, &format!("ssh option of '{host}'"), &arg)?;
+    }
+    Ok(())
+}
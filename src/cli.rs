@@ -1,12 +1,53 @@
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
 use serde::{Deserialize, Serialize};
 
+use crate::config::{
+    AuditLogConfig, ConfirmPolicy, ConsoleLinks, KeyAliasConfig, KnockConfig, ResolverConfig,
+    SecondFactorConfig, StopPolicy, Transport, X11Forwarding,
+};
+
+/// The exit statuses, which stay the same across releases for scripts to rely on
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0    Success
+  1    Any other failure
+  2    Invalid arguments
+  68   The host could not be reached, resolved or started before running ssh
+  69   The key could not be fetched from AWS or a plugin
+  77   A confirmation was declined, like removing a key alias that is in use
+  78   The config is invalid or lacks the hosts or key aliases used
+  79   ssh exited with 68, 69, 77, 78 or 79, the status is in the output of --error-format json
+  N    ssh exited with N, 255 when ssh itself failed, for example to authenticate";
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_long_help = EXIT_STATUS_HELP)]
 pub struct Args {
+    /// Increase the logging verbosity, can be repeated. Goes before the subcommand, like
+    /// `smssh -v connect HOST`, since `-v` after the host is passed to ssh
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Only log warnings and errors. Goes before the subcommand, since `-q` after the host is
+    /// passed to ssh
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// When to color the output. Auto colors terminals unless `NO_COLOR` is set
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+    /// How to print failures. JSON prints a single object on stderr with a stable `code`, like
+    /// `not_found`, `access_denied` or `ssh_exit`, for wrapper scripts to branch on
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+    /// Run unattended, for pipelines and cron: never prompt or draw spinners, run ssh with
+    /// `BatchMode=yes` and report progress as tab separated lines on stderr. Also turned on by
+    /// `SMSSH_BATCH=1`
+    #[arg(long, global = true)]
+    pub batch: bool,
     /// The subcommand to run
     #[command(subcommand)]
     pub command: SMSSHCommand,
@@ -16,11 +57,39 @@ pub struct Args {
 #[command()]
 pub enum SMSSHCommand {
     /// Connect to a remote machine using the host configuration. SSH args are optional.
+    /// Destinations that are not configured as hosts use the default key alias, if set.
     #[command(alias = "c")]
     Connect {
-        /// The host configuration to use
+        /// The host configuration to use, or a destination such as user@hostname
         #[arg()]
         host: String,
+        /// Print the ssh command and the key alias that would be used without fetching the key
+        #[arg(long)]
+        dry_run: bool,
+        /// Record the session to the given file, or to the recordings directory. Files ending
+        /// with `.cast` are written in the asciicast format, others as script(1) typescripts
+        #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+        record: Option<Option<PathBuf>>,
+        /// Retry when ssh fails to connect, up to N times (5 by default) with a growing delay.
+        /// The key is fetched once for all attempts
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "5")]
+        retry: Option<u32>,
+        /// Retry until the host accepts connections, for hosts that are still booting
+        #[arg(long, conflicts_with = "retry")]
+        wait: bool,
+        /// Probe the ssh port before fetching the key, to tell DNS failures, closed ports and
+        /// unreachable hosts apart without waiting for AWS. With --retry or --wait the probe is
+        /// retried as well
+        #[arg(long)]
+        check: bool,
+        /// Start the EC2 instance of the host if it is stopped and wait until it accepts
+        /// connections
+        #[arg(long)]
+        start: bool,
+        /// Key alias to connect with instead of the configured one, like a break-glass key or
+        /// the new key during a rotation. The fallback key aliases of the host are not tried
+        #[arg(short = 'a', long)]
+        alias: Option<String>,
         /// The arguments to pass to the SSH command
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         ssh_args: Vec<String>,
@@ -32,40 +101,436 @@ pub enum SMSSHCommand {
         /// The key alias to use
         #[arg()]
         key_alias: String,
+        /// Print the ssh command and the key alias that would be used without fetching the key
+        #[arg(long)]
+        dry_run: bool,
+        /// Record the session to the given file, or to the recordings directory. Files ending
+        /// with `.cast` are written in the asciicast format, others as script(1) typescripts
+        #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+        record: Option<Option<PathBuf>>,
+        /// Retry when ssh fails to connect, up to N times (5 by default) with a growing delay.
+        /// The key is fetched once for all attempts
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "5")]
+        retry: Option<u32>,
+        /// Retry until the host accepts connections, for hosts that are still booting
+        #[arg(long, conflicts_with = "retry")]
+        wait: bool,
+        /// Probe the ssh port before fetching the key, to tell DNS failures, closed ports and
+        /// unreachable hosts apart without waiting for AWS. With --retry or --wait the probe is
+        /// retried as well
+        #[arg(long)]
+        check: bool,
         /// The arguments to pass to the SSH command
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         ssh_args: Vec<String>,
     },
+    /// Run a command configured for a host, like "logs" for `journalctl -u app -f`, see
+    /// `config set host --command`. Exits with the status of the command
+    Run {
+        /// The host the command is configured for
+        #[arg()]
+        host: String,
+        /// Name of the command
+        #[arg()]
+        command: String,
+        /// Print the ssh command and the key alias that would be used without fetching the key
+        #[arg(long)]
+        dry_run: bool,
+        /// Arguments appended to the command, quoted for the remote shell
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// List recent connections, or connect again to the one with the given index
+    #[command(alias = "r")]
+    Recent {
+        /// Index of the connection to repeat, 1 being the most recent
+        #[arg()]
+        index: Option<usize>,
+        /// Number of connections to list
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Print the ssh command and the key alias that would be used without fetching the key
+        #[arg(long, requires = "index")]
+        dry_run: bool,
+    },
+    /// Show how often and how long hosts or key aliases were used, based on the connection
+    /// history
+    #[command()]
+    Stats {
+        /// Group the usage by host or by key alias
+        #[arg(short, long, value_enum, default_value_t = StatsKind::Host)]
+        by: StatsKind,
+        /// Only show entries that were not used in this many days, including unused ones
+        #[arg(short, long, value_name = "DAYS")]
+        unused: Option<u64>,
+    },
+    /// Prefetch the keys of a host, a group or all key aliases through the daemon, so that the
+    /// next connects don't wait for AWS. Keys are only kept if the daemon runs with
+    /// `--cache-keys`
+    #[command()]
+    Warm {
+        /// Host or group to prefetch the keys of, all key aliases if not set
+        #[arg()]
+        target: Option<String>,
+    },
+    /// Generate an ed25519 keypair, store the private key in a new Secrets Manager secret and add
+    /// a key alias for it. Prints the public key to install on the hosts
+    #[command()]
+    Keygen {
+        /// Name of the new key alias
+        #[arg()]
+        name: String,
+        /// Name of the secret to create, the key alias name if not set
+        #[arg(long)]
+        secret_name: Option<String>,
+        /// AWS profile used to create and later fetch the secret, defaults to the default
+        /// credentials chain
+        #[arg(short = 'p', long)]
+        profile: Option<String>,
+        /// KMS key ID or ARN to encrypt the secret with, the default key of Secrets Manager if
+        /// not set
+        #[arg(long)]
+        kms_key_id: Option<String>,
+        /// Tag of the secret as KEY=VALUE, can be repeated
+        #[arg(long = "tag", value_parser = parse_env_var)]
+        tags: Vec<(String, String)>,
+        /// Comment of the public key, the key alias name if not set
+        #[arg(short = 'C', long)]
+        comment: Option<String>,
+        /// Also write the public key to this file
+        #[arg(short = 'o', long, value_name = "PATH")]
+        public_key_file: Option<PathBuf>,
+    },
+    /// Rotate the key of a Secrets Manager key alias: install a new public key on its hosts with
+    /// the current key, store the new key as a new version of the secret and check that the
+    /// hosts accept it
+    #[command()]
+    Rotate {
+        /// The key alias to rotate
+        #[arg()]
+        key_alias: String,
+        /// A host to rotate the key on, can be repeated. All hosts using the key alias if not
+        /// set, which the given hosts have to include since they share the secret
+        #[arg(long = "host")]
+        hosts: Vec<String>,
+        /// Remove the old public key from authorized_keys on the hosts that accept the new key
+        #[arg(long)]
+        remove_old: bool,
+        /// Print the secret and the hosts that would be changed without changing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the public key of a key alias, derived from its private key in memory
+    #[command()]
+    Pubkey {
+        /// The key alias to print the public key of
+        #[arg()]
+        key_alias: String,
+        /// Append a comment to the public key, the key alias name if no value is given
+        #[arg(short = 'C', long, num_args = 0..=1, require_equals = true)]
+        comment: Option<Option<String>>,
+        /// Copy the public key to the clipboard instead of printing it. On Linux, smssh waits
+        /// until a clipboard manager or another copy takes the clipboard over
+        #[arg(short = 'c', long)]
+        copy: bool,
+    },
+    /// Print the SHA256 fingerprint of the key of a key alias, or of the host keys a host offers
+    #[command()]
+    Fingerprint {
+        #[command(subcommand)]
+        target: FingerprintTarget,
+    },
+    /// Add a public key to the authorized_keys of a host, connecting with the key of its alias.
+    /// Keys that are already authorized are not added again
+    #[command(group(ArgGroup::new("public_key").required(true)))]
+    PushKey {
+        /// The host to add the public key to
+        #[arg()]
+        host: String,
+        /// File containing the public key, like ~/.ssh/id_ed25519.pub
+        #[arg(short = 'f', long, group = "public_key", value_name = "PATH")]
+        file: Option<PathBuf>,
+        /// The public key itself, like "ssh-ed25519 AAAA... user@laptop"
+        #[arg(short = 'k', long, group = "public_key")]
+        key: Option<String>,
+        /// Key alias to derive the public key from
+        #[arg(long, group = "public_key", value_name = "KEY_ALIAS")]
+        from_alias: Option<String>,
+        /// Remote user to authorize the key for through sudo, the user logged in as if not set
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+        /// Key alias to connect with, the key alias of the host if not set
+        #[arg(short = 'a', long)]
+        alias: Option<String>,
+    },
+    /// Log into a host without a terminal and report whether fetching the key, reaching the host,
+    /// verifying its host key and authenticating succeeded. Tests a group or all hosts with a
+    /// summary table
+    #[command(group(ArgGroup::new("target").required(true)))]
+    Test {
+        /// The host to test
+        #[arg(group = "target")]
+        host: Option<String>,
+        /// Test the hosts of this group
+        #[arg(short, long, group = "target")]
+        group: Option<String>,
+        /// Test all hosts
+        #[arg(long, group = "target")]
+        all: bool,
+    },
+    /// Upload a file or directory into a directory on several hosts in parallel, created if
+    /// missing. Keys are fetched once per key alias
+    #[command(group(ArgGroup::new("target").required(true)))]
+    Put {
+        /// The local file or directory to upload
+        #[arg()]
+        local: PathBuf,
+        /// The remote directory to upload into, relative to the home directory unless absolute
+        #[arg()]
+        remote_dir: String,
+        /// A host to upload to, can be repeated
+        #[arg(short = 'H', long = "host", group = "target")]
+        hosts: Vec<String>,
+        /// Upload to the hosts of this group
+        #[arg(short, long, group = "target")]
+        group: Option<String>,
+    },
+    /// Download a file or directory from several hosts in parallel, into a directory per host
+    /// named after it inside the local directory. Keys are fetched once per key alias
+    #[command(group(ArgGroup::new("target").required(true)))]
+    Get {
+        /// The remote file or directory to download, relative to the home directory unless
+        /// absolute
+        #[arg()]
+        remote: String,
+        /// The local directory the directories of the hosts are created in
+        #[arg(default_value = ".")]
+        local_dir: PathBuf,
+        /// A host to download from, can be repeated
+        #[arg(short = 'H', long = "host", group = "target")]
+        hosts: Vec<String>,
+        /// Download from the hosts of this group
+        #[arg(short, long, group = "target")]
+        group: Option<String>,
+    },
+    /// Open a tmux window or zellij tab per host, each connected with smssh. Keys from Secrets
+    /// Manager are fetched once per key alias and handed to the windows over a private socket
+    #[command(group(ArgGroup::new("multiplexer").required(true)))]
+    Open {
+        /// The hosts to open
+        #[arg(required_unless_present = "group", conflicts_with = "group")]
+        hosts: Vec<String>,
+        /// Open the hosts of this group
+        #[arg(short, long)]
+        group: Option<String>,
+        /// Open the hosts in tmux, in the current session when running inside tmux
+        #[arg(long, group = "multiplexer")]
+        tmux: bool,
+        /// Open the hosts in zellij, in the current session when running inside zellij
+        #[arg(long, group = "multiplexer")]
+        zellij: bool,
+    },
+    /// Forward a configured database to a local port through its bastion host and print a
+    /// connection string to it. The forward stays open until ssh exits or Ctrl-C is pressed.
+    /// The endpoint is looked up with the AWS CLI, which has to be installed
+    #[command()]
+    Db {
+        /// Name of the database
+        #[arg()]
+        name: String,
+        /// Local port of the forward, overriding the configured one
+        #[arg(short = 'p', long)]
+        local_port: Option<u16>,
+        /// Print the connection string and the ssh command without connecting
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show the AWS identity and region the credentials of a key alias resolve to, after any
+    /// role assumptions of its profile. Shows all key aliases if none is given
+    #[command()]
+    Whoami {
+        /// The key alias to check
+        #[arg()]
+        key_alias: Option<String>,
+    },
     /// Manage the SSH configuration
     #[command(alias = "cfg")]
     Config {
         #[command(subcommand)]
         command: SSHConfig,
     },
-    /// Generate shell completions
+    /// Interactively create the first key alias and host
+    #[command()]
+    Init,
+    /// Check the environment for common problems: ssh, AWS credentials, key storage, config
+    /// permissions and secret accessibility
+    #[command()]
+    Doctor,
+    /// Run in the foreground, keeping AWS credentials resolved for faster connects. Connects use
+    /// the daemon automatically while it runs
+    #[command()]
+    Daemon {
+        /// Also keep fetched keys in locked memory for this long, like "15m"
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        cache_keys: Option<String>,
+        /// Serve Prometheus metrics of the key fetches on this address, like "127.0.0.1:9464"
+        #[arg(long, value_name = "ADDRESS")]
+        metrics: Option<String>,
+        /// Exit after this long without requests, for starting on demand through systemd
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        idle_timeout: Option<String>,
+        /// Print user level systemd units starting it on the first connection to its socket,
+        /// and exit
+        #[arg(long)]
+        print_systemd_unit: bool,
+    },
+    /// Run an ssh agent in the foreground that fetches the keys of key aliases when a client
+    /// first signs with them. Point `IdentityAgent` or SSH_AUTH_SOCK at its socket to use the
+    /// keys from any tool speaking ssh, without key files. Key aliases are offered once their
+    /// public key is known, run `smssh pubkey ALIAS` once for new ones
+    #[command()]
+    Agent {
+        /// Key aliases to offer, all of them if not set
+        #[arg()]
+        aliases: Vec<String>,
+        /// How long a fetched key is kept before it is fetched again, like "15m"
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "15m")]
+        key_ttl: String,
+        /// Serve Prometheus metrics of the key fetches on this address, like "127.0.0.1:9464"
+        #[arg(long, value_name = "ADDRESS")]
+        metrics: Option<String>,
+        /// Exit after this long without requests, for starting on demand through systemd
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        idle_timeout: Option<String>,
+        /// Print user level systemd units starting it on the first connection to its socket,
+        /// and exit. Refused for key aliases with a second factor, which can't be asked for
+        #[arg(long)]
+        print_systemd_unit: bool,
+    },
+    /// Install shell completions where the shell loads them from, or print them
     #[command()]
     Completions {
-        /// The shell to generate completions for
-        #[arg(short, long, value_enum, default_value_t = Shell::Fish)]
-        shell: Shell,
+        /// The shell to generate completions for, detected from $SHELL if not set
+        #[arg(short, long, value_enum)]
+        shell: Option<Shell>,
+        /// Write the completions into the completions directory of the shell, the default
+        #[arg(long, conflicts_with = "print")]
+        install: bool,
+        /// Print the completions to stdout instead of installing them
+        #[arg(long)]
+        print: bool,
+    },
+    /// Generate man pages for smssh and all of its subcommands
+    #[command()]
+    Man {
+        /// The directory to write the man pages to
+        #[arg(default_value = ".")]
+        out_dir: PathBuf,
+    },
+    /// Print the configured names of the given kind, used by the shell completions
+    #[command(hide = true)]
+    CompleteNames {
+        #[arg(value_enum)]
+        kind: NameKind,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FingerprintTarget {
+    /// Fingerprint of the public key derived from the key of a key alias
+    Alias {
+        /// The key alias to fingerprint
+        #[arg()]
+        key_alias: String,
+    },
+    /// Fingerprints of the host keys offered by a host, scanned with ssh-keyscan
+    Host {
+        /// The host configuration or destination to scan
+        #[arg()]
+        host: String,
     },
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum NameKind {
+    Host,
+    Alias,
+    Template,
+    Group,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum StatsKind {
+    Host,
+    Alias,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+/// Orders of the host listing
+#[derive(ValueEnum, Debug, Clone, Copy, Default)]
+pub enum HostSort {
+    #[default]
+    Name,
+    /// Most recently connected first, hosts never connected to last
+    LastUsed,
+}
+
+/// Machine-readable output formats for list-like commands
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum OutputFormat {
+    Yaml,
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum SSHConfig {
     /// List the configured key aliases
     #[command(alias = "l")]
     List {
+        /// Print a machine-readable listing in the given format instead of the human-readable one
+        #[arg(short, long, global = true, value_enum)]
+        output: Option<OutputFormat>,
         /// The SSH configuration section to list
         #[command(subcommand)]
         section: ListConfigSection,
     },
-    /// Add a new configuration entry
+    /// Show a host or key alias in detail. Hosts are shown with their templates and defaults
+    /// applied, their key alias and the options ssh would use. Key aliases are shown with the
+    /// hosts, templates and patterns using them
+    #[command(alias = "g")]
+    Get {
+        /// Name of a host or key alias, both are shown if the name is used by both
+        #[arg()]
+        name: String,
+        /// Print the details in the given format instead of the human-readable one
+        #[arg(short, long, value_enum)]
+        output: Option<OutputFormat>,
+    },
+    /// Add a new configuration entry. Fails if the entry already exists unless `--force` or
+    /// `--update` is given
     #[command(alias = "s")]
     Set {
+        /// Replace an existing entry entirely
+        #[arg(short, long, global = true, conflicts_with = "update")]
+        force: bool,
+        /// Update only the given settings of an existing entry
+        #[arg(short, long, global = true)]
+        update: bool,
         /// The SSH configuration section to modify
         #[command(subcommand)]
-        section: SetConfigSection,
+        section: Box<SetConfigSection>,
     },
     /// Remove a configuration entry
     #[command(alias = "r")]
@@ -74,6 +539,71 @@ pub enum SSHConfig {
         #[command(subcommand)]
         section: RemoveConfigSection,
     },
+    /// Rename a configuration entry and update every entry referencing it
+    #[command(alias = "mv")]
+    Rename {
+        /// The SSH configuration section to modify
+        #[command(subcommand)]
+        section: RenameConfigSection,
+    },
+    /// Pull a shared host and alias inventory, which is merged read-only beneath the local
+    /// entries
+    #[command()]
+    Sync {
+        /// Source of the shared config: s3://bucket/key, an https:// URL,
+        /// git+<repository>[#path] with an https://, ssh:// or user@host:path repository, or a
+        /// file path. Defaults to the last synced source
+        #[arg()]
+        source: Option<String>,
+        /// Only show how the shared config differs from the last synced copy, failing if it does
+        #[arg(short, long)]
+        check: bool,
+    },
+    /// Show the entries that syncing the shared config from a source would add, remove or
+    /// change, taking the local entries overriding the shared ones into account
+    #[command()]
+    Diff {
+        /// Source of the shared config, like for `config sync`. Defaults to the last synced source
+        #[arg()]
+        source: Option<String>,
+    },
+    /// Import hosts from another tool
+    #[command()]
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Export the hosts for another tool, printed to stdout
+    #[command()]
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    /// Restore the configuration from a backup
+    #[command()]
+    Restore {
+        /// Timestamp of the backup to restore, defaults to the latest backup
+        #[arg()]
+        backup: Option<u128>,
+        /// List the available backups instead of restoring one
+        #[arg(short, long)]
+        list: bool,
+    },
+    /// Restrict the permissions of the config file and its backups to the current user
+    #[command()]
+    FixPermissions,
+    /// Encrypt the config and its backups at rest with a passphrase. The passphrase is read
+    /// from the SMSSH_CONFIG_PASSPHRASE environment variable or the OS keyring, or prompted for
+    /// on every load
+    #[command()]
+    Encrypt {
+        /// Store the passphrase in the OS keyring, which unlocks the config without prompting
+        #[arg(long)]
+        keyring: bool,
+    },
+    /// Store the config as plaintext again
+    #[command()]
+    Decrypt,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -83,7 +613,57 @@ pub enum ListConfigSection {
     Alias,
     /// Manage the SSH hosts
     #[command(alias = "h")]
-    Host,
+    Host {
+        /// Only list hosts with all of these tags
+        #[arg(short, long)]
+        tag: Vec<String>,
+        /// Only list hosts whose name, destination, key alias or tags match this case-insensitive
+        /// regex, or contain it if it is not a valid regex
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Order of the hosts
+        #[arg(short, long, value_enum, default_value_t)]
+        sort: HostSort,
+    },
+    /// Manage the host templates
+    #[command(alias = "t")]
+    Template,
+    /// Manage the host groups
+    #[command(alias = "g")]
+    Group,
+    /// Manage the databases forwarded by `smssh db`
+    #[command()]
+    Database,
+    /// Manage the networks required by hosts
+    #[command()]
+    Network,
+    /// Show the default key alias
+    #[command(alias = "d")]
+    DefaultAlias,
+    /// Show the key aliases used for destinations by their hostname, in matching order
+    #[command(alias = "p")]
+    Pattern,
+    /// Show where connections are audited
+    #[command()]
+    AuditLog,
+    /// Show the global maximum session duration
+    #[command()]
+    MaxSession,
+    /// Show the global timeout for fetching keys
+    #[command()]
+    FetchTimeout,
+    /// Show the maximum age of the keys used when AWS is unreachable
+    #[command()]
+    OfflineCache,
+    /// Show how long sessions have to run before their drops are notified
+    #[command()]
+    NotifyDrops,
+    /// Show the tags of hosts that ask before connecting
+    #[command()]
+    ConfirmTags,
+    /// Show whether the ssh agent is forwarded to hosts without their own policy
+    #[command()]
+    ForwardAgent,
 }
 
 #[derive(Subcommand, Debug)]
@@ -101,16 +681,429 @@ pub enum SetConfigSection {
         /// Name of this host configuration
         #[arg(short = 'n', long)]
         name: String,
-        /// Name of an existing key alias to use as the SSH private key
+        /// Other name to connect to the host by, can be repeated
+        #[arg(long = "nickname")]
+        nicknames: Vec<String>,
+        /// Name of an existing key alias to use as the SSH private key, inherited from the
+        /// template or the default key alias if not set
         #[arg(short = 'a', long)]
-        alias: String,
-        /// SSH destination, example: user@hostname
+        alias: Option<String>,
+        /// Key aliases tried in order when ssh fails with the previous one, can be repeated
+        #[arg(long = "fallback-alias")]
+        fallback_aliases: Vec<String>,
+        /// Key aliases whose keys ssh is offered along with the key alias, can be repeated
+        #[arg(long = "extra-key-alias")]
+        extra_key_aliases: Vec<String>,
+        /// Configured host to connect through with its own key alias, can be repeated to chain
+        /// them in order
+        #[arg(long = "jump-host")]
+        jump_hosts: Vec<String>,
+        /// Name of an existing host template to inherit settings from
+        #[arg(short = 'e', long)]
+        extends: Option<String>,
+        /// SSH destination, example: user@hostname. Required for new hosts
         #[arg(short = 'd', long)]
-        destination: String,
+        destination: Option<String>,
+        /// Tags used to organize and filter hosts, can be repeated
+        #[arg(short = 't', long = "tag")]
+        tags: Vec<String>,
+        /// Environment variables set on the ssh process as NAME=VALUE, can be repeated
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Remote shell command run by name with `smssh run` as NAME=COMMAND, can be repeated
+        #[arg(long = "command", value_name = "NAME=COMMAND", value_parser = parse_env_var)]
+        commands: Vec<(String, String)>,
+        /// Always record the sessions, see `connect --record`
+        #[arg(long)]
+        record: Option<bool>,
+        /// Terminate sessions after this long, like "8h" or "1h30m"
+        #[arg(long, value_parser = parse_duration)]
+        max_session: Option<String>,
+        /// Ask before connecting, to protect sensitive hosts from typos
+        #[arg(long, value_enum)]
+        confirm: Option<ConfirmPolicy>,
+        /// Probe the ssh port before fetching the key, see `connect --check`
+        #[arg(long)]
+        check_reachable: Option<bool>,
+        /// Forward the ssh agent to the host, overriding the global policy and the ssh config
+        #[arg(long)]
+        forward_agent: Option<bool>,
+        /// Forward X11 to the host with -X, or with -Y for trusted clients
+        #[arg(long, value_enum)]
+        forward_x11: Option<X11Forwarding>,
+        /// Keep the first connection open in the background this long after its sessions closed,
+        /// like "10m", so that the next sessions reuse it. Ignored if the host has a max session
+        #[arg(long, value_parser = parse_duration)]
+        control_persist: Option<String>,
+        /// ID of the EC2 instance running the host, like "i-0123456789abcdef0"
+        #[arg(long)]
+        instance_id: Option<String>,
+        /// Start the instance if it is stopped, see `connect --start`
+        #[arg(long)]
+        auto_start: Option<bool>,
+        /// Stop the instance when the last session to it closes
+        #[arg(long, value_enum)]
+        stop_on_exit: Option<StopPolicy>,
+        /// Print the AWS console, Session Manager and CloudWatch Logs links of the instance when
+        /// a session starts, or open its EC2 console page and print the others
+        #[arg(long, value_enum)]
+        console_links: Option<ConsoleLinks>,
+        /// Local shell command run before connecting, like starting a VPN. The connection is
+        /// aborted if it fails
+        #[arg(long)]
+        pre_connect: Option<String>,
+        /// Local shell command run after ssh exits, like cleaning up or sending a notification
+        #[arg(long)]
+        post_disconnect: Option<String>,
+        /// Port knocked on before connecting, as PORT[/tcp|udp][:DELAY_MS], can be repeated to
+        /// knock a sequence in order
+        #[arg(long = "knock", value_name = "KNOCK", value_parser = parse_knock)]
+        knock: Vec<KnockConfig>,
+        /// Network that has to be connected before the key is fetched, see `config set network`
+        #[arg(long)]
+        network: Option<String>,
+        /// Look up the hostname through AWS before connecting, as route53:<ZONE_ID> for a
+        /// hosted zone or ec2 for the private DNS names of instances. Route 53 is queried with
+        /// the AWS CLI, which has to be installed
+        #[arg(long, value_parser = parse_resolver)]
+        resolver: Option<ResolverConfig>,
+        /// Reach the instance through an SSM session or an EC2 Instance Connect Endpoint rather
+        /// than over the network, for instances without a route to their ssh port. Needs the
+        /// instance ID and the AWS CLI, and the Session Manager plugin for SSM
+        #[arg(long, value_enum)]
+        transport: Option<Transport>,
         /// Extra SSH arguments
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
+    /// Add a new host template with settings shared by the hosts extending it
+    #[command(alias = "t")]
+    Template {
+        /// Name of this host template
+        #[arg(short = 'n', long)]
+        name: String,
+        /// Name of an existing key alias to use as the SSH private key
+        #[arg(short = 'a', long)]
+        alias: Option<String>,
+        /// Key aliases tried in order when ssh fails with the previous one, can be repeated
+        #[arg(long = "fallback-alias")]
+        fallback_aliases: Vec<String>,
+        /// Key aliases whose keys ssh is offered along with the key alias, can be repeated
+        #[arg(long = "extra-key-alias")]
+        extra_key_aliases: Vec<String>,
+        /// Configured host to connect through with its own key alias, can be repeated to chain
+        /// them in order
+        #[arg(long = "jump-host")]
+        jump_hosts: Vec<String>,
+        /// Name of an existing host template to inherit settings from
+        #[arg(short = 'e', long)]
+        extends: Option<String>,
+        /// Tags used to organize and filter hosts, can be repeated
+        #[arg(short = 't', long = "tag")]
+        tags: Vec<String>,
+        /// Environment variables set on the ssh process as NAME=VALUE, can be repeated
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Remote shell command run by name with `smssh run` as NAME=COMMAND, can be repeated
+        #[arg(long = "command", value_name = "NAME=COMMAND", value_parser = parse_env_var)]
+        commands: Vec<(String, String)>,
+        /// Always record the sessions, see `connect --record`
+        #[arg(long)]
+        record: Option<bool>,
+        /// Terminate sessions after this long, like "8h" or "1h30m"
+        #[arg(long, value_parser = parse_duration)]
+        max_session: Option<String>,
+        /// Ask before connecting, to protect sensitive hosts from typos
+        #[arg(long, value_enum)]
+        confirm: Option<ConfirmPolicy>,
+        /// Probe the ssh port before fetching the key, see `connect --check`
+        #[arg(long)]
+        check_reachable: Option<bool>,
+        /// Forward the ssh agent to the host, overriding the global policy and the ssh config
+        #[arg(long)]
+        forward_agent: Option<bool>,
+        /// Forward X11 to the host with -X, or with -Y for trusted clients
+        #[arg(long, value_enum)]
+        forward_x11: Option<X11Forwarding>,
+        /// Keep the first connection open in the background this long after its sessions closed,
+        /// like "10m", so that the next sessions reuse it. Ignored if the host has a max session
+        #[arg(long, value_parser = parse_duration)]
+        control_persist: Option<String>,
+        /// ID of the EC2 instance running the host, like "i-0123456789abcdef0"
+        #[arg(long)]
+        instance_id: Option<String>,
+        /// Start the instance if it is stopped, see `connect --start`
+        #[arg(long)]
+        auto_start: Option<bool>,
+        /// Stop the instance when the last session to it closes
+        #[arg(long, value_enum)]
+        stop_on_exit: Option<StopPolicy>,
+        /// Print the AWS console, Session Manager and CloudWatch Logs links of the instance when
+        /// a session starts, or open its EC2 console page and print the others
+        #[arg(long, value_enum)]
+        console_links: Option<ConsoleLinks>,
+        /// Local shell command run before connecting, like starting a VPN. The connection is
+        /// aborted if it fails
+        #[arg(long)]
+        pre_connect: Option<String>,
+        /// Local shell command run after ssh exits, like cleaning up or sending a notification
+        #[arg(long)]
+        post_disconnect: Option<String>,
+        /// Port knocked on before connecting, as PORT[/tcp|udp][:DELAY_MS], can be repeated to
+        /// knock a sequence in order
+        #[arg(long = "knock", value_name = "KNOCK", value_parser = parse_knock)]
+        knock: Vec<KnockConfig>,
+        /// Network that has to be connected before the key is fetched, see `config set network`
+        #[arg(long)]
+        network: Option<String>,
+        /// Look up the hostname through AWS before connecting, as route53:<ZONE_ID> for a
+        /// hosted zone or ec2 for the private DNS names of instances. Route 53 is queried with
+        /// the AWS CLI, which has to be installed
+        #[arg(long, value_parser = parse_resolver)]
+        resolver: Option<ResolverConfig>,
+        /// Reach the instance through an SSM session or an EC2 Instance Connect Endpoint rather
+        /// than over the network, for instances without a route to their ssh port. Needs the
+        /// instance ID and the AWS CLI, and the Session Manager plugin for SSM
+        #[arg(long, value_enum)]
+        transport: Option<Transport>,
+        /// Extra SSH arguments
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Add a new host group
+    #[command(alias = "g")]
+    Group {
+        /// Name of this host group
+        #[arg(short = 'n', long)]
+        name: String,
+        /// Names of the existing host configurations in this group
+        #[arg(required = true)]
+        hosts: Vec<String>,
+    },
+    /// Add a database forwarded to a local port through a bastion host by `smssh db`
+    #[command(group(ArgGroup::new("source").required(true)))]
+    Database {
+        /// Name of this database
+        #[arg(short = 'n', long)]
+        name: String,
+        /// Name of the host the database is reached through
+        #[arg(short = 'b', long)]
+        bastion: String,
+        /// Identifier of an RDS instance
+        #[arg(long, group = "source")]
+        rds: Option<String>,
+        /// Identifier of an Aurora cluster, connected to through its writer endpoint
+        #[arg(long, group = "source")]
+        rds_cluster: Option<String>,
+        /// ID of an ElastiCache replication group
+        #[arg(long, group = "source")]
+        elasticache: Option<String>,
+        /// AWS profile used to look up the endpoint
+        #[arg(long)]
+        profile: Option<String>,
+        /// Local port of the forward, a free port is picked if not set
+        #[arg(long)]
+        local_port: Option<u16>,
+        /// User in the connection string, the master user if not set
+        #[arg(long)]
+        user: Option<String>,
+        /// Database name in the connection string, the initial database if not set
+        #[arg(long)]
+        database: Option<String>,
+    },
+    /// Add a network like a VPN or a tailnet that hosts can require with --network. It is
+    /// connected while all of its checks pass, and brought up by its up command if it is not.
+    #[command()]
+    Network {
+        /// Name of this network, like "corp-vpn"
+        #[arg(short = 'n', long)]
+        name: String,
+        /// Network interface present while connected, like "tailscale0"
+        #[arg(long)]
+        interface: Option<String>,
+        /// HOST:PORT only reachable while connected, like "10.0.0.2:53"
+        #[arg(long)]
+        probe: Option<String>,
+        /// Local shell command bringing the network up, like "tailscale up"
+        #[arg(long)]
+        up: Option<String>,
+    },
+    /// Set the key alias used when connecting to destinations that are not configured as hosts
+    #[command(alias = "d")]
+    DefaultAlias {
+        /// Name of an existing key alias
+        #[arg()]
+        alias: String,
+    },
+    /// Use a key alias for the destinations that are not configured as hosts and whose hostname
+    /// matches a pattern. Patterns are tried in the order they were added, before the default key
+    /// alias.
+    #[command(alias = "p")]
+    Pattern {
+        /// Hostname pattern, where `*` matches any characters and `?` a single one, like
+        /// "*.prod.example.com"
+        #[arg()]
+        pattern: String,
+        /// Name of an existing key alias
+        #[arg()]
+        alias: String,
+    },
+    /// Audit every key fetch and connection to an append-only log
+    #[command()]
+    AuditLog {
+        #[command(subcommand)]
+        kind: AuditLogKind,
+    },
+    /// Terminate sessions to hosts without their own limit after this long
+    #[command()]
+    MaxSession {
+        /// Maximum session duration, like "8h" or "1h30m"
+        #[arg(value_parser = parse_duration)]
+        duration: String,
+    },
+    /// Give up fetching keys of aliases without their own timeout after this long
+    #[command()]
+    FetchTimeout {
+        /// Timeout for resolving the credentials and for fetching the secret, like "10s"
+        #[arg(value_parser = parse_duration)]
+        duration: String,
+    },
+    /// Cache fetched keys, encrypted with the config passphrase, and fall back to them when AWS is
+    /// unreachable
+    #[command()]
+    OfflineCache {
+        /// Maximum age of the cached keys, like "7d"
+        #[arg(value_parser = parse_duration)]
+        max_age: String,
+    },
+    /// Show a desktop notification when a session drops, like a tunnel kept open behind an
+    /// editor. Uses notify-send on Linux and osascript on macOS
+    #[command()]
+    NotifyDrops {
+        /// Only notify drops of sessions that ran at least this long, so that failures to
+        /// connect are not notified
+        #[arg(value_parser = parse_duration, default_value = "1m")]
+        min_duration: String,
+    },
+    /// Ask before connecting to hosts with any of these tags, unless they set their own policy
+    #[command()]
+    ConfirmTags {
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Forward the ssh agent or not to hosts without their own policy, instead of leaving it to
+    /// the ssh config
+    #[command()]
+    ForwardAgent {
+        #[arg(action = ArgAction::Set)]
+        enabled: bool,
+    },
+}
+
+/// Validate a duration like "8h" or "1h30m", keeping it as written.
+fn parse_duration(value: &str) -> Result<String, String> {
+    crate::duration::parse(value).map_err(|e| e.to_string())?;
+    Ok(value.to_string())
+}
+
+/// Validate the name of a key provider plugin, keeping it as written.
+fn parse_plugin_name(value: &str) -> Result<String, String> {
+    crate::plugin::validate_name(value).map_err(|e| e.to_string())?;
+    Ok(value.to_string())
+}
+
+/// Parse a port knock like "7000", "7000/udp" or "7000/tcp:500".
+fn parse_knock(value: &str) -> Result<KnockConfig, String> {
+    value.parse().map_err(|e: color_eyre::Report| e.to_string())
+}
+
+/// Parse a resolver like "route53:Z0123456789ABC" or "ec2".
+fn parse_resolver(value: &str) -> Result<ResolverConfig, String> {
+    value.parse().map_err(|e: color_eyre::Report| e.to_string())
+}
+
+/// Parse a NAME=VALUE environment variable assignment.
+fn parse_env_var(assignment: &str) -> Result<(String, String), String> {
+    let (name, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("'{assignment}' is not in the NAME=VALUE format"))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportSource {
+    /// Import the hosts named in the Host blocks of an ssh config. HostName and User become the
+    /// destination, Port and ProxyJump become -p and -J, ForwardAgent and ForwardX11 become the
+    /// forwarding settings, and other options are passed with -o. Identity options are dropped in
+    /// favor of the key alias
+    SshConfig {
+        /// The ssh config to import, ~/.ssh/config if not set
+        #[arg()]
+        path: Option<PathBuf>,
+        /// Key alias to attach to all imported hosts instead of asking for each one
+        #[arg(short = 'a', long)]
+        alias: Option<String>,
+        /// Replace hosts that already exist instead of skipping them
+        #[arg(short, long)]
+        force: bool,
+        /// Print the hosts that would be imported without changing the config
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Import the EC2 instances with the given tags as hosts, with their instance IDs set so
+    /// that they can be started on connect
+    Ec2 {
+        /// Only import instances with this tag as KEY=VALUE, can be repeated
+        #[arg(short = 't', long = "tag", value_parser = parse_env_var)]
+        tags: Vec<(String, String)>,
+        /// AWS profile used to list the instances, defaults to the default credentials chain
+        #[arg(short = 'p', long)]
+        profile: Option<String>,
+        /// Tag holding the host names, instances without it are named after their ID
+        #[arg(long, default_value = "Name")]
+        name_tag: String,
+        /// Address of the instances to connect to
+        #[arg(long, value_enum, default_value_t = InstanceAddress::Private)]
+        address: InstanceAddress,
+        /// User to log in as on the instances
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+        /// Key alias to attach to all imported hosts, otherwise each instance is confirmed and
+        /// asked for its key alias
+        #[arg(short = 'a', long)]
+        alias: Option<String>,
+        /// Replace hosts that already exist instead of skipping them
+        #[arg(short, long)]
+        force: bool,
+        /// Print the hosts that would be imported without changing the config
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportTarget {
+    /// Export the hosts as an Ansible inventory with their address, user, port and ssh args.
+    /// Tags and groups become Ansible groups
+    Ansible {
+        /// Inventory format
+        #[arg(short, long, value_enum, default_value_t = InventoryFormat::Ini)]
+        format: InventoryFormat,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum InventoryFormat {
+    Ini,
+    Yaml,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum InstanceAddress {
+    Public,
+    Private,
+    /// The instance ID, for ssh configs proxying connections through SSM
+    InstanceId,
 }
 
 #[derive(Subcommand, Debug)]
@@ -121,6 +1114,12 @@ pub enum RemoveConfigSection {
         /// The key alias to remove
         #[arg()]
         alias_name: String,
+        /// Also remove the hosts using the key alias, after confirmation
+        #[arg(short, long)]
+        force: bool,
+        /// Only show what `--force` would remove
+        #[arg(long, requires = "force")]
+        dry_run: bool,
     },
     /// Remove a host configuration
     #[command(alias = "h")]
@@ -129,6 +1128,109 @@ pub enum RemoveConfigSection {
         #[arg()]
         name: String,
     },
+    /// Remove a host template
+    #[command(alias = "t")]
+    Template {
+        /// Name of the host template to remove
+        #[arg()]
+        name: String,
+    },
+    /// Remove a host group
+    #[command(alias = "g")]
+    Group {
+        /// Name of the host group to remove
+        #[arg()]
+        name: String,
+    },
+    /// Remove a database
+    #[command()]
+    Database {
+        /// Name of the database to remove
+        #[arg()]
+        name: String,
+    },
+    /// Remove a network
+    #[command()]
+    Network {
+        /// Name of the network to remove
+        #[arg()]
+        name: String,
+    },
+    /// Unset the default key alias
+    #[command(alias = "d")]
+    DefaultAlias,
+    /// Remove a hostname pattern
+    #[command(alias = "p")]
+    Pattern {
+        /// The pattern as it was added
+        #[arg()]
+        pattern: String,
+    },
+    /// Stop auditing connections
+    #[command()]
+    AuditLog,
+    /// Unset the global maximum session duration
+    #[command()]
+    MaxSession,
+    /// Unset the global timeout for fetching keys
+    #[command()]
+    FetchTimeout,
+    /// Disable the offline cache and stop caching keys
+    #[command()]
+    OfflineCache,
+    /// Stop notifying dropped sessions
+    #[command()]
+    NotifyDrops,
+    /// Stop asking before connecting to hosts by their tags
+    #[command()]
+    ConfirmTags,
+    /// Leave forwarding the ssh agent to hosts without their own policy to the ssh config
+    #[command()]
+    ForwardAgent,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RenameConfigSection {
+    /// Rename a key alias
+    #[command(alias = "a")]
+    Alias {
+        /// Current name of the key alias
+        #[arg()]
+        old_name: String,
+        /// New name of the key alias
+        #[arg()]
+        new_name: String,
+    },
+    /// Rename a host configuration
+    #[command(alias = "h")]
+    Host {
+        /// Current name of the host configuration
+        #[arg()]
+        old_name: String,
+        /// New name of the host configuration
+        #[arg()]
+        new_name: String,
+    },
+    /// Rename a host template
+    #[command(alias = "t")]
+    Template {
+        /// Current name of the host template
+        #[arg()]
+        old_name: String,
+        /// New name of the host template
+        #[arg()]
+        new_name: String,
+    },
+    /// Rename a host group
+    #[command(alias = "g")]
+    Group {
+        /// Current name of the host group
+        #[arg()]
+        old_name: String,
+        /// New name of the host group
+        #[arg()]
+        new_name: String,
+    },
 }
 
 #[derive(Subcommand, Serialize, Deserialize, Debug)]
@@ -142,13 +1244,67 @@ pub enum AliasKind {
         /// ARN of the Secrets Manager secret containing the SSH private key
         #[arg(short = 'a', long)]
         secret_arn: String,
+        /// AWS profile used to fetch the secret, defaults to the default credentials chain
+        #[arg(short = 'p', long)]
+        profile: Option<String>,
+        /// Ask for a TOTP code generated from this base32 secret before fetching the key. Only
+        /// allowed in an encrypted config, see `config encrypt`
+        #[arg(long)]
+        totp_secret: Option<String>,
+        /// Run this command before fetching the key, like one waiting for a security key touch.
+        /// The key is only fetched if it succeeds
+        #[arg(long, conflicts_with = "totp_secret")]
+        second_factor_command: Option<String>,
+        /// Give up resolving the credentials or fetching the secret after this long, like "10s"
+        #[arg(long, value_parser = parse_duration)]
+        fetch_timeout: Option<String>,
+        /// ARN of a secret containing the passphrase of the key, which is decrypted before ssh
+        /// gets it
+        #[arg(long)]
+        passphrase_secret_arn: Option<String>,
+    },
+    /// Key returned by an external smssh-provider-<PLUGIN> executable in the PATH
+    Plugin {
+        /// Alias name
+        #[arg(short = 'n', long)]
+        name: String,
+        /// Name of the plugin, without the smssh-provider- prefix. Only letters, digits, `_`
+        /// and `-`
+        #[arg(short = 'p', long, value_parser = parse_plugin_name)]
+        plugin: String,
+        /// Parameter passed to the plugin as KEY=VALUE, can be repeated
+        #[arg(long = "param", value_parser = parse_env_var)]
+        params: Vec<(String, String)>,
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum AuditLogKind {
+    /// Append JSON lines to a file
+    File {
+        /// Path of the audit log, environment variables are expanded
+        #[arg()]
+        path: String,
+    },
+    /// Send the records to the local syslog or journald socket
+    Syslog,
+}
+
+impl Args {
+    /// Logging verbosity, -1 for quiet and increased by each `-v`
+    pub fn verbosity(&self) -> i8 {
+        if self.quiet {
+            -1
+        } else {
+            self.verbose.min(i8::MAX as u8) as i8
+        }
+    }
+}
+
 impl AliasKind {
     pub fn name(&self) -> String {
         match self {
-            AliasKind::SecretsManager { name, .. } => name.clone(),
+            AliasKind::SecretsManager { name, .. } | AliasKind::Plugin { name, .. } => name.clone(),
         }
     }
 }
@@ -159,3 +1315,41 @@ impl Display for AliasKind {
         write!(f, "{}", yaml)
     }
 }
+
+impl From<AliasKind> for KeyAliasConfig {
+    fn from(kind: AliasKind) -> Self {
+        match kind {
+            AliasKind::SecretsManager {
+                secret_arn,
+                profile,
+                totp_secret,
+                second_factor_command,
+                fetch_timeout,
+                passphrase_secret_arn,
+                ..
+            } => Self::SecretsManager {
+                secret_arn,
+                profile,
+                second_factor: totp_secret
+                    .map(|secret| SecondFactorConfig::Totp { secret })
+                    .or(second_factor_command
+                        .map(|command| SecondFactorConfig::Command { command })),
+                fetch_timeout,
+                passphrase_secret_arn,
+            },
+            AliasKind::Plugin { plugin, params, .. } => Self::Plugin {
+                name: plugin,
+                params: params.into_iter().collect(),
+            },
+        }
+    }
+}
+
+impl From<AuditLogKind> for AuditLogConfig {
+    fn from(kind: AuditLogKind) -> Self {
+        match kind {
+            AuditLogKind::File { path } => Self::File { path },
+            AuditLogKind::Syslog => Self::Syslog,
+        }
+    }
+}
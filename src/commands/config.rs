@@ -1,98 +1,2468 @@
-use color_eyre::{Result, eyre::eyre};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use regex_lite::RegexBuilder;
+use serde::Serialize;
 
 use crate::{
-    cli::{ListConfigSection, RemoveConfigSection, SetConfigSection},
-    config::{Config, HostConfig, KeyAliasConfig},
+    ansible, aws,
+    cli::{
+        ExportTarget, HostSort, ImportSource, InstanceAddress, InventoryFormat, ListConfigSection,
+        OutputFormat, RemoveConfigSection, RenameConfigSection, SetConfigSection,
+    },
+    commands::print_output,
+    config::{
+        AuditLogConfig, Config, ConfirmPolicy, ConsoleLinks, DatabaseConfig, DatabaseSource,
+        GroupConfig, HostConfig, KeyAliasConfig, KnockConfig, NetworkConfig, PatternConfig,
+        ResolvedHost, SecondFactorConfig, StopPolicy, Transport, X11Forwarding, is_wildcard,
+    },
+    connect::{host_args, shell_quote},
+    duration, encryption,
+    history::{self, HistoryEntry},
+    key_cache,
+    prompt::{self, PromptError},
+    ssh_config, style, sync,
+    table::Table,
 };
+use tracing::warn;
+
+/// A key alias in the machine-readable listing
+#[derive(Serialize)]
+struct AliasOutput<'a> {
+    name: &'a str,
+    kind: &'static str,
+    secret_arn: Option<&'a str>,
+    profile: Option<&'a str>,
+    plugin: Option<&'a str>,
+    params: Option<&'a BTreeMap<String, String>>,
+    second_factor: Option<&'static str>,
+    passphrase_secret_arn: Option<&'a str>,
+}
+
+impl<'a> AliasOutput<'a> {
+    fn new(name: &'a str, alias: &'a KeyAliasConfig) -> Self {
+        match alias {
+            KeyAliasConfig::SecretsManager {
+                secret_arn,
+                profile,
+                second_factor,
+                passphrase_secret_arn,
+                ..
+            } => Self {
+                name,
+                kind: "secrets_manager",
+                secret_arn: Some(secret_arn),
+                profile: profile.as_deref(),
+                plugin: None,
+                params: None,
+                // Only the kind, the TOTP secret is not listed
+                second_factor: second_factor
+                    .as_ref()
+                    .map(|second_factor| match second_factor {
+                        SecondFactorConfig::Totp { .. } => "totp",
+                        SecondFactorConfig::Command { .. } => "command",
+                    }),
+                passphrase_secret_arn: passphrase_secret_arn.as_deref(),
+            },
+            KeyAliasConfig::Plugin {
+                name: plugin,
+                params,
+            } => Self {
+                name,
+                kind: "plugin",
+                secret_arn: None,
+                profile: None,
+                plugin: Some(plugin),
+                params: Some(params),
+                second_factor: None,
+                passphrase_secret_arn: None,
+            },
+        }
+    }
+}
+
+/// A host or template in the machine-readable listing. All fields are always present
+#[derive(Serialize)]
+struct HostOutput<'a> {
+    name: &'a str,
+    nicknames: &'a [String],
+    extends: Option<&'a str>,
+    key_alias: Option<&'a str>,
+    fallback_aliases: &'a [String],
+    extra_key_aliases: &'a [String],
+    jump_hosts: &'a [String],
+    destination: Option<&'a str>,
+    args: &'a [String],
+    tags: &'a [String],
+    env: &'a BTreeMap<String, String>,
+    commands: &'a BTreeMap<String, String>,
+    record: Option<bool>,
+    max_session: Option<&'a str>,
+    confirm: Option<ConfirmPolicy>,
+    check_reachable: Option<bool>,
+    forward_agent: Option<bool>,
+    forward_x11: Option<X11Forwarding>,
+    control_persist: Option<&'a str>,
+    instance_id: Option<&'a str>,
+    auto_start: Option<bool>,
+    stop_on_exit: Option<StopPolicy>,
+    console_links: Option<ConsoleLinks>,
+    pre_connect: Option<&'a str>,
+    post_disconnect: Option<&'a str>,
+    knock: &'a [KnockConfig],
+    network: Option<&'a str>,
+    resolver: Option<String>,
+    transport: Option<Transport>,
+}
+
+impl<'a> HostOutput<'a> {
+    fn new(name: &'a str, host: &'a HostConfig) -> Self {
+        Self {
+            name,
+            nicknames: &host.nicknames,
+            extends: host.extends.as_deref(),
+            key_alias: host.key_alias.as_deref(),
+            fallback_aliases: &host.fallback_aliases,
+            extra_key_aliases: &host.extra_key_aliases,
+            jump_hosts: &host.jump_hosts,
+            destination: host.destination.as_deref(),
+            args: &host.args,
+            tags: &host.tags,
+            env: &host.env,
+            commands: &host.commands,
+            record: host.record,
+            max_session: host.max_session.as_deref(),
+            confirm: host.confirm,
+            check_reachable: host.check_reachable,
+            forward_agent: host.forward_agent,
+            forward_x11: host.forward_x11,
+            control_persist: host.control_persist.as_deref(),
+            instance_id: host.instance_id.as_deref(),
+            auto_start: host.auto_start,
+            stop_on_exit: host.stop_on_exit,
+            console_links: host.console_links,
+            pre_connect: host.pre_connect.as_deref(),
+            post_disconnect: host.post_disconnect.as_deref(),
+            knock: &host.knock,
+            network: host.network.as_deref(),
+            resolver: host.resolver.as_ref().map(ToString::to_string),
+            transport: host.transport,
+        }
+    }
+}
+
+/// A host group in the machine-readable listing
+#[derive(Serialize)]
+struct GroupOutput<'a> {
+    name: &'a str,
+    hosts: &'a [String],
+}
+
+#[derive(Serialize)]
+struct DefaultAliasOutput<'a> {
+    default_alias: Option<&'a str>,
+}
+
+/// A database in the machine-readable listing
+#[derive(Serialize)]
+struct DatabaseOutput<'a> {
+    name: &'a str,
+    source: String,
+    bastion: &'a str,
+    profile: Option<&'a str>,
+    local_port: Option<u16>,
+    user: Option<&'a str>,
+    database: Option<&'a str>,
+}
+
+impl<'a> DatabaseOutput<'a> {
+    fn new(name: &'a str, db: &'a DatabaseConfig) -> Self {
+        Self {
+            name,
+            source: db.source.to_string(),
+            bastion: &db.bastion,
+            profile: db.profile.as_deref(),
+            local_port: db.local_port,
+            user: db.user.as_deref(),
+            database: db.database.as_deref(),
+        }
+    }
+}
+
+/// A network in the machine-readable listing
+#[derive(Serialize)]
+struct NetworkOutput<'a> {
+    name: &'a str,
+    interface: Option<&'a str>,
+    probe: Option<&'a str>,
+    up: Option<&'a str>,
+}
+
+impl<'a> NetworkOutput<'a> {
+    fn new(name: &'a str, network: &'a NetworkConfig) -> Self {
+        Self {
+            name,
+            interface: network.interface.as_deref(),
+            probe: network.probe.as_deref(),
+            up: network.up.as_deref(),
+        }
+    }
+}
+
+/// A hostname pattern in the machine-readable listing
+#[derive(Serialize)]
+struct PatternOutput<'a> {
+    pattern: &'a str,
+    key_alias: &'a str,
+}
+
+#[derive(Serialize)]
+struct MaxSessionOutput<'a> {
+    max_session: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct FetchTimeoutOutput<'a> {
+    fetch_timeout: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct OfflineCacheOutput<'a> {
+    offline_cache: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct NotifyDropsOutput<'a> {
+    notify_drops: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ConfirmTagsOutput<'a> {
+    confirm_tags: &'a [String],
+}
+
+#[derive(Serialize)]
+struct ForwardAgentOutput {
+    forward_agent: Option<bool>,
+}
+
+/// The audit log in the machine-readable listing, with an unset kind if auditing is disabled
+#[derive(Serialize)]
+struct AuditLogOutput<'a> {
+    kind: Option<&'static str>,
+    path: Option<&'a str>,
+}
+
+/// Sort the entries of a config section by name for a stable listing
+fn sorted<T>(entries: &HashMap<String, T>) -> Vec<(&String, &T)> {
+    let mut entries: Vec<_> = entries.iter().collect();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
+pub fn list_config(
+    config: &Config,
+    command: ListConfigSection,
+    output: Option<OutputFormat>,
+) -> Result<()> {
+    if let Some(format) = output {
+        return list_config_output(config, command, format);
+    }
 
-pub fn list_config(config: &Config, command: ListConfigSection) -> Result<()> {
     match command {
         ListConfigSection::Alias => {
-            let yaml = serde_yml::to_string(&config.key_aliases)?;
+            let mut table = Table::new(vec!["NAME", "TYPE", "SECRET", "PROFILE"]);
+            table.style_column(0, style::name);
+            for (name, alias) in sorted(&config.key_aliases) {
+                let source = alias.source().into_owned();
+                let alias = AliasOutput::new(name, alias);
+                table.add_row(vec![
+                    alias.name.to_string(),
+                    alias.kind.to_string(),
+                    source,
+                    alias.profile.unwrap_or_default().to_string(),
+                ]);
+            }
+            table.print();
+        }
+        ListConfigSection::Host { tag, filter, sort } => {
+            let history = history::load().unwrap_or_default();
+            let hosts = listed_hosts(config, &tag, filter.as_deref(), sort, &history);
+            let mut table = Table::new(vec!["NAME", "DESTINATION", "ALIAS", "TAGS", "LAST USED"]);
+            table.style_column(0, style::name);
+            table.style_column(1, style::destination);
+            for (name, host) in hosts {
+                // Show the effective settings, falling back to the raw ones if the host is broken
+                let mut row = match config.resolve_host(name) {
+                    Ok(resolved) => vec![
+                        resolved.name,
+                        resolved.destination,
+                        resolved.key_alias,
+                        resolved.tags.join(","),
+                    ],
+                    Err(_) => vec![
+                        name.clone(),
+                        host.destination.clone().unwrap_or_default(),
+                        host.key_alias.clone().unwrap_or_default(),
+                        host.tags.join(","),
+                    ],
+                };
+                row.push(
+                    history::last_used(&history, name)
+                        .map(history::format_ago)
+                        .unwrap_or_default(),
+                );
+                table.add_row(row);
+            }
+            table.print();
+        }
+        ListConfigSection::Template => {
+            let yaml = serde_yml::to_string(&config.templates)?;
             println!("{}", yaml);
         }
-        ListConfigSection::Host => {
-            let yaml = serde_yml::to_string(&config.hosts)?;
+        ListConfigSection::Group => {
+            let yaml = serde_yml::to_string(&config.groups)?;
             println!("{}", yaml);
         }
+        ListConfigSection::Database => {
+            let mut table = Table::new(vec!["NAME", "SOURCE", "BASTION", "LOCAL PORT"]);
+            table.style_column(0, style::name);
+            for (name, db) in sorted(&config.databases) {
+                table.add_row(vec![
+                    name.clone(),
+                    db.source.to_string(),
+                    db.bastion.clone(),
+                    db.local_port
+                        .map(|port| port.to_string())
+                        .unwrap_or_default(),
+                ]);
+            }
+            table.print();
+        }
+        ListConfigSection::Network => {
+            let mut table = Table::new(vec!["NAME", "INTERFACE", "PROBE", "UP"]);
+            table.style_column(0, style::name);
+            for (name, network) in sorted(&config.networks) {
+                table.add_row(vec![
+                    name.clone(),
+                    network.interface.clone().unwrap_or_default(),
+                    network.probe.clone().unwrap_or_default(),
+                    network.up.clone().unwrap_or_default(),
+                ]);
+            }
+            table.print();
+        }
+        ListConfigSection::DefaultAlias => match &config.default_alias {
+            Some(alias) => println!("{alias}"),
+            None => println!("No default key alias set"),
+        },
+        ListConfigSection::Pattern => {
+            let mut table = Table::new(vec!["PATTERN", "ALIAS"]);
+            table.style_column(0, style::destination);
+            for pattern in &config.patterns {
+                table.add_row(vec![pattern.pattern.clone(), pattern.key_alias.clone()]);
+            }
+            table.print();
+        }
+        ListConfigSection::AuditLog => match &config.audit_log {
+            Some(audit_log) => println!("{audit_log}"),
+            None => println!("No audit log set"),
+        },
+        ListConfigSection::MaxSession => match &config.max_session {
+            Some(max_session) => println!("{max_session}"),
+            None => println!("No maximum session duration set"),
+        },
+        ListConfigSection::FetchTimeout => match &config.fetch_timeout {
+            Some(fetch_timeout) => println!("{fetch_timeout}"),
+            None => println!("No fetch timeout set"),
+        },
+        ListConfigSection::OfflineCache => match &config.offline_cache {
+            Some(offline_cache) => println!("{offline_cache}"),
+            None => println!("Offline cache disabled"),
+        },
+        ListConfigSection::NotifyDrops => match &config.notify_drops {
+            Some(notify_drops) => println!("{notify_drops}"),
+            None => println!("Drop notifications disabled"),
+        },
+        ListConfigSection::ConfirmTags => {
+            if config.confirm_tags.is_empty() {
+                println!("No confirmation tags set");
+            }
+            for tag in &config.confirm_tags {
+                println!("{tag}");
+            }
+        }
+        ListConfigSection::ForwardAgent => match config.forward_agent {
+            Some(true) => println!("Forwarding the agent"),
+            Some(false) => println!("Not forwarding the agent"),
+            None => println!("No agent forwarding policy set"),
+        },
+    }
+    Ok(())
+}
+
+fn list_config_output(
+    config: &Config,
+    command: ListConfigSection,
+    format: OutputFormat,
+) -> Result<()> {
+    match command {
+        ListConfigSection::Alias => {
+            let aliases: Vec<_> = sorted(&config.key_aliases)
+                .into_iter()
+                .map(|(name, alias)| AliasOutput::new(name, alias))
+                .collect();
+            print_output(format, &aliases)
+        }
+        ListConfigSection::Host { tag, filter, sort } => {
+            let history = history::load().unwrap_or_default();
+            let hosts: Vec<_> = listed_hosts(config, &tag, filter.as_deref(), sort, &history)
+                .into_iter()
+                .map(|(name, host)| HostOutput::new(name, host))
+                .collect();
+            print_output(format, &hosts)
+        }
+        ListConfigSection::Template => {
+            let templates: Vec<_> = sorted(&config.templates)
+                .into_iter()
+                .map(|(name, template)| HostOutput::new(name, template))
+                .collect();
+            print_output(format, &templates)
+        }
+        ListConfigSection::Group => {
+            let groups: Vec<_> = sorted(&config.groups)
+                .into_iter()
+                .map(|(name, group)| GroupOutput {
+                    name,
+                    hosts: &group.hosts,
+                })
+                .collect();
+            print_output(format, &groups)
+        }
+        ListConfigSection::Database => {
+            let databases: Vec<_> = sorted(&config.databases)
+                .into_iter()
+                .map(|(name, db)| DatabaseOutput::new(name, db))
+                .collect();
+            print_output(format, &databases)
+        }
+        ListConfigSection::Network => {
+            let networks: Vec<_> = sorted(&config.networks)
+                .into_iter()
+                .map(|(name, network)| NetworkOutput::new(name, network))
+                .collect();
+            print_output(format, &networks)
+        }
+        ListConfigSection::DefaultAlias => print_output(
+            format,
+            &DefaultAliasOutput {
+                default_alias: config.default_alias.as_deref(),
+            },
+        ),
+        ListConfigSection::Pattern => {
+            let patterns: Vec<_> = config
+                .patterns
+                .iter()
+                .map(|pattern| PatternOutput {
+                    pattern: &pattern.pattern,
+                    key_alias: &pattern.key_alias,
+                })
+                .collect();
+            print_output(format, &patterns)
+        }
+        ListConfigSection::AuditLog => {
+            let output = match &config.audit_log {
+                Some(AuditLogConfig::File { path }) => AuditLogOutput {
+                    kind: Some("file"),
+                    path: Some(path),
+                },
+                Some(AuditLogConfig::Syslog) => AuditLogOutput {
+                    kind: Some("syslog"),
+                    path: None,
+                },
+                None => AuditLogOutput {
+                    kind: None,
+                    path: None,
+                },
+            };
+            print_output(format, &output)
+        }
+        ListConfigSection::MaxSession => print_output(
+            format,
+            &MaxSessionOutput {
+                max_session: config.max_session.as_deref(),
+            },
+        ),
+        ListConfigSection::FetchTimeout => print_output(
+            format,
+            &FetchTimeoutOutput {
+                fetch_timeout: config.fetch_timeout.as_deref(),
+            },
+        ),
+        ListConfigSection::OfflineCache => print_output(
+            format,
+            &OfflineCacheOutput {
+                offline_cache: config.offline_cache.as_deref(),
+            },
+        ),
+        ListConfigSection::NotifyDrops => print_output(
+            format,
+            &NotifyDropsOutput {
+                notify_drops: config.notify_drops.as_deref(),
+            },
+        ),
+        ListConfigSection::ConfirmTags => print_output(
+            format,
+            &ConfirmTagsOutput {
+                confirm_tags: &config.confirm_tags,
+            },
+        ),
+        ListConfigSection::ForwardAgent => print_output(
+            format,
+            &ForwardAgentOutput {
+                forward_agent: config.forward_agent,
+            },
+        ),
+    }
+}
+
+/// The hosts to list: the ones with all of the given tags and matching the filter, in the given
+/// order. The destinations, key aliases and tags are the effective ones, inherited from templates.
+fn listed_hosts<'a>(
+    config: &'a Config,
+    tags: &[String],
+    filter: Option<&str>,
+    sort: HostSort,
+    history: &[HistoryEntry],
+) -> Vec<(&'a String, &'a HostConfig)> {
+    let filter = filter.map(|filter| {
+        RegexBuilder::new(filter)
+            .case_insensitive(true)
+            .build()
+            .unwrap_or_else(|_| {
+                RegexBuilder::new(&regex_lite::escape(filter))
+                    .case_insensitive(true)
+                    .build()
+                    .expect("escaped patterns are valid")
+            })
+    });
+    let mut hosts: Vec<_> = config
+        .hosts
+        .iter()
+        .filter(|(name, host)| {
+            // Fall back to the raw settings if the host is broken
+            let (destination, key_alias, host_tags) = match config.resolve_host(name) {
+                Ok(resolved) => (
+                    Some(resolved.destination),
+                    Some(resolved.key_alias),
+                    resolved.tags,
+                ),
+                Err(_) => (
+                    host.destination.clone(),
+                    host.key_alias.clone(),
+                    host.tags.clone(),
+                ),
+            };
+            let matches_filter = filter.as_ref().is_none_or(|filter| {
+                [
+                    Some(name.as_str()),
+                    destination.as_deref(),
+                    key_alias.as_deref(),
+                ]
+                .into_iter()
+                .flatten()
+                .chain(host_tags.iter().map(String::as_str))
+                .any(|field| filter.is_match(field))
+            });
+            tags.iter().all(|tag| host_tags.contains(tag)) && matches_filter
+        })
+        .collect();
+    hosts.sort_by_key(|(name, _)| *name);
+    if let HostSort::LastUsed = sort {
+        // The sort is stable, so hosts never connected to stay sorted by name
+        hosts.sort_by_key(|(name, _)| Reverse(history::last_used(history, name)));
+    }
+    hosts
+}
+
+/// Options of `ssh -G` shown for a host, the ones deciding where ssh connects to
+static SSH_DETAIL_OPTIONS: [&str; 6] = [
+    "hostname",
+    "user",
+    "port",
+    "proxyjump",
+    "proxycommand",
+    "forwardagent",
+];
+
+/// A host with its templates and defaults applied, in the detail view
+#[derive(Serialize)]
+struct ResolvedHostOutput<'a> {
+    name: &'a str,
+    nicknames: &'a [String],
+    /// Templates applied to the host, the closest first
+    templates: Vec<String>,
+    destination: &'a str,
+    key_alias: &'a str,
+    /// Where the key alias is set: the host, a template or the default key alias
+    key_alias_from: String,
+    fallback_aliases: &'a [String],
+    extra_key_aliases: &'a [String],
+    jump_hosts: &'a [String],
+    secret: String,
+    profile: Option<&'a str>,
+    region: Option<&'a str>,
+    args: &'a [String],
+    ssh_command: String,
+    /// Unset if `ssh -G` failed
+    ssh_options: Option<BTreeMap<String, String>>,
+    tags: &'a [String],
+    /// Only the names, the values can hold secrets
+    env: Vec<&'a str>,
+    commands: &'a BTreeMap<String, String>,
+    record: bool,
+    max_session: Option<String>,
+    confirm: ConfirmPolicy,
+    check_reachable: bool,
+    forward_agent: Option<bool>,
+    forward_x11: Option<X11Forwarding>,
+    control_persist: Option<String>,
+    instance_id: Option<&'a str>,
+    auto_start: bool,
+    stop_on_exit: StopPolicy,
+    console_links: ConsoleLinks,
+    pre_connect: Option<&'a str>,
+    post_disconnect: Option<&'a str>,
+    knock: Vec<String>,
+    network: Option<&'a str>,
+    resolver: Option<String>,
+    transport: Option<Transport>,
+}
+
+/// A key alias with everything referencing it, in the detail view
+#[derive(Serialize)]
+struct AliasDetailOutput<'a> {
+    #[serde(flatten)]
+    alias: AliasOutput<'a>,
+    region: Option<&'a str>,
+    fetch_timeout: Option<String>,
+    default: bool,
+    /// Hosts connecting with this key alias, after applying templates and the default
+    hosts: Vec<String>,
+    /// Hosts falling back to this key alias
+    fallback_for: Vec<String>,
+    /// Hosts offering this key alias along with their own
+    extra_for: Vec<String>,
+    templates: Vec<String>,
+    patterns: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct GetOutput<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<ResolvedHostOutput<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<AliasDetailOutput<'a>>,
+}
+
+/// The templates a host extends, the closest first
+fn template_chain(config: &Config, host: &HostConfig) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut next = host.extends.clone();
+    while let Some(name) = next {
+        if chain.contains(&name) {
+            break;
+        }
+        next = config
+            .templates
+            .get(&name)
+            .and_then(|template| template.extends.clone());
+        chain.push(name);
+    }
+    chain
+}
+
+fn resolved_host_output<'a>(
+    config: &'a Config,
+    host: &'a ResolvedHost,
+    key_alias: &'a KeyAliasConfig,
+    secret: String,
+) -> ResolvedHostOutput<'a> {
+    let templates = template_chain(config, &config.hosts[&host.name]);
+    let key_alias_from = if config.hosts[&host.name].key_alias.is_some() {
+        "host".to_string()
+    } else {
+        templates
+            .iter()
+            .find(|name| {
+                config
+                    .templates
+                    .get(*name)
+                    .is_some_and(|template| template.key_alias.is_some())
+            })
+            .map(|name| format!("template '{name}'"))
+            .or_else(|| {
+                config
+                    .wildcard_hosts(&host.name, Some(&host.destination))
+                    .into_iter()
+                    .find(|pattern| config.hosts[*pattern].key_alias.is_some())
+                    .map(|pattern| format!("wildcard host '{pattern}'"))
+            })
+            .unwrap_or_else(|| "default key alias".to_string())
+    };
+    let args = host_args(host);
+    let ssh_command: Vec<String> = std::iter::once("ssh")
+        .chain(args.iter().map(String::as_str))
+        .chain([host.destination.as_str()])
+        .map(shell_quote)
+        .collect();
+    let ssh_options = ssh_config::effective_options(Some(&host.destination), &args)
+        .ok()
+        .map(|options| {
+            options
+                .into_iter()
+                .filter(|(option, value)| {
+                    SSH_DETAIL_OPTIONS.contains(&option.as_str()) && value != "none"
+                })
+                .collect()
+        });
+    ResolvedHostOutput {
+        name: &host.name,
+        nicknames: &host.nicknames,
+        templates,
+        destination: &host.destination,
+        key_alias: &host.key_alias,
+        key_alias_from,
+        fallback_aliases: &host.fallback_aliases,
+        extra_key_aliases: &host.extra_key_aliases,
+        jump_hosts: &host.jump_hosts,
+        secret,
+        profile: key_alias.profile(),
+        region: key_alias.region(),
+        args: &host.args,
+        ssh_command: ssh_command.join(" "),
+        ssh_options,
+        tags: &host.tags,
+        env: host.env.keys().map(String::as_str).collect(),
+        commands: &host.commands,
+        record: host.record,
+        max_session: host.max_session.map(duration::format),
+        confirm: host.confirm,
+        check_reachable: host.check_reachable,
+        forward_agent: host.forward_agent,
+        forward_x11: host.forward_x11,
+        control_persist: host.control_persist.map(duration::format),
+        instance_id: host.instance_id.as_deref(),
+        auto_start: host.auto_start,
+        stop_on_exit: host.stop_on_exit,
+        console_links: host.console_links,
+        pre_connect: host.pre_connect.as_deref(),
+        post_disconnect: host.post_disconnect.as_deref(),
+        knock: host.knock.iter().map(ToString::to_string).collect(),
+        network: host.network.as_deref(),
+        resolver: host.resolver.as_ref().map(ToString::to_string),
+        transport: host.transport,
+    }
+}
+
+fn alias_detail_output<'a>(
+    config: &'a Config,
+    name: &'a str,
+    alias: &'a KeyAliasConfig,
+) -> Result<AliasDetailOutput<'a>> {
+    let mut hosts = Vec::new();
+    let mut fallback_for = Vec::new();
+    let mut extra_for = Vec::new();
+    for (host_name, _) in sorted(&config.hosts) {
+        // Hosts that don't resolve can't connect with any key alias
+        let Ok(host) = config.resolve_host(host_name) else {
+            continue;
+        };
+        if host.key_alias == name {
+            hosts.push(host_name.clone());
+        }
+        if host
+            .fallback_aliases
+            .iter()
+            .any(|fallback| fallback == name)
+        {
+            fallback_for.push(host_name.clone());
+        }
+        if host.extra_key_aliases.iter().any(|extra| extra == name) {
+            extra_for.push(host_name.clone());
+        }
+    }
+    Ok(AliasDetailOutput {
+        alias: AliasOutput::new(name, alias),
+        region: alias.region(),
+        fetch_timeout: config.fetch_timeout(alias)?.map(duration::format),
+        default: config.default_alias.as_deref() == Some(name),
+        hosts,
+        fallback_for,
+        extra_for,
+        templates: hosts_matching(&config.templates, |template| template.uses_alias(name)),
+        patterns: config
+            .patterns
+            .iter()
+            .filter(|pattern| pattern.key_alias == name)
+            .map(|pattern| pattern.pattern.as_str())
+            .collect(),
+    })
+}
+
+/// Print a labeled line of the detail view, skipping empty values
+fn print_detail(label: &str, value: impl AsRef<str>) {
+    let value = value.as_ref();
+    if !value.is_empty() {
+        println!("{}: {value}", style::header(label));
+    }
+}
+
+fn print_host_detail(host: &ResolvedHostOutput) {
+    let flag = |enabled: bool| if enabled { "yes" } else { "" };
+    print_detail("Host", style::name(host.name));
+    print_detail("Nicknames", host.nicknames.join(", "));
+    print_detail("Templates", host.templates.join(" -> "));
+    print_detail("Destination", style::destination(host.destination));
+    print_detail(
+        "Key alias",
+        format!("{} (from the {})", host.key_alias, host.key_alias_from),
+    );
+    print_detail("Fallback key aliases", host.fallback_aliases.join(", "));
+    print_detail("Extra key aliases", host.extra_key_aliases.join(", "));
+    print_detail("Jump hosts", host.jump_hosts.join(" -> "));
+    print_detail("Secret", &host.secret);
+    print_detail("Profile", host.profile.unwrap_or_default());
+    print_detail("Region", host.region.unwrap_or_default());
+    print_detail("SSH command", &host.ssh_command);
+    match &host.ssh_options {
+        Some(options) => {
+            let options: Vec<String> = options
+                .iter()
+                .map(|(option, value)| format!("{option}={value}"))
+                .collect();
+            print_detail("SSH options", options.join(" "));
+        }
+        None => print_detail("SSH options", "unknown, `ssh -G` failed"),
+    }
+    print_detail("Tags", host.tags.join(", "));
+    print_detail("Environment", host.env.join(", "));
+    for (name, command) in host.commands {
+        print_detail(&format!("Command '{name}'"), command);
+    }
+    print_detail("Record", flag(host.record));
+    print_detail(
+        "Max session",
+        host.max_session.as_deref().unwrap_or_default(),
+    );
+    if host.confirm != ConfirmPolicy::Never {
+        print_detail("Confirm", format!("{:?}", host.confirm).to_lowercase());
+    }
+    print_detail("Check reachable", flag(host.check_reachable));
+    print_detail(
+        "Forward agent",
+        match host.forward_agent {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "",
+        },
+    );
+    if let Some(forward_x11) = host.forward_x11 {
+        print_detail("Forward X11", format!("{forward_x11:?}").to_lowercase());
+    }
+    print_detail(
+        "Control persist",
+        host.control_persist.as_deref().unwrap_or_default(),
+    );
+    print_detail("Instance", host.instance_id.unwrap_or_default());
+    print_detail("Auto start", flag(host.auto_start));
+    if host.stop_on_exit != StopPolicy::Never {
+        print_detail(
+            "Stop on exit",
+            format!("{:?}", host.stop_on_exit).to_lowercase(),
+        );
+    }
+    if host.console_links != ConsoleLinks::Never {
+        print_detail(
+            "Console links",
+            format!("{:?}", host.console_links).to_lowercase(),
+        );
+    }
+    print_detail("Pre-connect hook", host.pre_connect.unwrap_or_default());
+    print_detail(
+        "Post-disconnect hook",
+        host.post_disconnect.unwrap_or_default(),
+    );
+    print_detail("Knock", host.knock.join(" "));
+    print_detail("Network", host.network.unwrap_or_default());
+    print_detail("Resolver", host.resolver.as_deref().unwrap_or_default());
+    print_detail(
+        "Transport",
+        host.transport.map(|t| t.to_string()).unwrap_or_default(),
+    );
+}
+
+fn print_alias_detail(alias: &AliasDetailOutput) {
+    print_detail("Key alias", style::name(alias.alias.name));
+    print_detail("Type", alias.alias.kind);
+    print_detail("Secret", alias.alias.secret_arn.unwrap_or_default());
+    print_detail("Profile", alias.alias.profile.unwrap_or_default());
+    print_detail(
+        "Passphrase secret",
+        alias.alias.passphrase_secret_arn.unwrap_or_default(),
+    );
+    print_detail("Region", alias.region.unwrap_or_default());
+    print_detail("Plugin", alias.alias.plugin.unwrap_or_default());
+    if let Some(params) = alias.alias.params {
+        let params: Vec<String> = params
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        print_detail("Params", params.join(" "));
+    }
+    print_detail(
+        "Second factor",
+        alias.alias.second_factor.unwrap_or_default(),
+    );
+    print_detail(
+        "Fetch timeout",
+        alias.fetch_timeout.as_deref().unwrap_or_default(),
+    );
+    print_detail("Default", if alias.default { "yes" } else { "" });
+    print_detail("Hosts", alias.hosts.join(", "));
+    print_detail("Fallback for", alias.fallback_for.join(", "));
+    print_detail("Extra key for", alias.extra_for.join(", "));
+    print_detail("Templates", alias.templates.join(", "));
+    print_detail("Patterns", alias.patterns.join(", "));
+}
+
+/// Show a host with its templates and defaults applied, or a key alias with everything
+/// referencing it. Both are shown if the name is used by a host and a key alias.
+pub fn get_config(config: &Config, name: &str, output: Option<OutputFormat>) -> Result<()> {
+    let resolved = if config.host_name(name).is_some() {
+        let host = config.resolve_host(name)?;
+        let key_alias = config.key_alias(&host.key_alias)?;
+        let secret = config.key_source(&host.key_alias)?;
+        Some((host, key_alias, secret))
+    } else {
+        None
+    };
+    let host = resolved.as_ref().map(|(host, key_alias, secret)| {
+        resolved_host_output(config, host, key_alias, secret.clone())
+    });
+    let alias = config
+        .key_aliases
+        .get_key_value(name)
+        .map(|(name, alias)| alias_detail_output(config, name, alias))
+        .transpose()?;
+    if host.is_none() && alias.is_none() {
+        return Err(eyre!("No host or key alias named '{name}'"));
+    }
+
+    if let Some(format) = output {
+        return print_output(format, &GetOutput { host, alias });
+    }
+    if let Some(host) = &host {
+        print_host_detail(host);
+    }
+    if let Some(alias) = &alias {
+        if host.is_some() {
+            println!();
+        }
+        print_alias_detail(alias);
     }
     Ok(())
 }
 
-pub fn add_config(config: &mut Config, command: SetConfigSection) -> Result<()> {
+/// How `config set` treats entries that already exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetMode {
+    /// Fail if the entry exists
+    Create,
+    /// Replace the existing entry
+    Overwrite,
+    /// Change only the given settings of the existing entry
+    Update,
+}
+
+impl SetMode {
+    fn new(force: bool, update: bool) -> Self {
+        match (force, update) {
+            (true, _) => Self::Overwrite,
+            (_, true) => Self::Update,
+            _ => Self::Create,
+        }
+    }
+}
+
+/// Insert an entry according to the set mode and return the verb describing what happened.
+/// Update mode has to be handled by the caller for entries that support partial updates, here it
+/// replaces the entry like overwrite mode does.
+fn set_entry<T>(
+    entries: &mut HashMap<String, T>,
+    kind: &str,
+    name: &str,
+    entry: T,
+    mode: SetMode,
+) -> Result<&'static str> {
+    let exists = entries.contains_key(name);
+    match (mode, exists) {
+        (SetMode::Create, true) => Err(eyre!(
+            "{kind} '{name}' already exists, use --force to replace it or --update to modify it"
+        )),
+        (SetMode::Update, false) => Err(eyre!("{kind} '{name}' not found")),
+        (_, true) => {
+            entries.insert(name.to_string(), entry);
+            Ok("updated")
+        }
+        (_, false) => {
+            entries.insert(name.to_string(), entry);
+            Ok("added")
+        }
+    }
+}
+
+/// Apply the settings given in `update` to an existing host or template, keeping the settings
+/// that were not given.
+fn update_host(host: &HostConfig, update: HostConfig) -> HostConfig {
+    HostConfig {
+        extends: update.extends.or_else(|| host.extends.clone()),
+        nicknames: if update.nicknames.is_empty() {
+            host.nicknames.clone()
+        } else {
+            update.nicknames
+        },
+        key_alias: update.key_alias.or_else(|| host.key_alias.clone()),
+        fallback_aliases: if update.fallback_aliases.is_empty() {
+            host.fallback_aliases.clone()
+        } else {
+            update.fallback_aliases
+        },
+        extra_key_aliases: if update.extra_key_aliases.is_empty() {
+            host.extra_key_aliases.clone()
+        } else {
+            update.extra_key_aliases
+        },
+        jump_hosts: if update.jump_hosts.is_empty() {
+            host.jump_hosts.clone()
+        } else {
+            update.jump_hosts
+        },
+        args: if update.args.is_empty() {
+            host.args.clone()
+        } else {
+            update.args
+        },
+        destination: update.destination.or_else(|| host.destination.clone()),
+        tags: if update.tags.is_empty() {
+            host.tags.clone()
+        } else {
+            update.tags
+        },
+        env: if update.env.is_empty() {
+            host.env.clone()
+        } else {
+            update.env
+        },
+        commands: if update.commands.is_empty() {
+            host.commands.clone()
+        } else {
+            update.commands
+        },
+        record: update.record.or(host.record),
+        max_session: update.max_session.or_else(|| host.max_session.clone()),
+        confirm: update.confirm.or(host.confirm),
+        check_reachable: update.check_reachable.or(host.check_reachable),
+        forward_agent: update.forward_agent.or(host.forward_agent),
+        forward_x11: update.forward_x11.or(host.forward_x11),
+        control_persist: update
+            .control_persist
+            .or_else(|| host.control_persist.clone()),
+        instance_id: update.instance_id.or_else(|| host.instance_id.clone()),
+        auto_start: update.auto_start.or(host.auto_start),
+        stop_on_exit: update.stop_on_exit.or(host.stop_on_exit),
+        console_links: update.console_links.or(host.console_links),
+        pre_connect: update.pre_connect.or_else(|| host.pre_connect.clone()),
+        post_disconnect: update
+            .post_disconnect
+            .or_else(|| host.post_disconnect.clone()),
+        knock: if update.knock.is_empty() {
+            host.knock.clone()
+        } else {
+            update.knock
+        },
+        network: update.network.or_else(|| host.network.clone()),
+        resolver: update.resolver.or_else(|| host.resolver.clone()),
+        transport: update.transport.or(host.transport),
+    }
+}
+
+pub fn add_config(
+    config: &mut Config,
+    command: SetConfigSection,
+    force: bool,
+    update: bool,
+) -> Result<()> {
+    let mode = SetMode::new(force, update);
     match command {
         SetConfigSection::Alias { kind } => {
             let name = kind.name();
             let alias_config: KeyAliasConfig = kind.into();
-            config
-                .key_aliases
-                .entry(name.clone())
-                .or_insert(alias_config);
+            let verb = set_entry(
+                &mut config.key_aliases,
+                "Key alias",
+                &name,
+                alias_config,
+                mode,
+            )?;
             config.store()?;
-            println!("Key alias '{name}' added");
+            println!("Key alias '{}' {verb}", style::name(&name));
         }
         SetConfigSection::Host {
             name,
+            nicknames,
             alias,
+            fallback_aliases,
+            extra_key_aliases,
+            jump_hosts,
+            extends,
             args,
             destination,
+            tags,
+            env,
+            commands,
+            record,
+            max_session,
+            confirm,
+            check_reachable,
+            forward_agent,
+            forward_x11,
+            control_persist,
+            instance_id,
+            auto_start,
+            stop_on_exit,
+            console_links,
+            pre_connect,
+            post_disconnect,
+            knock,
+            network,
+            resolver,
+            transport,
+        } => {
+            ensure_host_references(
+                config,
+                alias.as_ref(),
+                &fallback_aliases,
+                &extra_key_aliases,
+                &jump_hosts,
+                extends.as_ref(),
+                network.as_ref(),
+            )?;
+            ensure_unique_names(config, &name, &nicknames)?;
+
+            let given = HostConfig {
+                extends,
+                nicknames,
+                key_alias: alias,
+                fallback_aliases,
+                extra_key_aliases,
+                jump_hosts,
+                args,
+                destination,
+                tags,
+                env: env.into_iter().collect(),
+                commands: commands.into_iter().collect(),
+                record,
+                max_session,
+                confirm,
+                check_reachable,
+                forward_agent,
+                forward_x11,
+                control_persist,
+                instance_id,
+                auto_start,
+                stop_on_exit,
+                console_links,
+                pre_connect,
+                post_disconnect,
+                knock,
+                network,
+                resolver,
+                transport,
+            };
+            let host = match (mode, config.hosts.get(&name)) {
+                (SetMode::Update, Some(host)) => update_host(host, given),
+                _ => {
+                    if given.destination.is_none() && !is_wildcard(&name) {
+                        return Err(eyre!("A destination is required for host '{name}'"));
+                    }
+                    given
+                }
+            };
+            let verb = set_entry(&mut config.hosts, "Host", &name, host, mode)?;
+            config.store()?;
+            println!("Host '{}' {verb}", style::name(&name));
+        }
+        SetConfigSection::Template {
+            name,
+            alias,
+            fallback_aliases,
+            extra_key_aliases,
+            jump_hosts,
+            extends,
+            tags,
+            env,
+            commands,
+            record,
+            max_session,
+            confirm,
+            check_reachable,
+            forward_agent,
+            forward_x11,
+            control_persist,
+            instance_id,
+            auto_start,
+            stop_on_exit,
+            console_links,
+            pre_connect,
+            post_disconnect,
+            knock,
+            network,
+            resolver,
+            transport,
+            args,
+        } => {
+            ensure_host_references(
+                config,
+                alias.as_ref(),
+                &fallback_aliases,
+                &extra_key_aliases,
+                &jump_hosts,
+                extends.as_ref(),
+                network.as_ref(),
+            )?;
+
+            let given = HostConfig {
+                extends,
+                nicknames: Vec::new(),
+                key_alias: alias,
+                fallback_aliases,
+                extra_key_aliases,
+                jump_hosts,
+                args,
+                destination: None,
+                tags,
+                env: env.into_iter().collect(),
+                commands: commands.into_iter().collect(),
+                record,
+                max_session,
+                confirm,
+                check_reachable,
+                forward_agent,
+                forward_x11,
+                control_persist,
+                instance_id,
+                auto_start,
+                stop_on_exit,
+                console_links,
+                pre_connect,
+                post_disconnect,
+                knock,
+                network,
+                resolver,
+                transport,
+            };
+            let template = match (mode, config.templates.get(&name)) {
+                (SetMode::Update, Some(template)) => update_host(template, given),
+                _ => given,
+            };
+            let verb = set_entry(&mut config.templates, "Template", &name, template, mode)?;
+            config.store()?;
+            println!("Template '{}' {verb}", style::name(&name));
+        }
+        SetConfigSection::Group { name, hosts } => {
+            // Ensure all hosts exist
+            for host in &hosts {
+                config
+                    .hosts
+                    .get(host)
+                    .ok_or_else(|| eyre!("Host '{host}' not found"))?;
+            }
+
+            let group = GroupConfig { hosts };
+            let verb = set_entry(&mut config.groups, "Group", &name, group, mode)?;
+            config.store()?;
+            println!("Group '{}' {verb}", style::name(&name));
+        }
+        SetConfigSection::Database {
+            name,
+            bastion,
+            rds,
+            rds_cluster,
+            elasticache,
+            profile,
+            local_port,
+            user,
+            database,
+        } => {
+            config
+                .hosts
+                .get(&bastion)
+                .ok_or_else(|| eyre!("Host '{bastion}' not found"))?;
+            let source = match (rds, rds_cluster, elasticache) {
+                (Some(instance), _, _) => DatabaseSource::Rds { instance },
+                (_, Some(cluster), _) => DatabaseSource::RdsCluster { cluster },
+                (_, _, Some(replication_group)) => {
+                    DatabaseSource::ElastiCache { replication_group }
+                }
+                _ => unreachable!("clap requires one database source"),
+            };
+            let db = DatabaseConfig {
+                source,
+                bastion,
+                profile,
+                local_port,
+                user,
+                database,
+            };
+            let verb = set_entry(&mut config.databases, "Database", &name, db, mode)?;
+            config.store()?;
+            println!("Database '{}' {verb}", style::name(&name));
+        }
+        SetConfigSection::Network {
+            name,
+            interface,
+            probe,
+            up,
         } => {
+            if interface.is_none() && probe.is_none() {
+                return Err(eyre!(
+                    "Network '{name}' needs an --interface or a --probe to detect it"
+                ));
+            }
+            let network = NetworkConfig {
+                interface,
+                probe,
+                up,
+            };
+            let verb = set_entry(&mut config.networks, "Network", &name, network, mode)?;
+            config.store()?;
+            println!("Network '{}' {verb}", style::name(&name));
+        }
+        SetConfigSection::DefaultAlias { alias } => {
             // Ensure the key alias exists
             config
                 .key_aliases
                 .get(&alias)
                 .ok_or_else(|| eyre!("Key alias '{alias}' not found"))?;
 
-            let host = HostConfig {
-                key_alias: alias,
-                args,
-                destination,
+            config.default_alias = Some(alias.clone());
+            config.store()?;
+            println!("Default key alias set to '{}'", style::name(&alias));
+        }
+        SetConfigSection::Pattern { pattern, alias } => {
+            if pattern.is_empty() {
+                return Err(eyre!("The pattern must not be empty"));
+            }
+            config
+                .key_aliases
+                .get(&alias)
+                .ok_or_else(|| eyre!("Key alias '{alias}' not found"))?;
+
+            let entry = PatternConfig {
+                pattern: pattern.clone(),
+                key_alias: alias.clone(),
+            };
+            // Replaced patterns keep their place in the matching order
+            let verb = match config.patterns.iter_mut().find(|p| p.pattern == pattern) {
+                Some(_) if mode == SetMode::Create => {
+                    return Err(eyre!(
+                        "Pattern '{pattern}' already exists, use --force to replace it or --update to modify it"
+                    ));
+                }
+                Some(existing) => {
+                    *existing = entry;
+                    "updated"
+                }
+                None if mode == SetMode::Update => {
+                    return Err(eyre!("Pattern '{pattern}' not found"));
+                }
+                None => {
+                    config.patterns.push(entry);
+                    "added"
+                }
             };
-            config.hosts.entry(name.clone()).or_insert(host);
             config.store()?;
-            println!("Host '{name}' added");
+            println!(
+                "Pattern '{}' {verb}, using key alias '{}'",
+                style::destination(&pattern),
+                style::name(&alias)
+            );
+        }
+        SetConfigSection::AuditLog { kind } => {
+            if config.base().is_some_and(|base| base.audit_log.is_some()) {
+                return Err(eyre!(
+                    "The audit log is set in the shared or system config and cannot be changed"
+                ));
+            }
+            let audit_log = AuditLogConfig::from(kind);
+            config.audit_log = Some(audit_log.clone());
+            config.store()?;
+            println!("Audit log set to {audit_log}");
+        }
+        SetConfigSection::MaxSession { duration } => {
+            config.max_session = Some(duration.clone());
+            config.store()?;
+            println!("Maximum session duration set to {duration}");
+        }
+        SetConfigSection::FetchTimeout { duration } => {
+            config.fetch_timeout = Some(duration.clone());
+            config.store()?;
+            println!("Fetch timeout set to {duration}");
+        }
+        SetConfigSection::OfflineCache { max_age } => {
+            if config.passphrase.is_none() {
+                warn!(
+                    "Keys are only cached while the config is encrypted, see `smssh config encrypt`"
+                );
+            }
+            config.offline_cache = Some(max_age.clone());
+            config.store()?;
+            println!("Offline cache enabled for keys fetched within {max_age}");
+        }
+        SetConfigSection::NotifyDrops { min_duration } => {
+            config.notify_drops = Some(min_duration.clone());
+            config.store()?;
+            println!("Notifying drops of sessions that ran at least {min_duration}");
+        }
+        SetConfigSection::ConfirmTags { tags } => {
+            config.confirm_tags = tags.clone();
+            config.store()?;
+            println!("Confirming connections to hosts tagged {}", tags.join(", "));
+        }
+        SetConfigSection::ForwardAgent { enabled } => {
+            config.forward_agent = Some(enabled);
+            config.store()?;
+            if enabled {
+                println!("Forwarding the agent to hosts without their own policy");
+            } else {
+                println!("Not forwarding the agent to hosts without their own policy");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ensure the key aliases, jump hosts and template referenced by a host or template exist.
+fn ensure_host_references(
+    config: &Config,
+    alias: Option<&String>,
+    fallback_aliases: &[String],
+    extra_key_aliases: &[String],
+    jump_hosts: &[String],
+    extends: Option<&String>,
+    network: Option<&String>,
+) -> Result<()> {
+    for alias in alias
+        .into_iter()
+        .chain(fallback_aliases)
+        .chain(extra_key_aliases)
+    {
+        config
+            .key_aliases
+            .get(alias)
+            .ok_or_else(|| eyre!("Key alias '{alias}' not found"))?;
+    }
+    for jump_host in jump_hosts {
+        config
+            .hosts
+            .get(jump_host)
+            .ok_or_else(|| eyre!("Host '{jump_host}' not found"))?;
+    }
+    if let Some(template) = extends {
+        config
+            .templates
+            .get(template)
+            .ok_or_else(|| eyre!("Template '{template}' not found"))?;
+    }
+    if let Some(network) = network {
+        config
+            .networks
+            .get(network)
+            .ok_or_else(|| eyre!("Network '{network}' not found"))?;
+    }
+    Ok(())
+}
+
+/// Ensure a host and its nicknames don't clash with the names and nicknames of the other hosts.
+fn ensure_unique_names(config: &Config, name: &str, nicknames: &[String]) -> Result<()> {
+    if is_wildcard(name) && !nicknames.is_empty() {
+        return Err(eyre!("Wildcard host '{name}' cannot have nicknames"));
+    }
+    for nickname in nicknames {
+        if nickname == name || config.hosts.contains_key(nickname) {
+            return Err(eyre!("Nickname '{nickname}' is the name of a host"));
+        }
+    }
+    for (other, host) in &config.hosts {
+        if other == name {
+            continue;
+        }
+        if let Some(nickname) = host
+            .nicknames
+            .iter()
+            .find(|nickname| *nickname == name || nicknames.contains(nickname))
+        {
+            return Err(eyre!(
+                "'{nickname}' is already a nickname of host '{other}'"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fail if the entry is defined in the read-only shared or system config, checked by `in_base`.
+fn ensure_not_shared(
+    config: &Config,
+    kind: &str,
+    name: &str,
+    in_base: impl Fn(&Config) -> bool,
+) -> Result<()> {
+    if config.base().is_some_and(in_base) {
+        return Err(eyre!(
+            "{kind} '{name}' is defined in the shared or system config and cannot be removed or renamed"
+        ));
+    }
+    Ok(())
+}
+
+/// Collect the sorted names of the hosts matching the predicate.
+fn hosts_matching(
+    hosts: &HashMap<String, HostConfig>,
+    predicate: impl Fn(&HostConfig) -> bool,
+) -> Vec<String> {
+    let mut names: Vec<String> = hosts
+        .iter()
+        .filter(|(_, host)| predicate(host))
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Remove a key alias together with every host that uses it, directly or through a template.
+/// Templates setting the key alias keep their other settings, the key alias is dropped from the
+/// fallback and extra key aliases of the rest and the default key alias is unset.
+fn remove_alias_cascade(config: &mut Config, alias: &str, dry_run: bool) -> Result<()> {
+    let uses_alias = |host: &HostConfig| host.key_alias.as_deref() == Some(alias);
+    let mut hosts: Vec<String> = config
+        .hosts
+        .iter()
+        .filter(|(name, host)| {
+            uses_alias(host)
+                || config
+                    .resolve_host(name)
+                    .is_ok_and(|resolved| resolved.key_alias == alias)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    hosts.sort();
+    let templates = hosts_matching(&config.templates, uses_alias);
+    let uses_fallback = |host: &HostConfig| {
+        host.fallback_aliases.iter().any(|a| a == alias)
+            || host.extra_key_aliases.iter().any(|a| a == alias)
+    };
+    let fallback_hosts: Vec<String> = hosts_matching(&config.hosts, uses_fallback)
+        .into_iter()
+        .filter(|host| !hosts.contains(host))
+        .collect();
+    let fallback_templates = hosts_matching(&config.templates, uses_fallback);
+    let is_default = config.default_alias.as_deref() == Some(alias);
+    let patterns: Vec<String> = config
+        .patterns
+        .iter()
+        .filter(|pattern| pattern.key_alias == alias)
+        .map(|pattern| pattern.pattern.clone())
+        .collect();
+    for host in &hosts {
+        ensure_not_shared(config, "Host", host, |base| base.hosts.contains_key(host))?;
+    }
+    for host in &fallback_hosts {
+        ensure_not_shared(config, "Host", host, |base| base.hosts.contains_key(host))?;
+    }
+    for template in templates.iter().chain(&fallback_templates) {
+        ensure_not_shared(config, "Template", template, |base| {
+            base.templates.contains_key(template)
+        })?;
+    }
+    for pattern in &patterns {
+        ensure_not_shared(config, "Pattern", pattern, |base| {
+            base.patterns.iter().any(|p| &p.pattern == pattern)
+        })?;
+    }
+
+    println!("Removing key alias '{}' will:", style::name(alias));
+    for host in &hosts {
+        println!("  remove host '{}'", style::name(host));
+    }
+    for template in &templates {
+        println!(
+            "  unset the key alias of template '{}'",
+            style::name(template)
+        );
+    }
+    for host in &fallback_hosts {
+        println!(
+            "  drop it from the fallback and extra key aliases of host '{}'",
+            style::name(host)
+        );
+    }
+    for template in &fallback_templates {
+        println!(
+            "  drop it from the fallback and extra key aliases of template '{}'",
+            style::name(template)
+        );
+    }
+    for pattern in &patterns {
+        println!("  remove pattern '{}'", style::destination(pattern));
+    }
+    if is_default {
+        println!("  unset the default key alias");
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if !prompt::confirm("Continue?", false)? {
+        return Err(PromptError::Declined(format!("Key alias '{alias}' was not removed")).into());
+    }
+
+    for host in &hosts {
+        config.hosts.remove(host);
+        for group in config.groups.values_mut() {
+            group.hosts.retain(|name| name != host);
+        }
+    }
+    for template in &templates {
+        if let Some(template) = config.templates.get_mut(template) {
+            template.key_alias = None;
         }
     }
+    for host in &fallback_hosts {
+        if let Some(host) = config.hosts.get_mut(host) {
+            host.fallback_aliases.retain(|a| a != alias);
+            host.extra_key_aliases.retain(|a| a != alias);
+        }
+    }
+    for template in &fallback_templates {
+        if let Some(template) = config.templates.get_mut(template) {
+            template.fallback_aliases.retain(|a| a != alias);
+            template.extra_key_aliases.retain(|a| a != alias);
+        }
+    }
+    config
+        .patterns
+        .retain(|pattern| !patterns.contains(&pattern.pattern));
+    if is_default {
+        config.default_alias = None;
+    }
+    config.key_aliases.remove(alias);
+    config.store()?;
+    println!("Key alias '{}' removed", style::name(alias));
     Ok(())
 }
 
 pub fn remove_config(config: &mut Config, command: RemoveConfigSection) -> Result<()> {
     match command {
-        RemoveConfigSection::Alias { alias_name: alias } => {
+        RemoveConfigSection::Alias {
+            alias_name: alias,
+            force,
+            dry_run,
+        } => {
             if !config.key_aliases.contains_key(&alias) {
                 return Err(eyre!("Key alias '{alias}' not found"));
             }
+            ensure_not_shared(config, "Key alias", &alias, |base| {
+                base.key_aliases.contains_key(&alias)
+            })?;
+            if force {
+                return remove_alias_cascade(config, &alias, dry_run);
+            }
 
-            // Don't allow removing aliases that are used by any hosts
-            let host_names: Vec<String> = config
-                .hosts
+            // Don't allow removing aliases that are used by any hosts or templates
+            let host_names = hosts_matching(&config.hosts, |host| host.uses_alias(&alias));
+            if !host_names.is_empty() {
+                return Err(eyre!(
+                    "Key alias '{alias}' cannot be removed because it is used by the following hosts: {host_names:?}. Use --force to remove them as well"
+                ));
+            }
+            let template_names =
+                hosts_matching(&config.templates, |template| template.uses_alias(&alias));
+            if !template_names.is_empty() {
+                return Err(eyre!(
+                    "Key alias '{alias}' cannot be removed because it is used by the following templates: {template_names:?}. Use --force to remove the hosts using them"
+                ));
+            }
+            let pattern_names: Vec<&str> = config
+                .patterns
                 .iter()
-                .filter_map(|(name, host)| {
-                    if host.key_alias == alias {
-                        Some(name.clone())
-                    } else {
-                        None
-                    }
-                })
+                .filter(|pattern| pattern.key_alias == alias)
+                .map(|pattern| pattern.pattern.as_str())
                 .collect();
-            if !host_names.is_empty() {
+            if !pattern_names.is_empty() {
+                return Err(eyre!(
+                    "Key alias '{alias}' cannot be removed because it is used by the following patterns: {pattern_names:?}. Use --force to remove them as well"
+                ));
+            }
+            if config.default_alias.as_ref() == Some(&alias) {
                 return Err(eyre!(
-                    "Key alias '{alias}' cannot be removed because it is used by the following hosts: {host_names:?}"
+                    "Key alias '{alias}' cannot be removed because it is the default key alias. Use --force to unset it"
                 ));
             }
 
             config.key_aliases.remove(&alias);
             config.store()?;
-            println!("Key alias '{alias}' removed");
+            println!("Key alias '{}' removed", style::name(&alias));
         }
         RemoveConfigSection::Host { name } => {
             if !config.hosts.contains_key(&name) {
                 return Err(eyre!("Host '{name}' not found"));
             }
+            ensure_not_shared(config, "Host", &name, |base| base.hosts.contains_key(&name))?;
+
+            // Don't allow removing the bastions of databases
+            let mut databases: Vec<&String> = config
+                .databases
+                .iter()
+                .filter(|(_, db)| db.bastion == name)
+                .map(|(db_name, _)| db_name)
+                .collect();
+            if !databases.is_empty() {
+                databases.sort();
+                return Err(eyre!(
+                    "Host '{name}' cannot be removed because it is the bastion of: {databases:?}"
+                ));
+            }
+            let jumps_through = |host: &HostConfig| host.jump_hosts.contains(&name);
+            let dependents: Vec<String> = hosts_matching(&config.hosts, jumps_through)
+                .into_iter()
+                .chain(hosts_matching(&config.templates, jumps_through))
+                .collect();
+            if !dependents.is_empty() {
+                return Err(eyre!(
+                    "Host '{name}' cannot be removed because it is a jump host of: {dependents:?}"
+                ));
+            }
             config.hosts.remove(&name);
+
+            // Drop the host from any groups that contain it
+            for (group_name, group) in config.groups.iter_mut() {
+                if group.hosts.contains(&name) {
+                    group.hosts.retain(|host| host != &name);
+                    println!(
+                        "Host '{}' removed from group '{}'",
+                        style::name(&name),
+                        style::name(group_name)
+                    );
+                }
+            }
+
+            config.store()?;
+            println!("Host '{}' removed", style::name(&name));
+        }
+        RemoveConfigSection::Template { name } => {
+            if !config.templates.contains_key(&name) {
+                return Err(eyre!("Template '{name}' not found"));
+            }
+            ensure_not_shared(config, "Template", &name, |base| {
+                base.templates.contains_key(&name)
+            })?;
+
+            // Don't allow removing templates that are extended by any hosts or templates
+            let extends_template = |host: &HostConfig| host.extends.as_ref() == Some(&name);
+            let dependents: Vec<String> = hosts_matching(&config.hosts, extends_template)
+                .into_iter()
+                .chain(hosts_matching(&config.templates, extends_template))
+                .collect();
+            if !dependents.is_empty() {
+                return Err(eyre!(
+                    "Template '{name}' cannot be removed because it is extended by: {dependents:?}"
+                ));
+            }
+
+            config.templates.remove(&name);
+            config.store()?;
+            println!("Template '{}' removed", style::name(&name));
+        }
+        RemoveConfigSection::Group { name } => {
+            if !config.groups.contains_key(&name) {
+                return Err(eyre!("Group '{name}' not found"));
+            }
+            ensure_not_shared(config, "Group", &name, |base| {
+                base.groups.contains_key(&name)
+            })?;
+            config.groups.remove(&name);
+            config.store()?;
+            println!("Group '{}' removed", style::name(&name));
+        }
+        RemoveConfigSection::Database { name } => {
+            if !config.databases.contains_key(&name) {
+                return Err(eyre!("Database '{name}' not found"));
+            }
+            ensure_not_shared(config, "Database", &name, |base| {
+                base.databases.contains_key(&name)
+            })?;
+            config.databases.remove(&name);
+            config.store()?;
+            println!("Database '{}' removed", style::name(&name));
+        }
+        RemoveConfigSection::Network { name } => {
+            if !config.networks.contains_key(&name) {
+                return Err(eyre!("Network '{name}' not found"));
+            }
+            ensure_not_shared(config, "Network", &name, |base| {
+                base.networks.contains_key(&name)
+            })?;
+
+            // Don't allow removing networks required by any hosts or templates
+            let requires_network = |host: &HostConfig| host.network.as_ref() == Some(&name);
+            let dependents: Vec<String> = hosts_matching(&config.hosts, requires_network)
+                .into_iter()
+                .chain(hosts_matching(&config.templates, requires_network))
+                .collect();
+            if !dependents.is_empty() {
+                return Err(eyre!(
+                    "Network '{name}' cannot be removed because it is required by: {dependents:?}"
+                ));
+            }
+
+            config.networks.remove(&name);
+            config.store()?;
+            println!("Network '{}' removed", style::name(&name));
+        }
+        RemoveConfigSection::DefaultAlias => {
+            if config.default_alias.take().is_none() {
+                return Err(eyre!("No default key alias set"));
+            }
+            config.store()?;
+            println!("Default key alias unset");
+        }
+        RemoveConfigSection::Pattern { pattern } => {
+            if !config.patterns.iter().any(|p| p.pattern == pattern) {
+                return Err(eyre!("Pattern '{pattern}' not found"));
+            }
+            ensure_not_shared(config, "Pattern", &pattern, |base| {
+                base.patterns.iter().any(|p| p.pattern == pattern)
+            })?;
+            config.patterns.retain(|p| p.pattern != pattern);
+            config.store()?;
+            println!("Pattern '{}' removed", style::destination(&pattern));
+        }
+        RemoveConfigSection::AuditLog => {
+            // The shared and system configs are usually managed by the team requiring the audit log
+            if config.base().is_some_and(|base| base.audit_log.is_some()) {
+                return Err(eyre!(
+                    "The audit log is set in the shared or system config and cannot be removed"
+                ));
+            }
+            if config.audit_log.take().is_none() {
+                return Err(eyre!("No audit log set"));
+            }
+            config.store()?;
+            println!("Audit log unset");
+        }
+        RemoveConfigSection::MaxSession => {
+            if config.base().is_some_and(|base| base.max_session.is_some()) {
+                return Err(eyre!(
+                    "The maximum session duration is set in the shared or system config and cannot be removed"
+                ));
+            }
+            if config.max_session.take().is_none() {
+                return Err(eyre!("No maximum session duration set"));
+            }
+            config.store()?;
+            println!("Maximum session duration unset");
+        }
+        RemoveConfigSection::FetchTimeout => {
+            if config
+                .base()
+                .is_some_and(|base| base.fetch_timeout.is_some())
+            {
+                return Err(eyre!(
+                    "The fetch timeout is set in the shared or system config and cannot be removed"
+                ));
+            }
+            if config.fetch_timeout.take().is_none() {
+                return Err(eyre!("No fetch timeout set"));
+            }
+            config.store()?;
+            println!("Fetch timeout unset");
+        }
+        RemoveConfigSection::OfflineCache => {
+            if config
+                .base()
+                .is_some_and(|base| base.offline_cache.is_some())
+            {
+                return Err(eyre!(
+                    "The offline cache is enabled in the shared or system config and cannot be disabled"
+                ));
+            }
+            if config.offline_cache.take().is_none() {
+                return Err(eyre!("The offline cache is not enabled"));
+            }
+            config.store()?;
+            // The cached keys are never used without the setting
+            key_cache::clear()?;
+            println!("Offline cache disabled");
+        }
+        RemoveConfigSection::NotifyDrops => {
+            if config
+                .base()
+                .is_some_and(|base| base.notify_drops.is_some())
+            {
+                return Err(eyre!(
+                    "Drop notifications are enabled in the shared or system config and cannot be disabled"
+                ));
+            }
+            if config.notify_drops.take().is_none() {
+                return Err(eyre!("Drop notifications are not enabled"));
+            }
+            config.store()?;
+            println!("Drop notifications disabled");
+        }
+        RemoveConfigSection::ConfirmTags => {
+            if config
+                .base()
+                .is_some_and(|base| !base.confirm_tags.is_empty())
+            {
+                return Err(eyre!(
+                    "The confirmation tags are set in the shared or system config and cannot be removed"
+                ));
+            }
+            if config.confirm_tags.is_empty() {
+                return Err(eyre!("No confirmation tags set"));
+            }
+            config.confirm_tags.clear();
             config.store()?;
-            println!("Host '{name}' removed");
+            println!("Confirmation tags unset");
         }
+        RemoveConfigSection::ForwardAgent => {
+            if config
+                .base()
+                .is_some_and(|base| base.forward_agent.is_some())
+            {
+                return Err(eyre!(
+                    "The agent forwarding policy is set in the shared or system config and cannot be removed"
+                ));
+            }
+            if config.forward_agent.take().is_none() {
+                return Err(eyre!("No agent forwarding policy set"));
+            }
+            config.store()?;
+            println!("Agent forwarding policy unset, leaving it to the ssh config");
+        }
+    }
+    Ok(())
+}
+
+/// Move the entry `old_name` to `new_name`, failing if the entry doesn't exist or the new name is
+/// already taken.
+fn rename_entry<T>(
+    entries: &mut HashMap<String, T>,
+    kind: &str,
+    old_name: &str,
+    new_name: &str,
+) -> Result<()> {
+    if entries.contains_key(new_name) {
+        return Err(eyre!("{kind} '{new_name}' already exists"));
+    }
+    let entry = entries
+        .remove(old_name)
+        .ok_or_else(|| eyre!("{kind} '{old_name}' not found"))?;
+    entries.insert(new_name.to_string(), entry);
+    Ok(())
+}
+
+/// Replace `old_name` with `new_name` in an optional reference.
+fn rename_reference(reference: &mut Option<String>, old_name: &str, new_name: &str) {
+    if reference.as_deref() == Some(old_name) {
+        *reference = Some(new_name.to_string());
+    }
+}
+
+pub fn rename_config(config: &mut Config, command: RenameConfigSection) -> Result<()> {
+    match command {
+        RenameConfigSection::Alias { old_name, new_name } => {
+            ensure_not_shared(config, "Key alias", &old_name, |base| {
+                base.key_aliases.contains_key(&old_name)
+            })?;
+            rename_entry(&mut config.key_aliases, "Key alias", &old_name, &new_name)?;
+            for host in config
+                .hosts
+                .values_mut()
+                .chain(config.templates.values_mut())
+            {
+                rename_reference(&mut host.key_alias, &old_name, &new_name);
+                for alias in host
+                    .fallback_aliases
+                    .iter_mut()
+                    .chain(&mut host.extra_key_aliases)
+                {
+                    if *alias == old_name {
+                        *alias = new_name.clone();
+                    }
+                }
+            }
+            rename_reference(&mut config.default_alias, &old_name, &new_name);
+            for pattern in &mut config.patterns {
+                if pattern.key_alias == old_name {
+                    pattern.key_alias = new_name.clone();
+                }
+            }
+            config.store()?;
+            println!(
+                "Key alias '{}' renamed to '{}'",
+                style::name(&old_name),
+                style::name(&new_name)
+            );
+        }
+        RenameConfigSection::Host { old_name, new_name } => {
+            ensure_not_shared(config, "Host", &old_name, |base| {
+                base.hosts.contains_key(&old_name)
+            })?;
+            ensure_unique_names(config, &new_name, &[])?;
+            rename_entry(&mut config.hosts, "Host", &old_name, &new_name)?;
+            for group in config.groups.values_mut() {
+                for host in group.hosts.iter_mut() {
+                    if *host == old_name {
+                        *host = new_name.clone();
+                    }
+                }
+            }
+            for db in config.databases.values_mut() {
+                if db.bastion == old_name {
+                    db.bastion = new_name.clone();
+                }
+            }
+            for host in config
+                .hosts
+                .values_mut()
+                .chain(config.templates.values_mut())
+            {
+                for jump_host in host.jump_hosts.iter_mut() {
+                    if *jump_host == old_name {
+                        *jump_host = new_name.clone();
+                    }
+                }
+            }
+            config.store()?;
+            println!(
+                "Host '{}' renamed to '{}'",
+                style::name(&old_name),
+                style::name(&new_name)
+            );
+        }
+        RenameConfigSection::Template { old_name, new_name } => {
+            ensure_not_shared(config, "Template", &old_name, |base| {
+                base.templates.contains_key(&old_name)
+            })?;
+            rename_entry(&mut config.templates, "Template", &old_name, &new_name)?;
+            for host in config
+                .hosts
+                .values_mut()
+                .chain(config.templates.values_mut())
+            {
+                rename_reference(&mut host.extends, &old_name, &new_name);
+            }
+            config.store()?;
+            println!(
+                "Template '{}' renamed to '{}'",
+                style::name(&old_name),
+                style::name(&new_name)
+            );
+        }
+        RenameConfigSection::Group { old_name, new_name } => {
+            ensure_not_shared(config, "Group", &old_name, |base| {
+                base.groups.contains_key(&old_name)
+            })?;
+            rename_entry(&mut config.groups, "Group", &old_name, &new_name)?;
+            config.store()?;
+            println!(
+                "Group '{}' renamed to '{}'",
+                style::name(&old_name),
+                style::name(&new_name)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Ask which key alias to attach to an imported host, offering the last answer as the default.
+/// An empty answer leaves the host on the default key alias, if there is one.
+fn ask_alias(config: &Config, host: &str, last: &mut Option<String>) -> Result<Option<String>> {
+    loop {
+        let default = last.as_deref().or(config.default_alias.as_deref());
+        let question = format!("Key alias for '{host}'");
+        let answer = if default.is_some() {
+            prompt::input(&question, default)?
+        } else {
+            prompt::input(&question, None)?
+        };
+        if config.key_aliases.contains_key(&answer) {
+            *last = Some(answer.clone());
+            // Hosts on the default key alias follow it when it changes
+            return Ok(Some(answer).filter(|alias| config.default_alias.as_ref() != Some(alias)));
+        }
+        println!("Key alias '{answer}' does not exist");
+    }
+}
+
+/// How imported hosts are added to the config
+struct ImportOptions {
+    /// Key alias attached to all hosts, asked for each host if not set
+    alias: Option<String>,
+    /// Ask before importing each host
+    confirm: bool,
+    /// Replace existing hosts instead of skipping them
+    force: bool,
+    dry_run: bool,
+}
+
+/// Add imported hosts to the config and store it, returning how many were added
+fn add_imported_hosts(
+    config: &mut Config,
+    hosts: Vec<(String, HostConfig)>,
+    options: &ImportOptions,
+) -> Result<usize> {
+    if let Some(alias) = &options.alias
+        && !config.key_aliases.contains_key(alias)
+    {
+        return Err(eyre!("Key alias '{alias}' does not exist"));
+    }
+    if options.alias.is_none() && !options.dry_run && config.key_aliases.is_empty() {
+        return Err(eyre!(
+            "Add a key alias to attach to the imported hosts first, see `smssh config set alias`"
+        ));
+    }
+
+    let mut last_alias = None;
+    let mut imported = 0;
+    for (name, mut host) in hosts {
+        if config.hosts.contains_key(&name) && !options.force {
+            println!("Host '{}' already exists, skipped", style::name(&name));
+            continue;
+        }
+        if options.dry_run {
+            println!("Host '{}':", style::name(&name));
+            print!("{host}");
+            continue;
+        }
+        let destination = host.destination.as_deref().unwrap_or_default();
+        if options.confirm && !prompt::confirm(&format!("Import '{name}' ({destination})?"), true)?
+        {
+            continue;
+        }
+        host.key_alias = match &options.alias {
+            Some(alias) => Some(alias.clone()),
+            None => ask_alias(config, &name, &mut last_alias)?,
+        };
+        config.hosts.insert(name, host);
+        imported += 1;
+    }
+
+    if !options.dry_run {
+        config.store()?;
+    }
+    Ok(imported)
+}
+
+fn ssh_config_hosts(path: &Path) -> Result<Vec<(String, HostConfig)>> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read the ssh config {path:?}"))?;
+    let ssh_config = ssh_config::parse(&contents);
+    for pattern in &ssh_config.skipped_patterns {
+        warn!("Skipping host pattern '{pattern}', only hosts named in full can be imported");
+    }
+    for include in &ssh_config.includes {
+        warn!("Not following 'Include {include}', import the included file separately");
+    }
+    Ok(ssh_config
+        .hosts
+        .iter()
+        .map(|host| (host.name.clone(), host.to_host_config()))
+        .collect())
+}
+
+/// A host name for the value of a name tag. Anyone able to tag the instance picks it, so
+/// everything but letters, digits, `.`, `_` and `-` is replaced, keeping out wildcards, spaces
+/// and names that read as options.
+fn host_name_from_tag(value: &str) -> Option<String> {
+    let mut name = String::new();
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+            name.push(c);
+        } else if !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_matches(|c| c == '-' || c == '.');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Hosts for the instances with all of the given tags, named after the value of `name_tag`.
+/// Instances without a usable tag are named after their ID, and names used by several instances
+/// get the instance ID appended.
+fn ec2_hosts(
+    profile: Option<&str>,
+    tags: &[(String, String)],
+    name_tag: &str,
+    address: InstanceAddress,
+    user: Option<&str>,
+) -> Result<Vec<(String, HostConfig)>> {
+    let instances = aws::block_on(async {
+        let client = aws::ec2(profile).await;
+        aws::list_instances(&client, tags).await
+    })??;
+    let name_of = |instance: &aws::Ec2Instance| {
+        instance
+            .tags
+            .get(name_tag)
+            .and_then(|value| host_name_from_tag(value))
+            .unwrap_or_else(|| instance.instance_id.clone())
+    };
+
+    let mut hosts = Vec::new();
+    for instance in &instances {
+        let ip = match address {
+            InstanceAddress::Public => instance.public_ip.as_deref(),
+            InstanceAddress::Private => instance.private_ip.as_deref(),
+            InstanceAddress::InstanceId => Some(instance.instance_id.as_str()),
+        };
+        let Some(ip) = ip else {
+            warn!(
+                "Skipping instance '{}' ({}), it has no {} address",
+                instance.instance_id,
+                instance.state,
+                format!("{address:?}").to_lowercase()
+            );
+            continue;
+        };
+        let mut name = name_of(instance);
+        if let Some(value) = instance.tags.get(name_tag)
+            && *value != name
+        {
+            warn!(
+                "Naming instance '{}' '{name}', its {name_tag} tag '{}' is not a valid host name",
+                instance.instance_id,
+                value.escape_debug()
+            );
+        }
+        if instances
+            .iter()
+            .filter(|other| name_of(other) == name)
+            .count()
+            > 1
+        {
+            name = format!("{name}-{}", instance.instance_id);
+        }
+        let destination = match user {
+            Some(user) => format!("{user}@{ip}"),
+            None => ip.to_string(),
+        };
+        hosts.push((
+            name,
+            HostConfig {
+                destination: Some(destination),
+                instance_id: Some(instance.instance_id.clone()),
+                ..Default::default()
+            },
+        ));
+    }
+    if hosts.is_empty() {
+        return Err(eyre!("No instances match the tags {tags:?}"));
+    }
+    Ok(hosts)
+}
+
+pub fn import_config(config: &mut Config, source: ImportSource) -> Result<()> {
+    match source {
+        ImportSource::SshConfig {
+            path,
+            alias,
+            force,
+            dry_run,
+        } => {
+            let path = path.unwrap_or_else(ssh_config::default_path);
+            let hosts = ssh_config_hosts(&path)?;
+            let options = ImportOptions {
+                alias,
+                confirm: false,
+                force,
+                dry_run,
+            };
+            let imported = add_imported_hosts(config, hosts, &options)?;
+            if !dry_run {
+                println!("Imported {imported} hosts from {path:?}");
+            }
+        }
+        ImportSource::Ec2 {
+            tags,
+            profile,
+            name_tag,
+            address,
+            user,
+            alias,
+            force,
+            dry_run,
+        } => {
+            let hosts = ec2_hosts(
+                profile.as_deref(),
+                &tags,
+                &name_tag,
+                address,
+                user.as_deref(),
+            )?;
+            // Without a key alias for all of them, the instances are picked one by one
+            let options = ImportOptions {
+                confirm: alias.is_none(),
+                alias,
+                force,
+                dry_run,
+            };
+            let imported = add_imported_hosts(config, hosts, &options)?;
+            if !dry_run {
+                println!("Imported {imported} hosts from EC2");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn export_config(config: &Config, target: ExportTarget) -> Result<()> {
+    let ExportTarget::Ansible { format } = target;
+    let inventory = ansible::Inventory::new(config)?;
+    match format {
+        InventoryFormat::Ini => print!("{}", inventory.to_ini()),
+        InventoryFormat::Yaml => print!("{}", inventory.to_yaml()?),
+    }
+    Ok(())
+}
+
+pub fn diff_config(config: &Config, source: Option<String>) -> Result<()> {
+    let source = source
+        .or_else(|| config.sync_source.clone())
+        .ok_or_else(|| eyre!("No sync source configured, pass one to `config diff`"))?;
+
+    let contents = sync::fetch(&source)?;
+    let remote = Config::parse_shared(&contents)
+        .wrap_err_with(|| format!("The shared config from {source} is not valid"))?;
+    let changes = sync::diff(config, &config.with_shared(remote)?);
+    if changes.is_empty() {
+        println!("Syncing from {source} would not change the config");
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", style::change(change));
+    }
+    Ok(())
+}
+
+pub fn sync_config(config: &mut Config, source: Option<String>, check: bool) -> Result<()> {
+    let source = source
+        .or_else(|| config.sync_source.clone())
+        .ok_or_else(|| eyre!("No sync source configured, pass one to `config sync`"))?;
+
+    let contents = sync::fetch(&source)?;
+    let remote = Config::parse_shared(&contents)
+        .wrap_err_with(|| format!("The shared config from {source} is not valid"))?;
+    let current = if Config::shared_config_path().exists() {
+        Config::load_shared()?
+    } else {
+        Config::new()
+    };
+    let changes = sync::diff(&current, &remote);
+
+    if check {
+        if changes.is_empty() {
+            println!("The shared config is up to date with {source}");
+            return Ok(());
+        }
+        for change in &changes {
+            println!("{}", style::change(change));
+        }
+        return Err(eyre!("The shared config has drifted from {source}"));
+    }
+
+    Config::store_shared(&contents)?;
+    if config.sync_source.as_ref() != Some(&source) {
+        config.sync_source = Some(source.clone());
+        config.store()?;
+    }
+    for change in &changes {
+        println!("{}", style::change(change));
+    }
+    println!(
+        "Shared config synced from {source} with {} changes",
+        changes.len()
+    );
+    Ok(())
+}
+
+pub fn restore_config(backup: Option<u128>, list: bool) -> Result<()> {
+    if list {
+        for (timestamp, path) in Config::backups()? {
+            println!("{timestamp}: {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let timestamp = Config::restore(backup)?;
+    println!("Config restored from backup '{timestamp}'");
+    Ok(())
+}
+
+pub fn fix_permissions() -> Result<()> {
+    Config::fix_permissions()?;
+    println!("Config permissions restricted to the current user");
+    Ok(())
+}
+
+pub fn encrypt_config(config: &mut Config, keyring: bool) -> Result<()> {
+    let passphrase = encryption::new_passphrase()?;
+    // Stored first, so that an unreachable keyring fails before anything changed. A passphrase
+    // left in the keyring from before would no longer unlock the config.
+    if keyring {
+        encryption::store_in_keyring(&passphrase)?;
+    } else {
+        encryption::remove_from_keyring()?;
+    }
+    config.passphrase = Some(passphrase.clone());
+    config.store()?;
+    // Keys cached with the previous passphrase can no longer be decrypted
+    key_cache::clear()?;
+    Config::encrypt_backups(&passphrase)?;
+    println!("Config and its backups encrypted");
+    if keyring {
+        println!("The passphrase is stored in the OS keyring");
+    }
+    Ok(())
+}
+
+pub fn decrypt_config(config: &mut Config) -> Result<()> {
+    if config.passphrase.is_none() {
+        return Err(eyre!("The config is not encrypted"));
     }
+    config.passphrase = None;
+    config.store()?;
+    // Keys are only cached while the config is encrypted
+    key_cache::clear()?;
+    encryption::remove_from_keyring()?;
+    println!("Config decrypted");
     Ok(())
 }
@@ -0,0 +1,58 @@
+use color_eyre::{Result, eyre::eyre};
+use tracing::info;
+
+use crate::{
+    config::Config,
+    connect::{self, ConnectOptions},
+    database,
+};
+
+/// Forward a database to a local port through its bastion host, printing a connection string to
+/// the forward before connecting. Returns the exit status of ssh.
+pub fn db(config: &Config, name: &str, local_port: Option<u16>, dry_run: bool) -> Result<i32> {
+    let db = config
+        .databases
+        .get(name)
+        .ok_or(eyre!("Database '{name}' does not exist"))?;
+    if !config.hosts.contains_key(&db.bastion) {
+        return Err(eyre!(
+            "Bastion host '{}' of database '{name}' does not exist",
+            db.bastion
+        ));
+    }
+    let endpoint = database::resolve(&db.source, db.profile.as_deref())?;
+    let local_port = match local_port.or(db.local_port) {
+        Some(port) => port,
+        None => database::free_local_port()?,
+    };
+
+    println!(
+        "{}",
+        database::connection_string(
+            &endpoint,
+            db.user.as_deref(),
+            db.database.as_deref(),
+            local_port
+        )
+    );
+    info!(
+        "Forwarding 127.0.0.1:{local_port} to {}:{} through '{}', press Ctrl-C to close",
+        endpoint.address, endpoint.port, db.bastion
+    );
+    // Only the forward is needed, and ssh has to fail if the local port is taken
+    let ssh_args = [
+        "-N".to_string(),
+        "-o".to_string(),
+        "ExitOnForwardFailure=yes".to_string(),
+        "-L".to_string(),
+        format!(
+            "127.0.0.1:{local_port}:{}:{}",
+            endpoint.address, endpoint.port
+        ),
+    ];
+    let options = ConnectOptions {
+        dry_run,
+        ..Default::default()
+    };
+    connect::connect_by_host(&db.bastion, config, &ssh_args, &options)
+}
@@ -0,0 +1,233 @@
+use std::{
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    aws::{self, SecretRequest},
+    config::{Config, KeyAliasConfig, ResolverConfig, Transport},
+    key_format, plugin, style,
+};
+
+/// Number of secrets fetched at the same time
+const FETCH_CONCURRENCY: usize = 8;
+
+/// Outcome of a single diagnostic check, either a success or a failure description.
+type CheckResult = std::result::Result<String, String>;
+
+fn check_ssh() -> CheckResult {
+    let output = Command::new("ssh")
+        .arg("-V")
+        .output()
+        .map_err(|e| format!("Failed to run ssh: {e}"))?;
+    // OpenSSH prints its version to stderr
+    let version = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if output.status.success() {
+        Ok(version)
+    } else {
+        Err(format!("ssh -V exited with {}: {version}", output.status))
+    }
+}
+
+/// The AWS CLI is only needed for databases, Route 53 resolvers and transports, so it is only
+/// checked when the config has one of them
+fn check_aws_cli(config: &Config) -> Option<CheckResult> {
+    let needed = !config.databases.is_empty()
+        || config
+            .hosts
+            .values()
+            .chain(config.templates.values())
+            .any(|host| {
+                matches!(host.resolver, Some(ResolverConfig::Route53 { .. }))
+                    || host.transport.is_some()
+            });
+    needed.then(|| {
+        aws::ensure_cli()
+            .map(|()| "Found in the PATH".to_string())
+            .map_err(|e| e.to_string())
+    })
+}
+
+/// The Session Manager plugin is only needed for the SSM transport
+fn check_session_manager_plugin(config: &Config) -> Option<CheckResult> {
+    let needed = config
+        .hosts
+        .values()
+        .chain(config.templates.values())
+        .any(|host| host.transport == Some(Transport::Ssm));
+    needed.then(|| {
+        aws::ensure_session_manager_plugin()
+            .map(|()| "Found in the PATH".to_string())
+            .map_err(|e| e.to_string())
+    })
+}
+
+fn check_aws_credentials() -> CheckResult {
+    let expiry = crate::aws::get_credentials_expiry_blocking()
+        .map_err(|e| format!("Failed to resolve AWS credentials: {e}"))?;
+    let Some(expiry) = expiry else {
+        return Ok("Credentials resolved, no expiry".to_string());
+    };
+    match expiry.duration_since(SystemTime::now()) {
+        Ok(remaining) => Ok(format!(
+            "Credentials resolved, expiring in {}",
+            format_duration(remaining)
+        )),
+        Err(_) => Err("Credentials have expired".to_string()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_key_storage() -> CheckResult {
+    tempfile::tempdir_in("/dev/shm")
+        .map(|_| "/dev/shm is writable".to_string())
+        .map_err(|e| {
+            format!(
+                "/dev/shm is not usable, keys will be stored in {:?}: {e}",
+                std::env::temp_dir()
+            )
+        })
+}
+
+/// Without memfds or /dev/shm, keys are held by a private ssh-agent
+#[cfg(all(unix, not(target_os = "linux")))]
+fn check_key_storage() -> CheckResult {
+    Command::new("ssh-agent")
+        .arg("-h")
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|_| "keys will be held by a private ssh-agent".to_string())
+        .map_err(|e| {
+            format!(
+                "ssh-agent is not usable, keys will be stored in {:?}: {e}",
+                std::env::temp_dir()
+            )
+        })
+}
+
+/// The ssh-agent of Windows is a system service, keys are stored in the temporary directory of
+/// the user
+#[cfg(windows)]
+fn check_key_storage() -> CheckResult {
+    let dir = std::env::temp_dir();
+    tempfile::tempdir_in(&dir)
+        .map(|_| format!("keys will be stored in {dir:?}"))
+        .map_err(|e| format!("{dir:?} is not writable: {e}"))
+}
+
+fn check_config_permissions() -> CheckResult {
+    let path = Config::config_path();
+    if !path.exists() {
+        return Ok(format!("{path:?} does not exist yet"));
+    }
+    match Config::insecure_permissions() {
+        Ok(None) => Ok(format!("{path:?} is only accessible by its owner")),
+        Ok(Some(mode)) => Err(format!(
+            "{path:?} has mode {mode:o}, run `smssh config fix-permissions` to restrict it"
+        )),
+        Err(e) => Err(format!("Failed to read {path:?} metadata: {e}")),
+    }
+}
+
+/// Check that the secret of every key alias can be fetched and holds a private key, fetching
+/// the secrets concurrently
+fn check_aliases(config: &Config) -> Vec<(String, CheckResult)> {
+    let mut names: Vec<&String> = config.key_aliases.keys().collect();
+    names.sort();
+    let aliases: Vec<_> = names
+        .iter()
+        .map(|name| config.key_aliases[*name].expanded())
+        .collect();
+    let requests: Vec<_> = aliases
+        .iter()
+        .filter_map(|alias| match alias {
+            Ok(KeyAliasConfig::SecretsManager {
+                secret_arn,
+                profile,
+                ..
+            }) => Some(SecretRequest {
+                secret_arn,
+                profile: profile.as_deref(),
+            }),
+            _ => None,
+        })
+        .collect();
+    let mut keys = match aws::fetch_many_blocking(&requests, FETCH_CONCURRENCY) {
+        Ok(keys) => keys.into_iter(),
+        Err(e) => return vec![("key aliases".to_string(), Err(e.to_string()))],
+    };
+
+    names
+        .into_iter()
+        .zip(&aliases)
+        .map(|(name, alias)| {
+            let result = match alias {
+                Ok(KeyAliasConfig::SecretsManager { secret_arn, .. }) => keys
+                    .next()
+                    .expect("a key is fetched for every expanded alias")
+                    .map_err(|e| format!("{secret_arn} is not accessible: {e}"))
+                    .and_then(|key| {
+                        key_format::normalize(&key).map_err(|e| format!("{secret_arn}: {e}"))
+                    })
+                    .map(|_| format!("{secret_arn} is accessible and contains a private key")),
+                Ok(
+                    alias @ KeyAliasConfig::Plugin {
+                        name: plugin,
+                        params,
+                    },
+                ) => config
+                    .fetch_timeout(alias)
+                    .and_then(|timeout| plugin::get_key(plugin, name, params, timeout))
+                    .map_err(|e| e.to_string())
+                    .and_then(|key| {
+                        key_format::normalize(&key).map_err(|e| format!("plugin {plugin}: {e}"))
+                    })
+                    .map(|_| format!("plugin {plugin} returned a private key")),
+                Err(e) => Err(e.to_string()),
+            };
+            (format!("key alias '{name}'"), result)
+        })
+        .collect()
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {}m", minutes / 60, minutes % 60)
+}
+
+/// Run all environment checks and print a pass/fail report. Fails if any of the checks failed.
+pub fn doctor(config: &Config) -> Result<()> {
+    let mut checks = vec![
+        ("ssh binary".to_string(), check_ssh()),
+        ("AWS credentials".to_string(), check_aws_credentials()),
+        ("key storage".to_string(), check_key_storage()),
+        ("config permissions".to_string(), check_config_permissions()),
+    ];
+
+    if let Some(result) = check_aws_cli(config) {
+        checks.push(("AWS CLI".to_string(), result));
+    }
+    if let Some(result) = check_session_manager_plugin(config) {
+        checks.push(("Session Manager plugin".to_string(), result));
+    }
+    checks.extend(check_aliases(config));
+
+    let mut failures = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(message) => println!("{} {name}: {message}", style::success("[PASS]")),
+            Err(message) => {
+                failures += 1;
+                println!("{} {name}: {message}", style::failure("[FAIL]"));
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(eyre!("{failures} of {} checks failed", checks.len()));
+    }
+    println!("All {} checks passed", checks.len());
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{cli::FingerprintTarget, config::Config, keygen, reachability, trusted_commands};
+
+/// Print the SHA256 fingerprint of the key of an alias, or of the host keys a host offers
+pub fn fingerprint(config: &Config, target: FingerprintTarget) -> Result<()> {
+    let fingerprints = match target {
+        FingerprintTarget::Alias { key_alias } => {
+            let key = super::fetch_key(config, &key_alias)?;
+            let public_key = keygen::public_key(&key)?;
+            keygen::fingerprints(&format!("{public_key} {key_alias}\n"))?
+        }
+        FingerprintTarget::Host { host } => {
+            // Unknown hosts are scanned as they are, like connect treats them as destinations
+            let (destination, args) = if config.host_name(&host).is_some() {
+                let host = config.resolve_host(&host)?;
+                // ssh-keyscan can't open the tunnel
+                if let Some(transport) = host.transport {
+                    return Err(eyre!(
+                        "'{}' is reached through {transport}, its host keys can't be scanned \
+                         directly",
+                        host.name
+                    ));
+                }
+                trusted_commands::ensure_trusted_args(config, &host.name, &host.args)?;
+                (host.destination, host.args)
+            } else {
+                (host, Vec::new())
+            };
+            keygen::fingerprints(&reachability::scan_host_keys(&destination, &args)?)?
+        }
+    };
+    for fingerprint in fingerprints {
+        println!("{fingerprint}");
+    }
+    Ok(())
+}
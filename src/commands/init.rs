@@ -0,0 +1,127 @@
+use color_eyre::Result;
+
+use crate::{
+    config::{Config, HostConfig, KeyAliasConfig},
+    prompt::{self, PromptError},
+};
+
+fn pick_profile() -> Result<Option<String>> {
+    let profiles = crate::aws::list_profiles();
+    if profiles.is_empty() {
+        println!("No AWS profiles found, the default credentials chain will be used");
+        return Ok(None);
+    }
+
+    println!("AWS profiles found:");
+    let index = prompt::select(
+        "Profile to fetch the key with (empty for the default credentials chain)",
+        &profiles,
+    )?;
+    Ok(index.map(|index| profiles[index].clone()))
+}
+
+fn pick_secret(profile: Option<&str>) -> Result<(String, Option<String>)> {
+    println!("Looking up secrets...");
+    let secrets = crate::aws::list_secrets_blocking(profile).unwrap_or_else(|e| {
+        println!("Failed to list secrets: {e:#}");
+        Vec::new()
+    });
+
+    if !secrets.is_empty() {
+        let options: Vec<String> = secrets
+            .iter()
+            .map(|(name, arn)| format!("{name} ({arn})"))
+            .collect();
+        println!("Secrets found:");
+        if let Some(index) = prompt::select(
+            "Secret containing the SSH private key (empty to enter an ARN)",
+            &options,
+        )? {
+            let (name, arn) = &secrets[index];
+            return Ok((arn.clone(), Some(name.clone())));
+        }
+    }
+
+    let arn = prompt::input("ARN of the secret containing the SSH private key", None)?;
+    Ok((arn, None))
+}
+
+/// Interactively create the first key alias and host.
+pub fn init(config: &mut Config) -> Result<()> {
+    println!(
+        "This wizard creates a key alias and a host in {:?}",
+        Config::config_path()
+    );
+
+    let profile = pick_profile()?;
+    let (secret_arn, secret_name) = pick_secret(profile.as_deref())?;
+
+    let alias_name = loop {
+        let name = prompt::input("Key alias name", secret_name.as_deref())?;
+        if !config.key_aliases.contains_key(&name) {
+            break name;
+        }
+        println!("Key alias '{name}' already exists");
+    };
+
+    println!("Verifying access to the secret...");
+    if let Err(e) = crate::aws::get_key_blocking(&secret_arn, profile.as_deref()) {
+        println!("The secret could not be fetched: {e:#}");
+        if !prompt::confirm("Save the key alias anyway?", false)? {
+            return Err(PromptError::Declined("Setup aborted".to_string()).into());
+        }
+    } else {
+        println!("The secret is accessible");
+    }
+
+    config.key_aliases.insert(
+        alias_name.clone(),
+        KeyAliasConfig::SecretsManager {
+            secret_arn,
+            profile,
+            second_factor: None,
+            fetch_timeout: None,
+            passphrase_secret_arn: None,
+        },
+    );
+    if config.default_alias.is_none()
+        && prompt::confirm(
+            &format!("Use '{alias_name}' as the default key alias?"),
+            true,
+        )?
+    {
+        config.default_alias = Some(alias_name.clone());
+    }
+
+    let mut host_name = None;
+    if prompt::confirm(&format!("Add a host using '{alias_name}'?"), true)? {
+        let name = loop {
+            let name = prompt::input("Host name", None)?;
+            if !config.hosts.contains_key(&name) {
+                break name;
+            }
+            println!("Host '{name}' already exists");
+        };
+        let destination = prompt::input("SSH destination, example: user@hostname", None)?;
+        config.hosts.insert(
+            name.clone(),
+            HostConfig {
+                key_alias: Some(alias_name.clone()),
+                destination: Some(destination),
+                ..Default::default()
+            },
+        );
+        host_name = Some(name);
+    }
+
+    config.store()?;
+    println!("Key alias '{alias_name}' added");
+
+    if let Some(host_name) = host_name {
+        println!("Host '{host_name}' added");
+        if prompt::confirm(&format!("Connect to '{host_name}' now?"), true)? {
+            crate::connect::connect_by_host(&host_name, config, &[], &Default::default())?;
+        }
+    }
+    Ok(())
+}
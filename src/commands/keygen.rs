@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+use color_eyre::{Result, eyre::eyre};
+use tracing::info;
+
+use crate::{
+    aws,
+    config::{Config, KeyAliasConfig},
+    keygen,
+};
+
+/// Arguments of `smssh keygen`
+pub struct KeygenOptions {
+    pub name: String,
+    pub secret_name: Option<String>,
+    pub profile: Option<String>,
+    pub kms_key_id: Option<String>,
+    pub tags: Vec<(String, String)>,
+    pub comment: Option<String>,
+    pub public_key_file: Option<PathBuf>,
+}
+
+/// Generate a keypair, store its private key in a new secret, add a key alias for the secret and
+/// print the public key.
+pub fn keygen(config: &mut Config, options: KeygenOptions) -> Result<()> {
+    let name = options.name;
+    if config.key_aliases.contains_key(&name) {
+        return Err(eyre!("Key alias '{name}' already exists"));
+    }
+
+    let keypair = keygen::generate_ed25519(options.comment.as_deref().unwrap_or(&name))?;
+    let secret_name = options.secret_name.as_deref().unwrap_or(&name);
+    let secret_arn = aws::create_secret_blocking(
+        secret_name,
+        &keypair.private_key,
+        options.profile.as_deref(),
+        options.kms_key_id.as_deref(),
+        &options.tags,
+    )?;
+    info!("Secret '{secret_name}' created: {secret_arn}");
+
+    config.key_aliases.insert(
+        name.clone(),
+        KeyAliasConfig::SecretsManager {
+            secret_arn,
+            profile: options.profile,
+            second_factor: None,
+            fetch_timeout: None,
+            passphrase_secret_arn: None,
+        },
+    );
+    config.store()?;
+    info!("Key alias '{name}' added");
+
+    if let Some(path) = &options.public_key_file {
+        std::fs::write(path, format!("{}\n", keypair.public_key))?;
+        info!("Public key written to {path:?}");
+    }
+    // The public key alone on stdout, so that it can be piped into authorized_keys
+    println!("{}", keypair.public_key);
+    Ok(())
+}
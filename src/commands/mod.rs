@@ -1,16 +1,259 @@
-use crate::cli::Args;
+use crate::{
+    aws,
+    cli::{Args, NameKind, OutputFormat},
+    config::{Config, is_wildcard},
+    encryption,
+    error::Error,
+    provider, second_factor, systemd,
+};
 use clap::CommandFactory;
-use clap_complete::{generate, Shell};
+use clap_complete::{Shell, generate};
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use secrecy::SecretString;
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
 
 pub mod config;
-pub mod connect;
+pub mod db;
+pub mod doctor;
+pub mod fingerprint;
+pub mod init;
+pub mod keygen;
+pub mod open;
+pub mod pubkey;
+pub mod push_key;
+pub mod recent;
+pub mod rotate;
+pub mod run;
+pub mod stats;
+pub mod test;
+pub mod transfer;
+pub mod warm;
+pub mod whoami;
 
-pub fn print_completions(shell: Shell) {
+/// Completes host names after `connect` and `run`, and key alias names after `connect-with-alias`
+static FISH_NAME_COMPLETIONS: &str = r#"
+complete -c smssh -n "__fish_seen_subcommand_from connect c run; and test (count (commandline -opc)) -eq 2" -f -a "(smssh complete-names host 2>/dev/null)"
+complete -c smssh -n "__fish_seen_subcommand_from connect-with-alias ca; and test (count (commandline -opc)) -eq 2" -f -a "(smssh complete-names alias 2>/dev/null)"
+"#;
+
+static BASH_NAME_COMPLETIONS: &str = r#"
+_smssh_names() {
+    if [[ ${COMP_CWORD} -eq 2 ]]; then
+        case "${COMP_WORDS[1]}" in
+            connect|c|run)
+                COMPREPLY=($(compgen -W "$(smssh complete-names host 2>/dev/null)" -- "${COMP_WORDS[2]}"))
+                return 0
+                ;;
+            connect-with-alias|ca)
+                COMPREPLY=($(compgen -W "$(smssh complete-names alias 2>/dev/null)" -- "${COMP_WORDS[2]}"))
+                return 0
+                ;;
+        esac
+    fi
+    _smssh "$@"
+}
+complete -F _smssh_names -o bashdefault -o default smssh
+"#;
+
+static ZSH_NAME_COMPLETIONS: &str = r#"
+_smssh_names() {
+    if (( CURRENT == 3 )); then
+        case $words[2] in
+            connect|c|run)
+                compadd -- ${(f)"$(smssh complete-names host 2>/dev/null)"}
+                return
+                ;;
+            connect-with-alias|ca)
+                compadd -- ${(f)"$(smssh complete-names alias 2>/dev/null)"}
+                return
+                ;;
+        esac
+    fi
+    _smssh "$@"
+}
+compdef _smssh_names smssh
+"#;
+
+/// The completion script for the shell. Fish, bash and zsh scripts also complete the configured
+/// host and key alias names.
+fn completion_script(shell: Shell) -> Vec<u8> {
     let cmd = &mut Args::command();
-    generate(
-        shell,
-        cmd,
-        cmd.get_name().to_string(),
-        &mut std::io::stdout(),
+    let mut script = Vec::new();
+    generate(shell, cmd, cmd.get_name().to_string(), &mut script);
+
+    let names = match shell {
+        Shell::Fish => FISH_NAME_COMPLETIONS,
+        Shell::Bash => BASH_NAME_COMPLETIONS,
+        Shell::Zsh => ZSH_NAME_COMPLETIONS,
+        _ => "",
+    };
+    script.extend_from_slice(names.as_bytes());
+    script
+}
+
+/// Print the completion script for the shell
+pub fn print_completions(shell: Shell) {
+    let _ = std::io::stdout().write_all(&completion_script(shell));
+}
+
+/// A base directory from an XDG environment variable, or its default under the home directory
+fn xdg_dir(variable: &str, default: &str) -> Result<PathBuf> {
+    match std::env::var_os(variable).filter(|dir| !dir.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => Ok(dirs::home_dir()
+            .ok_or(eyre!("Could not find the home directory"))?
+            .join(default)),
+    }
+}
+
+/// The first directory of the zsh fpath in the home directory, as set up by the .zshrc of the
+/// user. Completions in the system directories would need root and get lost on upgrades.
+fn zsh_user_fpath() -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    let output = Command::new("zsh")
+        .args(["-ic", "print -rl -- $fpath"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .find(|dir| dir.starts_with(&home) && dir.is_dir())
+}
+
+/// Write the completion script of the shell where the shell loads completions from on demand
+pub fn install_completions(shell: Shell) -> Result<()> {
+    let mut hint = None;
+    let path = match shell {
+        Shell::Fish => xdg_dir("XDG_CONFIG_HOME", ".config")?.join("fish/completions/smssh.fish"),
+        // Loaded by bash-completion, which looks for the script named like the command
+        Shell::Bash => match std::env::var_os("BASH_COMPLETION_USER_DIR") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("completions/smssh"),
+            _ => {
+                xdg_dir("XDG_DATA_HOME", ".local/share")?.join("bash-completion/completions/smssh")
+            }
+        },
+        Shell::Zsh => match zsh_user_fpath() {
+            Some(dir) => dir.join("_smssh"),
+            None => {
+                let dir = xdg_dir("ZDOTDIR", "")?.join(".zfunc");
+                hint = Some(format!(
+                    "Add `fpath=({} $fpath)` to your .zshrc before `compinit` is run to load them",
+                    dir.display()
+                ));
+                dir.join("_smssh")
+            }
+        },
+        _ => {
+            return Err(eyre!(
+                "Completions can't be installed for {shell}, print them with --print instead"
+            ));
+        }
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create directory {dir:?}"))?;
+    }
+    std::fs::write(&path, completion_script(shell))
+        .wrap_err_with(|| format!("Failed to write the completions to {path:?}"))?;
+    println!("Installed the {shell} completions to {path:?}");
+    if let Some(hint) = hint {
+        println!("{hint}");
+    }
+    println!("Start a new shell to use them");
+    Ok(())
+}
+
+/// Print the configured names of the given kind, one per line, for use by shell completions.
+pub fn print_names(kind: NameKind) -> Result<()> {
+    // Never prompt for the passphrase of an encrypted config while completing
+    if Config::is_encrypted()? && !encryption::has_env_passphrase() {
+        return Ok(());
+    }
+
+    // Completions run on every Tab, they don't migrate or store anything
+    let config = Config::load_read_only()?;
+    let mut names: Vec<&String> = match kind {
+        NameKind::Host => config
+            .hosts
+            .iter()
+            .flat_map(|(name, host)| std::iter::once(name).chain(&host.nicknames))
+            // Wildcard entries only provide settings, they can't be connected to by name
+            .filter(|name| !is_wildcard(name))
+            .collect(),
+        NameKind::Alias => config.key_aliases.keys().collect(),
+        NameKind::Template => config.templates.keys().collect(),
+        NameKind::Group => config.groups.keys().collect(),
+    };
+    names.sort();
+    for name in names {
+        println!("{name}");
+    }
+    Ok(())
+}
+
+/// Fetch and audit the key of an alias after verifying its second factor, within its fetch
+/// timeout
+pub fn fetch_key(config: &Config, name: &str) -> Result<SecretString> {
+    let alias = config
+        .key_aliases
+        .get(name)
+        .ok_or(Error::Config(format!("Key alias '{name}' does not exist")))?;
+    if let Some(second_factor) = alias.second_factor() {
+        second_factor::verify(config, name, second_factor)?;
+    }
+    aws::block_on(provider::fetch_key(config, name))?
+        .wrap_err_with(|| Error::Provider(format!("Failed to fetch the key of '{name}'")))
+}
+
+/// How long the daemon and agent started by systemd run without requests by default
+const SYSTEMD_IDLE_TIMEOUT: &str = "30m";
+
+/// Print the systemd units running the current command on demand, with the args it was given
+/// besides `--print-systemd-unit`. Units exit after [SYSTEMD_IDLE_TIMEOUT] without requests
+/// unless an idle timeout is given.
+pub fn print_systemd_units(name: &str, description: &str, socket: &Path) -> Result<()> {
+    let mut args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--print-systemd-unit")
+        .collect();
+    if !args.iter().any(|arg| arg.starts_with("--idle-timeout")) {
+        args.extend([
+            "--idle-timeout".to_string(),
+            SYSTEMD_IDLE_TIMEOUT.to_string(),
+        ]);
+    }
+    print!("{}", systemd::units(name, description, socket, &args)?);
+    println!(
+        "\n# Enable with `systemctl --user daemon-reload && systemctl --user enable --now {name}.socket`"
     );
+    Ok(())
+}
+
+/// Write a man page for the main command and each subcommand to `out_dir`
+pub fn generate_man_pages(out_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(out_dir)
+        .wrap_err_with(|| format!("Failed to create directory {out_dir:?}"))?;
+    clap_mangen::generate_to(Args::command(), out_dir)
+        .wrap_err_with(|| format!("Failed to write the man pages to {out_dir:?}"))?;
+    println!("Man pages written to {out_dir:?}");
+    Ok(())
+}
+
+/// Print a machine-readable listing in the given format
+pub fn print_output(format: OutputFormat, value: &impl Serialize) -> Result<()> {
+    match format {
+        OutputFormat::Yaml => print!("{}", serde_yml::to_string(value)?),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+    }
+    Ok(())
 }
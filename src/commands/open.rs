@@ -0,0 +1,255 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::{fs::PermissionsExt, net::UnixListener},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use tracing::info;
+#[cfg(windows)]
+use uds_windows::UnixListener;
+
+use crate::{
+    config::{Config, KeyAliasConfig},
+    connect::shell_quote,
+    daemon, duration,
+};
+
+/// How long the panes have to request their keys, including their confirmations and second
+/// factors
+const KEY_SERVE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Runs its arguments and waits for Enter if they fail
+static TMUX_HOLD_ON_FAILURE: &str = r#"sh -c '"$@" || { printf "\nsmssh exited with %s, press Enter to close" "$?"; read -r _; }' smssh"#;
+
+/// The terminal multiplexer the hosts are opened in
+#[derive(Debug, Clone, Copy)]
+pub enum Multiplexer {
+    Tmux,
+    Zellij,
+}
+
+/// The hosts to open, in the order they were given or listed in the group
+fn target_hosts(config: &Config, hosts: Vec<String>, group: Option<&str>) -> Result<Vec<String>> {
+    let hosts = match group {
+        Some(group) => config
+            .groups
+            .get(group)
+            .ok_or(eyre!("Group '{group}' does not exist"))?
+            .hosts
+            .clone(),
+        None => hosts,
+    };
+    for host in &hosts {
+        if config.host_name(host).is_none() {
+            return Err(eyre!("Host '{host}' does not exist"));
+        }
+    }
+    Ok(hosts)
+}
+
+/// The number of secrets the pane of a host pulls through the key socket: the Secrets Manager
+/// keys of its key alias, extra and fallback key aliases and jump hosts, and their passphrases.
+/// Fallback keys are only pulled when ssh fails with the key before, but the socket stays open
+/// for them.
+fn secret_requests(config: &Config, host: &str) -> Result<usize> {
+    let host = config.resolve_host(host)?;
+    // The keys of the jump hosts are pulled once per key alias
+    let jump_aliases = host
+        .jump_hosts
+        .iter()
+        .map(|jump_host| Ok(config.resolve_host(jump_host)?.key_alias))
+        .collect::<Result<BTreeSet<_>>>()?;
+    let aliases = [host.key_alias]
+        .into_iter()
+        .chain(host.extra_key_aliases)
+        .chain(host.fallback_aliases)
+        .chain(jump_aliases);
+    let mut requests = 0;
+    for alias in aliases {
+        if let KeyAliasConfig::SecretsManager {
+            passphrase_secret_arn,
+            ..
+        } = config.key_alias(&alias)?
+        {
+            requests += 1 + usize::from(passphrase_secret_arn.is_some());
+        }
+    }
+    Ok(requests)
+}
+
+/// The command line run in the pane of a host, connecting through the private key socket
+fn pane_command(smssh: &Path, socket: &Path, host: &str) -> Vec<String> {
+    vec![
+        "env".to_string(),
+        format!("{}={}", daemon::SOCKET_ENV, socket.display()),
+        smssh.display().to_string(),
+        "connect".to_string(),
+        host.to_string(),
+    ]
+}
+
+fn run(program: &str, args: &[OsString]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .wrap_err_with(|| format!("Failed to run {program}"))?;
+    if !status.success() {
+        return Err(eyre!("{program} failed with {status}"));
+    }
+    Ok(())
+}
+
+/// Open a tmux window per host, in a new session named after the group or in the current
+/// session when running inside tmux. Returns the command attaching to the new session.
+fn open_tmux(session: &str, panes: &[(String, Vec<String>)]) -> Result<Option<Command>> {
+    let inside = std::env::var_os("TMUX").is_some();
+    for (index, (host, command)) in panes.iter().enumerate() {
+        // tmux closes windows when their command exits, failed connections are kept open so that
+        // their errors can be read
+        let command: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+        let command = format!("{TMUX_HOLD_ON_FAILURE} {}", command.join(" "));
+        let args: Vec<OsString> = if index == 0 && !inside {
+            vec![
+                "new-session".into(),
+                "-d".into(),
+                "-s".into(),
+                session.into(),
+                "-n".into(),
+                host.into(),
+                command.into(),
+            ]
+        } else {
+            let mut args = vec!["new-window".into(), "-d".into()];
+            if !inside {
+                args.extend(["-t".into(), format!("{session}:").into()]);
+            }
+            args.extend(["-n".into(), host.into(), command.into()]);
+            args
+        };
+        run("tmux", &args)?;
+    }
+    if inside {
+        return Ok(None);
+    }
+    let mut attach = Command::new("tmux");
+    attach.args(["attach-session", "-t", session]);
+    Ok(Some(attach))
+}
+
+/// Quote a string for a KDL layout
+fn kdl_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Open a zellij tab per host, in a new session named after the group or in the current session
+/// when running inside zellij. Returns the command starting the new session.
+fn open_zellij(
+    session: &str,
+    panes: &[(String, Vec<String>)],
+    layout: &Path,
+) -> Result<Option<Command>> {
+    let mut kdl = String::from("layout {\n");
+    for (host, command) in panes {
+        let (program, args) = command.split_first().expect("pane commands are not empty");
+        let args: Vec<String> = args.iter().map(|arg| kdl_string(arg)).collect();
+        kdl.push_str(&format!(
+            "    tab name={} {{\n        pane command={} {{\n            args {}\n        }}\n    }}\n",
+            kdl_string(host),
+            kdl_string(program),
+            args.join(" ")
+        ));
+    }
+    kdl.push_str("}\n");
+    std::fs::write(layout, kdl)?;
+
+    if std::env::var_os("ZELLIJ").is_some() {
+        run(
+            "zellij",
+            &[
+                "action".into(),
+                "new-tab".into(),
+                "--layout".into(),
+                layout.into(),
+            ],
+        )?;
+        return Ok(None);
+    }
+    let mut start = Command::new("zellij");
+    start
+        .args(["--session", session, "--new-session-with-layout"])
+        .arg(layout);
+    Ok(Some(start))
+}
+
+/// Open a window per host in a terminal multiplexer, each connecting with `smssh connect`. The
+/// Secrets Manager keys are served to the windows over a private socket, so that each key is
+/// fetched once however many hosts use it.
+pub fn open(
+    config: &Config,
+    hosts: Vec<String>,
+    group: Option<&str>,
+    multiplexer: Multiplexer,
+) -> Result<()> {
+    let hosts = target_hosts(config, hosts, group)?;
+    if hosts.is_empty() {
+        return Err(eyre!("No hosts to open"));
+    }
+    let mut key_requests = 0;
+    for host in &hosts {
+        key_requests += secret_requests(config, host)?;
+    }
+
+    // The socket and layout live in a private directory removed once the keys were served
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("smssh-open");
+    #[cfg(unix)]
+    builder.permissions(Permissions::from_mode(0o700));
+    let dir = builder.tempdir()?;
+    let socket = dir.path().join("keys.sock");
+    let listener =
+        UnixListener::bind(&socket).wrap_err_with(|| format!("Failed to listen on {socket:?}"))?;
+    let smssh = std::env::current_exe()?;
+    let panes: Vec<(String, Vec<String>)> = hosts
+        .iter()
+        .map(|host| (host.clone(), pane_command(&smssh, &socket, host)))
+        .collect();
+
+    let session = format!("smssh-{}", group.unwrap_or("hosts"));
+    let attach = match multiplexer {
+        Multiplexer::Tmux => open_tmux(&session, &panes)?,
+        Multiplexer::Zellij => open_zellij(&session, &panes, &dir.path().join("layout.kdl"))?,
+    };
+    let deadline = Instant::now() + KEY_SERVE_TIMEOUT;
+    match attach {
+        // The keys are served while the new session is attached
+        Some(mut attach) => {
+            let server =
+                std::thread::spawn(move || daemon::serve_private(listener, key_requests, deadline));
+            attach.status()?;
+            if server.is_finished() {
+                server.join().expect("the key server does not panic")?;
+            }
+        }
+        None => {
+            println!(
+                "Opened {} windows, serving their keys for up to {}",
+                hosts.len(),
+                duration::format(KEY_SERVE_TIMEOUT)
+            );
+            daemon::serve_private(listener, key_requests, deadline)?;
+        }
+    }
+    info!("Stopped serving keys on {socket:?}");
+    Ok(())
+}
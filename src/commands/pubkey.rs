@@ -0,0 +1,65 @@
+use arboard::Clipboard;
+use color_eyre::{Result, eyre::Context};
+use tracing::warn;
+
+use crate::{agent, config::Config, keygen};
+
+/// Print the public key of a key alias, derived from its private key without writing it to disk.
+/// The comment defaults to the key alias name when `--comment` is given without a value. With
+/// `copy`, the public key goes to the clipboard instead, ready to paste into authorized_keys.
+/// On Linux, smssh only exits once something else owns the clipboard.
+pub fn pubkey(
+    config: &Config,
+    key_alias: &str,
+    comment: Option<Option<String>>,
+    copy: bool,
+) -> Result<()> {
+    let key = super::fetch_key(config, key_alias)?;
+    let public_key = keygen::public_key(&key)?;
+    if let Err(e) = agent::remember_public_key(key_alias, &public_key) {
+        warn!("Failed to remember the public key of '{key_alias}' for the agent: {e}");
+    }
+    let line = match comment {
+        Some(comment) => {
+            let comment = comment.unwrap_or_else(|| key_alias.to_string());
+            format!("{public_key} {comment}")
+        }
+        None => public_key,
+    };
+    if !copy {
+        println!("{line}");
+        return Ok(());
+    }
+    // Only the public key is copied, the private key stays in memory
+    let mut clipboard = Clipboard::new().wrap_err("Failed to open the clipboard")?;
+    copy_text(&mut clipboard, key_alias, line)
+        .wrap_err("Failed to copy the public key to the clipboard")
+}
+
+/// The clipboards of Linux are served by the process that set them, so smssh keeps serving it
+/// until a clipboard manager or another program takes it over
+#[cfg(target_os = "linux")]
+fn copy_text(
+    clipboard: &mut Clipboard,
+    key_alias: &str,
+    text: String,
+) -> Result<(), arboard::Error> {
+    use arboard::SetExtLinux;
+
+    println!(
+        "Copying the public key of key alias '{key_alias}' to the clipboard, smssh keeps it there \
+         until something else is copied"
+    );
+    clipboard.set().wait().text(text)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_text(
+    clipboard: &mut Clipboard,
+    key_alias: &str,
+    text: String,
+) -> Result<(), arboard::Error> {
+    clipboard.set_text(text)?;
+    println!("Public key of key alias '{key_alias}' copied to the clipboard");
+    Ok(())
+}
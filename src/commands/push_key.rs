@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use crate::{authorized_keys, config::Config, connect::Route, keygen};
+use color_eyre::{Result, eyre::Context};
+
+/// Where the public key to install comes from
+pub enum PublicKeySource {
+    File(PathBuf),
+    Literal(String),
+    /// Derived from the private key of a key alias
+    Alias(String),
+}
+
+fn public_key(config: &Config, source: &PublicKeySource) -> Result<String> {
+    let public_key = match source {
+        PublicKeySource::File(path) => std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read the public key {path:?}"))?,
+        PublicKeySource::Literal(key) => key.clone(),
+        PublicKeySource::Alias(name) => keygen::public_key(&super::fetch_key(config, name)?)?,
+    };
+    let public_key = public_key.trim().to_string();
+    authorized_keys::blob(&public_key)?;
+    Ok(public_key)
+}
+
+/// Add a public key to the authorized_keys of a host, connecting with the key of its alias or
+/// the given one
+pub fn push_key(
+    config: &Config,
+    host_name: &str,
+    alias: Option<&str>,
+    source: &PublicKeySource,
+    user: Option<&str>,
+) -> Result<()> {
+    let mut host = config.resolve_host(host_name)?;
+    if let Some(alias) = alias {
+        host.key_alias = alias.to_string();
+    }
+    let public_key = public_key(config, source)?;
+    let key = super::fetch_key(config, &host.key_alias)?;
+    let route = Route::new(config, &host)?;
+    authorized_keys::install(&host, &route, &key, &public_key, user)?;
+    let user = user
+        .map(|user| format!(" for '{user}'"))
+        .unwrap_or_default();
+    println!("The public key is authorized on '{host_name}'{user}");
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    config::Config,
+    connect::{ConnectOptions, connect_by_alias, connect_by_host},
+    history::{self, format_ago, format_duration},
+    style,
+    table::Table,
+};
+
+/// List the recent connections, most recent first, or repeat the connection with the given index
+/// List the recent connections, or connect again and return the exit status of ssh
+pub fn recent(config: &Config, index: Option<usize>, limit: usize, dry_run: bool) -> Result<i32> {
+    let entries = history::load()?;
+    let Some(index) = index else {
+        let mut table = Table::new(vec!["#", "WHEN", "HOST", "ALIAS", "DURATION", "EXIT"]);
+        table.style_column(2, style::name);
+        for (i, entry) in entries.iter().rev().take(limit).enumerate() {
+            table.add_row(vec![
+                (i + 1).to_string(),
+                format_ago(entry.timestamp),
+                entry.host.clone().unwrap_or_default(),
+                entry.key_alias.clone(),
+                format_duration(entry.duration_secs),
+                entry
+                    .exit_code
+                    .map(|code| code.to_string())
+                    .unwrap_or_default(),
+            ]);
+        }
+        table.print();
+        return Ok(0);
+    };
+
+    let entry = index
+        .checked_sub(1)
+        .and_then(|i| entries.iter().rev().nth(i))
+        .ok_or(eyre!("There is no recent connection number {index}"))?;
+    let options = ConnectOptions {
+        dry_run,
+        ..Default::default()
+    };
+    match &entry.host {
+        Some(host) => connect_by_host(host, config, &entry.ssh_args, &options),
+        None => connect_by_alias(&entry.key_alias, config, &entry.ssh_args, &options),
+    }
+}
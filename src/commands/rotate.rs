@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use color_eyre::{Result, eyre::eyre};
+use tracing::{info, warn};
+
+use crate::{
+    audit::{self, AuditRecord},
+    authorized_keys, aws,
+    config::{Config, KeyAliasConfig, ResolvedHost},
+    connect::{self, Route},
+    daemon,
+    fingerprints::{self, KnownKey},
+    key_cache, key_format, keygen, second_factor, style,
+};
+
+/// The hosts to rotate the key on, all hosts using the key alias. The secret holds one key for
+/// all of them, so the given hosts have to include every one of them, replacing the secret after
+/// installing the new key on only some would lock out the rest.
+fn target_hosts(config: &Config, key_alias: &str, hosts: &[String]) -> Result<Vec<ResolvedHost>> {
+    let mut names: Vec<&String> = config.host_names().collect();
+    names.sort();
+    let mut targets = Vec::new();
+    for name in names {
+        let host = config.resolve_host(name)?;
+        if host.key_alias == key_alias {
+            targets.push(host);
+        }
+    }
+    if targets.is_empty() {
+        return Err(eyre!("No hosts use key alias '{key_alias}'"));
+    }
+    if hosts.is_empty() {
+        return Ok(targets);
+    }
+
+    for name in hosts {
+        let host = config.resolve_host(name)?;
+        if host.key_alias != key_alias {
+            return Err(eyre!(
+                "Host '{name}' uses key alias '{}', not '{key_alias}'",
+                host.key_alias
+            ));
+        }
+    }
+    let missing: Vec<&str> = targets
+        .iter()
+        .map(|host| host.name.as_str())
+        .filter(|name| !hosts.iter().any(|given| given == name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(eyre!(
+            "Key alias '{key_alias}' is also used by {}, which would lose access when its secret \
+             is replaced, include them with --host or leave out --host to rotate all hosts",
+            missing
+                .iter()
+                .map(|name| format!("'{name}'"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    Ok(targets)
+}
+
+/// Run an action on every host, printing the outcome per host. Returns the hosts it succeeded
+/// on.
+fn run_on_hosts<'a>(
+    hosts: impl IntoIterator<Item = &'a ResolvedHost>,
+    label: &str,
+    action: impl Fn(&ResolvedHost) -> Result<()>,
+) -> Vec<&'a ResolvedHost> {
+    let mut succeeded = Vec::new();
+    for host in hosts {
+        match action(host) {
+            Ok(_) => {
+                println!(
+                    "{} {}",
+                    style::success(&format!("[{label}]")),
+                    style::name(&host.name)
+                );
+                succeeded.push(host);
+            }
+            Err(e) => println!(
+                "{} {}: {e}",
+                style::failure("[FAIL]"),
+                style::name(&host.name)
+            ),
+        }
+    }
+    succeeded
+}
+
+/// Replace the key of a Secrets Manager key alias with a new ed25519 key. The new public key is
+/// installed on the hosts with the current key before the secret is updated, so that a failure
+/// leaves every host reachable with the key stored in the secret. The new key has no passphrase,
+/// the passphrase secret of the alias keeps working since decrypting such a key is a no-op. The
+/// rotation is audited like key fetches.
+pub fn rotate(
+    config: &Config,
+    key_alias: &str,
+    hosts: &[String],
+    remove_old: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let alias = config.key_alias(key_alias)?;
+    let KeyAliasConfig::SecretsManager {
+        secret_arn,
+        profile,
+        ..
+    } = &alias
+    else {
+        return Err(eyre!(
+            "Key alias '{key_alias}' is fetched by a plugin, only Secrets Manager keys can be rotated"
+        ));
+    };
+    let hosts = target_hosts(config, key_alias, hosts)?;
+
+    if dry_run {
+        println!("Secret: {secret_arn}");
+        println!("Profile: {}", profile.as_deref().unwrap_or("default"));
+        for host in &hosts {
+            println!("Host: {} ({})", host.name, host.destination);
+        }
+        return Ok(());
+    }
+
+    let result = rotate_key(config, key_alias, &alias, &hosts, remove_old);
+    if let Some(audit_log) = &config.audit_log {
+        let source = config.key_source(key_alias)?;
+        let mut record = AuditRecord::new("rotate", None, None, key_alias, source.into());
+        record.error = result.as_ref().err().map(|e| e.to_string());
+        if let Err(e) = audit::record(audit_log, &record) {
+            warn!("Failed to audit the rotation: {e}");
+        }
+    }
+    result
+}
+
+fn rotate_key(
+    config: &Config,
+    key_alias: &str,
+    alias: &KeyAliasConfig,
+    hosts: &[ResolvedHost],
+    remove_old: bool,
+) -> Result<()> {
+    let KeyAliasConfig::SecretsManager {
+        secret_arn,
+        profile,
+        second_factor,
+        passphrase_secret_arn,
+        ..
+    } = alias
+    else {
+        unreachable!("only Secrets Manager key aliases are rotated");
+    };
+    if let Some(second_factor) = second_factor {
+        second_factor::verify(config, key_alias, second_factor)?;
+    }
+    info!("Fetching the current key of '{key_alias}'");
+    let old_key = key_format::normalize(&aws::get_key_blocking(secret_arn, profile.as_deref())?)?;
+    let old_key = match passphrase_secret_arn {
+        Some(passphrase_secret_arn) => keygen::decrypt(
+            &old_key,
+            &aws::get_key_blocking(passphrase_secret_arn, profile.as_deref())?,
+        )?,
+        None => old_key,
+    };
+    let old_public_key = keygen::public_key(&old_key)?;
+    let routes = hosts
+        .iter()
+        .map(|host| Ok((host.name.as_str(), Route::new(config, host)?)))
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let new = keygen::generate_ed25519(key_alias)?;
+    let new_key = key_format::normalize(&new.private_key)?;
+
+    let installed = run_on_hosts(hosts, "INSTALLED", |host| {
+        authorized_keys::install(
+            host,
+            &routes[host.name.as_str()],
+            &old_key,
+            &new.public_key,
+            None,
+        )
+    });
+    if installed.len() < hosts.len() {
+        return Err(eyre!(
+            "The new key could not be installed on {} of {} hosts, the secret was not changed",
+            hosts.len() - installed.len(),
+            hosts.len()
+        ));
+    }
+
+    let version = aws::put_secret_value_blocking(secret_arn, &new_key, profile.as_deref())?;
+    println!("Stored the new key as version {version} of {secret_arn}");
+    let known = KnownKey {
+        secret_arn: secret_arn.clone(),
+        fingerprint: fingerprints::fingerprint(&new_key)?,
+    };
+    if let Err(e) = fingerprints::store(key_alias, known) {
+        warn!("Failed to remember the new key fingerprint: {e}");
+    }
+    if let (Some(_), Some(passphrase)) = (&config.offline_cache, &config.passphrase)
+        && let Err(e) = key_cache::store(secret_arn, &new_key, passphrase)
+    {
+        warn!("Failed to cache the new key for offline use: {e}");
+    }
+    if daemon::socket_path().exists() {
+        warn!("Restart `smssh daemon` so that it does not serve a cached copy of the old key");
+    }
+
+    let verified = run_on_hosts(hosts, "VERIFIED", |host| {
+        connect::run_remote(host, &routes[host.name.as_str()], &new_key, "true", "").map(|_| ())
+    });
+    if remove_old {
+        run_on_hosts(verified.iter().copied(), "REMOVED OLD KEY", |host| {
+            authorized_keys::remove(
+                host,
+                &routes[host.name.as_str()],
+                &new_key,
+                &old_public_key,
+                None,
+            )
+        });
+    }
+    if verified.len() < hosts.len() {
+        return Err(eyre!(
+            "{} of {} hosts did not accept the new key, their old key was kept",
+            hosts.len() - verified.len(),
+            hosts.len()
+        ));
+    }
+    Ok(())
+}
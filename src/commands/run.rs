@@ -0,0 +1,57 @@
+use std::io::{IsTerminal, stdin};
+
+use color_eyre::Result;
+
+use crate::{
+    batch,
+    config::Config,
+    connect::{self, ConnectOptions, shell_quote},
+    error::Error,
+};
+
+/// Run a named command of a host the way `connect` reaches it, with the args appended to the
+/// command. Returns the exit status of ssh, which is the one of the command.
+pub fn run(
+    config: &Config,
+    host: &str,
+    command: &str,
+    args: &[String],
+    dry_run: bool,
+) -> Result<i32> {
+    let resolved = match config.host_name(host) {
+        Some(name) => config.resolve_host(name)?,
+        None if !config.wildcard_hosts(host, None).is_empty() => {
+            config.resolve_destination(host, None)?
+        }
+        None => return Err(Error::Config(format!("Host '{host}' does not exist")).into()),
+    };
+    let template = resolved.commands.get(command).ok_or_else(|| {
+        let known: Vec<&str> = resolved.commands.keys().map(String::as_str).collect();
+        Error::Config(if known.is_empty() {
+            format!("Host '{host}' has no commands")
+        } else {
+            format!(
+                "Host '{host}' has no command '{command}', only {}",
+                known.join(", ")
+            )
+        })
+    })?;
+    let script = std::iter::once(template.clone())
+        .chain(args.iter().map(|arg| shell_quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    // Commands like `sudo` or following a log need a terminal, which ssh only allocates for a
+    // login shell by default
+    let ssh_args = if stdin().is_terminal() && !batch::enabled() {
+        vec!["-t".to_string()]
+    } else {
+        Vec::new()
+    };
+    let options = ConnectOptions {
+        dry_run,
+        command: Some(script),
+        ..Default::default()
+    };
+    connect::connect_by_host(host, config, &ssh_args, &options)
+}
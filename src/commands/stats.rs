@@ -0,0 +1,82 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::Result;
+
+use crate::{
+    cli::StatsKind,
+    config::Config,
+    history::{self, format_ago, format_duration},
+    style,
+    table::Table,
+};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Default)]
+struct Usage {
+    connections: usize,
+    last_used: Option<u64>,
+    total_secs: u64,
+}
+
+/// Show the usage of each host or key alias, including the configured ones that were never used
+pub fn stats(config: &Config, kind: StatsKind, unused_days: Option<u64>) -> Result<()> {
+    let names: Vec<&String> = match kind {
+        StatsKind::Host => config.host_names().collect(),
+        StatsKind::Alias => config.key_aliases.keys().collect(),
+    };
+    let mut usage: HashMap<String, Usage> = names
+        .into_iter()
+        .map(|name| (name.clone(), Usage::default()))
+        .collect();
+
+    for entry in history::load()? {
+        let name = match kind {
+            StatsKind::Host => match entry.host {
+                Some(host) => host,
+                None => continue,
+            },
+            StatsKind::Alias => entry.key_alias,
+        };
+        let usage = usage.entry(name).or_default();
+        usage.connections += 1;
+        usage.last_used = usage.last_used.max(Some(entry.timestamp));
+        usage.total_secs += entry.duration_secs;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut usage: Vec<(String, Usage)> = usage
+        .into_iter()
+        .filter(|(_, usage)| match unused_days {
+            Some(days) => usage
+                .last_used
+                .is_none_or(|last_used| now.saturating_sub(last_used) >= days * SECONDS_PER_DAY),
+            None => true,
+        })
+        .collect();
+    // Most used first
+    usage.sort_by(|(a_name, a), (b_name, b)| {
+        b.connections
+            .cmp(&a.connections)
+            .then_with(|| a_name.cmp(b_name))
+    });
+
+    let mut table = Table::new(vec!["NAME", "CONNECTIONS", "LAST USED", "TOTAL TIME"]);
+    table.style_column(0, style::name);
+    for (name, usage) in usage {
+        table.add_row(vec![
+            name,
+            usage.connections.to_string(),
+            usage.last_used.map(format_ago).unwrap_or("never".into()),
+            format_duration(usage.total_secs),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
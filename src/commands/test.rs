@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    config::Config,
+    connect::Route,
+    connection_test::{self, Stage},
+    style,
+    table::Table,
+};
+
+/// The hosts to test, sorted by name
+fn target_hosts(config: &Config, host: Option<&str>, group: Option<&str>) -> Result<Vec<String>> {
+    let mut names = match (host, group) {
+        (Some(host), _) => vec![host.to_string()],
+        (_, Some(group)) => config
+            .groups
+            .get(group)
+            .ok_or(eyre!("Group '{group}' does not exist"))?
+            .hosts
+            .clone(),
+        _ => config.host_names().cloned().collect(),
+    };
+    names.sort();
+    Ok(names)
+}
+
+/// The cells of a tested host: "ok" for each stage that passed, "FAIL" for the failed one and
+/// "-" for the ones that were not reached
+fn stage_cells(failed: Option<Stage>) -> Vec<String> {
+    Stage::ALL
+        .iter()
+        .map(|stage| match failed {
+            Some(failed) if *stage == failed => "FAIL",
+            Some(failed) if *stage > failed => "-",
+            _ => "ok",
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Log into a host, a group or all hosts without a terminal and report which stage of each
+/// connection failed. Keys are fetched once per key alias.
+pub fn test(config: &Config, host: Option<&str>, group: Option<&str>) -> Result<()> {
+    let names = target_hosts(config, host, group)?;
+    let mut keys = HashMap::new();
+    let mut failures = Vec::new();
+    let mut table = Table::new(vec![
+        "HOST",
+        "ALIAS",
+        "KEY FETCH",
+        "NETWORK",
+        "HOST KEY",
+        "AUTH",
+    ]);
+    table.style_column(0, style::name);
+    for column in 2..6 {
+        table.style_column(column, style::outcome);
+    }
+
+    for name in &names {
+        // A host that can't be resolved fails before any stage, the others are still tested
+        let host = match config.resolve_host(name) {
+            Ok(host) => host,
+            Err(e) => {
+                let mut row = vec![name.clone(), "-".to_string()];
+                row.extend(Stage::ALL.iter().map(|_| "-".to_string()));
+                table.add_row(row);
+                failures.push((name, format!("invalid config: {e}")));
+                continue;
+            }
+        };
+        let key = keys.entry(host.key_alias.clone()).or_insert_with(|| {
+            super::fetch_key(config, &host.key_alias).map_err(|e| e.to_string())
+        });
+        let route = Route::new(config, &host).map_err(|e| e.to_string());
+        let result = connection_test::test(
+            &host,
+            key.as_ref().map_err(Clone::clone),
+            route.as_ref().map_err(Clone::clone),
+        );
+        let failed = result.as_ref().err().map(|failure| failure.stage);
+        let mut row = vec![name.clone(), host.key_alias.clone()];
+        row.extend(stage_cells(failed));
+        table.add_row(row);
+        if let Err(failure) = result {
+            failures.push((
+                name,
+                format!("{} failed: {}", failure.stage, failure.message),
+            ));
+        }
+    }
+
+    table.print();
+    for (name, failure) in &failures {
+        println!("{} {name}: {failure}", style::failure("[FAIL]"));
+    }
+    if !failures.is_empty() {
+        return Err(eyre!(
+            "{} of {} hosts failed the connection test",
+            failures.len(),
+            names.len()
+        ));
+    }
+    Ok(())
+}
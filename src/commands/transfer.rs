@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write, stderr},
+    path::PathBuf,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use color_eyre::{Result, eyre::eyre};
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveUp,
+    terminal::{Clear, ClearType},
+};
+use tracing::{Level, info};
+
+use crate::{
+    batch,
+    config::{Config, ResolvedHost},
+    connect::Route,
+    duration, style,
+    table::Table,
+    transfer,
+};
+
+/// How often the progress of the hosts is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What is copied, and where
+pub enum Direction {
+    /// Upload a local file or directory into a remote directory
+    Put { local: PathBuf, remote_dir: String },
+    /// Download a remote file or directory into a local directory per host
+    Get { remote: String, local_dir: PathBuf },
+}
+
+impl Direction {
+    fn verb(&self) -> &'static str {
+        match self {
+            Self::Put { .. } => "upload",
+            Self::Get { .. } => "download",
+        }
+    }
+}
+
+/// The progress of the transfer to or from one host
+struct HostTransfer {
+    name: String,
+    key_alias: String,
+    bytes: AtomicU64,
+    /// The result and how long it took, set once the transfer finished
+    outcome: OnceLock<(Result<(), String>, Duration)>,
+}
+
+impl HostTransfer {
+    fn status(&self) -> String {
+        match self.outcome.get() {
+            None => "...",
+            Some((Ok(()), _)) => "ok",
+            Some((Err(_), _)) => "FAIL",
+        }
+        .to_string()
+    }
+}
+
+/// A byte count in binary units, like "12.3 MiB"
+fn format_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 {
+            return if unit == "B" {
+                format!("{bytes} B")
+            } else {
+                format!("{value:.1} {unit}")
+            };
+        }
+        value /= 1024.0;
+    }
+    format!("{value:.1} TiB")
+}
+
+/// The hosts to copy to or from, in the order they were given or listed in the group
+fn target_hosts(config: &Config, hosts: Vec<String>, group: Option<&str>) -> Result<Vec<String>> {
+    let hosts = match group {
+        Some(group) => config
+            .groups
+            .get(group)
+            .ok_or(eyre!("Group '{group}' does not exist"))?
+            .hosts
+            .clone(),
+        None => hosts,
+    };
+    for host in &hosts {
+        if config.host_name(host).is_none() {
+            return Err(eyre!("Host '{host}' does not exist"));
+        }
+    }
+    if hosts.is_empty() {
+        return Err(eyre!("No hosts to copy to or from"));
+    }
+    Ok(hosts)
+}
+
+/// Draw a line per host on stderr until `done` is set, moving back up to redraw them in place
+fn draw_progress(transfers: &[HostTransfer], done: &AtomicBool) {
+    let width = transfers.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    let mut stderr = stderr();
+    let mut drawn = false;
+    loop {
+        // Checked before drawing so that the final state is always drawn
+        let finished = done.load(Ordering::Relaxed);
+        if drawn {
+            let _ = stderr.queue(MoveUp(transfers.len() as u16));
+        }
+        for transfer in transfers {
+            let _ = stderr.queue(Clear(ClearType::CurrentLine));
+            let _ = writeln!(
+                stderr,
+                "{:width$}  {:>10}  {}",
+                transfer.name,
+                format_bytes(transfer.bytes.load(Ordering::Relaxed)),
+                style::outcome(&transfer.status())
+            );
+        }
+        let _ = stderr.flush();
+        drawn = true;
+        if finished {
+            return;
+        }
+        std::thread::sleep(REDRAW_INTERVAL);
+    }
+}
+
+/// Write the outcome of a host as a tab separated line for scripts in batch mode, like
+/// `transfer<TAB>web<TAB>ok<TAB>1048576<TAB>2.5` with the bytes and the elapsed seconds
+fn report(
+    host: &ResolvedHost,
+    transfer: &HostTransfer,
+    result: &Result<(), String>,
+    elapsed: Duration,
+) {
+    if tracing::enabled!(Level::INFO) {
+        eprintln!(
+            "transfer\t{}\t{}\t{}\t{:.1}",
+            host.name,
+            if result.is_ok() { "ok" } else { "failed" },
+            transfer.bytes.load(Ordering::Relaxed),
+            elapsed.as_secs_f32()
+        );
+    }
+}
+
+/// Copy a path to or from the hosts in parallel, showing the progress of each host and a summary
+/// of the failures. Keys are fetched once per key alias before the transfers start, like the keys
+/// of the jump hosts of each host.
+pub fn transfer(
+    config: &Config,
+    hosts: Vec<String>,
+    group: Option<&str>,
+    direction: &Direction,
+) -> Result<()> {
+    let names = target_hosts(config, hosts, group)?;
+    if let Direction::Put { local, .. } = direction
+        && !local.exists()
+    {
+        return Err(eyre!("{local:?} does not exist"));
+    }
+
+    let mut resolved = Vec::new();
+    let mut keys = HashMap::new();
+    let mut routes = Vec::new();
+    for name in &names {
+        let host = config.resolve_host(name)?;
+        keys.entry(host.key_alias.clone()).or_insert_with(|| {
+            super::fetch_key(config, &host.key_alias).map_err(|e| e.to_string())
+        });
+        routes.push(Route::new(config, &host).map_err(|e| e.to_string()));
+        resolved.push(host);
+    }
+    let transfers: Vec<HostTransfer> = resolved
+        .iter()
+        .map(|host| HostTransfer {
+            name: host.name.clone(),
+            key_alias: host.key_alias.clone(),
+            bytes: AtomicU64::new(0),
+            outcome: OnceLock::new(),
+        })
+        .collect();
+
+    let interactive = !batch::enabled() && stderr().is_terminal() && tracing::enabled!(Level::INFO);
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = resolved
+            .iter()
+            .zip(&transfers)
+            .zip(&routes)
+            .map(|((host, transfer), route)| {
+                let key = &keys[&host.key_alias];
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let key = key.as_ref().map_err(Clone::clone);
+                    let route = route.as_ref().map_err(Clone::clone);
+                    let result = key
+                        .and_then(|key| Ok((key, route?)))
+                        .and_then(|(key, route)| {
+                            match direction {
+                                Direction::Put { local, remote_dir } => transfer::upload(
+                                    host,
+                                    route,
+                                    key,
+                                    local,
+                                    remote_dir,
+                                    &transfer.bytes,
+                                ),
+                                Direction::Get { remote, local_dir } => transfer::download(
+                                    host,
+                                    route,
+                                    key,
+                                    remote,
+                                    &local_dir.join(&host.name),
+                                    &transfer.bytes,
+                                ),
+                            }
+                            .map_err(|e| e.to_string())
+                        });
+                    let elapsed = started.elapsed();
+                    if batch::enabled() {
+                        report(host, transfer, &result, elapsed);
+                    } else if !interactive {
+                        match &result {
+                            Ok(()) => info!(
+                                "'{}': {} in {}",
+                                host.name,
+                                format_bytes(transfer.bytes.load(Ordering::Relaxed)),
+                                duration::format(elapsed)
+                            ),
+                            Err(_) => info!("'{}': failed", host.name),
+                        }
+                    }
+                    let _ = transfer.outcome.set((result, elapsed));
+                })
+            })
+            .collect();
+        if interactive {
+            let drawer = scope.spawn(|| draw_progress(&transfers, &done));
+            for worker in workers {
+                let _ = worker.join();
+            }
+            done.store(true, Ordering::Relaxed);
+            let _ = drawer.join();
+        }
+    });
+
+    let mut table = Table::new(vec!["HOST", "ALIAS", "STATUS", "SIZE", "TIME"]);
+    table.style_column(0, style::name);
+    table.style_column(2, style::outcome);
+    let mut failures = Vec::new();
+    for transfer in &transfers {
+        let (result, elapsed) = transfer
+            .outcome
+            .get()
+            .expect("all transfers finished with the scope");
+        table.add_row(vec![
+            transfer.name.clone(),
+            transfer.key_alias.clone(),
+            transfer.status(),
+            format_bytes(transfer.bytes.load(Ordering::Relaxed)),
+            duration::format(*elapsed),
+        ]);
+        if let Err(message) = result {
+            failures.push((&transfer.name, message));
+        }
+    }
+    table.print();
+    for (name, message) in &failures {
+        println!("{} {name}: {message}", style::failure("[FAIL]"));
+    }
+    if !failures.is_empty() {
+        return Err(eyre!(
+            "{} of {} hosts failed to {}",
+            failures.len(),
+            transfers.len(),
+            direction.verb()
+        ));
+    }
+    Ok(())
+}
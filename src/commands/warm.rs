@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    audit::{self, AuditRecord},
+    config::{Config, KeyAliasConfig},
+    daemon, style,
+};
+
+/// The key aliases used by a host or the hosts of a group, or all key aliases without a target
+fn target_aliases(config: &Config, target: Option<&str>) -> Result<BTreeSet<String>> {
+    let Some(target) = target else {
+        return Ok(config.key_aliases.keys().cloned().collect());
+    };
+    let hosts = if config.host_name(target).is_some() {
+        vec![target.to_string()]
+    } else if let Some(group) = config.groups.get(target) {
+        group.hosts.clone()
+    } else {
+        return Err(eyre!("No host or group named '{target}'"));
+    };
+    hosts
+        .iter()
+        .map(|host| Ok(config.resolve_host(host)?.key_alias))
+        .collect()
+}
+
+/// Fetch the keys of a host, a group or all key aliases through the daemon, which resolves their
+/// credentials and caches the keys if it runs with `--cache-keys`. The fetches are audited here,
+/// the daemon does not read the config.
+pub fn warm(config: &Config, target: Option<&str>) -> Result<()> {
+    let mut failures = 0;
+    let mut credentials_only = false;
+    let aliases = target_aliases(config, target)?;
+    for name in &aliases {
+        let alias = config
+            .key_aliases
+            .get(name)
+            .ok_or_else(|| eyre!("Key alias '{name}' does not exist"))?
+            .expanded()?;
+        let KeyAliasConfig::SecretsManager {
+            secret_arn,
+            profile,
+            passphrase_secret_arn,
+            ..
+        } = &alias
+        else {
+            // The daemon only fetches keys from Secrets Manager
+            println!("[SKIP] {}: plugin keys are not cached", style::name(name));
+            continue;
+        };
+        let warmed = daemon::warm_key(secret_arn, profile.as_deref()).and_then(|cached| {
+            match (cached, passphrase_secret_arn) {
+                (Some(_), Some(passphrase_secret_arn)) => {
+                    daemon::warm_key(passphrase_secret_arn, profile.as_deref())
+                }
+                (cached, _) => Ok(cached),
+            }
+        });
+        if let Some(audit_log) = &config.audit_log
+            && !matches!(warmed, Ok(None))
+        {
+            let source = config.key_source(name)?;
+            let mut record = AuditRecord::new("fetch", None, None, name, source.into());
+            record.error = warmed.as_ref().err().map(|e| e.to_string());
+            audit::record(audit_log, &record)?;
+        }
+        match warmed {
+            Ok(Some(true)) => println!("{} {}", style::success("[WARM]"), style::name(name)),
+            Ok(Some(false)) => {
+                credentials_only = true;
+                println!(
+                    "{} {}: credentials only",
+                    style::success("[WARM]"),
+                    style::name(name)
+                );
+            }
+            Ok(None) => {
+                return Err(eyre!(
+                    "The daemon is not running, start it with `smssh daemon --cache-keys <DURATION>`"
+                ));
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{} {}: {e}", style::failure("[FAIL]"), style::name(name));
+            }
+        }
+    }
+
+    if credentials_only {
+        println!(
+            "The daemon does not cache keys, only the AWS credentials were warmed. Start it with \
+             `smssh daemon --cache-keys <DURATION>` to keep the keys as well"
+        );
+    }
+    if failures > 0 {
+        return Err(eyre!("{failures} of {} keys failed to warm", aliases.len()));
+    }
+    Ok(())
+}
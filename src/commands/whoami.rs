@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    aws::{self, CallerIdentity},
+    config::{Config, KeyAliasConfig},
+    style,
+    table::Table,
+};
+
+/// Print the AWS identity and region that the credentials of one or all key aliases resolve to.
+/// Each distinct profile is resolved once.
+pub fn whoami(config: &Config, key_alias: Option<&str>) -> Result<()> {
+    let mut names: Vec<String> = match key_alias {
+        Some(name) => vec![name.to_string()],
+        None => config.key_aliases.keys().cloned().collect(),
+    };
+    names.sort();
+
+    let mut identities: HashMap<Option<String>, Result<CallerIdentity, String>> = HashMap::new();
+    let mut failures = Vec::new();
+    let mut table = Table::new(vec!["ALIAS", "PROFILE", "ACCOUNT", "IDENTITY", "REGION"]);
+    table.style_column(0, style::name);
+    for name in &names {
+        let KeyAliasConfig::SecretsManager { profile, .. } = config.key_alias(name)? else {
+            // Plugins fetch keys with credentials of their own
+            table.add_row(vec![
+                name.clone(),
+                "-".into(),
+                "-".into(),
+                "plugin".into(),
+                "-".into(),
+            ]);
+            continue;
+        };
+        let identity = identities.entry(profile.clone()).or_insert_with(|| {
+            aws::caller_identity_blocking(profile.as_deref()).map_err(|e| e.to_string())
+        });
+        let profile = profile.unwrap_or_else(|| "default".to_string());
+        match identity {
+            Ok(identity) => table.add_row(vec![
+                name.clone(),
+                profile,
+                identity.account.clone(),
+                identity.arn.clone(),
+                identity.region.clone().unwrap_or_default(),
+            ]),
+            Err(e) => {
+                table.add_row(vec![
+                    name.clone(),
+                    profile,
+                    "-".into(),
+                    "-".into(),
+                    "-".into(),
+                ]);
+                failures.push((name, e.clone()));
+            }
+        }
+    }
+
+    table.print();
+    for (name, error) in &failures {
+        println!("{} {name}: {error}", style::failure("[FAIL]"));
+    }
+    if !failures.is_empty() {
+        return Err(eyre!(
+            "The credentials of {} of {} key aliases could not be resolved",
+            failures.len(),
+            names.len()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use color_eyre::Report;
+use serde::Serialize;
+
+use crate::{aws, error::Error, prompt::PromptError};
+
+/// A failure as printed by `--error-format json`, on a single line of stderr
+#[derive(Serialize)]
+struct JsonError {
+    /// Stable kind of the failure for scripts to branch on
+    code: &'static str,
+    /// The kind deciding the exit status, like `config` or `provider`, if the failure has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+    message: String,
+    /// The underlying errors, outermost first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    causes: Vec<String>,
+    /// The error code of the AWS request that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aws_error_code: Option<String>,
+    /// The exit status of ssh, for `ssh_exit`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+}
+
+fn print(error: &JsonError) {
+    match serde_json::to_string(error) {
+        Ok(json) => eprintln!("{json}"),
+        Err(e) => eprintln!("Failed to serialize the error: {e}"),
+    }
+}
+
+/// The kind of an AWS error code
+fn aws_code(aws_error_code: &str) -> &'static str {
+    if aws_error_code.contains("AccessDenied") || aws_error_code == "UnauthorizedOperation" {
+        "access_denied"
+    } else if aws_error_code.contains("NotFound") || aws_error_code == "NoSuchKey" {
+        "not_found"
+    } else if aws_error_code.starts_with("ExpiredToken")
+        || aws_error_code == "UnrecognizedClientException"
+        || aws_error_code == "InvalidClientTokenId"
+    {
+        "invalid_credentials"
+    } else {
+        "aws_error"
+    }
+}
+
+/// The kind of a failure, from the AWS error code behind it or from the typed errors in its
+/// chain
+fn code(report: &Report, aws_error_code: Option<&str>) -> &'static str {
+    if let Some(aws_error_code) = aws_error_code {
+        return aws_code(aws_error_code);
+    }
+    match PromptError::of(report) {
+        Some(PromptError::Unavailable(_)) => return "prompt_unavailable",
+        Some(PromptError::Declined(_)) => return "aborted",
+        None => {}
+    }
+    if aws::is_timeout(report) {
+        "timeout"
+    } else if aws::lacks_credentials(report) {
+        "invalid_credentials"
+    } else if aws::is_unreachable(report) {
+        "aws_unreachable"
+    } else {
+        match Error::of(report) {
+            Some(Error::Config(_)) => "invalid_config",
+            Some(Error::Provider(_)) => "fetch_failed",
+            Some(Error::Transport(_)) => "unreachable",
+            Some(Error::SshFailed { .. }) => "ssh_exit",
+            None => "error",
+        }
+    }
+}
+
+/// Print a failure as a JSON object with a stable `code`, like `not_found`, `access_denied` or
+/// `ssh_exit`
+pub fn print_error(report: &Report) {
+    let mut messages: Vec<String> = report.chain().map(ToString::to_string).collect();
+    let aws_error_code = aws::error_code(report);
+    let code = code(report, aws_error_code.as_deref());
+    let message = messages.remove(0);
+    let error = Error::of(report);
+    let exit_status = match error {
+        Some(Error::SshFailed { status }) => Some(*status),
+        _ => None,
+    };
+    print(&JsonError {
+        code: match exit_status {
+            Some(_) => "ssh_exit",
+            None => code,
+        },
+        kind: error.map(Error::kind),
+        message,
+        causes: messages,
+        aws_error_code,
+        exit_status,
+    });
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, fmt::MakeWriter, prelude::*};
+
+use crate::style;
+
+static REDACTED: &str = "[REDACTED]";
+static PRIVATE_KEY_BEGIN: &str = "-----BEGIN";
+static PRIVATE_KEY_END: &str = "-----END";
+static PRIVATE_KEY_MARKER: &str = "PRIVATE KEY-----";
+
+/// Initialize logging to stderr. `verbosity` is -1 for quiet, 0 by default and increased by each
+/// `-v` flag.
+pub fn init(verbosity: i8) {
+    let level = match verbosity {
+        ..0 => Level::WARN,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let mut targets = Targets::new()
+        .with_target(env!("CARGO_PKG_NAME"), level)
+        .with_target("smssh_core", level);
+    // The AWS SDK logs its requests, credential resolution and retries at debug level
+    if verbosity >= 2 {
+        targets = targets
+            .with_target("aws_config", Level::DEBUG)
+            .with_target("aws_smithy_runtime", Level::DEBUG);
+    }
+
+    let format = tracing_subscriber::fmt::layer()
+        .with_writer(RedactingStderr)
+        .with_ansi(style::enabled(&io::stderr()))
+        .with_target(verbosity >= 1);
+    if verbosity >= 1 {
+        tracing_subscriber::registry()
+            .with(format)
+            .with(targets)
+            .init();
+    } else {
+        tracing_subscriber::registry()
+            .with(format.without_time())
+            .with(targets)
+            .init();
+    }
+}
+
+/// Replace the contents of private key blocks with a placeholder, keeping the markers.
+fn redact(message: &str) -> String {
+    let mut redacted = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find(PRIVATE_KEY_BEGIN) {
+        let Some(marker) = rest[start..].find(PRIVATE_KEY_MARKER) else {
+            break;
+        };
+        let body_start = start + marker + PRIVATE_KEY_MARKER.len();
+        redacted.push_str(&rest[..body_start]);
+        redacted.push_str(REDACTED);
+        // Redact everything up to the end marker, or the rest of the message if there is none
+        match rest[body_start..].find(PRIVATE_KEY_END) {
+            Some(end) => rest = &rest[body_start + end..],
+            None => {
+                rest = "";
+                break;
+            }
+        }
+        redacted.push_str(&rest[..PRIVATE_KEY_END.len()]);
+        rest = &rest[PRIVATE_KEY_END.len()..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Writes log lines to stderr, redacting any key material that ended up in them.
+struct RedactingStderr;
+
+impl Write for RedactingStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let message = String::from_utf8_lossy(buf);
+        io::stderr().write_all(redact(&message).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RedactingStderr {
+    type Writer = RedactingStderr;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingStderr
+    }
+}
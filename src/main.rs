@@ -1,35 +1,368 @@
 use clap::Parser;
-use cli::{Args, SMSSHCommand, SSHConfig};
-use color_eyre::Result;
+use clap_complete::Shell;
+use cli::{Args, ErrorFormat, SMSSHCommand, SSHConfig};
+use color_eyre::{
+    Result,
+    config::{HookBuilder, Theme},
+    eyre::eyre,
+};
+use connect::ConnectOptions;
+use error::Error;
+use prompt::PromptError;
+use std::process::ExitCode;
 
-mod aws;
 mod cli;
 mod commands;
-mod config;
+mod error_output;
+mod logging;
+mod style;
+mod table;
 
-fn main() -> Result<()> {
-    color_eyre::install()?;
+// The binary modules refer to the library modules through the crate root
+use smssh_core::{
+    agent, ansible, audit, authorized_keys, aws, batch, config, connect, connection_test, daemon,
+    database, duration, encryption, error, fingerprints, history, key_cache, key_format, keygen,
+    plugin, prompt, provider, reachability, second_factor, ssh_config, sync, systemd, transfer,
+    trusted_commands,
+};
+
+/// Exit status of failures that are not of a known kind
+const GENERIC_EXIT_STATUS: u8 = 1;
+
+/// Number of connection retries for `--retry` or `--wait`
+fn retries(retry: Option<u32>, wait: bool) -> u32 {
+    if wait { u32::MAX } else { retry.unwrap_or(0) }
+}
+
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
+    if args.batch {
+        batch::enable();
+    }
+    style::init(args.color);
+    if style::enabled(&std::io::stderr()) {
+        color_eyre::install()?;
+    } else {
+        HookBuilder::new().theme(Theme::new()).install()?;
+    }
+    logging::init(args.verbosity());
+
+    let error_format = args.error_format;
+    let report = match run(args) {
+        Ok(exit_code) => return Ok(exit_code),
+        Err(report) => report,
+    };
+    match error_format {
+        ErrorFormat::Human => eprintln!("Error: {report:?}"),
+        ErrorFormat::Json => error_output::print_error(&report),
+    }
+    // Failures of a known kind have their own exit status, see `smssh --help`. A declined
+    // confirmation is told apart first, as it can be wrapped in the error of the command.
+    if let Some(PromptError::Declined(_)) = PromptError::of(&report) {
+        return Ok(ExitCode::from(error::DECLINED_EXIT_STATUS));
+    }
+    Ok(ExitCode::from(
+        Error::of(&report).map_or(GENERIC_EXIT_STATUS, Error::exit_status),
+    ))
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    // These commands must not load the config, which might prompt for a passphrase
+    match &args.command {
+        SMSSHCommand::CompleteNames { kind } => {
+            commands::print_names(*kind)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        SMSSHCommand::Completions { shell, print, .. } => {
+            let shell = shell.or_else(Shell::from_env).ok_or(eyre!(
+                "Could not detect the shell from $SHELL, pass it with --shell"
+            ))?;
+            if *print {
+                commands::print_completions(shell);
+            } else {
+                commands::install_completions(shell)?;
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        SMSSHCommand::Man { out_dir } => {
+            commands::generate_man_pages(out_dir)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        SMSSHCommand::Daemon {
+            cache_keys,
+            metrics,
+            idle_timeout,
+            print_systemd_unit,
+        } => {
+            if *print_systemd_unit {
+                commands::print_systemd_units(
+                    "smssh-daemon",
+                    "smssh daemon",
+                    &daemon::socket_path(),
+                )?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            let key_ttl = cache_keys.as_deref().map(duration::parse).transpose()?;
+            let idle_timeout = idle_timeout.as_deref().map(duration::parse).transpose()?;
+            daemon::serve(key_ttl, metrics.as_deref(), idle_timeout)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
     let mut config = config::Config::load()?;
+    // Exit status of ssh, passed on to scripts wrapping smssh
+    let mut status = 0;
 
     match args.command {
-        SMSSHCommand::Connect { host, ssh_args } => {
-            commands::connect::connect_by_host(&host, &config, &ssh_args)?
+        SMSSHCommand::Connect {
+            host,
+            dry_run,
+            record,
+            retry,
+            wait,
+            check,
+            start,
+            alias,
+            ssh_args,
+        } => {
+            let options = ConnectOptions {
+                dry_run,
+                record,
+                retries: retries(retry, wait),
+                check_reachable: check,
+                start_instance: start,
+                key_alias: alias,
+                command: None,
+            };
+            status = connect::connect_by_host(&host, &config, &ssh_args, &options)?
         }
 
         SMSSHCommand::ConnectWithAlias {
             key_alias,
+            dry_run,
+            record,
+            retry,
+            wait,
+            check,
             ssh_args,
-        } => commands::connect::connect_by_alias(&key_alias, &config, &ssh_args)?,
+        } => {
+            let options = ConnectOptions {
+                dry_run,
+                record,
+                retries: retries(retry, wait),
+                check_reachable: check,
+                ..Default::default()
+            };
+            status = connect::connect_by_alias(&key_alias, &config, &ssh_args, &options)?
+        }
+
+        SMSSHCommand::Agent {
+            aliases,
+            key_ttl,
+            metrics,
+            idle_timeout,
+            print_systemd_unit,
+        } => {
+            for alias in &aliases {
+                config.key_alias(alias)?;
+            }
+            let aliases = if aliases.is_empty() {
+                let mut aliases: Vec<String> = config.key_aliases.keys().cloned().collect();
+                aliases.sort();
+                aliases
+            } else {
+                aliases
+            };
+            if print_systemd_unit {
+                // Started by systemd, the agent has no terminal to ask for the code on
+                if let Some(alias) = aliases
+                    .iter()
+                    .find(|alias| config.key_aliases[*alias].second_factor().is_some())
+                {
+                    return Err(Error::Config(format!(
+                        "Key alias '{alias}' needs a second factor, which the agent can't ask \
+                         for when started by systemd, name the key aliases to offer without it"
+                    ))
+                    .into());
+                }
+                commands::print_systemd_units("smssh-agent", "smssh agent", &agent::socket_path())?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            agent::serve(
+                aliases,
+                duration::parse(&key_ttl)?,
+                metrics.as_deref(),
+                idle_timeout.as_deref().map(duration::parse).transpose()?,
+                &|alias| commands::fetch_key(&config, alias),
+            )?
+        }
+
+        SMSSHCommand::Run {
+            host,
+            command,
+            dry_run,
+            args,
+        } => status = commands::run::run(&config, &host, &command, &args, dry_run)?,
+
+        SMSSHCommand::Recent {
+            index,
+            limit,
+            dry_run,
+        } => status = commands::recent::recent(&config, index, limit, dry_run)?,
+
+        SMSSHCommand::Stats { by, unused } => commands::stats::stats(&config, by, unused)?,
+
+        SMSSHCommand::Warm { target } => commands::warm::warm(&config, target.as_deref())?,
+
+        SMSSHCommand::Keygen {
+            name,
+            secret_name,
+            profile,
+            kms_key_id,
+            tags,
+            comment,
+            public_key_file,
+        } => commands::keygen::keygen(
+            &mut config,
+            commands::keygen::KeygenOptions {
+                name,
+                secret_name,
+                profile,
+                kms_key_id,
+                tags,
+                comment,
+                public_key_file,
+            },
+        )?,
+
+        SMSSHCommand::Rotate {
+            key_alias,
+            hosts,
+            remove_old,
+            dry_run,
+        } => commands::rotate::rotate(&config, &key_alias, &hosts, remove_old, dry_run)?,
+
+        SMSSHCommand::Pubkey {
+            key_alias,
+            comment,
+            copy,
+        } => commands::pubkey::pubkey(&config, &key_alias, comment, copy)?,
+
+        SMSSHCommand::Fingerprint { target } => {
+            commands::fingerprint::fingerprint(&config, target)?
+        }
+
+        SMSSHCommand::PushKey {
+            host,
+            file,
+            key,
+            from_alias,
+            user,
+            alias,
+        } => {
+            let source = match (file, key, from_alias) {
+                (Some(path), _, _) => commands::push_key::PublicKeySource::File(path),
+                (_, Some(key), _) => commands::push_key::PublicKeySource::Literal(key),
+                (_, _, Some(alias)) => commands::push_key::PublicKeySource::Alias(alias),
+                _ => unreachable!("clap requires one public key source"),
+            };
+            commands::push_key::push_key(
+                &config,
+                &host,
+                alias.as_deref(),
+                &source,
+                user.as_deref(),
+            )?
+        }
+
+        SMSSHCommand::Test { host, group, .. } => {
+            commands::test::test(&config, host.as_deref(), group.as_deref())?
+        }
+
+        SMSSHCommand::Put {
+            local,
+            remote_dir,
+            hosts,
+            group,
+        } => commands::transfer::transfer(
+            &config,
+            hosts,
+            group.as_deref(),
+            &commands::transfer::Direction::Put { local, remote_dir },
+        )?,
+        SMSSHCommand::Get {
+            remote,
+            local_dir,
+            hosts,
+            group,
+        } => commands::transfer::transfer(
+            &config,
+            hosts,
+            group.as_deref(),
+            &commands::transfer::Direction::Get { remote, local_dir },
+        )?,
+
+        SMSSHCommand::Open {
+            hosts, group, tmux, ..
+        } => {
+            let multiplexer = if tmux {
+                commands::open::Multiplexer::Tmux
+            } else {
+                commands::open::Multiplexer::Zellij
+            };
+            commands::open::open(&config, hosts, group.as_deref(), multiplexer)?
+        }
+
+        SMSSHCommand::Db {
+            name,
+            local_port,
+            dry_run,
+        } => status = commands::db::db(&config, &name, local_port, dry_run)?,
+
+        SMSSHCommand::Whoami { key_alias } => {
+            commands::whoami::whoami(&config, key_alias.as_deref())?
+        }
 
         SMSSHCommand::Config { command } => match command {
-            SSHConfig::List { section } => commands::config::list_config(&config, section)?,
-            SSHConfig::Set { section } => commands::config::add_config(&mut config, section)?,
+            SSHConfig::List { output, section } => {
+                commands::config::list_config(&config, section, output)?
+            }
+            SSHConfig::Get { name, output } => {
+                commands::config::get_config(&config, &name, output)?
+            }
+            SSHConfig::Set {
+                force,
+                update,
+                section,
+            } => commands::config::add_config(&mut config, *section, force, update)?,
             SSHConfig::Remove { section } => commands::config::remove_config(&mut config, section)?,
+            SSHConfig::Rename { section } => commands::config::rename_config(&mut config, section)?,
+            SSHConfig::Sync { source, check } => {
+                commands::config::sync_config(&mut config, source, check)?
+            }
+            SSHConfig::Diff { source } => commands::config::diff_config(&config, source)?,
+            SSHConfig::Import { source } => commands::config::import_config(&mut config, source)?,
+            SSHConfig::Export { target } => commands::config::export_config(&config, target)?,
+            SSHConfig::Restore { backup, list } => commands::config::restore_config(backup, list)?,
+            SSHConfig::FixPermissions => commands::config::fix_permissions()?,
+            SSHConfig::Encrypt { keyring } => {
+                commands::config::encrypt_config(&mut config, keyring)?
+            }
+            SSHConfig::Decrypt => commands::config::decrypt_config(&mut config)?,
         },
 
-        SMSSHCommand::Completions { shell } => commands::print_completions(shell),
+        SMSSHCommand::Init => commands::init::init(&mut config)?,
+
+        SMSSHCommand::Doctor => commands::doctor::doctor(&config)?,
+
+        SMSSHCommand::CompleteNames { .. }
+        | SMSSHCommand::Completions { .. }
+        | SMSSHCommand::Man { .. }
+        | SMSSHCommand::Daemon { .. } => unreachable!("Handled before loading the config"),
     }
 
-    Ok(())
+    if status != 0 && args.error_format == ErrorFormat::Json {
+        error_output::print_error(&Error::SshFailed { status }.into());
+    }
+    Ok(ExitCode::from(error::ssh_exit_status(status)))
 }
@@ -0,0 +1,78 @@
+use std::{
+    io::{IsTerminal, stdout},
+    sync::OnceLock,
+};
+
+use crossterm::style::{ContentStyle, Stylize};
+
+use crate::cli::ColorChoice;
+
+static COLOR_CHOICE: OnceLock<ColorChoice> = OnceLock::new();
+static STDOUT_COLOR: OnceLock<bool> = OnceLock::new();
+
+pub fn init(choice: ColorChoice) {
+    let _ = COLOR_CHOICE.set(choice);
+}
+
+/// Whether to color the output written to `stream`. In auto mode, colors are used for terminals
+/// unless the `NO_COLOR` environment variable is set to a non-empty value.
+pub fn enabled(stream: &impl IsTerminal) -> bool {
+    match COLOR_CHOICE.get().copied().unwrap_or(ColorChoice::Auto) {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && stream.is_terminal()
+        }
+    }
+}
+
+fn paint(text: &str, style: ContentStyle) -> String {
+    if *STDOUT_COLOR.get_or_init(|| enabled(&stdout())) {
+        style.apply(text).to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Names of configuration entries
+pub fn name(text: &str) -> String {
+    paint(text, ContentStyle::new().cyan().bold())
+}
+
+/// SSH destinations
+pub fn destination(text: &str) -> String {
+    paint(text, ContentStyle::new().green())
+}
+
+/// Table headers
+pub fn header(text: &str) -> String {
+    paint(text, ContentStyle::new().bold())
+}
+
+pub fn success(text: &str) -> String {
+    paint(text, ContentStyle::new().green().bold())
+}
+
+pub fn failure(text: &str) -> String {
+    paint(text, ContentStyle::new().red().bold())
+}
+
+/// Lines describing config changes, colored by their `+`, `-` or `~` prefix
+pub fn change(line: &str) -> String {
+    match line.chars().next() {
+        Some('+') => paint(line, ContentStyle::new().green()),
+        Some('-') => paint(line, ContentStyle::new().red()),
+        Some('~') => paint(line, ContentStyle::new().yellow()),
+        _ => line.to_string(),
+    }
+}
+
+/// Outcomes of checks, colored by whether they are "ok" or "FAIL"
+pub fn outcome(text: &str) -> String {
+    match text.trim() {
+        "ok" => success(text),
+        "FAIL" => failure(text),
+        _ => text.to_string(),
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::{IsTerminal, stdout};
+
+use crate::style;
+
+/// Styles a cell, see the `style` module
+type Style = fn(&str) -> String;
+
+static COLUMN_SEPARATOR: &str = "  ";
+static ELLIPSIS: char = '…';
+
+/// A table of aligned text columns, shrunk to the terminal width when printed to a terminal
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+    styles: Vec<Option<Style>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Self {
+            styles: vec![None; headers.len()],
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Style the cells of a column, applied after they are aligned
+    pub fn style_column(&mut self, column: usize, style: Style) {
+        self.styles[column] = Some(style);
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// Width of each column, shrinking the widest columns until the table fits in `max_width`.
+    /// Columns are never shrunk below the width of their header.
+    fn column_widths(&self, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| header.len()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let Some(max_width) = max_width else {
+            return widths;
+        };
+        let separators = COLUMN_SEPARATOR.len() * widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + separators > max_width {
+            let Some((widest, _)) = widths
+                .iter()
+                .enumerate()
+                .filter(|(i, width)| **width > self.headers[*i].len())
+                .max_by_key(|(_, width)| **width)
+            else {
+                break;
+            };
+            widths[widest] -= 1;
+        }
+        widths
+    }
+
+    fn format_row<S: AsRef<str>>(row: &[S], widths: &[usize], styles: &[Option<Style>]) -> String {
+        let last = row.len().saturating_sub(1);
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .zip(styles)
+            .enumerate()
+            .map(|(i, ((cell, width), style))| {
+                let cell = truncate(cell.as_ref(), *width);
+                // Don't pad the last column to avoid trailing whitespace
+                let cell = if i == last {
+                    cell
+                } else {
+                    format!("{cell:width$}")
+                };
+                match style {
+                    Some(style) if !cell.trim().is_empty() => style(&cell),
+                    _ => cell,
+                }
+            })
+            .collect();
+        cells.join(COLUMN_SEPARATOR).trim_end().to_string()
+    }
+
+    pub fn print(&self) {
+        let max_width = stdout()
+            .is_terminal()
+            .then(|| crossterm::terminal::size().ok())
+            .flatten()
+            // Pseudo terminals without a configured size report zero columns
+            .filter(|(columns, _)| *columns > 0)
+            .map(|(columns, _)| columns as usize);
+        let widths = self.column_widths(max_width);
+
+        let header_styles = vec![Some(style::header as Style); self.headers.len()];
+        println!(
+            "{}",
+            Self::format_row(&self.headers, &widths, &header_styles)
+        );
+        for row in &self.rows {
+            println!("{}", Self::format_row(row, &widths, &self.styles));
+        }
+    }
+}
+
+/// Shorten `text` to at most `width` characters, marking the cut with an ellipsis
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push(ELLIPSIS);
+    truncated
+}